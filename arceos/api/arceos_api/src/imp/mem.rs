@@ -3,6 +3,9 @@ use core::alloc::Layout;
 cfg_alloc! {
     use core::ptr::NonNull;
 
+    #[cfg(feature = "myalloc")]
+    pub use axalloc::MyByteAllocatorIf;
+
     pub fn ax_alloc(layout: Layout) -> Option<NonNull<u8>> {
         axalloc::global_allocator().alloc(layout).ok()
     }
@@ -12,6 +15,20 @@ cfg_alloc! {
     }
 }
 
+cfg_alloc_tag! {
+    use alloc::vec::Vec;
+
+    pub use axalloc::{AllocTag as AxAllocTag, TagStats as AxTagStats};
+
+    pub fn ax_alloc_tag_stats() -> Vec<AxTagStats> {
+        axalloc::tag_stats()
+    }
+
+    pub fn ax_with_alloc_tag(tag: &'static str, f: &mut dyn FnMut()) {
+        axtask::with_alloc_tag(tag, f)
+    }
+}
+
 cfg_dma! {
     pub use axdma::DMAInfo;
 
@@ -18,9 +18,15 @@ pub fn ax_yield_now() {
 
 pub fn ax_exit(_exit_code: i32) -> ! {
     #[cfg(feature = "multitask")]
-    axtask::exit(_exit_code);
+    {
+        // Exiting the main task shuts down the system.
+        if axtask::current().is_init() {
+            axruntime::terminate();
+        }
+        axtask::exit(_exit_code);
+    }
     #[cfg(not(feature = "multitask"))]
-    axhal::misc::terminate();
+    axruntime::terminate();
 }
 
 cfg_task! {
@@ -52,6 +58,14 @@ cfg_task! {
         }
     }
 
+    /// A handle to a timer.
+    ///
+    /// Dropping the handle does not cancel the timer.
+    pub struct AxTimerHandle {
+        #[cfg(feature = "irq")]
+        inner: axtask::timers::TimerHandle,
+    }
+
     pub fn ax_current_task_id() -> u64 {
         axtask::current().id().as_u64()
     }
@@ -99,6 +113,17 @@ cfg_task! {
         false
     }
 
+    pub fn ax_wait_queue_wait_or_killed(
+        wq: &AxWaitQueueHandle,
+        until_condition: impl Fn() -> bool,
+    ) -> bool {
+        wq.0.wait_until_or_killed(until_condition)
+    }
+
+    pub fn ax_exit_killed() -> ! {
+        axtask::exit(axtask::KILLED_EXIT_CODE)
+    }
+
     pub fn ax_wait_queue_wake(wq: &AxWaitQueueHandle, count: u32) {
         if count == u32::MAX {
             wq.0.notify_all(true);
@@ -108,4 +133,57 @@ cfg_task! {
             }
         }
     }
+
+    pub fn ax_set_timer(
+        deadline: crate::time::AxTimeValue,
+        period: Option<Duration>,
+        callback: impl Fn() + Send + Sync + 'static,
+    ) -> AxTimerHandle {
+        #[cfg(feature = "irq")]
+        {
+            let inner = match period {
+                Some(period) => axtask::timers::set_periodic_timer(deadline, period, callback),
+                None => axtask::timers::set_timer(deadline, callback),
+            };
+            AxTimerHandle { inner }
+        }
+        #[cfg(not(feature = "irq"))]
+        {
+            let _ = (deadline, period, callback);
+            axlog::warn!("ax_set_timer: timers never fire without the `irq` feature");
+            AxTimerHandle {}
+        }
+    }
+
+    pub fn ax_rearm_timer(timer: &AxTimerHandle, deadline: crate::time::AxTimeValue) {
+        #[cfg(feature = "irq")]
+        timer.inner.rearm(deadline);
+        #[cfg(not(feature = "irq"))]
+        let _ = (timer, deadline);
+    }
+
+    pub fn ax_cancel_timer(timer: &AxTimerHandle) -> bool {
+        #[cfg(feature = "irq")]
+        return timer.inner.cancel();
+        #[cfg(not(feature = "irq"))]
+        {
+            let _ = timer;
+            false
+        }
+    }
+
+    #[cfg(feature = "lockdep")]
+    pub fn ax_lockdep_check_acquire(class: &'static core::panic::Location<'static>) {
+        axsync::lockdep::check_acquire(class);
+    }
+
+    #[cfg(feature = "lockdep")]
+    pub fn ax_lockdep_on_acquire(class: &'static core::panic::Location<'static>) {
+        axsync::lockdep::on_acquire(class);
+    }
+
+    #[cfg(feature = "lockdep")]
+    pub fn ax_lockdep_on_release(class: &'static core::panic::Location<'static>) {
+        axsync::lockdep::on_release(class);
+    }
 }
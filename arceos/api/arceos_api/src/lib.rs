@@ -31,6 +31,9 @@ pub mod config {
 pub mod sys {
     define_api! {
         /// Shutdown the whole system and all CPUs.
+        ///
+        /// The dirty blocks of the filesystems are written back to the disk
+        /// beforehand.
         pub fn ax_terminate() -> !;
     }
 }
@@ -75,6 +78,27 @@ pub mod mem {
         pub unsafe fn ax_dealloc(ptr: NonNull<u8>, layout: Layout);
     }
 
+    define_api_type! {
+        @cfg "alloc";
+        #[cfg(feature = "myalloc")]
+        pub type MyByteAllocatorIf;
+    }
+
+    define_api_type! {
+        @cfg "alloc-tag";
+        pub type AxAllocTag;
+        pub type AxTagStats;
+    }
+
+    define_api! {
+        @cfg "alloc-tag";
+        /// Returns the heap and page usage statistics of each allocation tag.
+        pub fn ax_alloc_tag_stats() -> alloc::vec::Vec<AxTagStats>;
+        /// Calls `f` with the allocations of the current task attributed to
+        /// the subsystem `tag`, e.g., `"net buffers"`.
+        pub fn ax_with_alloc_tag(tag: &'static str, f: &mut dyn FnMut());
+    }
+
     define_api_type! {
         @cfg "dma";
         pub type DMAInfo;
@@ -121,6 +145,7 @@ pub mod task {
         @cfg "multitask";
         pub type AxTaskHandle;
         pub type AxWaitQueueHandle;
+        pub type AxTimerHandle;
     }
 
     define_api! {
@@ -164,11 +189,53 @@ pub mod task {
             until_condition: impl Fn() -> bool,
             timeout: Option<core::time::Duration>,
         ) -> bool;
+        /// Blocks the current task and put it into the wait queue, until the
+        /// given condition becomes true, or the current task is killed.
+        ///
+        /// Returns `false` if it's killed, then the caller should undo its
+        /// changes for waiting, and exit by [`ax_exit_killed`].
+        pub fn ax_wait_queue_wait_or_killed(
+            wq: &AxWaitQueueHandle,
+            until_condition: impl Fn() -> bool,
+        ) -> bool;
+        /// Exits the current task that has been killed, with the exit code of
+        /// killed tasks.
+        pub fn ax_exit_killed() -> !;
         /// Wakes up one or more tasks in the wait queue.
         ///
         /// The maximum number of tasks to wake up is specified by `count`. If
         /// `count` is `u32::MAX`, it will wake up all tasks in the wait queue.
         pub fn ax_wait_queue_wake(wq: &AxWaitQueueHandle, count: u32);
+
+        /// Sets a timer that calls `callback` at the given `deadline`, and
+        /// then every `period` if specified.
+        ///
+        /// The callback runs in the timer interrupt handler, so it must not
+        /// block. Timers never fire if the feature `irq` is not enabled.
+        pub fn ax_set_timer(
+            deadline: crate::time::AxTimeValue,
+            period: Option<core::time::Duration>,
+            callback: impl Fn() + Send + Sync + 'static,
+        ) -> AxTimerHandle;
+        /// Re-arms the timer to fire at the given deadline, no matter whether
+        /// it has fired or been cancelled.
+        pub fn ax_rearm_timer(timer: &AxTimerHandle, deadline: crate::time::AxTimeValue);
+        /// Cancels the timer, returns whether it was pending.
+        pub fn ax_cancel_timer(timer: &AxTimerHandle) -> bool;
+    }
+
+    define_api! {
+        @cfg "lockdep";
+
+        /// Checks the acquisition of the lock created at `class` by the
+        /// current task against the recorded lock order, before it may block.
+        pub fn ax_lockdep_check_acquire(class: &'static core::panic::Location<'static>);
+        /// Records that the lock created at `class` is held by the current
+        /// task.
+        pub fn ax_lockdep_on_acquire(class: &'static core::panic::Location<'static>);
+        /// Records that the lock created at `class` is released by the
+        /// current task.
+        pub fn ax_lockdep_on_release(class: &'static core::panic::Location<'static>);
     }
 }
 
@@ -89,6 +89,10 @@ macro_rules! cfg_alloc {
     ($($item:item)*) => { _cfg_common!{ "alloc" $($item)* } }
 }
 
+macro_rules! cfg_alloc_tag {
+    ($($item:item)*) => { _cfg_common!{ "alloc-tag" $($item)* } }
+}
+
 macro_rules! cfg_dma {
     ($($item:item)*) => { _cfg_common!{ "dma" $($item)* } }
 }
@@ -34,7 +34,13 @@ pub fn sys_getpid() -> c_int {
 pub fn sys_exit(exit_code: c_int) -> ! {
     debug!("sys_exit <= {}", exit_code);
     #[cfg(feature = "multitask")]
-    axtask::exit(exit_code);
+    {
+        // Exiting the main task shuts down the system.
+        if axtask::current().is_init() {
+            axruntime::terminate();
+        }
+        axtask::exit(exit_code);
+    }
     #[cfg(not(feature = "multitask"))]
-    axhal::misc::terminate();
+    axruntime::terminate();
 }
@@ -21,9 +21,11 @@ const CMD_TABLE: &[(&str, CmdHandler)] = &[
     ("cd", do_cd),
     ("echo", do_echo),
     ("exit", do_exit),
+    ("free", do_free),
     ("help", do_help),
     ("ls", do_ls),
     ("mkdir", do_mkdir),
+    ("mount", do_mount),
     ("pwd", do_pwd),
     ("rm", do_rm),
     ("uname", do_uname),
@@ -147,6 +149,20 @@ fn do_cat(args: &str) {
     }
 }
 
+fn do_free(_args: &str) {
+    match fs::read_to_string("/proc/meminfo") {
+        Ok(meminfo) => print!("{}", meminfo),
+        Err(e) => print_err!("free", "/proc/meminfo", e),
+    }
+}
+
+fn do_mount(_args: &str) {
+    match fs::read_to_string("/proc/mounts") {
+        Ok(mounts) => print!("{}", mounts),
+        Err(e) => print_err!("mount", "/proc/mounts", e),
+    }
+}
+
 fn do_echo(args: &str) {
     fn echo_file(fname: &str, text_list: &[&str]) -> io::Result<()> {
         let mut file = File::create(fname)?;
@@ -0,0 +1,72 @@
+//! Replays the allocation pattern of the lab1 challenge, to compare byte
+//! allocators.
+//!
+//! Each round allocates a group of blocks of increasing sizes, then frees
+//! every other block in the pool, so the pool keeps growing until the heap is
+//! exhausted. The number of finished rounds is the "Indicator": the larger,
+//! the less memory is wasted by the byte allocator.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+extern crate alloc;
+
+#[cfg(feature = "myalloc")]
+mod my_alloc;
+
+use alloc::collections::TryReserveError;
+use alloc::vec::Vec;
+
+const MIN_BLOCK_SIZE: usize = 32;
+const MAX_BLOCK_SIZE: usize = 512 * 1024;
+
+/// Allocates blocks of sizes from [`MIN_BLOCK_SIZE`] to [`MAX_BLOCK_SIZE`]
+/// plus `round`, filled with the round number.
+fn alloc_pass(pool: &mut Vec<Vec<u8>>, round: usize) -> Result<(), TryReserveError> {
+    let mut size = MIN_BLOCK_SIZE;
+    while size <= MAX_BLOCK_SIZE {
+        let mut block = Vec::new();
+        block.try_reserve_exact(size + round)?;
+        block.resize(size + round, round as u8);
+        pool.try_reserve(1)?;
+        pool.push(block);
+        size *= 2;
+    }
+    Ok(())
+}
+
+/// Frees every other block in the pool, checking their content.
+fn free_pass(pool: &mut Vec<Vec<u8>>) {
+    let mut index = 0;
+    pool.retain(|block| {
+        index += 1;
+        if index % 2 == 0 {
+            assert!(
+                block.iter().all(|&b| b == block[0]),
+                "block corrupted before free"
+            );
+            false
+        } else {
+            true
+        }
+    });
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    println!("Running allocator benchmark...");
+
+    let mut pool = Vec::new();
+    let mut round = 0;
+    while alloc_pass(&mut pool, round).is_ok() {
+        free_pass(&mut pool);
+        round += 1;
+        println!("Indicator: {}", round);
+    }
+
+    println!("No memory after {} rounds.", round);
+    println!("Final indicator: {}", round);
+}
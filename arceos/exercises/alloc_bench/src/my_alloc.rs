@@ -0,0 +1,44 @@
+//! A sample custom byte allocator, which simply wraps the TLSF allocator.
+//!
+//! Replace it with your own algorithm to compare the indicators.
+
+use allocator::{AllocResult, BaseAllocator, ByteAllocator, TlsfByteAllocator};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+use kspin::SpinNoIrq;
+use std::os::arceos::api::mem::MyByteAllocatorIf;
+
+static ALLOCATOR: SpinNoIrq<TlsfByteAllocator> = SpinNoIrq::new(TlsfByteAllocator::new());
+
+struct MyByteAllocatorIfImpl;
+
+#[crate_interface::impl_interface]
+impl MyByteAllocatorIf for MyByteAllocatorIfImpl {
+    fn init(start: usize, size: usize) {
+        ALLOCATOR.lock().init(start, size)
+    }
+
+    fn add_memory(start: usize, size: usize) -> AllocResult {
+        ALLOCATOR.lock().add_memory(start, size)
+    }
+
+    fn alloc(layout: Layout) -> AllocResult<NonNull<u8>> {
+        ALLOCATOR.lock().alloc(layout)
+    }
+
+    fn dealloc(pos: NonNull<u8>, layout: Layout) {
+        ALLOCATOR.lock().dealloc(pos, layout)
+    }
+
+    fn total_bytes() -> usize {
+        ALLOCATOR.lock().total_bytes()
+    }
+
+    fn used_bytes() -> usize {
+        ALLOCATOR.lock().used_bytes()
+    }
+
+    fn available_bytes() -> usize {
+        ALLOCATOR.lock().available_bytes()
+    }
+}
@@ -0,0 +1,57 @@
+//! Tests the attribution of heap and page allocations to tags.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+extern crate alloc;
+
+use alloc::vec;
+
+use axalloc::GlobalPage;
+use std::os::arceos::alloc_tag::{tag_stats, with_alloc_tag, AllocTag, TagStats};
+use std::thread;
+
+const PAGE_SIZE: usize = 0x1000;
+
+fn stats_of(name: &str) -> Option<TagStats> {
+    tag_stats()
+        .into_iter()
+        .find(|s| s.tag == AllocTag::Named(name))
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    let buf = with_alloc_tag("test buffers", || vec![0u8; 0x10000]);
+    let stats = stats_of("test buffers").unwrap();
+    assert_eq!((stats.used_bytes, stats.live_allocs), (0x10000, 1));
+    drop(buf);
+    // The slot of an idle tag may be reused by another one.
+    assert!(stats_of("test buffers").map_or(true, |s| s.used_bytes == 0));
+    println!("Heap allocations tagged");
+
+    let pages = with_alloc_tag("test pages", || {
+        GlobalPage::alloc_contiguous(8, PAGE_SIZE).unwrap()
+    });
+    let stats = stats_of("test pages").unwrap();
+    assert_eq!((stats.used_pages, stats.peak_pages), (8, 8));
+    drop(pages);
+    assert!(stats_of("test pages").map_or(true, |s| s.used_pages == 0));
+    println!("Page allocations tagged");
+
+    // Kernel stacks are mapped in the kernel address space, with pages from
+    // the page allocator.
+    let before = stats_of("task stacks").map_or(0, |s| s.used_pages);
+    let handle = thread::spawn(|| {
+        let stats = stats_of("task stacks").unwrap();
+        println!("Task stacks: {} pages", stats.used_pages);
+        stats.used_pages
+    });
+    let during = handle.join().unwrap();
+    assert!(during > before);
+    println!("Kernel stacks tagged");
+
+    println!("Alloc tags OK!");
+}
@@ -0,0 +1,114 @@
+//! Tests copy-on-write cloning of address spaces: the frames are shared
+//! after cloning, each address space gets private copies on writes, and the
+//! frames are freed when the address spaces are dropped.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+use axhal::mem::{PhysAddr, VirtAddr};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+
+const BASE: usize = 0x1000_0000;
+const SIZE: usize = 0x10_0000;
+const PAGE_SIZE: usize = 0x1000;
+
+fn query(aspace: &AddrSpace, vaddr: VirtAddr) -> (PhysAddr, MappingFlags) {
+    let (frame, flags, _) = aspace.page_table().query(vaddr).unwrap();
+    (frame, flags)
+}
+
+fn read_byte(aspace: &AddrSpace, vaddr: VirtAddr) -> u8 {
+    let mut buf = [0];
+    aspace.read(vaddr, &mut buf).unwrap();
+    buf[0]
+}
+
+fn used_pages() -> usize {
+    axalloc::global_allocator().used_pages()
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    let page0 = VirtAddr::from(BASE);
+    let page1 = page0 + PAGE_SIZE;
+    let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+
+    let mut parent = AddrSpace::new_empty(page0, SIZE).unwrap();
+    parent.map_alloc(page0, PAGE_SIZE * 2, flags, true).unwrap();
+    parent.write(page0, &[1; PAGE_SIZE * 2]).unwrap();
+    let used = used_pages();
+
+    // The frames are shared, and write-protected in both address spaces.
+    let mut child = parent.clone_cow().unwrap();
+    for vaddr in [page0, page1] {
+        let (frame, flags) = query(&parent, vaddr);
+        assert_eq!(query(&child, vaddr).0, frame);
+        assert!(!flags.contains(MappingFlags::WRITE));
+        assert!(!query(&child, vaddr).1.contains(MappingFlags::WRITE));
+    }
+    println!("Frames shared after cloning");
+
+    // A read fault does not copy the frame, but a write fault does.
+    assert!(!child.handle_page_fault(page0, MappingFlags::READ));
+    assert_eq!(query(&child, page0).0, query(&parent, page0).0);
+    assert!(child.handle_page_fault(page0, MappingFlags::WRITE));
+    assert_ne!(query(&child, page0).0, query(&parent, page0).0);
+    assert!(query(&child, page0).1.contains(MappingFlags::WRITE));
+    child.write(page0, &[2]).unwrap();
+
+    // `write` copies the shared frame too.
+    parent.write(page1, &[3]).unwrap();
+    assert_ne!(query(&child, page1).0, query(&parent, page1).0);
+
+    assert_eq!(read_byte(&parent, page0), 1);
+    assert_eq!(read_byte(&child, page0), 2);
+    assert_eq!(read_byte(&parent, page1), 3);
+    assert_eq!(read_byte(&child, page1), 1);
+    println!("Pages diverged after writes");
+
+    // Two frames have been copied. Dropping the child frees its copy, the
+    // original frame only referenced by it, and its page table.
+    drop(child);
+    assert_eq!(used_pages(), used);
+
+    // The frame is no longer shared, and is reused on a write fault.
+    let (frame, _) = query(&parent, page0);
+    assert!(parent.handle_page_fault(page0, MappingFlags::WRITE));
+    assert_eq!(query(&parent, page0), (frame, flags));
+
+    // Write-protecting a page makes write faults fail, whether or not the
+    // frame is shared, and the pages faulted in later are read-only too.
+    let page2 = page1 + PAGE_SIZE;
+    parent.map_alloc(page2, PAGE_SIZE * 2, flags, false).unwrap();
+    parent.write(page2, &[4]).unwrap();
+    let child = parent.clone_cow().unwrap();
+    let ro_flags = MappingFlags::READ | MappingFlags::USER;
+    parent.protect(page0, PAGE_SIZE * 4, ro_flags).unwrap();
+    assert_eq!(parent.area_at(page2).unwrap().flags, ro_flags);
+    for vaddr in [page0, page2, page2 + PAGE_SIZE] {
+        assert!(!parent.handle_page_fault(vaddr, MappingFlags::WRITE));
+    }
+    assert!(parent.handle_page_fault(page2 + PAGE_SIZE, MappingFlags::READ));
+    assert!(!query(&parent, page2 + PAGE_SIZE).1.contains(MappingFlags::WRITE));
+    assert_eq!(query(&parent, page0), (frame, ro_flags));
+    assert_eq!(query(&parent, page2).0, query(&child, page2).0);
+
+    // Making it writable again keeps the shared frames write-protected, so
+    // they are still copied on write faults.
+    parent.protect(page0, PAGE_SIZE * 4, flags).unwrap();
+    assert_eq!(query(&parent, page0), (frame, flags));
+    assert!(!query(&parent, page2).1.contains(MappingFlags::WRITE));
+    assert!(parent.handle_page_fault(page2, MappingFlags::WRITE));
+    assert_ne!(query(&parent, page2).0, query(&child, page2).0);
+    drop(child);
+    println!("Write-protected pages OK");
+
+    drop(parent);
+    assert!(used_pages() < used);
+    println!("Copy-on-write OK!");
+}
@@ -0,0 +1,86 @@
+//! Tests the information of the mapped areas: listing them as lines of
+//! `/proc/self/maps`, finding the area at an address, and naming areas.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+extern crate alloc;
+
+use alloc::string::ToString;
+use alloc::vec::Vec;
+
+use axhal::mem::{PhysAddr, VirtAddr};
+use axhal::paging::MappingFlags;
+use axmm::{AddrSpace, BackendKind};
+
+const BASE: usize = 0x1000_0000;
+const SIZE: usize = 0x10_0000;
+const PAGE_SIZE: usize = 0x1000;
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    let rw = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+    let heap = VirtAddr::from(BASE);
+    let stack = heap + PAGE_SIZE * 16;
+    let linear = heap + PAGE_SIZE * 64;
+
+    let mut aspace = AddrSpace::new_empty(heap, SIZE).unwrap();
+    aspace.map_alloc(heap, PAGE_SIZE * 4, rw, true).unwrap();
+    aspace.map_alloc(stack, PAGE_SIZE * 8, rw, false).unwrap();
+    // Linear mappings are not areas.
+    let ro = MappingFlags::READ | MappingFlags::USER;
+    aspace
+        .map_linear(linear, PhysAddr::from(0x8000_0000), PAGE_SIZE, ro)
+        .unwrap();
+    aspace.set_area_name(heap, PAGE_SIZE * 4, "[heap]").unwrap();
+    aspace
+        .set_area_name(stack, PAGE_SIZE * 8, "[stack]")
+        .unwrap();
+
+    let lines: Vec<_> = aspace.areas().map(|area| area.to_string()).collect();
+    for line in &lines {
+        println!("{}", line);
+    }
+    assert_eq!(
+        lines,
+        [
+            "10000000-10004000 rw-p 00000000 00:00 0    [heap]",
+            "10010000-10018000 rw-p 00000000 00:00 0    [stack]",
+        ]
+    );
+    println!("Areas listed");
+
+    // The populated heap is resident, the stack is mapped on demand.
+    let area = aspace.area_at(heap + PAGE_SIZE * 3 + 8).unwrap();
+    assert_eq!((area.start, area.end()), (heap, heap + PAGE_SIZE * 4));
+    assert_eq!(area.kind, BackendKind::Alloc);
+    assert_eq!((area.resident_pages, area.reserved_pages()), (4, 4));
+    assert_eq!(aspace.area_at(stack).unwrap().resident_pages, 0);
+    assert!(aspace.handle_page_fault(stack + PAGE_SIZE * 7, MappingFlags::WRITE));
+    assert_eq!(aspace.area_at(stack).unwrap().resident_pages, 1);
+    assert!(aspace.area_at(heap + PAGE_SIZE * 4).is_none());
+    assert!(aspace.area_at(linear).is_none());
+    println!("Area found at addresses");
+
+    // Renaming a part of a named range truncates the old name, and unmapping
+    // keeps the names of the remaining areas.
+    aspace
+        .set_area_name(stack, PAGE_SIZE * 2, "[guard]")
+        .unwrap();
+    assert_eq!(aspace.area_at(stack).unwrap().name, Some("[guard]"));
+    aspace.unmap(stack, PAGE_SIZE * 2).unwrap();
+    let area = aspace.area_at(stack + PAGE_SIZE * 2).unwrap();
+    assert_eq!(area.start, stack + PAGE_SIZE * 2);
+    assert_eq!(area.name, Some("[stack]"));
+    // The name of the unmapped range is removed.
+    aspace.map_alloc(stack, PAGE_SIZE * 2, rw, false).unwrap();
+    assert_eq!(aspace.area_at(stack).unwrap().name, None);
+    assert!(aspace.set_area_name(heap + 1, PAGE_SIZE, "x").is_err());
+    assert!(aspace.set_area_name(heap + SIZE, PAGE_SIZE, "x").is_err());
+    println!("Areas renamed");
+
+    println!("Area info OK!");
+}
@@ -0,0 +1,86 @@
+//! Tests the async TCP socket operations: an echo server and a client run as
+//! futures on one executor.
+//!
+//! Run with `NET=y`. The client connects to the host of QEMU user networking
+//! (10.0.2.2), which forwards the connection back to the server by
+//! `hostfwd=tcp::5555-:5555`.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+use core::net::{Ipv4Addr, SocketAddr};
+
+use axnet::TcpSocket;
+use axtask::Executor;
+
+const SERVER_PORT: u16 = 5555;
+const HOST_IP: Ipv4Addr = Ipv4Addr::new(10, 0, 2, 2);
+
+const DATA_LEN: usize = 64 * 1024;
+const CHUNK_LEN: usize = 4096;
+
+fn data_byte(i: usize) -> u8 {
+    (i % 251) as u8
+}
+
+async fn server(listener: TcpSocket) {
+    let stream = listener.accept_async().await.unwrap();
+    println!("Accepted a connection from {}", stream.peer_addr().unwrap());
+    let mut buf = [0u8; CHUNK_LEN];
+    loop {
+        let n = stream.recv_async(&mut buf).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        let mut sent = 0;
+        while sent < n {
+            sent += stream.send_async(&buf[sent..n]).await.unwrap();
+        }
+    }
+    println!("Server closed");
+}
+
+async fn client() {
+    let stream = TcpSocket::new();
+    let addr = SocketAddr::new(HOST_IP.into(), SERVER_PORT);
+    stream.connect_async(addr).await.unwrap();
+    println!("Connected to {}", addr);
+
+    let mut buf = [0u8; CHUNK_LEN];
+    for start in (0..DATA_LEN).step_by(CHUNK_LEN) {
+        let chunk: [u8; CHUNK_LEN] = core::array::from_fn(|i| data_byte(start + i));
+        let mut sent = 0;
+        while sent < CHUNK_LEN {
+            sent += stream.send_async(&chunk[sent..]).await.unwrap();
+        }
+        // The echo has not arrived yet, so the receiving waits for the waker.
+        let mut received = 0;
+        while received < CHUNK_LEN {
+            let n = stream.recv_async(&mut buf[received..]).await.unwrap();
+            assert_ne!(n, 0, "connection closed early");
+            received += n;
+        }
+        assert_eq!(buf, chunk);
+    }
+    stream.shutdown().unwrap();
+    println!("Echoed {} bytes", DATA_LEN);
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    let listener = TcpSocket::new();
+    listener
+        .bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), SERVER_PORT))
+        .unwrap();
+    listener.listen().unwrap();
+
+    let executor = Executor::new();
+    executor.spawn(server(listener));
+    executor.spawn(client());
+    executor.run();
+    println!("Async TCP OK!");
+}
@@ -0,0 +1,106 @@
+//! Tests file mappings: writes to a private mapping never reach the file,
+//! while those to a shared mapping are written back by `msync` and on
+//! unmapping.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+extern crate alloc;
+
+use alloc::sync::Arc;
+
+use axfs::fops::{File, OpenOptions};
+use axhal::mem::VirtAddr;
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+
+const BASE: usize = 0x1000_0000;
+const SIZE: usize = 0x10_0000;
+const PAGE_SIZE: usize = 0x1000;
+const PATH: &str = "/mmap_file.txt";
+const FILE_LEN: usize = PAGE_SIZE + 100;
+
+fn open(write: bool) -> Arc<File> {
+    let mut opts = OpenOptions::new();
+    opts.read(true);
+    opts.write(write);
+    Arc::new(File::open(PATH, &opts).unwrap())
+}
+
+fn file_byte(offset: u64) -> u8 {
+    let mut buf = [0];
+    assert_eq!(open(false).read_at(offset, &mut buf).unwrap(), 1);
+    buf[0]
+}
+
+fn read_byte(aspace: &AddrSpace, vaddr: VirtAddr) -> u8 {
+    let mut buf = [0];
+    aspace.read(vaddr, &mut buf).unwrap();
+    buf[0]
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    let mut opts = OpenOptions::new();
+    opts.write(true);
+    opts.create(true);
+    opts.truncate(true);
+    let file = File::open(PATH, &opts).unwrap();
+    assert_eq!(file.write_at(0, &[1; FILE_LEN]).unwrap(), FILE_LEN);
+    drop(file);
+
+    let page0 = VirtAddr::from(BASE);
+    let page1 = page0 + PAGE_SIZE;
+    let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+    let mut aspace = AddrSpace::new_empty(page0, SIZE).unwrap();
+
+    // Writes to a private mapping are not written back.
+    aspace
+        .map_file(page0, PAGE_SIZE * 2, flags, open(true), 0, false)
+        .unwrap();
+    assert!(aspace.handle_page_fault(page0, MappingFlags::WRITE));
+    assert!(aspace.handle_page_fault(page1, MappingFlags::READ));
+    assert_eq!(read_byte(&aspace, page0), 1);
+    // The part beyond the end of the file is filled with zeros.
+    assert_eq!(read_byte(&aspace, page1 + 99), 1);
+    assert_eq!(read_byte(&aspace, page1 + 100), 0);
+    aspace.write(page0, &[2; PAGE_SIZE]).unwrap();
+    aspace.msync(page0, PAGE_SIZE * 2).unwrap();
+    aspace.unmap(page0, PAGE_SIZE * 2).unwrap();
+    assert_eq!(file_byte(0), 1);
+    println!("Private mapping OK");
+
+    // Writes to a shared mapping are written back, without extending the
+    // file.
+    aspace
+        .map_file(page0, PAGE_SIZE * 2, flags, open(true), 0, true)
+        .unwrap();
+    assert!(aspace.handle_page_fault(page0, MappingFlags::WRITE));
+    assert!(aspace.handle_page_fault(page1, MappingFlags::WRITE));
+    aspace.write(page0, &[3; PAGE_SIZE * 2]).unwrap();
+    aspace.msync(page0, PAGE_SIZE).unwrap();
+    assert_eq!(file_byte(0), 3);
+    assert_eq!(file_byte(PAGE_SIZE as u64), 1);
+    aspace.unmap(page0, PAGE_SIZE * 2).unwrap();
+    assert_eq!(file_byte(PAGE_SIZE as u64 + 99), 3);
+    assert_eq!(open(false).get_attr().unwrap().size(), FILE_LEN as u64);
+    println!("Shared mapping OK");
+
+    // A shared writable mapping needs a file opened for writing.
+    assert!(aspace
+        .map_file(page0, PAGE_SIZE, flags, open(false), 0, true)
+        .is_err());
+    let read_only = MappingFlags::READ | MappingFlags::USER;
+    aspace
+        .map_file(page0, PAGE_SIZE, read_only, open(false), 0, true)
+        .unwrap();
+    assert!(aspace.protect(page0, PAGE_SIZE, flags).is_err());
+    // Syncing a range that is not mapped fails.
+    assert!(aspace.msync(page0, PAGE_SIZE * 2).is_err());
+    aspace.unmap(page0, PAGE_SIZE).unwrap();
+
+    println!("File mapping OK!");
+}
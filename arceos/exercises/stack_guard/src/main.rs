@@ -0,0 +1,35 @@
+//! Overflows the kernel stack of a task, which should be reported as a stack
+//! overflow by the guard page below the stack.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+use core::hint::black_box;
+use std::thread;
+
+const STACK_SIZE: usize = 0x4000;
+
+/// Recurses until the stack overflows, each frame takes at least 256 bytes.
+fn recurse(depth: usize) -> usize {
+    let frame = black_box([depth as u8; 256]);
+    if black_box(depth) == usize::MAX {
+        return 0;
+    }
+    recurse(depth + 1) + frame[depth % 256] as usize
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    println!("Overflowing a task stack of {:#x} bytes...", STACK_SIZE);
+    let task = thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(|| recurse(0))
+        .unwrap();
+    task.join().unwrap();
+    // Not reached: the kernel panics with a stack overflow.
+    println!("Stack guard failed!");
+}
@@ -0,0 +1,128 @@
+//! Tests swapping: with most of the memory taken, two address spaces write
+//! more pages than the free memory, the pages are swapped out, including
+//! those of the other address space, and read back with the same contents.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::cell::RefCell;
+
+use axfs::fops::{File, OpenOptions};
+use axhal::mem::VirtAddr;
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+
+const BASE: usize = 0x1000_0000;
+const SIZE: usize = 0x10_0000;
+const PAGE_SIZE: usize = 0x1000;
+const NR_PAGES: usize = 32;
+const FREE_PAGES: usize = 8;
+const SWAP_PATH: &str = "/swapfile";
+const SWAP_PAGES: usize = 128;
+
+fn swap_on() {
+    let mut opts = OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    opts.create(true);
+    opts.truncate(true);
+    let file = File::open(SWAP_PATH, &opts).unwrap();
+    for i in 0..SWAP_PAGES {
+        let offset = (i * PAGE_SIZE) as u64;
+        assert_eq!(file.write_at(offset, &[0; PAGE_SIZE]).unwrap(), PAGE_SIZE);
+    }
+    axmm::swap_on(file).unwrap();
+}
+
+fn page(i: usize) -> VirtAddr {
+    VirtAddr::from(BASE + i * PAGE_SIZE)
+}
+
+/// Faults in the page `i` of the address space as a page fault handler does,
+/// if the access is not allowed by the page table.
+fn fault_in(aspace: &RefCell<AddrSpace>, i: usize, access: MappingFlags) {
+    if let Ok((_, flags, _)) = aspace.borrow().page_table().query(page(i)) {
+        if flags.contains(access) {
+            return;
+        }
+    }
+    let lock = || aspace.borrow_mut();
+    assert!(axmm::handle_page_fault_unlocked(lock, page(i), access));
+}
+
+fn write_pages(aspace: &RefCell<AddrSpace>, tag: u8) {
+    for i in 0..NR_PAGES {
+        fault_in(aspace, i, MappingFlags::WRITE);
+        let value = tag.wrapping_add(i as u8);
+        aspace
+            .borrow_mut()
+            .write(page(i), &[value; PAGE_SIZE])
+            .unwrap();
+    }
+}
+
+fn check_pages(aspace: &RefCell<AddrSpace>, tag: u8) {
+    for i in 0..NR_PAGES {
+        fault_in(aspace, i, MappingFlags::READ);
+        let mut buf = [0; PAGE_SIZE];
+        aspace.borrow().read(page(i), &mut buf).unwrap();
+        let value = tag.wrapping_add(i as u8);
+        assert!(buf.iter().all(|&b| b == value), "page {} corrupted", i);
+    }
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    swap_on();
+
+    let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+    let new_aspace = || {
+        let mut aspace = AddrSpace::new_empty(page(0), SIZE).unwrap();
+        aspace
+            .map_alloc(page(0), NR_PAGES * PAGE_SIZE, flags, false)
+            .unwrap();
+        RefCell::new(aspace)
+    };
+    let a = new_aspace();
+    let b = new_aspace();
+    // Allocate the page tables before taking the memory.
+    fault_in(&a, 0, MappingFlags::READ);
+    fault_in(&b, 0, MappingFlags::READ);
+
+    // Keep some free space in the heap for the swap states, then take all
+    // but `FREE_PAGES` of the free memory.
+    drop(Vec::<u8>::with_capacity(0x4_0000));
+    let allocator = axalloc::global_allocator();
+    let mut taken = Vec::with_capacity(allocator.available_pages());
+    while allocator.available_pages() > FREE_PAGES {
+        taken.push(allocator.alloc_pages(1, PAGE_SIZE).unwrap());
+    }
+
+    write_pages(&b, 1);
+    assert!(b.borrow().swapped_pages() > 0);
+    println!("Swapped out {} pages", axmm::swap_usage().0);
+
+    // The inactive pages of `b` are reclaimed for `a`.
+    let b_swapped = b.borrow().swapped_pages();
+    write_pages(&a, 101);
+    assert!(b.borrow().swapped_pages() > b_swapped);
+    println!("Reclaimed pages of another address space");
+
+    check_pages(&b, 1);
+    check_pages(&a, 101);
+    println!("Swapped in pages OK");
+
+    drop(a);
+    drop(b);
+    assert_eq!(axmm::swap_usage().0, 0);
+    for vaddr in taken {
+        allocator.dealloc_pages(vaddr, 1);
+    }
+    println!("Swap OK!");
+}
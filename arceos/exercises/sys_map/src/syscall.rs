@@ -1,9 +1,12 @@
 #![allow(dead_code)]
 
 use core::ffi::{c_void, c_char, c_int};
+use alloc::vec;
 use axhal::arch::TrapFrame;
 use axhal::trap::{register_trap_handler, SYSCALL};
-use axerrno::LinuxError;
+use axerrno::{AxResult, LinuxError};
+use axhal::mem::VirtAddr;
+use axmm::{check_user_range, copy_from_user, copy_to_user, read_user_cstr, AddrSpace, UserPtr};
 use axtask::current;
 use axtask::TaskExtRef;
 use axhal::paging::MappingFlags;
@@ -21,6 +24,9 @@ const SYS_SET_TID_ADDRESS: usize = 96;
 const SYS_MMAP: usize = 222;
 
 const AT_FDCWD: i32 = -100;
+const MAX_PATH_LEN: usize = 4096;
+/// The size of the kernel buffer that `read` and `write` copy through.
+const USER_COPY_CHUNK: usize = 4096;
 
 /// Macro to generate syscall body
 ///
@@ -143,9 +149,25 @@ fn sys_mmap(
     unimplemented!("no sys_mmap!");
 }
 
+/// Accesses the user memory through the address space of the current task.
+///
+/// Errors are converted to negative error numbers, e.g., `-EFAULT` for bad
+/// user pointers.
+fn with_user_aspace<R>(f: impl FnOnce(&mut AddrSpace) -> AxResult<R>) -> Result<R, isize> {
+    let curr = current();
+    let mut aspace = curr.task_ext().aspace.lock();
+    f(&mut aspace).map_err(|e| -LinuxError::from(e).code() as isize)
+}
+
 fn sys_openat(dfd: c_int, fname: *const c_char, flags: c_int, mode: api::ctypes::mode_t) -> isize {
     assert_eq!(dfd, AT_FDCWD);
-    api::sys_open(fname, flags, mode) as isize
+    let fname = VirtAddr::from(fname as usize);
+    let mut path = match with_user_aspace(|aspace| read_user_cstr(aspace, fname, MAX_PATH_LEN)) {
+        Ok(path) => path.into_bytes(),
+        Err(e) => return e,
+    };
+    path.push(0);
+    api::sys_open(path.as_ptr() as _, flags, mode) as isize
 }
 
 fn sys_close(fd: i32) -> isize {
@@ -153,15 +175,74 @@ fn sys_close(fd: i32) -> isize {
 }
 
 fn sys_read(fd: i32, buf: *mut c_void, count: usize) -> isize {
-    api::sys_read(fd, buf, count)
+    let buf = VirtAddr::from(buf as usize);
+    // Data read from the file can't be put back, so check the whole buffer
+    // before reading.
+    if let Err(e) =
+        with_user_aspace(|aspace| check_user_range(aspace, buf, count, MappingFlags::WRITE))
+    {
+        return e;
+    }
+    let mut kbuf = vec![0u8; count.min(USER_COPY_CHUNK)];
+    let mut total = 0;
+    while total < count {
+        let len = (count - total).min(USER_COPY_CHUNK);
+        let n = api::sys_read(fd, kbuf.as_mut_ptr() as _, len);
+        if n <= 0 {
+            return if total > 0 { total as isize } else { n };
+        }
+        let chunk = &kbuf[..n as usize];
+        if let Err(e) = with_user_aspace(|aspace| copy_to_user(aspace, buf + total, chunk)) {
+            return e;
+        }
+        total += n as usize;
+        if (n as usize) < len {
+            break;
+        }
+    }
+    total as isize
 }
 
 fn sys_write(fd: i32, buf: *const c_void, count: usize) -> isize {
-    api::sys_write(fd, buf, count)
+    let buf = VirtAddr::from(buf as usize);
+    let mut kbuf = vec![0u8; count.min(USER_COPY_CHUNK)];
+    let mut total = 0;
+    while total < count {
+        let len = (count - total).min(USER_COPY_CHUNK);
+        let chunk = &mut kbuf[..len];
+        if let Err(e) = with_user_aspace(|aspace| copy_from_user(aspace, chunk, buf + total)) {
+            return if total > 0 { total as isize } else { e };
+        }
+        let n = api::sys_write(fd, kbuf.as_ptr() as _, len);
+        if n <= 0 {
+            return if total > 0 { total as isize } else { n };
+        }
+        total += n as usize;
+        if (n as usize) < len {
+            break;
+        }
+    }
+    total as isize
 }
 
 fn sys_writev(fd: i32, iov: *const api::ctypes::iovec, iocnt: i32) -> isize {
-    unsafe { api::sys_writev(fd, iov, iocnt) }
+    if !(0..=1024).contains(&iocnt) {
+        return -LinuxError::EINVAL.code() as _;
+    }
+    let iov = UserPtr::<api::ctypes::iovec>::from(iov as usize);
+    let iovs = match with_user_aspace(|aspace| iov.read_array(aspace, iocnt as usize)) {
+        Ok(iovs) => iovs,
+        Err(e) => return e,
+    };
+    let mut ret = 0;
+    for iov in iovs {
+        let n = sys_write(fd, iov.iov_base, iov.iov_len);
+        if n < 0 {
+            return n;
+        }
+        ret += n;
+    }
+    ret
 }
 
 fn sys_set_tid_address(tid_ptd: *const i32) -> isize {
@@ -0,0 +1,446 @@
+//! Heap debugging mode, enabled by the `debug` feature.
+//!
+//! Each heap block made through [`GlobalAlloc`](core::alloc::GlobalAlloc) is
+//! laid out as follows:
+//!
+//! ```text
+//! | header | front redzone | data (poisoned) | back redzone |
+//! ```
+//!
+//! The header records the size and the name of the allocating task. New data
+//! is filled with [`ALLOC_POISON`] to expose reads of uninitialized memory.
+//! When a block is freed, its redzones are checked, its data is filled with
+//! [`FREE_POISON`], and it is kept in a quarantine for a while instead of
+//! being returned to the byte allocator. When it leaves the quarantine, the
+//! poison is checked to catch writes after free. The quarantine holds at most
+//! [`QUARANTINE_LEN`] blocks of [`QUARANTINE_BYTES`] bytes in total, and it is
+//! drained when an allocation fails, so that it never causes out-of-memory
+//! errors.
+//!
+//! Any damage, double free or invalid free panics with the name of the task
+//! that allocated the block, which is obtained by [`DebugAllocIf`].
+
+use core::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+
+use allocator::{AllocError, AllocResult};
+use kspin::SpinNoIrq;
+
+use crate::GlobalAllocator;
+
+/// The minimum size of each redzone.
+const REDZONE_SIZE: usize = 16;
+/// The byte that redzones are filled with.
+const REDZONE_BYTE: u8 = 0xfd;
+/// The byte that newly allocated data is filled with.
+const ALLOC_POISON: u8 = 0xa5;
+/// The byte that freed data is filled with.
+const FREE_POISON: u8 = 0x6b;
+/// The maximum number of recently freed blocks kept in the quarantine.
+const QUARANTINE_LEN: usize = 256;
+/// The maximum total size of the blocks kept in the quarantine.
+const QUARANTINE_BYTES: usize = 0x10_0000; // 1M
+/// The maximum length of the task name recorded in the header.
+const TASK_NAME_LEN: usize = 32;
+
+const MAGIC_ALLOCATED: usize = 0xa110_c8ed;
+const MAGIC_FREED: usize = 0xf4ee_d0ff;
+
+/// The interface to get the context of allocations, for error reports.
+#[crate_interface::def_interface]
+pub trait DebugAllocIf {
+    /// Writes the name of the current task to `buf`, truncated if it's too
+    /// long. Returns the length written.
+    ///
+    /// It must not allocate from the heap.
+    fn current_task_name(buf: &mut [u8]) -> usize;
+}
+
+#[repr(C)]
+struct Header {
+    magic: usize,
+    size: usize,
+    task_name_len: usize,
+    task_name: [u8; TASK_NAME_LEN],
+}
+
+impl Header {
+    fn task_name(&self) -> &str {
+        let len = self.task_name_len.min(TASK_NAME_LEN);
+        core::str::from_utf8(&self.task_name[..len]).unwrap_or("?")
+    }
+}
+
+/// A freed block in the quarantine.
+#[derive(Clone, Copy)]
+struct FreedBlock {
+    block: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl Send for FreedBlock {}
+
+/// A FIFO of freed blocks.
+struct Quarantine {
+    blocks: [Option<FreedBlock>; QUARANTINE_LEN],
+    /// The index of the oldest block.
+    head: usize,
+    len: usize,
+    /// The total size of the blocks.
+    bytes: usize,
+}
+
+impl Quarantine {
+    /// Puts a block into the quarantine, returns the oldest one if it's full.
+    fn push(&mut self, block: FreedBlock) -> Option<FreedBlock> {
+        let oldest = if self.len == QUARANTINE_LEN {
+            self.pop()
+        } else {
+            None
+        };
+        self.blocks[(self.head + self.len) % QUARANTINE_LEN] = Some(block);
+        self.len += 1;
+        self.bytes += block.layout.size();
+        oldest
+    }
+
+    /// Takes the oldest block out of the quarantine.
+    fn pop(&mut self) -> Option<FreedBlock> {
+        if self.len == 0 {
+            return None;
+        }
+        let oldest = self.blocks[self.head].take().unwrap();
+        self.head = (self.head + 1) % QUARANTINE_LEN;
+        self.len -= 1;
+        self.bytes -= oldest.layout.size();
+        Some(oldest)
+    }
+
+    /// Takes the oldest block out if the blocks are too large in total.
+    fn pop_over_bytes(&mut self) -> Option<FreedBlock> {
+        if self.bytes > QUARANTINE_BYTES {
+            self.pop()
+        } else {
+            None
+        }
+    }
+}
+
+static QUARANTINE: SpinNoIrq<Quarantine> = SpinNoIrq::new(Quarantine {
+    blocks: [None; QUARANTINE_LEN],
+    head: 0,
+    len: 0,
+    bytes: 0,
+});
+
+/// Returns the layout of the whole block, and the offset of the data.
+fn block_layout(layout: Layout) -> AllocResult<(Layout, usize)> {
+    let align = layout.align().max(align_of::<Header>());
+    let offset = (size_of::<Header>() + REDZONE_SIZE).next_multiple_of(align);
+    let size = layout
+        .size()
+        .checked_add(offset + REDZONE_SIZE)
+        .ok_or(AllocError::InvalidParam)?;
+    let block_layout =
+        Layout::from_size_align(size, align).map_err(|_| AllocError::InvalidParam)?;
+    Ok((block_layout, offset))
+}
+
+/// Returns whether all bytes in `[start, start + len)` are `byte`.
+unsafe fn is_filled(start: *const u8, len: usize, byte: u8) -> bool {
+    core::slice::from_raw_parts(start, len)
+        .iter()
+        .all(|&b| b == byte)
+}
+
+/// Checks the redzones around the data of an allocated block.
+unsafe fn check_redzones(block: *mut u8, offset: usize, header: &Header, data: *const u8) {
+    let front = block.add(size_of::<Header>());
+    if !is_filled(front, offset - size_of::<Header>(), REDZONE_BYTE) {
+        panic!(
+            "heap buffer underflow before {:#x} (size {}), allocated by task {:?}",
+            data as usize,
+            header.size,
+            header.task_name()
+        );
+    }
+    if !is_filled(data.add(header.size), REDZONE_SIZE, REDZONE_BYTE) {
+        panic!(
+            "heap buffer overflow after {:#x} (size {}), allocated by task {:?}",
+            data as usize,
+            header.size,
+            header.task_name()
+        );
+    }
+}
+
+/// Allocates a block with redzones around the data.
+pub(crate) fn alloc(ga: &GlobalAllocator, layout: Layout) -> AllocResult<NonNull<u8>> {
+    let (block_layout, offset) = block_layout(layout)?;
+    let mut header = Header {
+        magic: MAGIC_ALLOCATED,
+        size: layout.size(),
+        task_name_len: 0,
+        task_name: [0; TASK_NAME_LEN],
+    };
+    header.task_name_len =
+        crate_interface::call_interface!(DebugAllocIf::current_task_name, &mut header.task_name);
+
+    let block = match ga.alloc(block_layout) {
+        Ok(block) => block,
+        Err(AllocError::NoMemory) => {
+            // The quarantined blocks may be enough for the allocation.
+            drain(ga);
+            ga.alloc(block_layout)?
+        }
+        Err(e) => return Err(e),
+    }
+    .as_ptr();
+    unsafe {
+        block.cast::<Header>().write(header);
+        let front = block.add(size_of::<Header>());
+        ptr::write_bytes(front, REDZONE_BYTE, offset - size_of::<Header>());
+        let data = block.add(offset);
+        ptr::write_bytes(data, ALLOC_POISON, layout.size());
+        ptr::write_bytes(data.add(layout.size()), REDZONE_BYTE, REDZONE_SIZE);
+        Ok(NonNull::new_unchecked(data))
+    }
+}
+
+/// Frees a block allocated by [`alloc`], after checking it.
+///
+/// The block is kept in the quarantine, and the oldest blocks in the
+/// quarantine are returned to the byte allocator if it's full.
+///
+/// # Safety
+///
+/// `ptr` must be allocated by [`alloc`] with the same `layout`.
+pub(crate) unsafe fn dealloc(ga: &GlobalAllocator, ptr: NonNull<u8>, layout: Layout) {
+    let data = ptr.as_ptr();
+    let (block_layout, offset) = block_layout(layout).unwrap();
+    let block = data.sub(offset);
+    let header = &mut *block.cast::<Header>();
+    match header.magic {
+        MAGIC_ALLOCATED => {}
+        MAGIC_FREED => panic!(
+            "double free of {:#x} (size {}), allocated by task {:?}",
+            data as usize,
+            header.size,
+            header.task_name()
+        ),
+        _ => panic!(
+            "invalid free of {:#x}: not allocated, or the heap is corrupted",
+            data as usize
+        ),
+    }
+    if header.size != layout.size() {
+        panic!(
+            "free of {:#x} with size {}, but allocated with size {} by task {:?}",
+            data as usize,
+            layout.size(),
+            header.size,
+            header.task_name()
+        );
+    }
+    check_redzones(block, offset, header, data);
+
+    header.magic = MAGIC_FREED;
+    ptr::write_bytes(data, FREE_POISON, layout.size());
+    let freed = FreedBlock {
+        block: NonNull::new_unchecked(block),
+        layout: block_layout,
+    };
+    let oldest = QUARANTINE.lock().push(freed);
+    if let Some(oldest) = oldest {
+        release(ga, oldest);
+    }
+    // Don't hold the lock while releasing, which may panic.
+    let pop_over_bytes = || QUARANTINE.lock().pop_over_bytes();
+    while let Some(oldest) = pop_over_bytes() {
+        release(ga, oldest);
+    }
+}
+
+/// Returns all blocks in the quarantine to the byte allocator.
+fn drain(ga: &GlobalAllocator) {
+    let pop = || QUARANTINE.lock().pop();
+    while let Some(oldest) = pop() {
+        unsafe { release(ga, oldest) };
+    }
+}
+
+/// Checks a block leaving the quarantine, and returns it to the byte
+/// allocator.
+unsafe fn release(ga: &GlobalAllocator, freed: FreedBlock) {
+    let block = freed.block.as_ptr();
+    let header = &*block.cast::<Header>();
+    let offset = freed.layout.size() - header.size - REDZONE_SIZE;
+    let data = block.add(offset);
+    if header.magic != MAGIC_FREED || !is_filled(data, header.size, FREE_POISON) {
+        panic!(
+            "use after free of {:#x} (size {}), allocated by task {:?}",
+            data as usize,
+            header.size,
+            header.task_name()
+        );
+    }
+    check_redzones(block, offset, header, data);
+    ga.dealloc(freed.block, freed.layout);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard, Once};
+    use std::vec::Vec;
+
+    /// The quarantine is shared by all tests, so they use the same allocator
+    /// and can not run in parallel.
+    static SERIAL: Mutex<()> = Mutex::new(());
+    static INIT: Once = Once::new();
+    static GA: GlobalAllocator = GlobalAllocator::new();
+
+    const HEAP_SIZE: usize = 0x40_0000; // 4M
+
+    #[repr(align(4096))]
+    struct Heap([u8; HEAP_SIZE]);
+
+    static mut HEAP: Heap = Heap([0; HEAP_SIZE]);
+
+    struct DebugAllocIfImpl;
+
+    #[crate_interface::impl_interface]
+    impl DebugAllocIf for DebugAllocIfImpl {
+        fn current_task_name(buf: &mut [u8]) -> usize {
+            let name = b"test";
+            buf[..name.len()].copy_from_slice(name);
+            name.len()
+        }
+    }
+
+    /// Returns the allocator, and a guard to serialize the tests.
+    fn setup() -> (&'static GlobalAllocator, MutexGuard<'static, ()>) {
+        // A test panics on purpose with the lock held.
+        let guard = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        INIT.call_once(|| unsafe {
+            GA.init(core::ptr::addr_of_mut!(HEAP) as usize, HEAP_SIZE);
+        });
+        (&GA, guard)
+    }
+
+    fn data_of(ptr: NonNull<u8>, len: usize) -> &'static mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(ptr.as_ptr(), len) }
+    }
+
+    #[test]
+    fn poisoned() {
+        let (ga, _guard) = setup();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let ptr = alloc(ga, layout).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % 8, 0);
+        assert!(data_of(ptr, 100).iter().all(|&b| b == ALLOC_POISON));
+        data_of(ptr, 100).fill(0);
+        unsafe { dealloc(ga, ptr, layout) };
+        // Still in the quarantine.
+        assert!(data_of(ptr, 100).iter().all(|&b| b == FREE_POISON));
+        drain(ga);
+    }
+
+    #[test]
+    #[should_panic(expected = "heap buffer overflow")]
+    fn overflow() {
+        let (ga, _guard) = setup();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let ptr = alloc(ga, layout).unwrap();
+        unsafe {
+            ptr.as_ptr().add(100).write(0);
+            dealloc(ga, ptr, layout);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "heap buffer underflow")]
+    fn underflow() {
+        let (ga, _guard) = setup();
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        let ptr = alloc(ga, layout).unwrap();
+        assert_eq!(ptr.as_ptr() as usize % 64, 0);
+        unsafe {
+            ptr.as_ptr().sub(1).write(0);
+            dealloc(ga, ptr, layout);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn double_free() {
+        let (ga, _guard) = setup();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let ptr = alloc(ga, layout).unwrap();
+        unsafe {
+            dealloc(ga, ptr, layout);
+            dealloc(ga, ptr, layout);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "use after free")]
+    fn use_after_free() {
+        let (ga, _guard) = setup();
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        let ptr = alloc(ga, layout).unwrap();
+        unsafe { dealloc(ga, ptr, layout) };
+        data_of(ptr, 100)[50] = 0;
+        // Checked when leaving the quarantine.
+        drain(ga);
+    }
+
+    #[test]
+    fn quarantine_limits() {
+        let (ga, _guard) = setup();
+        drain(ga);
+        let small = Layout::from_size_align(16, 8).unwrap();
+        for _ in 0..QUARANTINE_LEN * 2 {
+            let ptr = alloc(ga, small).unwrap();
+            unsafe { dealloc(ga, ptr, small) };
+        }
+        assert_eq!(QUARANTINE.lock().len, QUARANTINE_LEN);
+
+        let large = Layout::from_size_align(QUARANTINE_BYTES / 4, 8).unwrap();
+        for _ in 0..8 {
+            let ptr = alloc(ga, large).unwrap();
+            unsafe { dealloc(ga, ptr, large) };
+        }
+        let quarantine = QUARANTINE.lock();
+        assert!(quarantine.bytes <= QUARANTINE_BYTES);
+        assert!(quarantine.len < QUARANTINE_LEN);
+        drop(quarantine);
+        drain(ga);
+    }
+
+    #[test]
+    fn drained_on_failure() {
+        let (ga, _guard) = setup();
+        drain(ga);
+        let layout = Layout::from_size_align(0x1_0000, 8).unwrap();
+        let ptr = alloc(ga, layout).unwrap();
+        unsafe { dealloc(ga, ptr, layout) };
+
+        // Use up the memory, except the quarantined block.
+        let filler = Layout::from_size_align(0x1000, 8).unwrap();
+        let mut used = Vec::new();
+        while let Ok(ptr) = ga.alloc(filler) {
+            used.push(ptr);
+        }
+        assert!(QUARANTINE.lock().len > 0);
+        let ptr = alloc(ga, layout).expect("the quarantine is not drained");
+        assert_eq!(QUARANTINE.lock().len, 0);
+
+        unsafe { dealloc(ga, ptr, layout) };
+        for ptr in used {
+            ga.dealloc(ptr, filler);
+        }
+        drain(ga);
+    }
+}
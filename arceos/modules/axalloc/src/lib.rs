@@ -4,27 +4,70 @@
 //! [`core::alloc::GlobalAlloc`]. A static global variable of type
 //! [`GlobalAllocator`] is defined with the `#[global_allocator]` attribute, to
 //! be registered as the standard library’s default allocator.
+//!
+//! # Cargo Features
+//!
+//! - `tlsf`: Use the TLSF byte allocator. This feature is **enabled** by
+//!   default.
+//! - `slab`: Use the slab byte allocator.
+//! - `buddy`: Use the buddy byte allocator.
+//! - `myalloc`: Use the byte allocator defined in user apps, which must
+//!   implement [`MyByteAllocatorIf`]. It overrides the allocators above.
+//! - `tagging`: Attribute heap and page allocations to tasks and subsystems, see
+//!   [`tag_stats`]. The runtime must implement [`AllocTagIf`].
+//! - `early`: Serve allocations from an [`EarlyAllocator`] set up by
+//!   [`global_early_init`] until [`global_init`] is called, so that memory
+//!   can be allocated before the memory regions are discovered.
+//!
+//!   [`EarlyAllocator`]: bump_allocator::EarlyAllocator
+//! - `debug`: Check heap allocations with redzones, poisoning and a quarantine
+//!   of freed blocks, to catch overflows, use-after-free and double free. The
+//!   runtime must implement [`DebugAllocIf`].
 
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
 extern crate alloc;
 
+#[cfg(feature = "debug")]
+mod debug;
+#[cfg(feature = "myalloc")]
+mod myalloc;
 mod page;
+mod region;
+#[cfg(feature = "tagging")]
+mod tag;
 
-use allocator::{AllocResult, BaseAllocator, BitmapPageAllocator, ByteAllocator, PageAllocator};
+use alloc::string::String;
+use alloc::vec::Vec;
+use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
+#[cfg(feature = "early")]
+use bump_allocator::EarlyAllocator;
 use core::alloc::{GlobalAlloc, Layout};
+use core::fmt::Write;
 use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use kspin::SpinNoIrq;
+use region::{MultiRegionPageAllocator, MAX_REGION_SIZE};
 
 const PAGE_SIZE: usize = 0x1000;
 const MIN_HEAP_SIZE: usize = 0x8000; // 32 K
 
+#[cfg(feature = "debug")]
+pub use debug::DebugAllocIf;
+#[cfg(feature = "myalloc")]
+pub use myalloc::{MyByteAllocator, MyByteAllocatorIf};
 pub use page::GlobalPage;
+pub use region::{PageConstraint, PageRegionInfo, MAX_PAGE_REGIONS};
+#[cfg(feature = "tagging")]
+pub use tag::{tag_stats, AllocTag, AllocTagIf, TagStats};
 
 cfg_if::cfg_if! {
-    if #[cfg(feature = "slab")] {
+    if #[cfg(feature = "myalloc")] {
+        /// The default byte allocator.
+        pub type DefaultByteAllocator = MyByteAllocator;
+    } else if #[cfg(feature = "slab")] {
         /// The default byte allocator.
         pub type DefaultByteAllocator = allocator::SlabByteAllocator;
     } else if #[cfg(feature = "buddy")] {
@@ -44,12 +87,27 @@ cfg_if::cfg_if! {
 /// the byte allocator.
 ///
 /// Currently, [`TlsfByteAllocator`] is used as the byte allocator, while
-/// [`BitmapPageAllocator`] is used as the page allocator.
+/// the page allocator manages up to [`MAX_PAGE_REGIONS`] discontiguous
+/// regions, each with a [`BitmapPageAllocator`]. The bitmaps of the regions
+/// added after [`init`](Self::init) are allocated from the heap.
 ///
 /// [`TlsfByteAllocator`]: allocator::TlsfByteAllocator
+/// [`BitmapPageAllocator`]: allocator::BitmapPageAllocator
 pub struct GlobalAllocator {
     balloc: SpinNoIrq<DefaultByteAllocator>,
-    palloc: SpinNoIrq<BitmapPageAllocator<PAGE_SIZE>>,
+    palloc: SpinNoIrq<MultiRegionPageAllocator>,
+    peak_used_bytes: AtomicUsize,
+    peak_used_pages: AtomicUsize,
+    #[cfg(feature = "early")]
+    early: SpinNoIrq<EarlyAllocator<PAGE_SIZE>>,
+    #[cfg(feature = "early")]
+    early_active: core::sync::atomic::AtomicBool,
+    /// The memory range of the early allocator, checked on deallocations
+    /// without locking it.
+    #[cfg(feature = "early")]
+    early_start: AtomicUsize,
+    #[cfg(feature = "early")]
+    early_end: AtomicUsize,
 }
 
 impl GlobalAllocator {
@@ -57,14 +115,26 @@ impl GlobalAllocator {
     pub const fn new() -> Self {
         Self {
             balloc: SpinNoIrq::new(DefaultByteAllocator::new()),
-            palloc: SpinNoIrq::new(BitmapPageAllocator::new()),
+            palloc: SpinNoIrq::new(MultiRegionPageAllocator::new()),
+            peak_used_bytes: AtomicUsize::new(0),
+            peak_used_pages: AtomicUsize::new(0),
+            #[cfg(feature = "early")]
+            early: SpinNoIrq::new(EarlyAllocator::new()),
+            #[cfg(feature = "early")]
+            early_active: core::sync::atomic::AtomicBool::new(false),
+            #[cfg(feature = "early")]
+            early_start: AtomicUsize::new(0),
+            #[cfg(feature = "early")]
+            early_end: AtomicUsize::new(0),
         }
     }
 
     /// Returns the name of the allocator.
     pub const fn name(&self) -> &'static str {
         cfg_if::cfg_if! {
-            if #[cfg(feature = "slab")] {
+            if #[cfg(feature = "myalloc")] {
+                "custom"
+            } else if #[cfg(feature = "slab")] {
                 "slab"
             } else if #[cfg(feature = "buddy")] {
                 "buddy"
@@ -74,26 +144,111 @@ impl GlobalAllocator {
         }
     }
 
+    /// Initializes the early allocator with the given region, which serves
+    /// all allocations until [`init`](Self::init) is called.
+    #[cfg(feature = "early")]
+    pub fn early_init(&self, start_vaddr: usize, size: usize) {
+        self.early.lock().init(start_vaddr, size);
+        self.early_start.store(start_vaddr, Ordering::Relaxed);
+        self.early_end.store(start_vaddr + size, Ordering::Relaxed);
+        self.early_active.store(true, Ordering::Release);
+    }
+
+    /// Whether allocations are still served by the early allocator.
+    #[cfg(feature = "early")]
+    fn is_early(&self) -> bool {
+        self.early_active.load(Ordering::Acquire)
+    }
+
+    /// Whether `addr` is allocated from the early allocator.
+    #[cfg(feature = "early")]
+    fn in_early_range(&self, addr: usize) -> bool {
+        let start = self.early_start.load(Ordering::Relaxed);
+        let end = self.early_end.load(Ordering::Relaxed);
+        (start..end).contains(&addr)
+    }
+
     /// Initializes the allocator with the given region.
     ///
-    /// It firstly adds the whole region to the page allocator, then allocates
-    /// a small region (32 KB) to initialize the byte allocator. Therefore,
-    /// the given region must be larger than 32 KB.
+    /// It firstly adds the region to the page allocator as the first page
+    /// region, then allocates a small region (32 KB) to initialize the byte
+    /// allocator. Therefore, the given region must be larger than 32 KB. The
+    /// part beyond 2 GB is added as new page regions afterwards, since their
+    /// bitmaps are allocated from the heap.
+    ///
+    /// If the early allocator is in use, all allocations are served by this
+    /// allocator afterwards. The memory allocated from the early allocator
+    /// stays valid, and its pages are accounted as permanently used.
     pub fn init(&self, start_vaddr: usize, size: usize) {
         assert!(size > MIN_HEAP_SIZE);
         let init_heap_size = MIN_HEAP_SIZE;
-        self.palloc.lock().init(start_vaddr, size);
-        let heap_ptr = self
-            .alloc_pages(init_heap_size / PAGE_SIZE, PAGE_SIZE)
-            .unwrap();
+        let first_size = size.min(MAX_REGION_SIZE);
+        self.palloc
+            .lock()
+            .add_region(start_vaddr, first_size, &mut Vec::new())
+            .expect("failed to add the first page region");
+        #[cfg(feature = "early")]
+        if self.early_active.swap(false, Ordering::AcqRel) {
+            let early = self.early.lock();
+            info!(
+                "hand off from the early allocator: {} bytes and {} pages in use",
+                early.used_bytes(),
+                early.used_pages()
+            );
+        }
+        let heap_ptr = self.alloc_heap_pages(init_heap_size / PAGE_SIZE).unwrap();
         self.balloc.lock().init(heap_ptr, init_heap_size);
+        if size > first_size {
+            let rest = start_vaddr + first_size..start_vaddr + size;
+            if let Err(e) = self.add_page_region(rest.start, rest.len()) {
+                warn!("failed to add memory {:#x?}: {:?}", rest, e);
+            }
+        }
+    }
+
+    /// Adds the given region to the page allocator as new page regions, and
+    /// returns the index of the first one.
+    ///
+    /// A region larger than 2 GB is split into multiple page regions. It fails
+    /// if there are not enough free slots for them, or no memory for their
+    /// bitmaps.
+    pub fn add_page_region(&self, start_vaddr: usize, size: usize) -> AllocResult<usize> {
+        // Allocate the bitmaps before locking, as it may expand the heap.
+        let mut spare = region::alloc_spare_regions(size)?;
+        self.palloc.lock().add_region(start_vaddr, size, &mut spare)
     }
 
     /// Add the given region to the allocator.
     ///
-    /// It will add the whole region to the byte allocator.
+    /// It's added to the page allocator as new page regions, so that it can
+    /// be removed by [`remove_memory`](Self::remove_memory) later. If there
+    /// are not enough free slots for page regions, or the region is smaller
+    /// than a page, the whole region is added to the byte allocator instead,
+    /// and can never be removed.
     pub fn add_memory(&self, start_vaddr: usize, size: usize) -> AllocResult {
-        self.balloc.lock().add_memory(start_vaddr, size)
+        match self.add_page_region(start_vaddr, size) {
+            Ok(_) => Ok(()),
+            Err(AllocError::NoMemory | AllocError::InvalidParam) => {
+                self.balloc.lock().add_memory(start_vaddr, size)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Removes the page regions in the given range from the allocator, e.g.,
+    /// for memory hot-unplug.
+    ///
+    /// The range must cover whole page regions added by [`init`](Self::init),
+    /// [`add_memory`](Self::add_memory) or
+    /// [`add_page_region`](Self::add_page_region), and all pages in them must
+    /// be free. Otherwise, it fails with [`AllocError::MemoryOverlap`].
+    ///
+    /// Note that the pages used to expand the byte allocator are never freed,
+    /// so it can't succeed for a region the heap has grown into, including the
+    /// first region given to [`init`](Self::init). The heap may grow into
+    /// any region, so removing memory is only best-effort.
+    pub fn remove_memory(&self, start_vaddr: usize, size: usize) -> AllocResult {
+        self.palloc.lock().remove_region(start_vaddr, size)
     }
 
     /// Allocate arbitrary number of bytes. Returns the left bound of the
@@ -103,10 +258,16 @@ impl GlobalAllocator {
     /// memory, it asks the page allocator for more memory and adds it to the
     /// byte allocator.
     pub fn alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        #[cfg(feature = "early")]
+        if self.is_early() {
+            return self.early.lock().alloc(layout);
+        }
         // simple two-level allocator: if no heap memory, allocate from the page allocator.
         let mut balloc = self.balloc.lock();
         loop {
             if let Ok(ptr) = balloc.alloc(layout) {
+                self.peak_used_bytes
+                    .fetch_max(balloc.used_bytes(), Ordering::Relaxed);
                 return Ok(ptr);
             } else {
                 let old_size = balloc.total_bytes();
@@ -114,7 +275,7 @@ impl GlobalAllocator {
                     .max(layout.size())
                     .next_power_of_two()
                     .max(PAGE_SIZE);
-                let heap_ptr = self.alloc_pages(expand_size / PAGE_SIZE, PAGE_SIZE)?;
+                let heap_ptr = self.alloc_heap_pages(expand_size / PAGE_SIZE)?;
                 debug!(
                     "expand heap memory: [{:#x}, {:#x})",
                     heap_ptr,
@@ -133,6 +294,15 @@ impl GlobalAllocator {
     ///
     /// [`alloc`]: GlobalAllocator::alloc
     pub fn dealloc(&self, pos: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "early")]
+        if self.in_early_range(pos.as_ptr() as usize) {
+            // After the hand-off, the early allocator is never used again, so
+            // don't bother to lock it.
+            if self.is_early() {
+                self.early.lock().dealloc(pos, layout);
+            }
+            return;
+        }
         self.balloc.lock().dealloc(pos, layout)
     }
 
@@ -143,7 +313,50 @@ impl GlobalAllocator {
     /// `align_pow2` must be a power of 2, and the returned region bound will be
     /// aligned to it.
     pub fn alloc_pages(&self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
-        self.palloc.lock().alloc_pages(num_pages, align_pow2)
+        self.alloc_pages_in(num_pages, align_pow2, PageConstraint::Any)
+    }
+
+    /// Allocates contiguous pages that satisfy the given constraint, e.g.,
+    /// from a specific page region, or below an address.
+    ///
+    /// The early allocator, if in use, ignores the constraint. With the
+    /// `tagging` feature, the pages are attributed to the current tag.
+    pub fn alloc_pages_in(
+        &self,
+        num_pages: usize,
+        align_pow2: usize,
+        constraint: PageConstraint,
+    ) -> AllocResult<usize> {
+        let pos = self.alloc_pages_untagged(num_pages, align_pow2, constraint)?;
+        #[cfg(feature = "tagging")]
+        tag::account_pages(pos, num_pages);
+        Ok(pos)
+    }
+
+    /// Allocates pages to expand the byte allocator.
+    ///
+    /// They are not attributed to any tag, as the heap allocations in them
+    /// are. It's also called with the byte allocator locked, when the page
+    /// tags can't be recorded since that allocates.
+    fn alloc_heap_pages(&self, num_pages: usize) -> AllocResult<usize> {
+        self.alloc_pages_untagged(num_pages, PAGE_SIZE, PageConstraint::Any)
+    }
+
+    fn alloc_pages_untagged(
+        &self,
+        num_pages: usize,
+        align_pow2: usize,
+        constraint: PageConstraint,
+    ) -> AllocResult<usize> {
+        #[cfg(feature = "early")]
+        if self.is_early() {
+            return self.early.lock().alloc_pages(num_pages, align_pow2);
+        }
+        let mut palloc = self.palloc.lock();
+        let pos = palloc.alloc_pages(num_pages, align_pow2, constraint)?;
+        self.peak_used_pages
+            .fetch_max(palloc.used_pages(), Ordering::Relaxed);
+        Ok(pos)
     }
 
     /// Gives back the allocated pages starts from `pos` to the page allocator.
@@ -154,6 +367,14 @@ impl GlobalAllocator {
     ///
     /// [`alloc_pages`]: GlobalAllocator::alloc_pages
     pub fn dealloc_pages(&self, pos: usize, num_pages: usize) {
+        // Pages from the early allocator are attributed too.
+        #[cfg(feature = "tagging")]
+        tag::unaccount_pages(pos, num_pages);
+        #[cfg(feature = "early")]
+        if self.in_early_range(pos) {
+            // Pages from the early allocator are never freed.
+            return;
+        }
         self.palloc.lock().dealloc_pages(pos, num_pages)
     }
 
@@ -168,27 +389,77 @@ impl GlobalAllocator {
     }
 
     /// Returns the number of allocated pages in the page allocator.
+    ///
+    /// The pages allocated from the early allocator are included.
     pub fn used_pages(&self) -> usize {
-        self.palloc.lock().used_pages()
+        #[cfg(feature = "early")]
+        let early_pages = self.early.lock().used_pages();
+        #[cfg(not(feature = "early"))]
+        let early_pages = 0;
+        self.palloc.lock().used_pages() + early_pages
     }
 
     /// Returns the number of available pages in the page allocator.
     pub fn available_pages(&self) -> usize {
         self.palloc.lock().available_pages()
     }
+
+    /// Returns the usage of each page region.
+    pub fn page_regions(&self) -> Vec<PageRegionInfo> {
+        self.palloc.lock().region_infos()
+    }
+
+    /// Returns the maximum number of allocated bytes in the byte allocator
+    /// since initialization.
+    pub fn peak_used_bytes(&self) -> usize {
+        self.peak_used_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns the maximum number of allocated pages in the page allocator
+    /// since initialization.
+    pub fn peak_used_pages(&self) -> usize {
+        self.peak_used_pages.load(Ordering::Relaxed)
+    }
+
+    /// Allocates for [`GlobalAlloc`], with the heap checks if the `debug`
+    /// feature is enabled.
+    fn heap_alloc(&self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        #[cfg(feature = "debug")]
+        return debug::alloc(self, layout);
+        #[cfg(not(feature = "debug"))]
+        return self.alloc(layout);
+    }
+
+    /// Deallocates the memory allocated by [`heap_alloc`](Self::heap_alloc).
+    unsafe fn heap_dealloc(&self, ptr: NonNull<u8>, layout: Layout) {
+        #[cfg(feature = "debug")]
+        debug::dealloc(self, ptr, layout);
+        #[cfg(not(feature = "debug"))]
+        self.dealloc(ptr, layout);
+    }
 }
 
 unsafe impl GlobalAlloc for GlobalAllocator {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
-        if let Ok(ptr) = GlobalAllocator::alloc(self, layout) {
-            ptr.as_ptr()
-        } else {
-            alloc::alloc::handle_alloc_error(layout)
+        #[cfg(feature = "tagging")]
+        let res = tag::alloc(self, layout);
+        #[cfg(not(feature = "tagging"))]
+        let res = self.heap_alloc(layout);
+        // Return null on failure as required, so that fallible allocations
+        // like `Vec::try_reserve` can handle it. The infallible ones call
+        // `handle_alloc_error` themselves.
+        match res {
+            Ok(ptr) => ptr.as_ptr(),
+            Err(_) => core::ptr::null_mut(),
         }
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
-        GlobalAllocator::dealloc(self, NonNull::new(ptr).expect("dealloc null ptr"), layout)
+        let ptr = NonNull::new(ptr).expect("dealloc null ptr");
+        #[cfg(feature = "tagging")]
+        tag::dealloc(self, ptr, layout);
+        #[cfg(not(feature = "tagging"))]
+        self.heap_dealloc(ptr, layout);
     }
 }
 
@@ -217,12 +488,43 @@ pub fn global_init(start_vaddr: usize, size: usize) {
     GLOBAL_ALLOCATOR.init(start_vaddr, size);
 }
 
+/// Initializes the early allocator with the given memory region, which serves
+/// all allocations until [`global_init`] is called.
+///
+/// It's used to allocate memory before the memory regions are discovered.
+/// The region must stay valid forever, as the memory allocated from it is
+/// still in use after [`global_init`].
+#[cfg(feature = "early")]
+pub fn global_early_init(start_vaddr: usize, size: usize) {
+    debug!(
+        "initialize early allocator at: [{:#x}, {:#x})",
+        start_vaddr,
+        start_vaddr + size
+    );
+    GLOBAL_ALLOCATOR.early_init(start_vaddr, size);
+}
+
+/// Add the given memory region to the page allocator of the global
+/// allocator, and returns the index of its first page region.
+///
+/// Users should ensure that the region is valid and not being used by others,
+/// so that the allocated memory is also valid.
+pub fn global_add_page_region(start_vaddr: usize, size: usize) -> AllocResult<usize> {
+    debug!(
+        "add a page region to global allocator: [{:#x}, {:#x})",
+        start_vaddr,
+        start_vaddr + size
+    );
+    GLOBAL_ALLOCATOR.add_page_region(start_vaddr, size)
+}
+
 /// Add the given memory region to the global allocator.
 ///
 /// Users should ensure that the region is valid and not being used by others,
 /// so that the allocated memory is also valid.
 ///
-/// It's similar to [`global_init`], but can be called multiple times.
+/// It's similar to [`global_init`], but can be called multiple times, also
+/// at runtime, e.g., for memory hot-add.
 pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
     debug!(
         "add a memory region to global allocator: [{:#x}, {:#x})",
@@ -231,3 +533,73 @@ pub fn global_add_memory(start_vaddr: usize, size: usize) -> AllocResult {
     );
     GLOBAL_ALLOCATOR.add_memory(start_vaddr, size)
 }
+
+/// Removes the given memory region from the global allocator.
+///
+/// See [`GlobalAllocator::remove_memory`] for the requirements.
+pub fn global_remove_memory(start_vaddr: usize, size: usize) -> AllocResult {
+    debug!(
+        "remove a memory region from global allocator: [{:#x}, {:#x})",
+        start_vaddr,
+        start_vaddr + size
+    );
+    GLOBAL_ALLOCATOR.remove_memory(start_vaddr, size)
+}
+
+/// Returns the memory statistics of the global allocator in the format of
+/// `/proc/meminfo`, with the heap and page usage of each tag if the `tagging`
+/// feature is enabled.
+pub fn meminfo() -> String {
+    let ga = global_allocator();
+    let kb = |pages: usize| pages * PAGE_SIZE / 1024;
+    let used_pages = ga.used_pages();
+    let mut s = String::new();
+    let _ = writeln!(
+        s,
+        "MemTotal:     {:>10} kB",
+        kb(used_pages + ga.available_pages())
+    );
+    let _ = writeln!(s, "MemFree:      {:>10} kB", kb(ga.available_pages()));
+    let _ = writeln!(s, "MemUsed:      {:>10} kB", kb(used_pages));
+    let _ = writeln!(s, "MemUsedPeak:  {:>10} kB", kb(ga.peak_used_pages()));
+    let _ = writeln!(s, "HeapUsed:     {:>10} kB", ga.used_bytes() / 1024);
+    let _ = writeln!(s, "HeapFree:     {:>10} kB", ga.available_bytes() / 1024);
+    let _ = writeln!(s, "HeapUsedPeak: {:>10} kB", ga.peak_used_bytes() / 1024);
+    let _ = writeln!(
+        s,
+        "\n{:<6} {:>18} {:>18} {:>12} {:>12}",
+        "Region", "Start", "End", "Used(kB)", "Free(kB)"
+    );
+    for r in ga.page_regions() {
+        let _ = writeln!(
+            s,
+            "{:<6} {:>#18x} {:>#18x} {:>12} {:>12}",
+            r.index,
+            r.start,
+            r.start + r.size,
+            kb(r.used_pages),
+            kb(r.available_pages)
+        );
+    }
+    #[cfg(feature = "tagging")]
+    {
+        let _ = writeln!(
+            s,
+            "\n{:<20} {:>12} {:>12} {:>8} {:>12} {:>12}",
+            "Tag", "Used(B)", "Peak(B)", "Allocs", "Pages(kB)", "PeakPages(kB)"
+        );
+        for stats in tag_stats() {
+            let _ = writeln!(
+                s,
+                "{:<20} {:>12} {:>12} {:>8} {:>12} {:>12}",
+                alloc::format!("{}", stats.tag),
+                stats.used_bytes,
+                stats.peak_bytes,
+                stats.live_allocs,
+                kb(stats.used_pages),
+                kb(stats.peak_pages)
+            );
+        }
+    }
+    s
+}
@@ -0,0 +1,82 @@
+//! Custom byte allocator defined in user apps.
+
+use allocator::{AllocResult, BaseAllocator, ByteAllocator};
+use core::alloc::Layout;
+use core::ptr::NonNull;
+
+/// The interface to define a custom byte allocator in user apps.
+///
+/// The allocator is used by [`GlobalAllocator`](crate::GlobalAllocator) in
+/// place of the built-in byte allocators. When it runs out of memory,
+/// [`GlobalAllocator`](crate::GlobalAllocator) allocates pages from the page
+/// allocator and adds them by [`add_memory`](Self::add_memory), as for the
+/// built-in ones.
+///
+/// All methods are called with the lock of the global allocator held, so
+/// they are never called concurrently.
+#[crate_interface::def_interface]
+pub trait MyByteAllocatorIf {
+    /// Initializes the allocator with the given region.
+    fn init(start: usize, size: usize);
+    /// Adds a free memory region to the allocator.
+    fn add_memory(start: usize, size: usize) -> AllocResult;
+    /// Allocates memory with the given size (in bytes) and alignment.
+    fn alloc(layout: Layout) -> AllocResult<NonNull<u8>>;
+    /// Deallocates memory at the given position, size, and alignment.
+    fn dealloc(pos: NonNull<u8>, layout: Layout);
+    /// Returns total memory size in bytes.
+    fn total_bytes() -> usize;
+    /// Returns allocated memory size in bytes.
+    fn used_bytes() -> usize;
+    /// Returns available memory size in bytes.
+    fn available_bytes() -> usize;
+}
+
+/// A [`ByteAllocator`] that forwards all calls to the [`MyByteAllocatorIf`]
+/// implemented by the user app.
+pub struct MyByteAllocator;
+
+impl MyByteAllocator {
+    /// Creates a new [`MyByteAllocator`].
+    pub const fn new() -> Self {
+        Self
+    }
+}
+
+impl Default for MyByteAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BaseAllocator for MyByteAllocator {
+    fn init(&mut self, start: usize, size: usize) {
+        crate_interface::call_interface!(MyByteAllocatorIf::init(start, size))
+    }
+
+    fn add_memory(&mut self, start: usize, size: usize) -> AllocResult {
+        crate_interface::call_interface!(MyByteAllocatorIf::add_memory(start, size))
+    }
+}
+
+impl ByteAllocator for MyByteAllocator {
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        crate_interface::call_interface!(MyByteAllocatorIf::alloc(layout))
+    }
+
+    fn dealloc(&mut self, pos: NonNull<u8>, layout: Layout) {
+        crate_interface::call_interface!(MyByteAllocatorIf::dealloc(pos, layout))
+    }
+
+    fn total_bytes(&self) -> usize {
+        crate_interface::call_interface!(MyByteAllocatorIf::total_bytes())
+    }
+
+    fn used_bytes(&self) -> usize {
+        crate_interface::call_interface!(MyByteAllocatorIf::used_bytes())
+    }
+
+    fn available_bytes(&self) -> usize {
+        crate_interface::call_interface!(MyByteAllocatorIf::available_bytes())
+    }
+}
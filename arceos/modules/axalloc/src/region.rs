@@ -0,0 +1,489 @@
+//! Page allocation from multiple discontiguous memory regions.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use allocator::{AllocError, AllocResult, BaseAllocator, BitmapPageAllocator, PageAllocator};
+use core::alloc::Layout;
+
+use crate::PAGE_SIZE;
+
+/// The maximum number of page regions.
+pub const MAX_PAGE_REGIONS: usize = 8;
+
+/// The maximum size of a page region. Larger memory regions are split into
+/// multiple page regions, to fit in the bitmap of [`BitmapPageAllocator`].
+pub(crate) const MAX_REGION_SIZE: usize = 1 << 31; // 2 G
+
+/// The constraint on the pages allocated by
+/// [`GlobalAllocator::alloc_pages_in`](crate::GlobalAllocator::alloc_pages_in).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageConstraint {
+    /// Allocate from any page region.
+    Any,
+    /// Allocate from the page region with the given index.
+    Region(usize),
+    /// All allocated pages must be below the given (virtual) address.
+    ///
+    /// For memory that is linearly mapped, it also constrains the physical
+    /// address, e.g., below 4 GiB for 32-bit DMA devices.
+    Below(usize),
+}
+
+/// The usage of a page region, returned by
+/// [`GlobalAllocator::page_regions`](crate::GlobalAllocator::page_regions).
+#[derive(Debug, Clone, Copy)]
+pub struct PageRegionInfo {
+    /// The index of the region, used by [`PageConstraint::Region`].
+    pub index: usize,
+    /// The start (virtual) address of the region.
+    pub start: usize,
+    /// The size of the region in bytes.
+    pub size: usize,
+    /// The number of allocated pages.
+    pub used_pages: usize,
+    /// The number of available pages.
+    pub available_pages: usize,
+}
+
+/// A page region. Its bitmap takes about 128 KB.
+pub(crate) struct PageRegion {
+    start: usize,
+    size: usize,
+    inner: BitmapPageAllocator<PAGE_SIZE>,
+}
+
+impl PageRegion {
+    const EMPTY: Self = Self {
+        start: 0,
+        size: 0,
+        inner: BitmapPageAllocator::new(),
+    };
+
+    /// Allocates an empty page region from the heap.
+    ///
+    /// It's too large to be created on the stack and moved, so it's zeroed
+    /// in place instead, which is the same as [`PageRegion::EMPTY`].
+    fn new_boxed() -> AllocResult<Box<Self>> {
+        let layout = Layout::new::<Self>();
+        let ptr = unsafe { alloc::alloc::alloc_zeroed(layout) }.cast::<Self>();
+        if ptr.is_null() {
+            return Err(AllocError::NoMemory);
+        }
+        Ok(unsafe { Box::from_raw(ptr) })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    fn contains(&self, addr: usize) -> bool {
+        (self.start..self.start + self.size).contains(&addr)
+    }
+}
+
+/// Allocates the empty page regions needed to add a memory region of `size`
+/// bytes by [`MultiRegionPageAllocator::add_region`].
+///
+/// It allocates from the heap, which may need more pages, so it must be
+/// called without the page allocator locked.
+pub(crate) fn alloc_spare_regions(size: usize) -> AllocResult<Vec<Box<PageRegion>>> {
+    (0..size.div_ceil(MAX_REGION_SIZE))
+        .map(|_| PageRegion::new_boxed())
+        .collect()
+}
+
+/// A page allocator that manages multiple page regions, each with a
+/// [`BitmapPageAllocator`].
+///
+/// Only the first page region is embedded, as it's added before the heap is
+/// ready. The others are allocated from the heap when they are added, so
+/// unused slots take no memory.
+pub(crate) struct MultiRegionPageAllocator {
+    first: PageRegion,
+    others: [Option<Box<PageRegion>>; MAX_PAGE_REGIONS - 1],
+}
+
+impl MultiRegionPageAllocator {
+    pub const fn new() -> Self {
+        Self {
+            first: PageRegion::EMPTY,
+            others: [const { None }; MAX_PAGE_REGIONS - 1],
+        }
+    }
+
+    /// Returns the page region with the given index, if it's not empty.
+    fn region(&self, index: usize) -> Option<&PageRegion> {
+        match index {
+            0 => Some(&self.first).filter(|r| !r.is_empty()),
+            _ => self.others.get(index - 1)?.as_deref(),
+        }
+    }
+
+    /// Returns the non-empty page regions, with their indices.
+    fn regions(&self) -> impl Iterator<Item = (usize, &PageRegion)> {
+        (0..MAX_PAGE_REGIONS).filter_map(|i| self.region(i).map(|r| (i, r)))
+    }
+
+    fn regions_mut(&mut self) -> impl Iterator<Item = (usize, &mut PageRegion)> {
+        let first = Some(&mut self.first).filter(|r| !r.is_empty());
+        let others = self.others.iter_mut().map(|r| r.as_deref_mut());
+        core::iter::once(first)
+            .chain(others)
+            .enumerate()
+            .filter_map(|(i, r)| r.map(|r| (i, r)))
+    }
+
+    fn num_free_slots(&self) -> usize {
+        MAX_PAGE_REGIONS - self.regions().count()
+    }
+
+    /// Adds a memory region, returns the index of the first page region.
+    ///
+    /// The memory region is split if it's larger than [`MAX_REGION_SIZE`].
+    /// The first slot is used in place if it's free, and the other page
+    /// regions are taken from `spare`, allocated by [`alloc_spare_regions`].
+    pub fn add_region(
+        &mut self,
+        start: usize,
+        size: usize,
+        spare: &mut Vec<Box<PageRegion>>,
+    ) -> AllocResult<usize> {
+        let num_chunks = size.div_ceil(MAX_REGION_SIZE);
+        let first_free = self.first.is_empty() as usize;
+        if size < PAGE_SIZE {
+            return Err(AllocError::InvalidParam);
+        } else if num_chunks > self.num_free_slots() || num_chunks > spare.len() + first_free {
+            return Err(AllocError::NoMemory);
+        } else if self
+            .regions()
+            .any(|(_, r)| r.start < start + size && start < r.start + r.size)
+        {
+            return Err(AllocError::MemoryOverlap);
+        }
+
+        let mut first = None;
+        let mut offset = 0;
+        while offset < size {
+            let chunk_size = (size - offset).min(MAX_REGION_SIZE);
+            let (index, region) = if self.first.is_empty() {
+                (0, &mut self.first)
+            } else {
+                let i = self.others.iter().position(|r| r.is_none()).unwrap();
+                (i + 1, &mut **self.others[i].insert(spare.pop().unwrap()))
+            };
+            region.start = start + offset;
+            region.size = chunk_size;
+            region.inner.init(start + offset, chunk_size);
+            first.get_or_insert(index);
+            offset += chunk_size;
+        }
+        Ok(first.unwrap())
+    }
+
+    /// Removes the page regions in `[start, start + size)`.
+    ///
+    /// The range must cover whole page regions, and all pages in them must
+    /// be free. Otherwise, nothing is removed.
+    pub fn remove_region(&mut self, start: usize, size: usize) -> AllocResult {
+        let end = start.checked_add(size).ok_or(AllocError::InvalidParam)?;
+        let mut found = false;
+        for (_, r) in self.regions() {
+            if r.start < end && start < r.start + r.size {
+                if r.start < start || r.start + r.size > end {
+                    return Err(AllocError::InvalidParam);
+                } else if r.inner.used_pages() > 0 {
+                    return Err(AllocError::MemoryOverlap);
+                }
+                found = true;
+            }
+        }
+        if !found {
+            return Err(AllocError::NotAllocated);
+        }
+        let covered = |r: &PageRegion| start <= r.start && r.start + r.size <= end;
+        if covered(&self.first) {
+            self.first.start = 0;
+            self.first.size = 0;
+        }
+        for slot in self.others.iter_mut() {
+            if slot.as_deref().is_some_and(covered) {
+                // Freeing the bitmap only locks the byte allocator.
+                *slot = None;
+            }
+        }
+        Ok(())
+    }
+
+    /// Allocates contiguous pages that satisfy the constraint.
+    pub fn alloc_pages(
+        &mut self,
+        num_pages: usize,
+        align_pow2: usize,
+        constraint: PageConstraint,
+    ) -> AllocResult<usize> {
+        let size = num_pages
+            .checked_mul(PAGE_SIZE)
+            .ok_or(AllocError::InvalidParam)?;
+        for (index, region) in self.regions_mut() {
+            match constraint {
+                PageConstraint::Any => {}
+                PageConstraint::Region(i) if i == index => {}
+                PageConstraint::Below(limit) if region.start < limit => {}
+                _ => continue,
+            }
+            let Ok(pos) = region.inner.alloc_pages(num_pages, align_pow2) else {
+                continue;
+            };
+            if let PageConstraint::Below(limit) = constraint {
+                if pos + size > limit {
+                    region.inner.dealloc_pages(pos, num_pages);
+                    continue;
+                }
+            }
+            return Ok(pos);
+        }
+        match constraint {
+            PageConstraint::Region(i) if self.region(i).is_none() => Err(AllocError::InvalidParam),
+            _ => Err(AllocError::NoMemory),
+        }
+    }
+
+    /// Deallocates the pages to the page region they belong to.
+    pub fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
+        match self.regions_mut().find(|(_, r)| r.contains(pos)) {
+            Some((_, region)) => region.inner.dealloc_pages(pos, num_pages),
+            None => error!("dealloc pages not in any region: {:#x}", pos),
+        }
+    }
+
+    pub fn total_pages(&self) -> usize {
+        self.regions().map(|(_, r)| r.inner.total_pages()).sum()
+    }
+
+    pub fn used_pages(&self) -> usize {
+        self.regions().map(|(_, r)| r.inner.used_pages()).sum()
+    }
+
+    pub fn available_pages(&self) -> usize {
+        self.regions().map(|(_, r)| r.inner.available_pages()).sum()
+    }
+
+    /// Returns the usage of all page regions.
+    pub fn region_infos(&self) -> Vec<PageRegionInfo> {
+        self.regions()
+            .map(|(index, r)| PageRegionInfo {
+                index,
+                start: r.start,
+                size: r.size,
+                used_pages: r.inner.used_pages(),
+                available_pages: r.inner.available_pages(),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOW: usize = 0x1000_0000;
+    const HIGH: usize = 0x8000_0000;
+    const REGION_SIZE: usize = 16 * PAGE_SIZE;
+
+    /// The bitmaps never access the memory, so any address range works.
+    fn new_allocator() -> MultiRegionPageAllocator {
+        let mut palloc = MultiRegionPageAllocator::new();
+        let mut spare = alloc_spare_regions(REGION_SIZE).unwrap();
+        assert_eq!(palloc.add_region(HIGH, REGION_SIZE, &mut Vec::new()), Ok(0));
+        assert_eq!(palloc.add_region(LOW, REGION_SIZE, &mut spare), Ok(1));
+        palloc
+    }
+
+    #[test]
+    fn constraint_any() {
+        let mut palloc = new_allocator();
+        for _ in 0..2 * REGION_SIZE / PAGE_SIZE {
+            palloc
+                .alloc_pages(1, PAGE_SIZE, PageConstraint::Any)
+                .unwrap();
+        }
+        assert_eq!(palloc.used_pages(), 32);
+        assert_eq!(
+            palloc.alloc_pages(1, PAGE_SIZE, PageConstraint::Any),
+            Err(AllocError::NoMemory)
+        );
+    }
+
+    #[test]
+    fn constraint_below() {
+        let mut palloc = new_allocator();
+        let limit = LOW + 4 * PAGE_SIZE;
+        let below = PageConstraint::Below(limit);
+
+        // Only the low region is below the limit, and only 4 pages of it. The
+        // pages allocated across the limit are given back.
+        assert_eq!(
+            palloc.alloc_pages(8, PAGE_SIZE, below),
+            Err(AllocError::NoMemory)
+        );
+        assert_eq!(palloc.used_pages(), 0);
+        assert_eq!(palloc.alloc_pages(4, PAGE_SIZE, below), Ok(LOW));
+        assert_eq!(
+            palloc.alloc_pages(1, PAGE_SIZE, below),
+            Err(AllocError::NoMemory)
+        );
+        assert_eq!(palloc.used_pages(), 4);
+
+        let pos = palloc
+            .alloc_pages(1, PAGE_SIZE, PageConstraint::Any)
+            .unwrap();
+        assert!(pos >= HIGH);
+        assert_eq!(palloc.used_pages(), 5);
+
+        palloc.dealloc_pages(LOW, 4);
+        assert_eq!(palloc.alloc_pages(2, PAGE_SIZE, below), Ok(LOW));
+        let pos = palloc.alloc_pages(2, PAGE_SIZE, below).unwrap();
+        assert!(pos + 2 * PAGE_SIZE <= limit);
+        assert_eq!(
+            palloc.alloc_pages(1, PAGE_SIZE, PageConstraint::Below(LOW)),
+            Err(AllocError::NoMemory)
+        );
+    }
+
+    #[test]
+    fn constraint_region() {
+        let mut palloc = new_allocator();
+        let pos = palloc
+            .alloc_pages(1, PAGE_SIZE, PageConstraint::Region(1))
+            .unwrap();
+        assert!((LOW..LOW + REGION_SIZE).contains(&pos));
+        let pos = palloc
+            .alloc_pages(1, PAGE_SIZE, PageConstraint::Region(0))
+            .unwrap();
+        assert!((HIGH..HIGH + REGION_SIZE).contains(&pos));
+
+        // Empty or out-of-range slots.
+        for i in [2, MAX_PAGE_REGIONS, usize::MAX] {
+            assert_eq!(
+                palloc.alloc_pages(1, PAGE_SIZE, PageConstraint::Region(i)),
+                Err(AllocError::InvalidParam)
+            );
+        }
+
+        // A full region doesn't fall back to the others.
+        palloc
+            .alloc_pages(15, PAGE_SIZE, PageConstraint::Region(1))
+            .unwrap();
+        assert_eq!(
+            palloc.alloc_pages(1, PAGE_SIZE, PageConstraint::Region(1)),
+            Err(AllocError::NoMemory)
+        );
+        assert_eq!(palloc.region_infos()[0].used_pages, 1);
+    }
+
+    #[test]
+    fn add_region_needs_spare() {
+        let mut palloc = new_allocator();
+        let start = HIGH + MAX_REGION_SIZE;
+        assert_eq!(
+            palloc.add_region(start, REGION_SIZE, &mut Vec::new()),
+            Err(AllocError::NoMemory)
+        );
+        let mut spare = alloc_spare_regions(REGION_SIZE).unwrap();
+        assert_eq!(palloc.add_region(start, REGION_SIZE, &mut spare), Ok(2));
+        assert!(spare.is_empty());
+        assert_eq!(palloc.total_pages(), 48);
+    }
+
+    #[test]
+    fn remove_partial_overlap() {
+        let mut palloc = new_allocator();
+        let half = REGION_SIZE / 2;
+        for (start, size) in [
+            (LOW, half),
+            (LOW + half, REGION_SIZE),
+            (LOW - half, REGION_SIZE),
+        ] {
+            assert_eq!(
+                palloc.remove_region(start, size),
+                Err(AllocError::InvalidParam)
+            );
+        }
+        // Both regions are in the range, but the high one only partially.
+        assert_eq!(
+            palloc.remove_region(LOW, HIGH - LOW + half),
+            Err(AllocError::InvalidParam)
+        );
+        assert_eq!(palloc.region_infos().len(), 2);
+
+        assert_eq!(
+            palloc.remove_region(LOW + REGION_SIZE, HIGH - LOW - REGION_SIZE),
+            Err(AllocError::NotAllocated)
+        );
+        assert_eq!(
+            palloc.remove_region(usize::MAX, 2),
+            Err(AllocError::InvalidParam)
+        );
+
+        // A larger range is fine.
+        assert_eq!(palloc.remove_region(LOW - half, REGION_SIZE * 2), Ok(()));
+        assert_eq!(palloc.region_infos().len(), 1);
+        assert_eq!(palloc.total_pages(), 16);
+    }
+
+    #[test]
+    fn remove_busy_pages() {
+        let mut palloc = new_allocator();
+        let pos = palloc
+            .alloc_pages(1, PAGE_SIZE, PageConstraint::Region(1))
+            .unwrap();
+        assert_eq!(
+            palloc.remove_region(LOW, REGION_SIZE),
+            Err(AllocError::MemoryOverlap)
+        );
+        // Nothing is removed if any of the regions is busy.
+        assert_eq!(
+            palloc.remove_region(LOW, HIGH - LOW + REGION_SIZE),
+            Err(AllocError::MemoryOverlap)
+        );
+        assert_eq!(palloc.region_infos().len(), 2);
+
+        palloc.dealloc_pages(pos, 1);
+        assert_eq!(palloc.remove_region(LOW, HIGH - LOW + REGION_SIZE), Ok(()));
+        assert!(palloc.region_infos().is_empty());
+        assert_eq!(
+            palloc.alloc_pages(1, PAGE_SIZE, PageConstraint::Any),
+            Err(AllocError::NoMemory)
+        );
+    }
+
+    #[test]
+    fn remove_slot_reuse() {
+        let mut palloc = new_allocator();
+        let start = HIGH + MAX_REGION_SIZE;
+        let mut spare = alloc_spare_regions(REGION_SIZE).unwrap();
+        assert_eq!(palloc.add_region(start, REGION_SIZE, &mut spare), Ok(2));
+        assert_eq!(palloc.remove_region(LOW, REGION_SIZE), Ok(()));
+        assert_eq!(
+            palloc.alloc_pages(1, PAGE_SIZE, PageConstraint::Region(1)),
+            Err(AllocError::InvalidParam)
+        );
+
+        // The freed slot is reused, and the new region starts empty.
+        let mut spare = alloc_spare_regions(REGION_SIZE).unwrap();
+        assert_eq!(palloc.add_region(LOW, REGION_SIZE, &mut spare), Ok(1));
+        let info = palloc.region_infos()[1];
+        assert_eq!((info.index, info.start, info.used_pages), (1, LOW, 0));
+        assert_eq!(
+            palloc.alloc_pages(1, PAGE_SIZE, PageConstraint::Region(1)),
+            Ok(LOW)
+        );
+
+        // The first slot is reused in place, without spare regions.
+        assert_eq!(palloc.remove_region(HIGH, REGION_SIZE), Ok(()));
+        assert_eq!(palloc.add_region(HIGH, REGION_SIZE, &mut Vec::new()), Ok(0));
+        assert_eq!(palloc.region_infos().len(), 3);
+        assert_eq!(palloc.total_pages(), 48);
+        assert_eq!(palloc.used_pages(), 1);
+    }
+}
@@ -0,0 +1,277 @@
+//! Attribution of heap allocations to tasks and subsystems, enabled by the
+//! `tagging` feature.
+//!
+//! Each heap allocation made through [`GlobalAlloc`](core::alloc::GlobalAlloc)
+//! is prefixed with a small header recording the slot of its tag in a fixed
+//! table, so that the deallocation is attributed to the same tag. The tag of
+//! the current context is obtained by [`AllocTagIf`], which is implemented by
+//! the runtime.
+//!
+//! Page allocations made by [`GlobalAllocator::alloc_pages`] are attributed
+//! to the current tag as well. Pages have no room for a header, so the tag of
+//! each page block is recorded in a map keyed by its start address, which
+//! costs a small heap allocation per block. The pages used to expand the heap
+//! are not attributed, as the heap allocations in them are.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::fmt;
+use core::ptr::NonNull;
+
+use allocator::{AllocError, AllocResult};
+use kspin::SpinNoIrq;
+
+use crate::{GlobalAllocator, PAGE_SIZE};
+
+/// The maximum number of tags tracked at the same time.
+///
+/// A slot is reused when all allocations and pages of its tag are freed. When the table
+/// is full, allocations are attributed to [`AllocTag::Untagged`].
+const MAX_TAGS: usize = 64;
+
+/// The size of the header before each allocation, which holds the slot.
+const HEADER_SIZE: usize = core::mem::size_of::<usize>();
+
+/// The tag that allocations are attributed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AllocTag {
+    /// Not attributed to any task or subsystem, e.g., before the scheduler is
+    /// initialized.
+    Untagged,
+    /// Allocated by the task with the ID.
+    Task(u64),
+    /// Allocated by a subsystem, e.g., `"net buffers"`, `"fs cache"` or
+    /// `"task stacks"`.
+    Named(&'static str),
+}
+
+impl fmt::Display for AllocTag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Untagged => write!(f, "untagged"),
+            Self::Task(id) => write!(f, "task {}", id),
+            Self::Named(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// The interface to get the tag of allocations in the current context.
+#[crate_interface::def_interface]
+pub trait AllocTagIf {
+    /// Returns the tag that allocations are attributed to now.
+    fn current_tag() -> AllocTag;
+}
+
+/// Heap usage statistics of a tag.
+#[derive(Debug, Clone, Copy)]
+pub struct TagStats {
+    /// The tag.
+    pub tag: AllocTag,
+    /// Bytes currently allocated.
+    pub used_bytes: usize,
+    /// The maximum of `used_bytes` since the tag is tracked.
+    pub peak_bytes: usize,
+    /// Number of allocations not freed yet.
+    pub live_allocs: usize,
+    /// Number of allocations since the tag is tracked.
+    pub total_allocs: usize,
+    /// Pages currently allocated.
+    pub used_pages: usize,
+    /// The maximum of `used_pages` since the tag is tracked.
+    pub peak_pages: usize,
+}
+
+impl TagStats {
+    const fn new(tag: AllocTag) -> Self {
+        Self {
+            tag,
+            used_bytes: 0,
+            peak_bytes: 0,
+            live_allocs: 0,
+            total_allocs: 0,
+            used_pages: 0,
+            peak_pages: 0,
+        }
+    }
+
+    fn is_idle(&self) -> bool {
+        self.live_allocs == 0 && self.used_pages == 0
+    }
+}
+
+/// Slot 0 is always [`AllocTag::Untagged`].
+static TAGS: SpinNoIrq<[Option<TagStats>; MAX_TAGS]> = {
+    let mut tags = [None; MAX_TAGS];
+    tags[0] = Some(TagStats::new(AllocTag::Untagged));
+    SpinNoIrq::new(tags)
+};
+
+/// The page blocks allocated by [`GlobalAllocator::alloc_pages`], mapped from
+/// their start addresses to the slots of their tags and the numbers of pages.
+static PAGE_TAGS: SpinNoIrq<BTreeMap<usize, (usize, usize)>> = SpinNoIrq::new(BTreeMap::new());
+
+/// Returns the stats of `tag`, and its slot.
+fn stats_of(tags: &mut [Option<TagStats>; MAX_TAGS], tag: AllocTag) -> (&mut TagStats, usize) {
+    if let Some(slot) = tags.iter().position(|s| s.is_some_and(|s| s.tag == tag)) {
+        return (tags[slot].as_mut().unwrap(), slot);
+    }
+    let reusable = |s: &Option<TagStats>| s.map_or(true, |s| s.is_idle());
+    match tags.iter().skip(1).position(reusable) {
+        Some(idx) => {
+            // Take a free slot, or reuse the slot of a tag without live
+            // allocations.
+            let slot = idx + 1;
+            (tags[slot].insert(TagStats::new(tag)), slot)
+        }
+        // The table is full, fall back to the untagged slot as it is.
+        None => (tags[0].as_mut().unwrap(), 0),
+    }
+}
+
+fn account_alloc(tag: AllocTag, size: usize) -> usize {
+    let mut tags = TAGS.lock();
+    let (stats, slot) = stats_of(&mut tags, tag);
+    stats.used_bytes += size;
+    stats.peak_bytes = stats.peak_bytes.max(stats.used_bytes);
+    stats.live_allocs += 1;
+    stats.total_allocs += 1;
+    slot
+}
+
+fn account_dealloc(slot: usize, size: usize) {
+    if let Some(stats) = TAGS.lock()[slot].as_mut() {
+        stats.used_bytes -= size;
+        stats.live_allocs -= 1;
+    }
+}
+
+/// Attributes the pages allocated at `pos` to the current tag.
+pub(crate) fn account_pages(pos: usize, num_pages: usize) {
+    let tag = crate_interface::call_interface!(AllocTagIf::current_tag);
+    let slot = {
+        let mut tags = TAGS.lock();
+        let (stats, slot) = stats_of(&mut tags, tag);
+        stats.used_pages += num_pages;
+        stats.peak_pages = stats.peak_pages.max(stats.used_pages);
+        slot
+    };
+    // Inserting allocates, so `TAGS` must not be locked here.
+    PAGE_TAGS.lock().insert(pos, (slot, num_pages));
+}
+
+/// Removes the attribution of the pages freed at `pos`.
+///
+/// A block may be freed in parts, e.g., after a huge page is split, and the
+/// rest of it stays attributed to the same tag.
+pub(crate) fn unaccount_pages(pos: usize, num_pages: usize) {
+    let end = pos + num_pages * PAGE_SIZE;
+    let mut page_tags = PAGE_TAGS.lock();
+    let Some((&start, &(slot, block_pages))) = page_tags.range(..=pos).next_back() else {
+        return;
+    };
+    let block_end = start + block_pages * PAGE_SIZE;
+    if pos >= block_end {
+        return;
+    }
+    page_tags.remove(&start);
+    if start < pos {
+        page_tags.insert(start, (slot, (pos - start) / PAGE_SIZE));
+    }
+    if end < block_end {
+        page_tags.insert(end, (slot, (block_end - end) / PAGE_SIZE));
+    }
+    drop(page_tags);
+    if let Some(stats) = TAGS.lock()[slot].as_mut() {
+        stats.used_pages -= (end.min(block_end) - pos) / PAGE_SIZE;
+    }
+}
+
+/// Returns the layout with the header, and the offset of the allocation
+/// after the header.
+fn layout_with_header(layout: Layout) -> AllocResult<(Layout, usize)> {
+    let offset = layout.align().max(HEADER_SIZE);
+    let size = layout
+        .size()
+        .checked_add(offset)
+        .ok_or(AllocError::InvalidParam)?;
+    let layout = Layout::from_size_align(size, offset).map_err(|_| AllocError::InvalidParam)?;
+    Ok((layout, offset))
+}
+
+/// Allocates with the header, and attributes it to the current tag.
+pub(crate) fn alloc(ga: &GlobalAllocator, layout: Layout) -> AllocResult<NonNull<u8>> {
+    let (full_layout, offset) = layout_with_header(layout)?;
+    let tag = crate_interface::call_interface!(AllocTagIf::current_tag);
+    let ptr = ga.heap_alloc(full_layout)?;
+    let slot = account_alloc(tag, layout.size());
+    unsafe {
+        let user_ptr = ptr.as_ptr().add(offset);
+        user_ptr.cast::<usize>().sub(1).write(slot);
+        Ok(NonNull::new_unchecked(user_ptr))
+    }
+}
+
+/// Deallocates an allocation made by [`alloc`].
+///
+/// # Safety
+///
+/// `ptr` must be allocated by [`alloc`] with the same `layout`.
+pub(crate) unsafe fn dealloc(ga: &GlobalAllocator, ptr: NonNull<u8>, layout: Layout) {
+    let (full_layout, offset) = layout_with_header(layout).unwrap();
+    let slot = ptr.as_ptr().cast::<usize>().sub(1).read();
+    account_dealloc(slot, layout.size());
+    ga.heap_dealloc(
+        NonNull::new_unchecked(ptr.as_ptr().sub(offset)),
+        full_layout,
+    );
+}
+
+/// Returns the heap and page usage statistics of the tracked tags.
+pub fn tag_stats() -> Vec<TagStats> {
+    // Copy them out first, as collecting into a `Vec` allocates.
+    let tags = *TAGS.lock();
+    tags.into_iter().flatten().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct AllocTagIfImpl;
+
+    #[crate_interface::impl_interface]
+    impl AllocTagIf for AllocTagIfImpl {
+        fn current_tag() -> AllocTag {
+            AllocTag::Untagged
+        }
+    }
+
+    #[test]
+    fn full_table() {
+        let mut tags = [None; MAX_TAGS];
+        tags[0] = Some(TagStats::new(AllocTag::Untagged));
+        stats_of(&mut tags, AllocTag::Untagged).0.live_allocs += 1;
+        for id in 1..MAX_TAGS {
+            let (stats, slot) = stats_of(&mut tags, AllocTag::Task(id as u64));
+            assert_eq!(slot, id);
+            stats.live_allocs += 1;
+        }
+
+        // Attributed to the untagged slot, which keeps its stats.
+        let (stats, slot) = stats_of(&mut tags, AllocTag::Task(MAX_TAGS as u64));
+        assert_eq!(slot, 0);
+        assert_eq!(stats.tag, AllocTag::Untagged);
+        assert_eq!(stats.live_allocs, 1);
+
+        // Only an idle slot is reused.
+        tags[5].as_mut().unwrap().live_allocs = 0;
+        let (stats, slot) = stats_of(&mut tags, AllocTag::Named("test"));
+        assert_eq!(slot, 5);
+        assert_eq!(stats.tag, AllocTag::Named("test"));
+        assert_eq!(stats.live_allocs, 0);
+        let (stats, slot) = stats_of(&mut tags, AllocTag::Task(1));
+        assert_eq!(slot, 1);
+        assert_eq!(stats.live_allocs, 1);
+    }
+}
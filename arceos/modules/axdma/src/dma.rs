@@ -1,11 +1,14 @@
 use core::{alloc::Layout, ptr::NonNull};
 
 use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator};
-use axalloc::{global_allocator, DefaultByteAllocator};
-use axhal::{mem::virt_to_phys, paging::MappingFlags};
+use axalloc::{global_allocator, DefaultByteAllocator, PageConstraint};
+use axhal::{
+    mem::{phys_to_virt, virt_to_phys},
+    paging::MappingFlags,
+};
 use kspin::SpinNoIrq;
 use log::{debug, error};
-use memory_addr::{va, VirtAddr, PAGE_SIZE_4K};
+use memory_addr::{pa, va, VirtAddr, PAGE_SIZE_4K};
 
 use crate::{phys_to_bus, BusAddr, DMAInfo};
 
@@ -30,7 +33,7 @@ impl DmaAllocator {
     /// byte allocator.
     pub unsafe fn alloc_coherent(&mut self, layout: Layout) -> AllocResult<DMAInfo> {
         if layout.size() >= PAGE_SIZE_4K {
-            self.alloc_coherent_pages(layout)
+            self.alloc_coherent_pages(layout, PageConstraint::Any)
         } else {
             self.alloc_coherent_bytes(layout)
         }
@@ -69,10 +72,24 @@ impl DmaAllocator {
         }
     }
 
-    fn alloc_coherent_pages(&mut self, layout: Layout) -> AllocResult<DMAInfo> {
+    /// Allocates whole pages with bus addresses below 4 GiB, for devices
+    /// that only support 32-bit DMA.
+    pub unsafe fn alloc_coherent_dma32(&mut self, layout: Layout) -> AllocResult<DMAInfo> {
+        let limit = dma32_limit().ok_or(AllocError::NoMemory)?;
+        self.alloc_coherent_pages(layout, PageConstraint::Below(limit))
+    }
+
+    fn alloc_coherent_pages(
+        &mut self,
+        layout: Layout,
+        constraint: PageConstraint,
+    ) -> AllocResult<DMAInfo> {
         let num_pages = layout_pages(&layout);
-        let vaddr_raw =
-            global_allocator().alloc_pages(num_pages, PAGE_SIZE_4K.max(layout.align()))?;
+        let vaddr_raw = global_allocator().alloc_pages_in(
+            num_pages,
+            PAGE_SIZE_4K.max(layout.align()),
+            constraint,
+        )?;
         let vaddr = va!(vaddr_raw);
         self.update_flags(
             vaddr,
@@ -104,18 +121,36 @@ impl DmaAllocator {
     /// Gives back the allocated region to the byte allocator.
     pub unsafe fn dealloc_coherent(&mut self, dma: DMAInfo, layout: Layout) {
         if layout.size() >= PAGE_SIZE_4K {
-            let num_pages = layout_pages(&layout);
-            let virt_raw = dma.cpu_addr.as_ptr() as usize;
-            global_allocator().dealloc_pages(virt_raw, num_pages);
-            let _ = self.update_flags(
-                va!(virt_raw),
-                num_pages,
-                MappingFlags::READ | MappingFlags::WRITE,
-            );
+            self.dealloc_coherent_pages(dma, layout)
         } else {
             self.alloc.dealloc(dma.cpu_addr, layout)
         }
     }
+
+    /// Gives back the pages allocated by [`alloc_coherent_dma32`].
+    ///
+    /// [`alloc_coherent_dma32`]: DmaAllocator::alloc_coherent_dma32
+    pub unsafe fn dealloc_coherent_dma32(&mut self, dma: DMAInfo, layout: Layout) {
+        self.dealloc_coherent_pages(dma, layout)
+    }
+
+    fn dealloc_coherent_pages(&mut self, dma: DMAInfo, layout: Layout) {
+        let num_pages = layout_pages(&layout);
+        let virt_raw = dma.cpu_addr.as_ptr() as usize;
+        global_allocator().dealloc_pages(virt_raw, num_pages);
+        let _ = self.update_flags(
+            va!(virt_raw),
+            num_pages,
+            MappingFlags::READ | MappingFlags::WRITE,
+        );
+    }
+}
+
+/// Returns the virtual address mapped to the bus address 4 GiB, the limit of
+/// 32-bit DMA.
+fn dma32_limit() -> Option<usize> {
+    let paddr = (1usize << 32).checked_sub(axconfig::PHYS_BUS_OFFSET)?;
+    Some(phys_to_virt(pa!(paddr)).as_usize())
 }
 
 const fn virt_to_bus(addr: VirtAddr) -> BusAddr {
@@ -48,6 +48,24 @@ pub unsafe fn dealloc_coherent(dma: DMAInfo, layout: Layout) {
     ALLOCATOR.lock().dealloc_coherent(dma, layout)
 }
 
+/// Allocates **coherent** memory for devices that only support 32-bit DMA,
+/// i.e., the bus address of the memory is below 4 GiB.
+///
+/// The memory is allocated in whole pages from the page regions below the
+/// limit, and must be freed by [`dealloc_coherent_dma32`].
+/// # Safety
+/// This function is unsafe because it directly interacts with the global allocator, which can potentially cause memory leaks or other issues if not used correctly.
+pub unsafe fn alloc_coherent_dma32(layout: Layout) -> AllocResult<DMAInfo> {
+    ALLOCATOR.lock().alloc_coherent_dma32(layout)
+}
+
+/// Frees coherent memory previously allocated by [`alloc_coherent_dma32`].
+/// # Safety
+/// This function is unsafe because it directly interacts with the global allocator, which can potentially cause memory leaks or other issues if not used correctly.
+pub unsafe fn dealloc_coherent_dma32(dma: DMAInfo, layout: Layout) {
+    ALLOCATOR.lock().dealloc_coherent_dma32(dma, layout)
+}
+
 /// A bus memory address.
 ///
 /// It's a wrapper type around an [`u64`].
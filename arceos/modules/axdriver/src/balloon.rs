@@ -0,0 +1,378 @@
+//! VirtIO memory balloon driver.
+//!
+//! The host sets the target number of pages in the balloon through the config
+//! space of the device. [`VirtIoBalloonDev::update`] inflates the balloon by
+//! allocating pages from the global allocator and giving them to the host, or
+//! deflates it by taking pages back from the host and freeing them. So the
+//! host can overcommit the memory of guests.
+//!
+//! Only the MMIO transport (both legacy and modern) is supported. The driver
+//! accesses the registers and sets up the virtqueues by itself, as the balloon
+//! device is not a device category of [`AllDevices`](crate::AllDevices).
+
+use alloc::vec::Vec;
+use core::sync::atomic::{fence, Ordering};
+
+use axalloc::global_allocator;
+use axdriver_base::{DevError, DevResult};
+use axhal::mem::{phys_to_virt, virt_to_phys};
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// The balloon always uses 4K pages, regardless of the guest page size.
+const BALLOON_PFN_SHIFT: usize = 12;
+/// The maximum number of pages in one inflate or deflate request.
+const PFNS_PER_REQUEST: usize = 256;
+
+const MAGIC_VALUE: u32 = 0x7472_6976; // "virt"
+const DEVICE_ID_BALLOON: u32 = 5;
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const QUEUE_INFLATE: u32 = 0;
+const QUEUE_DEFLATE: u32 = 1;
+const QUEUE_SIZE: u32 = 8;
+
+/// Offsets of the MMIO registers.
+mod reg {
+    pub const MAGIC: usize = 0x000;
+    pub const VERSION: usize = 0x004;
+    pub const DEVICE_ID: usize = 0x008;
+    pub const DEVICE_FEATURES: usize = 0x010;
+    pub const DEVICE_FEATURES_SEL: usize = 0x014;
+    pub const DRIVER_FEATURES: usize = 0x020;
+    pub const DRIVER_FEATURES_SEL: usize = 0x024;
+    pub const LEGACY_GUEST_PAGE_SIZE: usize = 0x028;
+    pub const QUEUE_SEL: usize = 0x030;
+    pub const QUEUE_NUM_MAX: usize = 0x034;
+    pub const QUEUE_NUM: usize = 0x038;
+    pub const LEGACY_QUEUE_ALIGN: usize = 0x03c;
+    pub const LEGACY_QUEUE_PFN: usize = 0x040;
+    pub const QUEUE_READY: usize = 0x044;
+    pub const QUEUE_NOTIFY: usize = 0x050;
+    pub const INTERRUPT_STATUS: usize = 0x060;
+    pub const INTERRUPT_ACK: usize = 0x064;
+    pub const STATUS: usize = 0x070;
+    pub const QUEUE_DESC: usize = 0x080;
+    pub const QUEUE_DRIVER: usize = 0x090;
+    pub const QUEUE_DEVICE: usize = 0x0a0;
+    /// `num_pages` in the config space, the target set by the host.
+    pub const CONFIG_NUM_PAGES: usize = 0x100;
+    /// `actual` in the config space, the number of pages in the balloon.
+    pub const CONFIG_ACTUAL: usize = 0x104;
+    pub const END: usize = 0x108;
+}
+
+/// Bits of the device status register.
+mod status {
+    pub const ACKNOWLEDGE: u32 = 1;
+    pub const DRIVER: u32 = 2;
+    pub const DRIVER_OK: u32 = 4;
+    pub const FEATURES_OK: u32 = 8;
+}
+
+const fn align_up(pos: usize, align: usize) -> usize {
+    (pos + align - 1) & !(align - 1)
+}
+
+struct Mmio {
+    base: usize,
+}
+
+impl Mmio {
+    fn read(&self, offset: usize) -> u32 {
+        unsafe { ((self.base + offset) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, offset: usize, value: u32) {
+        unsafe { ((self.base + offset) as *mut u32).write_volatile(value) }
+    }
+
+    /// Writes a 64-bit value to a pair of low and high registers.
+    fn write64(&self, offset: usize, value: u64) {
+        self.write(offset, value as u32);
+        self.write(offset + 4, (value >> 32) as u32);
+    }
+}
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+/// A split virtqueue that sends one device-readable buffer at a time.
+///
+/// It uses the legacy layout, so it works for both legacy and modern devices.
+struct VirtQueue {
+    index: u32,
+    size: u16,
+    vaddr: usize,
+    num_pages: usize,
+    avail_offset: usize,
+    used_offset: usize,
+    avail_idx: u16,
+    used_idx: u16,
+}
+
+impl VirtQueue {
+    fn new(mmio: &Mmio, index: u32, legacy: bool) -> DevResult<Self> {
+        mmio.write(reg::QUEUE_SEL, index);
+        let size = mmio.read(reg::QUEUE_NUM_MAX).min(QUEUE_SIZE) as usize;
+        if size == 0 {
+            return Err(DevError::BadState);
+        }
+        let avail_offset = size_of::<Descriptor>() * size;
+        let used_offset = align_up(avail_offset + 6 + 2 * size, PAGE_SIZE);
+        let num_pages = align_up(used_offset + 6 + 8 * size, PAGE_SIZE) / PAGE_SIZE;
+        let vaddr = global_allocator()
+            .alloc_pages(num_pages, PAGE_SIZE)
+            .map_err(|_| DevError::NoMemory)?;
+        unsafe { core::ptr::write_bytes(vaddr as *mut u8, 0, num_pages * PAGE_SIZE) };
+
+        let paddr = virt_to_phys(vaddr.into()).as_usize();
+        mmio.write(reg::QUEUE_NUM, size as u32);
+        if legacy {
+            mmio.write(reg::LEGACY_QUEUE_ALIGN, PAGE_SIZE as u32);
+            mmio.write(reg::LEGACY_QUEUE_PFN, (paddr / PAGE_SIZE) as u32);
+        } else {
+            mmio.write64(reg::QUEUE_DESC, paddr as u64);
+            mmio.write64(reg::QUEUE_DRIVER, (paddr + avail_offset) as u64);
+            mmio.write64(reg::QUEUE_DEVICE, (paddr + used_offset) as u64);
+            mmio.write(reg::QUEUE_READY, 1);
+        }
+        Ok(Self {
+            index,
+            size: size as u16,
+            vaddr,
+            num_pages,
+            avail_offset,
+            used_offset,
+            avail_idx: 0,
+            used_idx: 0,
+        })
+    }
+
+    /// Sends a device-readable buffer, and waits until the device has used it.
+    fn send(&mut self, mmio: &Mmio, paddr: usize, len: usize) {
+        let desc = Descriptor {
+            addr: (paddr as u64).to_le(),
+            len: (len as u32).to_le(),
+            flags: 0,
+            next: 0,
+        };
+        let avail = (self.vaddr + self.avail_offset) as *mut u16;
+        let used_idx = (self.vaddr + self.used_offset + 2) as *const u16;
+        unsafe {
+            // Always use the first descriptor, as there is only one request
+            // in flight.
+            (self.vaddr as *mut Descriptor).write_volatile(desc);
+            let slot = (self.avail_idx % self.size) as usize;
+            avail.add(2 + slot).write_volatile(0);
+            fence(Ordering::SeqCst);
+            self.avail_idx = self.avail_idx.wrapping_add(1);
+            avail.add(1).write_volatile(self.avail_idx.to_le());
+            fence(Ordering::SeqCst);
+            mmio.write(reg::QUEUE_NOTIFY, self.index);
+
+            while u16::from_le(used_idx.read_volatile()) == self.used_idx {
+                core::hint::spin_loop();
+            }
+            fence(Ordering::SeqCst);
+        }
+        self.used_idx = self.used_idx.wrapping_add(1);
+    }
+}
+
+impl Drop for VirtQueue {
+    fn drop(&mut self) {
+        global_allocator().dealloc_pages(self.vaddr, self.num_pages);
+    }
+}
+
+/// The VirtIO memory balloon device.
+pub struct VirtIoBalloonDev {
+    mmio: Mmio,
+    irq_num: Option<usize>,
+    inflate_vq: VirtQueue,
+    deflate_vq: VirtQueue,
+    /// A page to hold the PFNs of a request.
+    pfns: usize,
+    /// Pages given to the host.
+    pages: Vec<usize>,
+}
+
+impl VirtIoBalloonDev {
+    /// Probes the MMIO region, and initializes the device if it's a VirtIO
+    /// balloon device.
+    ///
+    /// `irq_num` is the IRQ number of the MMIO region, if its interrupts are
+    /// supported.
+    pub fn probe_mmio(mmio_base: usize, mmio_size: usize, irq_num: Option<usize>) -> Option<Self> {
+        let mmio = Mmio {
+            base: phys_to_virt(mmio_base.into()).as_usize(),
+        };
+        if mmio_size < reg::END
+            || mmio.read(reg::MAGIC) != MAGIC_VALUE
+            || mmio.read(reg::DEVICE_ID) != DEVICE_ID_BALLOON
+        {
+            return None;
+        }
+        match Self::init(mmio, irq_num) {
+            Ok(dev) => Some(dev),
+            Err(e) => {
+                warn!(
+                    "failed to initialize VirtIO balloon at [PA:{:#x}, PA:{:#x}): {:?}",
+                    mmio_base,
+                    mmio_base + mmio_size,
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn init(mmio: Mmio, irq_num: Option<usize>) -> DevResult<Self> {
+        let legacy = match mmio.read(reg::VERSION) {
+            1 => true,
+            2 => false,
+            _ => return Err(DevError::Unsupported),
+        };
+        mmio.write(reg::STATUS, 0);
+        let mut dev_status = status::ACKNOWLEDGE | status::DRIVER;
+        mmio.write(reg::STATUS, dev_status);
+
+        // No device-specific features are needed.
+        if legacy {
+            mmio.write(reg::DRIVER_FEATURES_SEL, 0);
+            mmio.write(reg::DRIVER_FEATURES, 0);
+            mmio.write(reg::LEGACY_GUEST_PAGE_SIZE, PAGE_SIZE as u32);
+        } else {
+            mmio.write(reg::DEVICE_FEATURES_SEL, 1);
+            let features = (mmio.read(reg::DEVICE_FEATURES) as u64) << 32;
+            mmio.write(reg::DRIVER_FEATURES_SEL, 1);
+            mmio.write(
+                reg::DRIVER_FEATURES,
+                ((features & VIRTIO_F_VERSION_1) >> 32) as u32,
+            );
+            mmio.write(reg::DRIVER_FEATURES_SEL, 0);
+            mmio.write(reg::DRIVER_FEATURES, 0);
+            dev_status |= status::FEATURES_OK;
+            mmio.write(reg::STATUS, dev_status);
+            if mmio.read(reg::STATUS) & status::FEATURES_OK == 0 {
+                return Err(DevError::Unsupported);
+            }
+        }
+
+        let inflate_vq = VirtQueue::new(&mmio, QUEUE_INFLATE, legacy)?;
+        let deflate_vq = VirtQueue::new(&mmio, QUEUE_DEFLATE, legacy)?;
+        let pfns = global_allocator()
+            .alloc_pages(1, PAGE_SIZE)
+            .map_err(|_| DevError::NoMemory)?;
+        mmio.write(reg::STATUS, dev_status | status::DRIVER_OK);
+
+        Ok(Self {
+            mmio,
+            irq_num,
+            inflate_vq,
+            deflate_vq,
+            pfns,
+            pages: Vec::new(),
+        })
+    }
+
+    /// Returns the IRQ number of the device, if its interrupts are supported.
+    ///
+    /// The device raises an interrupt when the host changes the target size.
+    pub fn irq_num(&self) -> Option<usize> {
+        self.irq_num
+    }
+
+    /// Returns the number of pages that the host wants in the balloon.
+    pub fn target_pages(&self) -> usize {
+        u32::from_le(self.mmio.read(reg::CONFIG_NUM_PAGES)) as usize
+    }
+
+    /// Returns the number of pages in the balloon.
+    pub fn actual_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Inflates or deflates the balloon to the target size set by the host,
+    /// and returns the number of pages in the balloon.
+    ///
+    /// It should be called on the interrupt of the device, which is raised
+    /// when the configuration is changed, and acknowledges it. If there is not
+    /// enough free memory, the balloon is inflated as much as possible.
+    pub fn update(&mut self) -> usize {
+        // Acknowledge the configuration change interrupt, if any.
+        let irq_status = self.mmio.read(reg::INTERRUPT_STATUS);
+        self.mmio.write(reg::INTERRUPT_ACK, irq_status);
+
+        let target = self.target_pages();
+        let old = self.pages.len();
+        while self.pages.len() < target {
+            if !self.inflate(target - self.pages.len()) {
+                break;
+            }
+        }
+        while self.pages.len() > target {
+            self.deflate(self.pages.len() - target);
+        }
+        if self.pages.len() != old {
+            debug!(
+                "balloon: {} -> {} pages (target {})",
+                old,
+                self.pages.len(),
+                target
+            );
+            self.mmio
+                .write(reg::CONFIG_ACTUAL, (self.pages.len() as u32).to_le());
+        }
+        self.pages.len()
+    }
+
+    /// Gives at most `num_pages` free pages to the host. Returns `false` if
+    /// no page can be allocated.
+    fn inflate(&mut self, num_pages: usize) -> bool {
+        let num_pages = num_pages.min(PFNS_PER_REQUEST);
+        if self.pages.try_reserve(num_pages).is_err() {
+            return false;
+        }
+        let pfns = self.pfns as *mut u32;
+        let mut n = 0;
+        while n < num_pages {
+            let Ok(vaddr) = global_allocator().alloc_pages(1, PAGE_SIZE) else {
+                break;
+            };
+            let pfn = virt_to_phys(vaddr.into()).as_usize() >> BALLOON_PFN_SHIFT;
+            unsafe { pfns.add(n).write((pfn as u32).to_le()) };
+            self.pages.push(vaddr);
+            n += 1;
+        }
+        if n > 0 {
+            let pfns_paddr = virt_to_phys(self.pfns.into()).as_usize();
+            self.inflate_vq
+                .send(&self.mmio, pfns_paddr, n * size_of::<u32>());
+        }
+        n > 0
+    }
+
+    /// Takes at most `num_pages` pages back from the host, and frees them.
+    fn deflate(&mut self, num_pages: usize) {
+        let num_pages = num_pages.min(PFNS_PER_REQUEST);
+        let start = self.pages.len() - num_pages;
+        let pfns = self.pfns as *mut u32;
+        for (i, &vaddr) in self.pages[start..].iter().enumerate() {
+            let pfn = virt_to_phys(vaddr.into()).as_usize() >> BALLOON_PFN_SHIFT;
+            unsafe { pfns.add(i).write((pfn as u32).to_le()) };
+        }
+        let pfns_paddr = virt_to_phys(self.pfns.into()).as_usize();
+        self.deflate_vq
+            .send(&self.mmio, pfns_paddr, num_pages * size_of::<u32>());
+        for vaddr in self.pages.drain(start..) {
+            global_allocator().dealloc_pages(vaddr, 1);
+        }
+    }
+}
@@ -4,8 +4,23 @@ use crate::{prelude::*, AllDevices};
 impl AllDevices {
     pub(crate) fn probe_bus_devices(&mut self) {
         // TODO: parse device tree
+        #[cfg(feature = "virtio-balloon")]
+        for (i, reg) in axconfig::VIRTIO_MMIO_REGIONS.iter().enumerate() {
+            if self.balloon.is_none() {
+                let irq_num =
+                    (axconfig::VIRTIO_MMIO_IRQ != 0).then_some(axconfig::VIRTIO_MMIO_IRQ + i);
+                self.balloon = crate::VirtIoBalloonDev::probe_mmio(reg.0, reg.1, irq_num);
+                if self.balloon.is_some() {
+                    info!(
+                        "registered a new VirtIO balloon device at [PA:{:#x}, PA:{:#x})",
+                        reg.0,
+                        reg.0 + reg.1,
+                    );
+                }
+            }
+        }
         #[cfg(feature = "virtio")]
-        for reg in axconfig::VIRTIO_MMIO_REGIONS {
+        for (i, reg) in axconfig::VIRTIO_MMIO_REGIONS.iter().enumerate() {
             for_each_drivers!(type Driver, {
                 if let Some(dev) = Driver::probe_mmio(reg.0, reg.1) {
                     info!(
@@ -14,6 +29,17 @@ impl AllDevices {
                         reg.0, reg.0 + reg.1,
                         dev.device_name(),
                     );
+                    #[cfg(feature = "net")]
+                    if dev.device_type() == DeviceType::Net
+                        && self.net.is_empty()
+                        && axconfig::VIRTIO_MMIO_IRQ != 0
+                    {
+                        let base = axhal::mem::phys_to_virt(reg.0.into()).as_usize();
+                        self.net_irq = Some(crate::VirtIoMmioIrq::new(
+                            axconfig::VIRTIO_MMIO_IRQ + i,
+                            base,
+                        ));
+                    }
                     self.add_device(dev);
                     continue; // skip to the next device
                 }
@@ -0,0 +1,36 @@
+//! Interrupts of devices whose drivers don't handle them.
+
+/// Offsets of the VirtIO MMIO interrupt registers.
+const INTERRUPT_STATUS: usize = 0x060;
+const INTERRUPT_ACK: usize = 0x064;
+
+/// The interrupt of a VirtIO MMIO device.
+///
+/// The VirtIO drivers don't handle interrupts, so the user of the device
+/// registers the interrupt handler, which must acknowledge the interrupt by
+/// [`VirtIoMmioIrq::ack`]. Otherwise, it's raised again.
+pub struct VirtIoMmioIrq {
+    irq_num: usize,
+    /// The virtual address of the MMIO registers.
+    base: usize,
+}
+
+impl VirtIoMmioIrq {
+    #[cfg(all(feature = "virtio", bus = "mmio"))]
+    pub(crate) const fn new(irq_num: usize, base: usize) -> Self {
+        Self { irq_num, base }
+    }
+
+    /// Returns the IRQ number.
+    pub const fn irq_num(&self) -> usize {
+        self.irq_num
+    }
+
+    /// Acknowledges all pending interrupts of the device.
+    pub fn ack(&self) {
+        unsafe {
+            let status = ((self.base + INTERRUPT_STATUS) as *const u32).read_volatile();
+            ((self.base + INTERRUPT_ACK) as *mut u32).write_volatile(status);
+        }
+    }
+}
@@ -35,6 +35,7 @@
 //! | Network | `virtio-net` | VirtIO network device |
 //! | Display | `virtio-gpu` | VirtIO graphics device |
 //! | Input | `virtio-input` | VirtIO input device (mouse, tablet) |
+//! | - | `virtio-balloon` | VirtIO memory balloon device (MMIO only), see [`VirtIoBalloonDev`] |
 //!
 //! # Other Cargo Features
 //!
@@ -63,7 +64,7 @@
 #[macro_use]
 extern crate log;
 
-#[cfg(feature = "dyn")]
+#[cfg(any(feature = "dyn", feature = "virtio-balloon"))]
 extern crate alloc;
 
 #[macro_use]
@@ -77,6 +78,12 @@ mod structs;
 #[cfg(feature = "virtio")]
 mod virtio;
 
+#[cfg(feature = "virtio-balloon")]
+mod balloon;
+
+#[cfg(feature = "net")]
+mod irq;
+
 #[cfg(feature = "ixgbe")]
 mod ixgbe;
 
@@ -86,6 +93,11 @@ pub mod prelude;
 use self::prelude::*;
 pub use self::structs::{AxDeviceContainer, AxDeviceEnum};
 
+#[cfg(feature = "virtio-balloon")]
+pub use self::balloon::VirtIoBalloonDev;
+#[cfg(feature = "net")]
+pub use self::irq::VirtIoMmioIrq;
+
 #[cfg(feature = "block")]
 pub use self::structs::AxBlockDevice;
 #[cfg(feature = "display")]
@@ -101,6 +113,10 @@ pub struct AllDevices {
     /// All network device drivers.
     #[cfg(feature = "net")]
     pub net: AxDeviceContainer<AxNetDevice>,
+    /// The interrupt of the first network device, if it's a VirtIO MMIO device
+    /// and the interrupts of the MMIO regions are supported.
+    #[cfg(feature = "net")]
+    pub net_irq: Option<VirtIoMmioIrq>,
     /// All block device drivers.
     #[cfg(feature = "block")]
     pub block: AxDeviceContainer<AxBlockDevice>,
@@ -110,6 +126,9 @@ pub struct AllDevices {
     /// All input device drivers.
     #[cfg(feature = "input")]
     pub input: AxDeviceContainer<AxInputDevice>,
+    /// The memory balloon device.
+    #[cfg(feature = "virtio-balloon")]
+    pub balloon: Option<VirtIoBalloonDev>,
 }
 
 impl AllDevices {
@@ -197,5 +216,8 @@ pub fn init_drivers() -> AllDevices {
         }
     }
 
+    #[cfg(feature = "virtio-balloon")]
+    debug!("balloon device: {}", all_devs.balloon.is_some());
+
     all_devs
 }
@@ -158,6 +158,25 @@ impl File {
         self.inner.truncate(size)
     }
 
+    /// Attempts to sync all file content and metadata to the disk.
+    ///
+    /// The whole block cache is written back, so it works for files opened
+    /// read-only as well.
+    pub fn sync_all(&self) -> Result<()> {
+        if self.inner.is_writable() {
+            // Also writes back the metadata kept in the file, e.g., its size.
+            self.inner.flush()
+        } else {
+            super::sync()
+        }
+    }
+
+    /// Attempts to sync file content to the disk, same as
+    /// [`sync_all`](File::sync_all).
+    pub fn sync_data(&self) -> Result<()> {
+        self.sync_all()
+    }
+
     /// Queries metadata about the underlying file.
     pub fn metadata(&self) -> Result<Metadata> {
         self.inner.get_attr().map(Metadata)
@@ -5,8 +5,11 @@ mod file;
 
 pub use self::dir::{DirBuilder, DirEntry, ReadDir};
 pub use self::file::{File, FileType, Metadata, OpenOptions, Permissions};
+pub use crate::cache::CacheStats;
+pub use crate::root::{MountInfo, MountOptions};
 
-use alloc::{string::String, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use axfs_vfs::VfsOps;
 use axio::{self as io, prelude::*};
 
 /// Returns an iterator over the entries within a directory.
@@ -87,3 +90,43 @@ pub fn remove_file(path: &str) -> io::Result<()> {
 pub fn rename(old: &str, new: &str) -> io::Result<()> {
     crate::root::rename(old, new)
 }
+
+/// Mounts the filesystem `fs` at `path`.
+///
+/// The mount point directory is created in its parent filesystem if it does
+/// not exist.
+pub fn mount(fs: Arc<dyn VfsOps>, path: &str, options: MountOptions) -> io::Result<()> {
+    crate::root::mount(fs, path, options)
+}
+
+/// Unmounts the filesystem mounted at `path`.
+///
+/// Fails with [`ResourceBusy`](io::Error::ResourceBusy) if files or
+/// directories on it are still open, other filesystems are mounted on it, or
+/// the current directory is in it.
+pub fn umount(path: &str) -> io::Result<()> {
+    crate::root::umount(path)
+}
+
+/// Returns the mount table, sorted by the mount point paths.
+pub fn mounts() -> Vec<MountInfo> {
+    crate::root::mount_table()
+}
+
+/// Writes all dirty blocks in the block caches back to the disks.
+pub fn sync() -> io::Result<()> {
+    crate::cache::sync_all().map_err(|_| io::Error::Io)
+}
+
+/// Sets the maximum number of blocks in the block cache of each disk, 0
+/// disables them.
+///
+/// Dirty blocks are written back if some blocks need to be evicted.
+pub fn set_cache_capacity(blocks: usize) -> io::Result<()> {
+    crate::cache::set_capacity(blocks).map_err(|_| io::Error::Io)
+}
+
+/// Returns the statistics of the block caches, summed over all disks.
+pub fn cache_stats() -> CacheStats {
+    crate::cache::stats()
+}
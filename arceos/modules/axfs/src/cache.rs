@@ -0,0 +1,414 @@
+//! Write-back caches of disk blocks, one for each [`Disk`](crate::dev::Disk)
+//! shared by all filesystems on it.
+//!
+//! Blocks are evicted in LRU order, dirty blocks are written back when they
+//! are evicted or by [`sync`]. Sequential reads, e.g. by reading a file with
+//! `File::read`, miss at consecutive blocks, which is detected to read ahead
+//! more blocks in one request, doubling the window up to [`READAHEAD_MAX`].
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::{vec, vec::Vec};
+use axdriver::prelude::*;
+use axsync::Mutex;
+
+/// Size of a disk block in bytes.
+pub(crate) const BLOCK_SIZE: usize = 512;
+/// Default capacity of the cache, in blocks.
+const DEFAULT_CAPACITY: usize = 1024;
+/// Maximum number of blocks read ahead in one request.
+const READAHEAD_MAX: usize = 64;
+/// Maximum number of blocks written back in one request.
+const WRITEBACK_MAX: usize = 64;
+/// End of the LRU list.
+const NIL: usize = usize::MAX;
+
+/// The caches of all disks, to sync them and report their statistics.
+static CACHES: Mutex<Vec<Weak<Mutex<BlockCache>>>> = Mutex::new(Vec::new());
+
+/// Statistics of the block cache.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CacheStats {
+    /// Maximum number of cached blocks.
+    pub capacity: usize,
+    /// Number of cached blocks.
+    pub cached: usize,
+    /// Number of cached blocks not written back yet.
+    pub dirty: usize,
+    /// Number of block accesses found in the cache.
+    pub hits: u64,
+    /// Number of block accesses not found in the cache.
+    pub misses: u64,
+    /// Number of blocks read ahead of sequential reads.
+    pub readahead: u64,
+    /// Number of read requests sent to the device.
+    pub dev_reads: u64,
+    /// Number of write requests sent to the device.
+    pub dev_writes: u64,
+}
+
+struct Slot {
+    block_id: u64,
+    dirty: bool,
+    prev: usize,
+    next: usize,
+    data: [u8; BLOCK_SIZE],
+}
+
+pub(crate) struct BlockCache {
+    dev: AxBlockDevice,
+    capacity: usize,
+    /// Slots of cached blocks, indexed by block IDs.
+    index: BTreeMap<u64, usize>,
+    slots: Vec<Slot>,
+    free: Vec<usize>,
+    /// The most recently used slot.
+    head: usize,
+    /// The least recently used slot.
+    tail: usize,
+    /// The block after the last read from the device, a miss at it continues
+    /// a sequential read.
+    ra_next: u64,
+    /// Number of blocks read at the last miss.
+    ra_size: usize,
+    stats: CacheStats,
+}
+
+impl BlockCache {
+    fn new(dev: AxBlockDevice) -> Self {
+        Self {
+            dev,
+            capacity: DEFAULT_CAPACITY,
+            index: BTreeMap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            ra_next: u64::MAX,
+            ra_size: 0,
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = (self.slots[slot].prev, self.slots[slot].next);
+        match prev {
+            NIL => self.head = next,
+            _ => self.slots[prev].next = next,
+        }
+        match next {
+            NIL => self.tail = prev,
+            _ => self.slots[next].prev = prev,
+        }
+    }
+
+    fn push_front(&mut self, slot: usize) {
+        self.slots[slot].prev = NIL;
+        self.slots[slot].next = self.head;
+        match self.head {
+            NIL => self.tail = slot,
+            head => self.slots[head].prev = slot,
+        }
+        self.head = slot;
+    }
+
+    fn lookup(&mut self, block_id: u64) -> Option<usize> {
+        let slot = *self.index.get(&block_id)?;
+        if self.head != slot {
+            self.unlink(slot);
+            self.push_front(slot);
+        }
+        self.stats.hits += 1;
+        Some(slot)
+    }
+
+    fn write_back(&mut self, slot: usize) -> DevResult {
+        let slot = &mut self.slots[slot];
+        if slot.dirty {
+            self.dev.write_block(slot.block_id, &slot.data)?;
+            slot.dirty = false;
+            self.stats.dirty -= 1;
+            self.stats.dev_writes += 1;
+        }
+        Ok(())
+    }
+
+    /// Evicts the least recently used block.
+    fn evict(&mut self) -> DevResult {
+        let slot = self.tail;
+        self.write_back(slot)?;
+        self.unlink(slot);
+        self.index.remove(&self.slots[slot].block_id);
+        self.free.push(slot);
+        Ok(())
+    }
+
+    /// Caches `data` as the content of the block, returns its slot.
+    fn insert(&mut self, block_id: u64, data: &[u8]) -> DevResult<usize> {
+        if self.index.len() >= self.capacity {
+            self.evict()?;
+        }
+        let slot = match self.free.pop() {
+            Some(slot) => slot,
+            None => {
+                self.slots.push(Slot {
+                    block_id,
+                    dirty: false,
+                    prev: NIL,
+                    next: NIL,
+                    data: [0; BLOCK_SIZE],
+                });
+                self.slots.len() - 1
+            }
+        };
+        let s = &mut self.slots[slot];
+        s.block_id = block_id;
+        s.dirty = false;
+        s.data.copy_from_slice(data);
+        self.index.insert(block_id, slot);
+        self.push_front(slot);
+        Ok(slot)
+    }
+
+    /// Reads a missed block from the device, with the blocks following it if
+    /// the miss continues a sequential read. Returns the slot of the block.
+    fn load(&mut self, block_id: u64, readahead: bool) -> DevResult<usize> {
+        self.stats.misses += 1;
+        let mut count = 1;
+        if readahead {
+            self.ra_size = if block_id == self.ra_next {
+                (self.ra_size * 2).min(READAHEAD_MAX)
+            } else {
+                1
+            };
+            let remaining = self.dev.num_blocks().saturating_sub(block_id);
+            count = (self.ra_size as u64).min(remaining).max(1) as usize;
+            count = count.min(self.capacity);
+        }
+        // don't overwrite blocks already cached, they may be dirty
+        if let Some((&next_cached, _)) = self.index.range(block_id + 1..).next() {
+            count = count.min((next_cached - block_id) as usize);
+        }
+
+        let mut buf = vec![0; count * BLOCK_SIZE];
+        self.dev.read_block(block_id, &mut buf)?;
+        self.stats.dev_reads += 1;
+        self.stats.readahead += count as u64 - 1;
+        if readahead {
+            self.ra_next = block_id + count as u64;
+        }
+        // insert the requested block last, as the most recently used one
+        for (i, data) in buf.chunks_exact(BLOCK_SIZE).enumerate().skip(1).rev() {
+            self.insert(block_id + i as u64, data)?;
+        }
+        self.insert(block_id, &buf[..BLOCK_SIZE])
+    }
+
+    fn read(&mut self, block_id: u64, offset: usize, buf: &mut [u8]) -> DevResult {
+        if self.capacity == 0 {
+            let mut data = [0; BLOCK_SIZE];
+            self.stats.misses += 1;
+            self.stats.dev_reads += 1;
+            self.dev.read_block(block_id, &mut data)?;
+            buf.copy_from_slice(&data[offset..offset + buf.len()]);
+            return Ok(());
+        }
+        let slot = match self.lookup(block_id) {
+            Some(slot) => slot,
+            None => self.load(block_id, true)?,
+        };
+        buf.copy_from_slice(&self.slots[slot].data[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    fn write(&mut self, block_id: u64, offset: usize, buf: &[u8]) -> DevResult {
+        if self.capacity == 0 {
+            let mut data = [0; BLOCK_SIZE];
+            self.stats.misses += 1;
+            if buf.len() < BLOCK_SIZE {
+                self.stats.dev_reads += 1;
+                self.dev.read_block(block_id, &mut data)?;
+            }
+            data[offset..offset + buf.len()].copy_from_slice(buf);
+            self.stats.dev_writes += 1;
+            return self.dev.write_block(block_id, &data);
+        }
+        let slot = match self.lookup(block_id) {
+            Some(slot) => slot,
+            None if buf.len() == BLOCK_SIZE => {
+                self.stats.misses += 1;
+                self.insert(block_id, buf)?
+            }
+            None => self.load(block_id, false)?,
+        };
+        let slot = &mut self.slots[slot];
+        slot.data[offset..offset + buf.len()].copy_from_slice(buf);
+        if !slot.dirty {
+            slot.dirty = true;
+            self.stats.dirty += 1;
+        }
+        Ok(())
+    }
+
+    /// Writes all dirty blocks back, merging consecutive ones into one
+    /// request.
+    fn sync(&mut self) -> DevResult {
+        let mut buf = Vec::new();
+        let mut run: Vec<usize> = Vec::new();
+        let dirty: Vec<usize> = self
+            .index
+            .values()
+            .copied()
+            .filter(|&slot| self.slots[slot].dirty)
+            .collect();
+        for (i, &slot) in dirty.iter().enumerate() {
+            run.push(slot);
+            let next = dirty.get(i + 1).map(|&s| self.slots[s].block_id);
+            let block_id = self.slots[slot].block_id;
+            if next == Some(block_id + 1) && run.len() < WRITEBACK_MAX {
+                continue;
+            }
+            buf.clear();
+            for &slot in &run {
+                buf.extend_from_slice(&self.slots[slot].data);
+            }
+            let start = self.slots[run[0]].block_id;
+            self.dev.write_block(start, &buf)?;
+            self.stats.dev_writes += 1;
+            for &slot in &run {
+                self.slots[slot].dirty = false;
+            }
+            self.stats.dirty -= run.len();
+            run.clear();
+        }
+        self.dev.flush()
+    }
+
+    fn set_capacity(&mut self, capacity: usize) -> DevResult {
+        while self.index.len() > capacity {
+            self.evict()?;
+        }
+        if self.index.is_empty() {
+            self.slots = Vec::new();
+            self.free = Vec::new();
+        }
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            capacity: self.capacity,
+            cached: self.index.len(),
+            ..self.stats
+        }
+    }
+}
+
+impl Drop for BlockCache {
+    fn drop(&mut self) {
+        if let Err(e) = self.sync() {
+            warn!("failed to write back the block cache: {:?}", e);
+        }
+    }
+}
+
+/// Runs `f` on the locked cache, with its heap allocations attributed to the
+/// fs cache.
+fn with_cache<R>(cache: &Mutex<BlockCache>, f: impl FnOnce(&mut BlockCache) -> R) -> R {
+    let run = || f(&mut cache.lock());
+    #[cfg(feature = "alloc-tag")]
+    return axtask::with_alloc_tag("fs cache", run);
+    #[cfg(not(feature = "alloc-tag"))]
+    return run();
+}
+
+/// Returns the caches of the disks that are still in use.
+fn caches() -> Vec<Arc<Mutex<BlockCache>>> {
+    let mut caches = CACHES.lock();
+    caches.retain(|cache| cache.strong_count() > 0);
+    caches.iter().filter_map(Weak::upgrade).collect()
+}
+
+/// Creates the block cache of the disk device.
+pub(crate) fn new(dev: AxBlockDevice) -> Arc<Mutex<BlockCache>> {
+    assert_eq!(BLOCK_SIZE, dev.block_size());
+    let cache = Arc::new(Mutex::new(BlockCache::new(dev)));
+    let mut caches = CACHES.lock();
+    caches.retain(|cache| cache.strong_count() > 0);
+    caches.push(Arc::downgrade(&cache));
+    cache
+}
+
+/// Returns the number of blocks of the disk.
+pub(crate) fn num_blocks(cache: &Mutex<BlockCache>) -> u64 {
+    cache.lock().dev.num_blocks()
+}
+
+/// Reads `buf.len()` bytes at `offset` within the block.
+pub(crate) fn read(
+    cache: &Mutex<BlockCache>,
+    block_id: u64,
+    offset: usize,
+    buf: &mut [u8],
+) -> DevResult {
+    with_cache(cache, |cache| cache.read(block_id, offset, buf))
+}
+
+/// Writes `buf` at `offset` within the block.
+pub(crate) fn write(
+    cache: &Mutex<BlockCache>,
+    block_id: u64,
+    offset: usize,
+    buf: &[u8],
+) -> DevResult {
+    with_cache(cache, |cache| cache.write(block_id, offset, buf))
+}
+
+/// Writes all dirty blocks of the disk back.
+pub(crate) fn sync(cache: &Mutex<BlockCache>) -> DevResult {
+    with_cache(cache, |cache| cache.sync())
+}
+
+/// Writes all dirty blocks back to the disks.
+pub(crate) fn sync_all() -> DevResult {
+    caches().iter().try_for_each(|cache| sync(cache))
+}
+
+/// Writes all dirty blocks back to the disks, without blocking.
+///
+/// Returns `None` if a cache is in use, e.g., on a panic while accessing it.
+pub(crate) fn try_sync_all() -> Option<DevResult> {
+    let caches = CACHES.try_lock()?;
+    for cache in caches.iter().filter_map(Weak::upgrade) {
+        if let Err(e) = cache.try_lock()?.sync() {
+            return Some(Err(e));
+        }
+    }
+    Some(Ok(()))
+}
+
+/// Sets the maximum number of cached blocks of each disk, evicting blocks if
+/// there are more. The caches are disabled if `capacity` is 0.
+pub(crate) fn set_capacity(capacity: usize) -> DevResult {
+    caches()
+        .iter()
+        .try_for_each(|cache| with_cache(cache, |cache| cache.set_capacity(capacity)))
+}
+
+/// Returns the statistics of the block caches, summed over all disks.
+pub(crate) fn stats() -> CacheStats {
+    caches().iter().fold(CacheStats::default(), |sum, cache| {
+        let stats = cache.lock().stats();
+        CacheStats {
+            capacity: sum.capacity + stats.capacity,
+            cached: sum.cached + stats.cached,
+            dirty: sum.dirty + stats.dirty,
+            hits: sum.hits + stats.hits,
+            misses: sum.misses + stats.misses,
+            readahead: sum.readahead + stats.readahead,
+            dev_reads: sum.dev_reads + stats.dev_reads,
+            dev_writes: sum.dev_writes + stats.dev_writes,
+        }
+    })
+}
@@ -1,28 +1,32 @@
+use alloc::sync::Arc;
 use axdriver::prelude::*;
+use axsync::Mutex;
 
-const BLOCK_SIZE: usize = 512;
+use crate::cache::{self, BlockCache, BLOCK_SIZE};
 
 /// A disk device with a cursor.
+///
+/// All accesses go through the block cache of the device, call
+/// [`Disk::flush`] to write cached blocks back to it.
 pub struct Disk {
     block_id: u64,
     offset: usize,
-    dev: AxBlockDevice,
+    cache: Arc<Mutex<BlockCache>>,
 }
 
 impl Disk {
-    /// Create a new disk.
+    /// Create a new disk, with a block cache of its own.
     pub fn new(dev: AxBlockDevice) -> Self {
-        assert_eq!(BLOCK_SIZE, dev.block_size());
         Self {
             block_id: 0,
             offset: 0,
-            dev,
+            cache: cache::new(dev),
         }
     }
 
     /// Get the size of the disk.
     pub fn size(&self) -> u64 {
-        self.dev.num_blocks() * BLOCK_SIZE as u64
+        cache::num_blocks(&self.cache) * BLOCK_SIZE as u64
     }
 
     /// Get the position of the cursor.
@@ -38,55 +42,30 @@ impl Disk {
 
     /// Read within one block, returns the number of bytes read.
     pub fn read_one(&mut self, buf: &mut [u8]) -> DevResult<usize> {
-        let read_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
-            // whole block
-            self.dev
-                .read_block(self.block_id, &mut buf[0..BLOCK_SIZE])?;
-            self.block_id += 1;
-            BLOCK_SIZE
-        } else {
-            // partial block
-            let mut data = [0u8; BLOCK_SIZE];
-            let start = self.offset;
-            let count = buf.len().min(BLOCK_SIZE - self.offset);
-
-            self.dev.read_block(self.block_id, &mut data)?;
-            buf[..count].copy_from_slice(&data[start..start + count]);
-
-            self.offset += count;
-            if self.offset >= BLOCK_SIZE {
-                self.block_id += 1;
-                self.offset -= BLOCK_SIZE;
-            }
-            count
-        };
-        Ok(read_size)
+        let count = buf.len().min(BLOCK_SIZE - self.offset);
+        cache::read(&self.cache, self.block_id, self.offset, &mut buf[..count])?;
+        self.advance(count);
+        Ok(count)
     }
 
     /// Write within one block, returns the number of bytes written.
     pub fn write_one(&mut self, buf: &[u8]) -> DevResult<usize> {
-        let write_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
-            // whole block
-            self.dev.write_block(self.block_id, &buf[0..BLOCK_SIZE])?;
-            self.block_id += 1;
-            BLOCK_SIZE
-        } else {
-            // partial block
-            let mut data = [0u8; BLOCK_SIZE];
-            let start = self.offset;
-            let count = buf.len().min(BLOCK_SIZE - self.offset);
+        let count = buf.len().min(BLOCK_SIZE - self.offset);
+        cache::write(&self.cache, self.block_id, self.offset, &buf[..count])?;
+        self.advance(count);
+        Ok(count)
+    }
 
-            self.dev.read_block(self.block_id, &mut data)?;
-            data[start..start + count].copy_from_slice(&buf[..count]);
-            self.dev.write_block(self.block_id, &data)?;
+    /// Write all cached blocks back to the device.
+    pub fn flush(&mut self) -> DevResult {
+        cache::sync(&self.cache)
+    }
 
-            self.offset += count;
-            if self.offset >= BLOCK_SIZE {
-                self.block_id += 1;
-                self.offset -= BLOCK_SIZE;
-            }
-            count
-        };
-        Ok(write_size)
+    fn advance(&mut self, count: usize) {
+        self.offset += count;
+        if self.offset >= BLOCK_SIZE {
+            self.block_id += 1;
+            self.offset -= BLOCK_SIZE;
+        }
     }
 }
@@ -1,11 +1,14 @@
 //! Low-level filesystem operations.
 
+use alloc::{format, string::String};
 use axerrno::{ax_err, ax_err_type, AxError, AxResult};
 use axfs_vfs::{VfsError, VfsNodeRef};
 use axio::SeekFrom;
 use cap_access::{Cap, WithCap};
 use core::fmt;
 
+use crate::root::MountRef;
+
 #[cfg(feature = "myfs")]
 pub use crate::dev::Disk;
 #[cfg(feature = "myfs")]
@@ -25,6 +28,7 @@ pub struct File {
     node: WithCap<VfsNodeRef>,
     is_append: bool,
     offset: u64,
+    _mount: MountRef,
 }
 
 /// An opened directory object, with open permissions and a cursor for
@@ -32,6 +36,8 @@ pub struct File {
 pub struct Directory {
     node: WithCap<VfsNodeRef>,
     entry_idx: usize,
+    path: String,
+    _mount: MountRef,
 }
 
 /// Options and flags which can be used to configure how a file is opened.
@@ -116,7 +122,12 @@ impl File {
         self.node.access_or_err(cap, AxError::PermissionDenied)
     }
 
-    fn _open_at(dir: Option<&VfsNodeRef>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
+    fn _open_at(
+        dir: Option<&VfsNodeRef>,
+        mount: MountRef,
+        path: &str,
+        opts: &OpenOptions,
+    ) -> AxResult<Self> {
         debug!("open file: {} {:?}", path, opts);
         if !opts.is_valid() {
             return ax_err!(InvalidInput);
@@ -160,13 +171,14 @@ impl File {
             node: WithCap::new(node, access_cap),
             is_append: opts.append,
             offset: 0,
+            _mount: mount,
         })
     }
 
     /// Opens a file at the path relative to the current directory. Returns a
     /// [`File`] object.
     pub fn open(path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_at(None, path, opts)
+        Self::_open_at(None, crate::root::mount_ref(path)?, path, opts)
     }
 
     /// Truncates the file to the specified size.
@@ -223,9 +235,11 @@ impl File {
     }
 
     /// Flushes the file, writes all buffered data to the underlying device.
+    ///
+    /// Dirty blocks of other files in the block cache are also written back.
     pub fn flush(&self) -> AxResult {
         self.access_node(Cap::WRITE)?.fsync()?;
-        Ok(())
+        crate::cache::sync_all().map_err(|_| AxError::Io)
     }
 
     /// Sets the cursor of the file to the specified offset. Returns the new
@@ -246,6 +260,16 @@ impl File {
     pub fn get_attr(&self) -> AxResult<FileAttr> {
         self.access_node(Cap::empty())?.get_attr()
     }
+
+    /// Returns `true` if the file is opened for reading.
+    pub fn is_readable(&self) -> bool {
+        self.access_node(Cap::READ).is_ok()
+    }
+
+    /// Returns `true` if the file is opened for writing.
+    pub fn is_writable(&self) -> bool {
+        self.access_node(Cap::WRITE).is_ok()
+    }
 }
 
 impl Directory {
@@ -253,7 +277,12 @@ impl Directory {
         self.node.access_or_err(cap, AxError::PermissionDenied)
     }
 
-    fn _open_dir_at(dir: Option<&VfsNodeRef>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
+    fn _open_dir_at(
+        dir: Option<&VfsNodeRef>,
+        path: &str,
+        abs_path: String,
+        opts: &OpenOptions,
+    ) -> AxResult<Self> {
         debug!("open dir: {}", path);
         if !opts.read {
             return ax_err!(InvalidInput);
@@ -261,6 +290,7 @@ impl Directory {
         if opts.create || opts.create_new || opts.write || opts.append || opts.truncate {
             return ax_err!(InvalidInput);
         }
+        let mount = crate::root::mount_ref(&abs_path)?;
 
         let node = crate::root::lookup(dir, path)?;
         let attr = node.get_attr()?;
@@ -276,6 +306,8 @@ impl Directory {
         Ok(Self {
             node: WithCap::new(node, access_cap),
             entry_idx: 0,
+            path: abs_path,
+            _mount: mount,
         })
     }
 
@@ -287,22 +319,39 @@ impl Directory {
         }
     }
 
+    /// Returns the absolute path of the path relative to this directory.
+    fn absolute_path_at(&self, path: &str) -> String {
+        if path.starts_with('/') {
+            axfs_vfs::path::canonicalize(path)
+        } else {
+            axfs_vfs::path::canonicalize(&format!("{}/{}", self.path, path))
+        }
+    }
+
+    /// Returns the mount point of the path relative to this directory. It is
+    /// resolved from the absolute path, since the path may cross mount points
+    /// (e.g., relative to the root directory) or leave them with `..`.
+    fn mount_at(&self, path: &str) -> AxResult<MountRef> {
+        crate::root::mount_ref(&self.absolute_path_at(path))
+    }
+
     /// Opens a directory at the path relative to the current directory.
     /// Returns a [`Directory`] object.
     pub fn open_dir(path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_dir_at(None, path, opts)
+        Self::_open_dir_at(None, path, crate::root::absolute_path(path)?, opts)
     }
 
     /// Opens a directory at the path relative to this directory. Returns a
     /// [`Directory`] object.
     pub fn open_dir_at(&self, path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_dir_at(self.access_at(path)?, path, opts)
+        let abs_path = self.absolute_path_at(path);
+        Self::_open_dir_at(self.access_at(path)?, path, abs_path, opts)
     }
 
     /// Opens a file at the path relative to this directory. Returns a [`File`]
     /// object.
     pub fn open_file_at(&self, path: &str, opts: &OpenOptions) -> AxResult<File> {
-        File::_open_at(self.access_at(path)?, path, opts)
+        File::_open_at(self.access_at(path)?, self.mount_at(path)?, path, opts)
     }
 
     /// Creates an empty file at the path relative to this directory.
@@ -0,0 +1,285 @@
+//! Block and inode allocation with the bitmaps of block groups.
+
+use alloc::{vec, vec::Vec};
+use axfs_vfs::{VfsError, VfsResult};
+
+use super::layout::*;
+use super::volume::Volume;
+
+fn test_bit(bitmap: &[u8], bit: usize) -> bool {
+    bitmap[bit / 8] & (1 << (bit % 8)) != 0
+}
+
+fn set_bit(bitmap: &mut [u8], bit: usize) {
+    bitmap[bit / 8] |= 1 << (bit % 8);
+}
+
+fn clear_bit(bitmap: &mut [u8], bit: usize) {
+    bitmap[bit / 8] &= !(1 << (bit % 8));
+}
+
+/// Finds a zero bit in `[start, end)`, then in `[0, start)`.
+fn find_zero_bit(bitmap: &[u8], start: usize, end: usize) -> Option<usize> {
+    (start..end)
+        .chain(0..start.min(end))
+        .find(|&bit| !test_bit(bitmap, bit))
+}
+
+impl Volume {
+    fn group_first_block(&self, group: u32) -> u64 {
+        self.sb.first_data_block() as u64 + group as u64 * self.sb.blocks_per_group() as u64
+    }
+
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let first = self.group_first_block(group);
+        (self.sb.blocks_count() - first).min(self.sb.blocks_per_group() as u64) as u32
+    }
+
+    fn group_of_block(&self, blk: u64) -> u32 {
+        ((blk - self.sb.first_data_block() as u64) / self.sb.blocks_per_group() as u64) as u32
+    }
+
+    /// Whether the group has a backup of the superblock and the descriptors.
+    fn group_has_super(&self, group: u32) -> bool {
+        fn is_power_of(mut n: u32, base: u32) -> bool {
+            while n > 1 && n.is_multiple_of(base) {
+                n /= base;
+            }
+            n == 1
+        }
+        !self.sb.has_ro_compat(RO_COMPAT_SPARSE_SUPER)
+            || group <= 1
+            || is_power_of(group, 3)
+            || is_power_of(group, 5)
+            || is_power_of(group, 7)
+    }
+
+    fn has_group_csum(&self) -> bool {
+        self.sb
+            .has_ro_compat(RO_COMPAT_METADATA_CSUM | RO_COMPAT_GDT_CSUM)
+    }
+
+    /// Builds the block bitmap of a group not initialized yet, in which only
+    /// the filesystem metadata is in use.
+    fn init_block_bitmap(&self, group: u32) -> Vec<u8> {
+        let mut bitmap = vec![0; self.block_size];
+        let first = self.group_first_block(group);
+        let count = self.blocks_in_group(group);
+        let mut mark = |blk: u64| {
+            if blk >= first && blk < first + count as u64 {
+                set_bit(&mut bitmap, (blk - first) as usize);
+            }
+        };
+        if self.group_has_super(group) {
+            let gdt_blocks =
+                (self.group_count as usize * self.sb.desc_size()).div_ceil(self.block_size) as u64;
+            let meta_blocks = 1 + gdt_blocks + self.sb.reserved_gdt_blocks() as u64;
+            (first..first + meta_blocks).for_each(&mut mark);
+        }
+        let itable_blocks = (self.sb.inodes_per_group() as usize * self.sb.inode_size())
+            .div_ceil(self.block_size) as u64;
+        for desc in &self.groups {
+            mark(desc.block_bitmap());
+            mark(desc.inode_bitmap());
+            (desc.inode_table()..desc.inode_table() + itable_blocks).for_each(&mut mark);
+        }
+        for bit in count as usize..self.block_size * 8 {
+            set_bit(&mut bitmap, bit);
+        }
+        bitmap
+    }
+
+    fn load_block_bitmap(&mut self, group: u32) -> VfsResult<Vec<u8>> {
+        let desc = &self.groups[group as usize];
+        if self.has_group_csum() && desc.flags() & BG_BLOCK_UNINIT != 0 {
+            Ok(self.init_block_bitmap(group))
+        } else {
+            self.read_block(desc.block_bitmap())
+        }
+    }
+
+    fn store_block_bitmap(&mut self, group: u32, bitmap: &[u8]) -> VfsResult {
+        let blk = self.groups[group as usize].block_bitmap();
+        self.write_block(blk, bitmap)?;
+        let len = self.sb.blocks_per_group() as usize / 8;
+        let csum = crc32c(self.csum_seed, &bitmap[..len]);
+        let desc = &mut self.groups[group as usize];
+        if self.sb.has_ro_compat(RO_COMPAT_METADATA_CSUM) {
+            desc.set_block_bitmap_csum(csum);
+        }
+        desc.set_flags(desc.flags() & !BG_BLOCK_UNINIT);
+        Ok(())
+    }
+
+    fn store_inode_bitmap(&mut self, group: u32, bitmap: &[u8]) -> VfsResult {
+        let blk = self.groups[group as usize].inode_bitmap();
+        self.write_block(blk, bitmap)?;
+        let len = self.sb.inodes_per_group() as usize / 8;
+        let csum = crc32c(self.csum_seed, &bitmap[..len]);
+        let desc = &mut self.groups[group as usize];
+        if self.sb.has_ro_compat(RO_COMPAT_METADATA_CSUM) {
+            desc.set_inode_bitmap_csum(csum);
+        }
+        desc.set_flags(desc.flags() & !BG_INODE_UNINIT);
+        Ok(())
+    }
+
+    /// Allocates a block, preferably `goal` or the first free one after it.
+    pub fn alloc_block(&mut self, goal: u64) -> VfsResult<u64> {
+        if self.sb.free_blocks_count() == 0 {
+            return Err(VfsError::StorageFull);
+        }
+        let goal = goal.clamp(
+            self.sb.first_data_block() as u64,
+            self.sb.blocks_count() - 1,
+        );
+        let goal_group = self.group_of_block(goal);
+        for i in 0..self.group_count {
+            let group = (goal_group + i) % self.group_count;
+            if self.groups[group as usize].free_blocks_count() == 0 {
+                continue;
+            }
+            let mut bitmap = self.load_block_bitmap(group)?;
+            let first = self.group_first_block(group);
+            let start = if group == goal_group {
+                (goal - first) as usize
+            } else {
+                0
+            };
+            let count = self.blocks_in_group(group) as usize;
+            let Some(bit) = find_zero_bit(&bitmap, start, count) else {
+                continue;
+            };
+            set_bit(&mut bitmap, bit);
+            self.store_block_bitmap(group, &bitmap)?;
+            let desc = &mut self.groups[group as usize];
+            desc.set_free_blocks_count(desc.free_blocks_count() - 1);
+            self.write_group(group)?;
+            self.sb
+                .set_free_blocks_count(self.sb.free_blocks_count() - 1);
+            self.mark_super_dirty();
+            return Ok(first + bit as u64);
+        }
+        Err(VfsError::StorageFull)
+    }
+
+    /// Frees `count` contiguous blocks starting from `start`.
+    pub fn free_blocks(&mut self, mut start: u64, mut count: u64) -> VfsResult {
+        while count > 0 {
+            let group = self.group_of_block(start);
+            let first = self.group_first_block(group);
+            let end = (start + count).min(first + self.blocks_in_group(group) as u64);
+            let mut bitmap = self.load_block_bitmap(group)?;
+            let mut freed = 0;
+            for blk in start..end {
+                let bit = (blk - first) as usize;
+                if test_bit(&bitmap, bit) {
+                    clear_bit(&mut bitmap, bit);
+                    freed += 1;
+                } else {
+                    warn!("ext4: freeing free block {}", blk);
+                }
+            }
+            self.store_block_bitmap(group, &bitmap)?;
+            let desc = &mut self.groups[group as usize];
+            desc.set_free_blocks_count(desc.free_blocks_count() + freed);
+            self.write_group(group)?;
+            self.sb
+                .set_free_blocks_count(self.sb.free_blocks_count() + freed as u64);
+            self.mark_super_dirty();
+            count -= end - start;
+            start = end;
+        }
+        Ok(())
+    }
+
+    /// Allocates an inode, preferably in group `goal_group`.
+    pub fn alloc_inode(&mut self, goal_group: u32, is_dir: bool) -> VfsResult<u32> {
+        if self.sb.free_inodes_count() == 0 {
+            return Err(VfsError::StorageFull);
+        }
+        let ipg = self.sb.inodes_per_group();
+        for i in 0..self.group_count {
+            let group = (goal_group + i) % self.group_count;
+            let desc = &self.groups[group as usize];
+            if desc.free_inodes_count() == 0 {
+                continue;
+            }
+            let mut bitmap = if self.has_group_csum() && desc.flags() & BG_INODE_UNINIT != 0 {
+                let mut bitmap = vec![0; self.block_size];
+                for bit in ipg as usize..self.block_size * 8 {
+                    set_bit(&mut bitmap, bit);
+                }
+                bitmap
+            } else {
+                self.read_block(desc.inode_bitmap())?
+            };
+            // inodes before `first_ino` are reserved
+            let start = if group == 0 {
+                self.sb.first_ino() - 1
+            } else {
+                0
+            };
+            let Some(index) = (start as usize..ipg as usize).find(|&i| !test_bit(&bitmap, i))
+            else {
+                continue;
+            };
+            set_bit(&mut bitmap, index);
+            self.store_inode_bitmap(group, &bitmap)?;
+            if self.groups[group as usize].flags() & BG_BLOCK_UNINIT != 0 {
+                let bitmap = self.load_block_bitmap(group)?;
+                self.store_block_bitmap(group, &bitmap)?;
+            }
+
+            let has_group_csum = self.has_group_csum();
+            let desc = &mut self.groups[group as usize];
+            desc.set_free_inodes_count(desc.free_inodes_count() - 1);
+            if is_dir {
+                desc.set_used_dirs_count(desc.used_dirs_count() + 1);
+            }
+            if has_group_csum && index as u32 >= ipg - desc.itable_unused() {
+                desc.set_itable_unused(ipg - index as u32 - 1);
+            }
+            self.write_group(group)?;
+            self.sb
+                .set_free_inodes_count(self.sb.free_inodes_count() - 1);
+            self.mark_super_dirty();
+            return Ok(group * ipg + index as u32 + 1);
+        }
+        Err(VfsError::StorageFull)
+    }
+
+    pub fn free_inode(&mut self, ino: u32, is_dir: bool) -> VfsResult {
+        let ipg = self.sb.inodes_per_group();
+        let group = (ino - 1) / ipg;
+        let index = ((ino - 1) % ipg) as usize;
+        let mut bitmap = self.read_block(self.groups[group as usize].inode_bitmap())?;
+        if !test_bit(&bitmap, index) {
+            warn!("ext4: freeing free inode {}", ino);
+            return Ok(());
+        }
+        clear_bit(&mut bitmap, index);
+        self.store_inode_bitmap(group, &bitmap)?;
+        let desc = &mut self.groups[group as usize];
+        desc.set_free_inodes_count(desc.free_inodes_count() + 1);
+        if is_dir {
+            desc.set_used_dirs_count(desc.used_dirs_count().saturating_sub(1));
+        }
+        self.write_group(group)?;
+        self.sb
+            .set_free_inodes_count(self.sb.free_inodes_count() + 1);
+        self.mark_super_dirty();
+        Ok(())
+    }
+
+    /// Returns the group of an inode, used as the allocation goal of its
+    /// blocks and children.
+    pub fn inode_group(&self, ino: u32) -> u32 {
+        (ino - 1) / self.sb.inodes_per_group()
+    }
+
+    /// Returns the first block of a group, used as an allocation goal.
+    pub fn group_goal(&self, group: u32) -> u64 {
+        self.group_first_block(group)
+    }
+}
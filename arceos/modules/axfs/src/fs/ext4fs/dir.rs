@@ -0,0 +1,478 @@
+//! Directories: entries in linear blocks, and operations on the namespace.
+//!
+//! Hashed (`dir_index`) directories are read as linear ones, their index
+//! blocks look like empty entries. Before adding an entry to such a
+//! directory, the index is dropped and the directory becomes linear.
+
+use alloc::{string::String, vec, vec::Vec};
+use axfs_vfs::{VfsError, VfsNodeType, VfsResult};
+
+use super::layout::*;
+use super::volume::Volume;
+
+/// The maximum length of a file name.
+const NAME_LEN_MAX: usize = 255;
+/// Directories have at most this number of links with `dir_nlink`, more
+/// subdirectories set the links to 1.
+const LINK_MAX: u16 = 65000;
+
+/// A found directory entry.
+struct EntryPos {
+    lblk: u32,
+    pblk: u64,
+    offset: usize,
+    prev_offset: Option<usize>,
+    inode: u32,
+    file_type: u8,
+}
+
+impl Volume {
+    fn dirent_tail_size(&self) -> usize {
+        if self.has_metadata_csum() {
+            DIRENT_TAIL_SIZE
+        } else {
+            0
+        }
+    }
+
+    /// The file type in directory entries, if the filesystem stores it.
+    fn dirent_type(&self, mode: u16) -> u8 {
+        if self.sb.has_incompat(INCOMPAT_FILETYPE) {
+            mode_to_dirent_type(mode)
+        } else {
+            0
+        }
+    }
+
+    fn write_dir_block(&mut self, ino: u32, dir: &Inode, pblk: u64, block: &mut [u8]) -> VfsResult {
+        if self.has_metadata_csum() {
+            write_dirent_tail(block, self.inode_seed(ino, dir));
+        }
+        self.write_block(pblk, block)
+    }
+
+    /// Calls `f` with each entry in the directory, until it returns `true`.
+    fn for_each_entry(
+        &mut self,
+        dir: &Inode,
+        mut f: impl FnMut(&DirEntry, &EntryPos) -> bool,
+    ) -> VfsResult<Option<EntryPos>> {
+        if !dir.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        let blocks = dir.size().div_ceil(self.block_size as u64) as u32;
+        for lblk in 0..blocks {
+            let Some(pblk) = self.map_block(dir, lblk)? else {
+                continue;
+            };
+            let block = self.read_block(pblk)?;
+            let mut offset = 0;
+            let mut prev_offset = None;
+            while offset < self.block_size {
+                let entry = DirEntry::parse(&block, offset).ok_or_else(|| {
+                    warn!("ext4: corrupted directory entry in block {}", pblk);
+                    VfsError::InvalidData
+                })?;
+                if entry.inode != 0 {
+                    let pos = EntryPos {
+                        lblk,
+                        pblk,
+                        offset,
+                        prev_offset,
+                        inode: entry.inode,
+                        file_type: entry.file_type,
+                    };
+                    if f(&entry, &pos) {
+                        return Ok(Some(pos));
+                    }
+                }
+                prev_offset = Some(offset);
+                offset += entry.rec_len;
+            }
+        }
+        Ok(None)
+    }
+
+    fn find_entry(&mut self, dir: &Inode, name: &[u8]) -> VfsResult<Option<EntryPos>> {
+        self.for_each_entry(dir, |entry, _| entry.name == name)
+    }
+
+    /// Converts the file type in a directory entry to the node type, reading
+    /// the inode if the filesystem doesn't store types in entries.
+    fn entry_type(&mut self, ino: u32, file_type: u8) -> VfsResult<VfsNodeType> {
+        let mode = if self.sb.has_incompat(INCOMPAT_FILETYPE) && file_type != 0 {
+            match file_type {
+                1 => S_IFREG,
+                2 => S_IFDIR,
+                3 => S_IFCHR,
+                4 => S_IFBLK,
+                5 => S_IFIFO,
+                6 => S_IFSOCK,
+                7 => S_IFLNK,
+                _ => 0,
+            }
+        } else {
+            self.read_inode(ino)?.file_type()
+        };
+        Ok(mode_to_node_type(mode))
+    }
+
+    /// Looks up an entry in the directory, returns its inode number.
+    pub fn lookup(&mut self, dir_ino: u32, name: &str) -> VfsResult<u32> {
+        let dir = self.read_inode(dir_ino)?;
+        match self.find_entry(&dir, name.as_bytes())? {
+            Some(pos) => Ok(pos.inode),
+            None => Err(VfsError::NotFound),
+        }
+    }
+
+    /// Returns the entries in the directory, including `.` and `..`.
+    pub fn read_dir(&mut self, dir_ino: u32) -> VfsResult<Vec<(String, VfsNodeType)>> {
+        let dir = self.read_inode(dir_ino)?;
+        let mut entries = Vec::new();
+        self.for_each_entry(&dir, |entry, pos| {
+            let name = String::from_utf8_lossy(entry.name).into_owned();
+            entries.push((name, pos.inode, pos.file_type));
+            false
+        })?;
+        entries
+            .into_iter()
+            .map(|(name, ino, ty)| Ok((name, self.entry_type(ino, ty)?)))
+            .collect()
+    }
+
+    /// Drops the hash index of a directory, rewriting the index blocks as
+    /// empty linear blocks.
+    fn drop_dir_index(&mut self, ino: u32, dir: &mut Inode) -> VfsResult {
+        let bs = self.block_size;
+        let root_pblk = self.map_block(dir, 0)?.ok_or(VfsError::InvalidData)?;
+        let root = self.read_block(root_pblk)?;
+        // the root holds `.` (12 bytes), `..`, and the root info at 24
+        let info_len = root[29] as usize;
+        let levels = root[30];
+        let mut nodes = Vec::new();
+        let mut frontier = vec![(root, 24 + info_len)];
+        for _ in 0..levels {
+            let mut next = Vec::new();
+            for (node, countlimit) in &frontier {
+                let count = le16(node, countlimit + 2) as usize;
+                for i in 0..count {
+                    let lblk = le32(node, countlimit + i * 8 + 4) & 0x0fff_ffff;
+                    let pblk = self.map_block(dir, lblk)?.ok_or(VfsError::InvalidData)?;
+                    nodes.push(pblk);
+                    // an index node is a fake empty entry, and then the entries
+                    next.push((self.read_block(pblk)?, 8));
+                }
+            }
+            frontier = next;
+        }
+
+        let tail = self.dirent_tail_size();
+        let mut block = vec![0; bs];
+        for pblk in nodes {
+            write_dirent(&mut block, 0, 0, bs - tail, 0, &[]);
+            self.write_dir_block(ino, dir, pblk, &mut block)?;
+        }
+        let mut root = self.read_block(root_pblk)?;
+        let dot_len = DirEntry::parse(&root, 0)
+            .ok_or(VfsError::InvalidData)?
+            .rec_len;
+        let parent = le32(&root, dot_len);
+        let parent_type = root[dot_len + 7];
+        root[dot_len..].fill(0);
+        write_dirent(
+            &mut root,
+            dot_len,
+            parent,
+            bs - dot_len - tail,
+            parent_type,
+            b"..",
+        );
+        self.write_dir_block(ino, dir, root_pblk, &mut root)?;
+        dir.set_flags(dir.flags() & !INODE_FLAG_INDEX);
+        Ok(())
+    }
+
+    /// Adds an entry to the directory, which is not written back.
+    fn add_entry(
+        &mut self,
+        dir_ino: u32,
+        dir: &mut Inode,
+        name: &[u8],
+        ino: u32,
+        file_type: u8,
+    ) -> VfsResult {
+        if dir.has_flag(INODE_FLAG_INDEX) {
+            self.drop_dir_index(dir_ino, dir)?;
+        }
+        let bs = self.block_size;
+        let tail = self.dirent_tail_size();
+        let need = dirent_size(name.len());
+        let blocks = dir.size().div_ceil(bs as u64) as u32;
+        for lblk in 0..blocks {
+            let Some(pblk) = self.map_block(dir, lblk)? else {
+                continue;
+            };
+            let mut block = self.read_block(pblk)?;
+            let mut offset = 0;
+            while offset < bs - tail {
+                let entry = DirEntry::parse(&block, offset).ok_or(VfsError::InvalidData)?;
+                let rec_len = entry.rec_len;
+                let used = if entry.inode == 0 {
+                    0
+                } else {
+                    dirent_size(entry.name.len())
+                };
+                if rec_len >= used + need && !entry.is_tail() {
+                    if used > 0 {
+                        put_le16(&mut block, offset + 4, used as u16);
+                    }
+                    write_dirent(
+                        &mut block,
+                        offset + used,
+                        ino,
+                        rec_len - used,
+                        file_type,
+                        name,
+                    );
+                    return self.write_dir_block(dir_ino, dir, pblk, &mut block);
+                }
+                offset += rec_len;
+            }
+        }
+
+        // no space, append a new block
+        let mut block = vec![0; bs];
+        write_dirent(&mut block, 0, ino, bs - tail, file_type, name);
+        let pblk = self.append_dir_block(dir_ino, dir)?;
+        self.write_dir_block(dir_ino, dir, pblk, &mut block)
+    }
+
+    /// Allocates a new block at the end of the directory.
+    fn append_dir_block(&mut self, dir_ino: u32, dir: &mut Inode) -> VfsResult<u64> {
+        let size = dir.size();
+        // allocate by writing a placeholder, then the caller fills the block
+        self.write_inode(dir_ino, dir)?;
+        self.write_file(dir_ino, size, &vec![0; self.block_size])?;
+        *dir = self.read_inode(dir_ino)?;
+        self.map_block(dir, (size / self.block_size as u64) as u32)?
+            .ok_or(VfsError::Io)
+    }
+
+    fn remove_entry(&mut self, dir_ino: u32, dir: &Inode, pos: &EntryPos) -> VfsResult {
+        let mut block = self.read_block(pos.pblk)?;
+        match pos.prev_offset {
+            Some(prev) => {
+                let prev_len = DirEntry::parse(&block, prev)
+                    .ok_or(VfsError::InvalidData)?
+                    .rec_len;
+                let len = DirEntry::parse(&block, pos.offset)
+                    .ok_or(VfsError::InvalidData)?
+                    .rec_len;
+                put_le16(&mut block, prev + 4, (prev_len + len) as u16);
+            }
+            None => put_le32(&mut block, pos.offset, 0),
+        }
+        self.write_dir_block(dir_ino, dir, pos.pblk, &mut block)
+    }
+
+    fn is_dir_empty(&mut self, dir: &Inode) -> VfsResult<bool> {
+        let found =
+            self.for_each_entry(dir, |entry, _| entry.name != b"." && entry.name != b"..")?;
+        Ok(found.is_none())
+    }
+
+    fn inc_links(&self, dir: &mut Inode) {
+        let links = dir.links_count();
+        if links >= LINK_MAX - 1 && self.sb.has_ro_compat(RO_COMPAT_DIR_NLINK) {
+            dir.set_links_count(1);
+        } else if links > 1 || !self.sb.has_ro_compat(RO_COMPAT_DIR_NLINK) {
+            dir.set_links_count(links + 1);
+        }
+    }
+
+    fn dec_links(&self, dir: &mut Inode) {
+        let links = dir.links_count();
+        if links > 2 {
+            dir.set_links_count(links - 1);
+        }
+    }
+
+    /// Creates a file or directory in the directory.
+    pub fn create(&mut self, dir_ino: u32, name: &str, ty: VfsNodeType) -> VfsResult {
+        self.check_writable()?;
+        check_name(name)?;
+        let mut dir = self.read_inode(dir_ino)?;
+        if self.find_entry(&dir, name.as_bytes())?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+        let (mode, is_dir) = match ty {
+            VfsNodeType::File => (S_IFREG | 0o644, false),
+            VfsNodeType::Dir => (S_IFDIR | 0o755, true),
+            _ => return Err(VfsError::Unsupported),
+        };
+
+        let ino = self.alloc_inode(self.inode_group(dir_ino), is_dir)?;
+        let mut inode = Inode::new(self.sb.inode_size());
+        inode.set_mode(mode);
+        inode.set_links_count(if is_dir { 2 } else { 1 });
+        if self.sb.inode_size() > GOOD_OLD_INODE_SIZE {
+            let extra = match self.sb.want_extra_isize() {
+                0 => 32,
+                size => size,
+            };
+            inode.set_extra_isize(extra);
+        }
+        self.init_block_map(&mut inode);
+        self.write_inode(ino, &mut inode)?;
+
+        if is_dir {
+            let bs = self.block_size;
+            let tail = self.dirent_tail_size();
+            let pblk = self.append_dir_block(ino, &mut inode)?;
+            let dir_type = self.dirent_type(S_IFDIR);
+            let mut block = vec![0; bs];
+            write_dirent(&mut block, 0, ino, 12, dir_type, b".");
+            write_dirent(&mut block, 12, dir_ino, bs - 12 - tail, dir_type, b"..");
+            self.write_dir_block(ino, &inode, pblk, &mut block)?;
+            self.inc_links(&mut dir);
+        }
+        let file_type = self.dirent_type(mode);
+        self.add_entry(dir_ino, &mut dir, name.as_bytes(), ino, file_type)?;
+        self.write_inode(dir_ino, &mut dir)?;
+        self.flush()
+    }
+
+    /// Removes an entry from the directory, and frees the inode if it has no
+    /// links.
+    pub fn unlink(&mut self, dir_ino: u32, name: &str) -> VfsResult {
+        self.check_writable()?;
+        if name == "." || name == ".." {
+            return Err(VfsError::InvalidInput);
+        }
+        let mut dir = self.read_inode(dir_ino)?;
+        let pos = self
+            .find_entry(&dir, name.as_bytes())?
+            .ok_or(VfsError::NotFound)?;
+        let ino = pos.inode;
+        let mut inode = self.read_inode(ino)?;
+        let is_dir = inode.is_dir();
+        if is_dir && !self.is_dir_empty(&inode)? {
+            return Err(VfsError::DirectoryNotEmpty);
+        }
+        self.remove_entry(dir_ino, &dir, &pos)?;
+        if is_dir {
+            inode.set_links_count(0);
+            self.dec_links(&mut dir);
+            self.write_inode(dir_ino, &mut dir)?;
+        } else {
+            inode.set_links_count(inode.links_count().saturating_sub(1));
+        }
+        if inode.links_count() == 0 {
+            self.truncate_inode(ino, &mut inode, 0)?;
+            self.release_xattr_block(&mut inode)?;
+            // there is no clock, fsck only wants the deletion time to be set
+            inode.set_dtime(self.sb.write_time().max(1));
+            self.write_inode(ino, &mut inode)?;
+            self.free_inode(ino, is_dir)?;
+        } else {
+            self.write_inode(ino, &mut inode)?;
+        }
+        self.flush()
+    }
+
+    /// Whether `ancestor` is `ino` or one of its ancestors.
+    fn is_ancestor(&mut self, ancestor: u32, mut ino: u32) -> VfsResult<bool> {
+        loop {
+            if ino == ancestor {
+                return Ok(true);
+            }
+            if ino == ROOT_INO {
+                return Ok(false);
+            }
+            ino = self.lookup(ino, "..")?;
+        }
+    }
+
+    /// Moves an entry to another name, which must not exist.
+    pub fn rename(
+        &mut self,
+        src_dir: u32,
+        src_name: &str,
+        dst_dir: u32,
+        dst_name: &str,
+    ) -> VfsResult {
+        self.check_writable()?;
+        check_name(dst_name)?;
+        if src_name == "." || src_name == ".." {
+            return Err(VfsError::InvalidInput);
+        }
+        let dir = self.read_inode(src_dir)?;
+        let pos = self
+            .find_entry(&dir, src_name.as_bytes())?
+            .ok_or(VfsError::NotFound)?;
+        let ino = pos.inode;
+        let mut inode = self.read_inode(ino)?;
+        let is_dir = inode.is_dir();
+        if is_dir && src_dir != dst_dir && self.is_ancestor(ino, dst_dir)? {
+            return Err(VfsError::InvalidInput);
+        }
+
+        let mut dst = self.read_inode(dst_dir)?;
+        if self.find_entry(&dst, dst_name.as_bytes())?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+        self.add_entry(dst_dir, &mut dst, dst_name.as_bytes(), ino, pos.file_type)?;
+        if is_dir && src_dir != dst_dir {
+            self.inc_links(&mut dst);
+        }
+        self.write_inode(dst_dir, &mut dst)?;
+
+        // the source directory may be changed by adding the entry
+        let mut dir = self.read_inode(src_dir)?;
+        let pos = self
+            .find_entry(&dir, src_name.as_bytes())?
+            .ok_or(VfsError::NotFound)?;
+        self.remove_entry(src_dir, &dir, &pos)?;
+        if is_dir && src_dir != dst_dir {
+            self.dec_links(&mut dir);
+            self.write_inode(src_dir, &mut dir)?;
+            self.set_parent(ino, &mut inode, dst_dir)?;
+            self.write_inode(ino, &mut inode)?;
+        }
+        self.flush()
+    }
+
+    /// Points `..` of the directory to a new parent.
+    fn set_parent(&mut self, ino: u32, dir: &mut Inode, parent: u32) -> VfsResult {
+        if dir.has_flag(INODE_FLAG_INDEX) {
+            self.drop_dir_index(ino, dir)?;
+        }
+        let pos = self.find_entry(dir, b"..")?.ok_or(VfsError::InvalidData)?;
+        debug_assert_eq!(pos.lblk, 0);
+        let mut block = self.read_block(pos.pblk)?;
+        put_le32(&mut block, pos.offset, parent);
+        self.write_dir_block(ino, dir, pos.pblk, &mut block)
+    }
+}
+
+fn check_name(name: &str) -> VfsResult {
+    if name.is_empty() || name.len() > NAME_LEN_MAX || name == "." || name == ".." {
+        Err(VfsError::InvalidInput)
+    } else {
+        Ok(())
+    }
+}
+
+/// Converts the type bits of an inode mode to the node type.
+pub fn mode_to_node_type(mode: u16) -> VfsNodeType {
+    match mode & S_IFMT {
+        S_IFDIR => VfsNodeType::Dir,
+        S_IFCHR => VfsNodeType::CharDevice,
+        S_IFBLK => VfsNodeType::BlockDevice,
+        S_IFIFO => VfsNodeType::Fifo,
+        S_IFLNK => VfsNodeType::SymLink,
+        S_IFSOCK => VfsNodeType::Socket,
+        _ => VfsNodeType::File,
+    }
+}
@@ -0,0 +1,654 @@
+//! File data: mapping logical blocks with extent trees or indirect blocks,
+//! reading, writing and truncating.
+
+use alloc::{vec, vec::Vec};
+use axfs_vfs::{VfsError, VfsResult};
+
+use super::layout::*;
+use super::volume::Volume;
+
+/// Number of direct block pointers in an inode.
+const DIRECT_BLOCKS: u32 = 12;
+/// Number of extents in the root node in an inode.
+const ROOT_EXTENTS: usize = 4;
+
+/// Leaf extents of a file, loaded in memory to be modified.
+struct ExtentList {
+    extents: Vec<Extent>,
+    /// Blocks of the tree nodes, reused when the tree is stored back.
+    tree_blocks: Vec<u64>,
+    changed: bool,
+}
+
+/// Parses the header of an extent tree node. A child node must be exactly one
+/// level below its parent, so a corrupted tree can't loop or go too deep.
+fn parse_extent_node(node: &[u8], expected_depth: Option<u16>) -> VfsResult<ExtentHeader> {
+    let hdr = ExtentHeader::parse(node).ok_or(VfsError::InvalidData)?;
+    if hdr.depth > EXTENT_MAX_DEPTH || expected_depth.is_some_and(|depth| hdr.depth != depth) {
+        warn!("ext4: bad extent tree depth {}", hdr.depth);
+        return Err(VfsError::InvalidData);
+    }
+    Ok(hdr)
+}
+
+impl Volume {
+    /// Whether `i_blocks` of the inode counts filesystem blocks rather than
+    /// 512-byte sectors.
+    fn counts_fs_blocks(&self, inode: &Inode) -> bool {
+        self.sb.has_ro_compat(RO_COMPAT_HUGE_FILE) && inode.has_flag(INODE_FLAG_HUGE_FILE)
+    }
+
+    fn add_inode_blocks(&self, inode: &mut Inode, delta: i64) {
+        let unit = if self.counts_fs_blocks(inode) {
+            1
+        } else {
+            self.block_size as i64 / 512
+        };
+        inode.set_blocks(inode.blocks().saturating_add_signed(delta * unit));
+    }
+
+    /// Returns the number of 512-byte sectors used by the inode.
+    pub fn inode_sectors(&self, inode: &Inode) -> u64 {
+        if self.counts_fs_blocks(inode) {
+            inode.blocks() * (self.block_size as u64 / 512)
+        } else {
+            inode.blocks()
+        }
+    }
+
+    /// Initializes the block mapping of a new inode.
+    pub fn init_block_map(&self, inode: &mut Inode) {
+        if self.sb.has_incompat(INCOMPAT_EXTENTS) {
+            inode.set_flags(inode.flags() | INODE_FLAG_EXTENTS);
+            let hdr = ExtentHeader {
+                entries: 0,
+                max: ROOT_EXTENTS as u16,
+                depth: 0,
+            };
+            hdr.write(inode.block_area_mut());
+        }
+    }
+
+    /// Maps a logical block of the inode to a physical block. Returns `None`
+    /// for holes and unwritten blocks.
+    pub fn map_block(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Option<u64>> {
+        if inode.has_flag(INODE_FLAG_INLINE_DATA) {
+            return Err(VfsError::Unsupported);
+        }
+        if inode.has_flag(INODE_FLAG_EXTENTS) {
+            self.map_extent(inode, lblk)
+        } else {
+            self.map_indirect(inode, lblk)
+        }
+    }
+
+    fn map_extent(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Option<u64>> {
+        let mut node = inode.block_area().to_vec();
+        let mut expected_depth = None;
+        loop {
+            let hdr = parse_extent_node(&node, expected_depth)?;
+            let entries = node[EXTENT_HEADER_SIZE..]
+                .chunks(EXTENT_ENTRY_SIZE)
+                .take(hdr.entries as usize);
+            if hdr.depth == 0 {
+                for ext in entries.map(Extent::parse) {
+                    if lblk >= ext.lblk && lblk - ext.lblk < ext.len {
+                        return Ok((!ext.uninit).then(|| ext.pblk + (lblk - ext.lblk) as u64));
+                    }
+                }
+                return Ok(None);
+            }
+            let child = entries
+                .map(ExtentIndex::parse)
+                .take_while(|idx| idx.lblk <= lblk)
+                .last();
+            match child {
+                Some(idx) => node = self.read_block(idx.child)?,
+                None => return Ok(None),
+            }
+            expected_depth = Some(hdr.depth - 1);
+        }
+    }
+
+    /// Returns the pointer indices from the inode to the logical block.
+    fn indirect_path(&self, lblk: u32) -> VfsResult<Vec<usize>> {
+        let ppb = (self.block_size / 4) as u64;
+        let mut rest = lblk as u64;
+        if rest < DIRECT_BLOCKS as u64 {
+            return Ok(vec![rest as usize]);
+        }
+        rest -= DIRECT_BLOCKS as u64;
+        let mut span = ppb;
+        for level in 1..=3 {
+            if rest < span {
+                let mut path = vec![DIRECT_BLOCKS as usize + level - 1];
+                for _ in 0..level {
+                    span /= ppb;
+                    path.push((rest / span) as usize);
+                    rest %= span;
+                }
+                return Ok(path);
+            }
+            rest -= span;
+            span *= ppb;
+        }
+        Err(VfsError::InvalidInput)
+    }
+
+    fn map_indirect(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Option<u64>> {
+        let path = self.indirect_path(lblk)?;
+        let mut blk = inode.block_ptr(path[0]);
+        for &idx in &path[1..] {
+            if blk == 0 {
+                break;
+            }
+            let mut ptr = [0; 4];
+            self.read_bytes(
+                blk as u64 * self.block_size as u64 + idx as u64 * 4,
+                &mut ptr,
+            )?;
+            blk = u32::from_le_bytes(ptr);
+        }
+        Ok((blk != 0).then_some(blk as u64))
+    }
+
+    /// Maps a logical block of a block-mapped inode, allocating the block and
+    /// the indirect blocks if missing. Returns the block and whether it is
+    /// newly allocated.
+    fn map_alloc_indirect(
+        &mut self,
+        inode: &mut Inode,
+        lblk: u32,
+        goal: u64,
+    ) -> VfsResult<(u64, bool)> {
+        let path = self.indirect_path(lblk)?;
+        let mut blk = inode.block_ptr(path[0]) as u64;
+        let mut fresh = false;
+        if blk == 0 {
+            blk = self.alloc_block(goal)?;
+            self.add_inode_blocks(inode, 1);
+            inode.set_block_ptr(path[0], blk as u32);
+            if path.len() > 1 {
+                self.write_block(blk, &vec![0; self.block_size])?;
+            }
+            fresh = true;
+        }
+        for (i, &idx) in path.iter().enumerate().skip(1) {
+            let pos = blk * self.block_size as u64 + idx as u64 * 4;
+            let mut ptr = [0; 4];
+            self.read_bytes(pos, &mut ptr)?;
+            let next = u32::from_le_bytes(ptr) as u64;
+            if next != 0 {
+                blk = next;
+                fresh = false;
+                continue;
+            }
+            let new = self.alloc_block(blk + 1)?;
+            self.add_inode_blocks(inode, 1);
+            if i + 1 < path.len() {
+                self.write_block(new, &vec![0; self.block_size])?;
+            }
+            self.write_bytes(pos, &(new as u32).to_le_bytes())?;
+            blk = new;
+            fresh = true;
+        }
+        Ok((blk, fresh))
+    }
+
+    fn load_extents(&mut self, inode: &Inode) -> VfsResult<ExtentList> {
+        let mut list = ExtentList {
+            extents: Vec::new(),
+            tree_blocks: Vec::new(),
+            changed: false,
+        };
+        self.collect_extents(inode.block_area(), None, &mut list)?;
+        Ok(list)
+    }
+
+    fn collect_extents(
+        &mut self,
+        node: &[u8],
+        expected_depth: Option<u16>,
+        list: &mut ExtentList,
+    ) -> VfsResult {
+        let hdr = parse_extent_node(node, expected_depth)?;
+        let entries = node[EXTENT_HEADER_SIZE..]
+            .chunks(EXTENT_ENTRY_SIZE)
+            .take(hdr.entries as usize);
+        if hdr.depth == 0 {
+            list.extents.extend(entries.map(Extent::parse));
+        } else {
+            for idx in entries.map(ExtentIndex::parse) {
+                let child = self.read_block(idx.child)?;
+                list.tree_blocks.push(idx.child);
+                self.collect_extents(&child, Some(hdr.depth - 1), list)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the extents back as a tree, rooted in the inode.
+    fn store_extents(&mut self, ino: u32, inode: &mut Inode, list: ExtentList) -> VfsResult {
+        if !list.changed {
+            return Ok(());
+        }
+        let seed = self.inode_seed(ino, inode);
+        let per_block = (self.block_size - EXTENT_HEADER_SIZE) / EXTENT_ENTRY_SIZE;
+        let mut free_pool = list.tree_blocks;
+        free_pool.reverse();
+        let mut goal = list.extents.first().map_or(0, |ext| ext.pblk);
+
+        // Build the tree bottom-up, until the top level fits in the inode.
+        let mut items: Vec<(u32, Vec<u8>)> = list
+            .extents
+            .iter()
+            .map(|ext| {
+                let mut entry = vec![0; EXTENT_ENTRY_SIZE];
+                ext.write(&mut entry);
+                (ext.lblk, entry)
+            })
+            .collect();
+        let mut depth = 0;
+        while items.len() > ROOT_EXTENTS {
+            let mut upper = Vec::new();
+            for chunk in items.chunks(per_block) {
+                let blk = match free_pool.pop() {
+                    Some(blk) => blk,
+                    None => {
+                        let blk = self.alloc_block(goal)?;
+                        self.add_inode_blocks(inode, 1);
+                        blk
+                    }
+                };
+                goal = blk + 1;
+                let mut node = vec![0; self.block_size];
+                let hdr = ExtentHeader {
+                    entries: chunk.len() as u16,
+                    max: per_block as u16,
+                    depth,
+                };
+                hdr.write(&mut node);
+                for (i, (_, entry)) in chunk.iter().enumerate() {
+                    let off = EXTENT_HEADER_SIZE + i * EXTENT_ENTRY_SIZE;
+                    node[off..off + EXTENT_ENTRY_SIZE].copy_from_slice(entry);
+                }
+                if self.has_metadata_csum() {
+                    let tail = EXTENT_HEADER_SIZE + per_block * EXTENT_ENTRY_SIZE;
+                    let csum = crc32c(seed, &node[..tail]);
+                    put_le32(&mut node, tail, csum);
+                }
+                self.write_block(blk, &node)?;
+
+                let mut entry = vec![0; EXTENT_ENTRY_SIZE];
+                ExtentIndex {
+                    lblk: chunk[0].0,
+                    child: blk,
+                }
+                .write(&mut entry);
+                upper.push((chunk[0].0, entry));
+            }
+            items = upper;
+            depth += 1;
+        }
+
+        let root = inode.block_area_mut();
+        root.fill(0);
+        let hdr = ExtentHeader {
+            entries: items.len() as u16,
+            max: ROOT_EXTENTS as u16,
+            depth,
+        };
+        hdr.write(root);
+        for (i, (_, entry)) in items.iter().enumerate() {
+            let off = EXTENT_HEADER_SIZE + i * EXTENT_ENTRY_SIZE;
+            root[off..off + EXTENT_ENTRY_SIZE].copy_from_slice(entry);
+        }
+        for blk in free_pool {
+            self.free_blocks(blk, 1)?;
+            self.add_inode_blocks(inode, -1);
+        }
+        Ok(())
+    }
+
+    /// Maps a logical block in the loaded extents, allocating the block if
+    /// missing. Returns the block and whether it is newly allocated or was
+    /// unwritten, so its old content must not be used.
+    fn map_alloc_extent(
+        &mut self,
+        inode: &mut Inode,
+        list: &mut ExtentList,
+        lblk: u32,
+        goal: u64,
+    ) -> VfsResult<(u64, bool)> {
+        let exts = &mut list.extents;
+        let pos = exts.partition_point(|ext| ext.lblk <= lblk);
+        if pos > 0 {
+            let ext = exts[pos - 1];
+            if lblk - ext.lblk < ext.len {
+                let pblk = ext.pblk + (lblk - ext.lblk) as u64;
+                if !ext.uninit {
+                    return Ok((pblk, false));
+                }
+                // split the unwritten extent around the block
+                let off = lblk - ext.lblk;
+                let mut pieces = Vec::new();
+                if off > 0 {
+                    pieces.push(Extent { len: off, ..ext });
+                }
+                pieces.push(Extent {
+                    lblk,
+                    len: 1,
+                    pblk,
+                    uninit: false,
+                });
+                if off + 1 < ext.len {
+                    pieces.push(Extent {
+                        lblk: lblk + 1,
+                        len: ext.len - off - 1,
+                        pblk: pblk + 1,
+                        uninit: true,
+                    });
+                }
+                exts.splice(pos - 1..pos, pieces);
+                list.changed = true;
+                return Ok((pblk, true));
+            }
+        }
+
+        // allocate a new block, after the previous extent if possible
+        let goal = match pos {
+            0 => goal,
+            _ => exts[pos - 1].pblk + (lblk - exts[pos - 1].lblk) as u64,
+        };
+        let pblk = self.alloc_block(goal)?;
+        self.add_inode_blocks(inode, 1);
+        let new = Extent {
+            lblk,
+            len: 1,
+            pblk,
+            uninit: false,
+        };
+        let contiguous = |a: &Extent, b: &Extent| {
+            !a.uninit
+                && !b.uninit
+                && a.lblk + a.len == b.lblk
+                && a.pblk + a.len as u64 == b.pblk
+                && a.len + b.len <= EXTENT_MAX_INIT_LEN
+        };
+        if pos > 0 && contiguous(&exts[pos - 1], &new) {
+            exts[pos - 1].len += 1;
+            if pos < exts.len() && contiguous(&exts[pos - 1], &exts[pos]) {
+                exts[pos - 1].len += exts[pos].len;
+                exts.remove(pos);
+            }
+        } else if pos < exts.len() && contiguous(&new, &exts[pos]) {
+            exts[pos].lblk = lblk;
+            exts[pos].pblk = pblk;
+            exts[pos].len += 1;
+        } else {
+            exts.insert(pos, new);
+        }
+        list.changed = true;
+        Ok((pblk, true))
+    }
+
+    /// Reads the content of a fast symlink stored in the inode.
+    fn read_inline_symlink(inode: &Inode, offset: u64, buf: &mut [u8]) -> usize {
+        let target = &inode.block_area()[..inode.size() as usize];
+        let start = (offset as usize).min(target.len());
+        let len = buf.len().min(target.len() - start);
+        buf[..len].copy_from_slice(&target[start..start + len]);
+        len
+    }
+
+    pub fn read_file(&mut self, ino: u32, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let inode = self.read_inode(ino)?;
+        let size = inode.size();
+        if inode.file_type() == S_IFLNK && size < 60 {
+            return Ok(Self::read_inline_symlink(&inode, offset, buf));
+        }
+        if offset >= size {
+            return Ok(0);
+        }
+        let len = buf.len().min((size - offset) as usize);
+        let bs = self.block_size as u64;
+        let mut block = vec![0; self.block_size];
+        let mut done = 0;
+        while done < len {
+            let pos = offset + done as u64;
+            let in_block = (pos % bs) as usize;
+            let count = (self.block_size - in_block).min(len - done);
+            let dst = &mut buf[done..done + count];
+            match self.map_block(&inode, (pos / bs) as u32)? {
+                Some(pblk) if count == self.block_size => self.read_block_into(pblk, dst)?,
+                Some(pblk) => {
+                    self.read_block_into(pblk, &mut block)?;
+                    dst.copy_from_slice(&block[in_block..in_block + count]);
+                }
+                None => dst.fill(0),
+            }
+            done += count;
+        }
+        Ok(len)
+    }
+
+    pub fn write_file(&mut self, ino: u32, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.check_writable()?;
+        let mut inode = self.read_inode(ino)?;
+        if inode.has_flag(INODE_FLAG_INLINE_DATA) {
+            return Err(VfsError::Unsupported);
+        }
+        let bs = self.block_size as u64;
+        offset
+            .checked_add(buf.len() as u64)
+            .filter(|&end| end.div_ceil(bs) <= u32::MAX as u64)
+            .ok_or(VfsError::InvalidInput)?;
+        let mut extents = if inode.has_flag(INODE_FLAG_EXTENTS) {
+            Some(self.load_extents(&inode)?)
+        } else {
+            None
+        };
+        let goal = self.group_goal(self.inode_group(ino));
+
+        let mut block = vec![0; self.block_size];
+        let mut done = 0;
+        // on errors, keep the blocks written so far
+        let mut write_blocks = || -> VfsResult {
+            while done < buf.len() {
+                let pos = offset + done as u64;
+                let in_block = (pos % bs) as usize;
+                let count = (self.block_size - in_block).min(buf.len() - done);
+                let lblk = (pos / bs) as u32;
+                let (pblk, fresh) = match &mut extents {
+                    Some(list) => self.map_alloc_extent(&mut inode, list, lblk, goal)?,
+                    None => self.map_alloc_indirect(&mut inode, lblk, goal)?,
+                };
+                let src = &buf[done..done + count];
+                if count == self.block_size {
+                    self.write_block(pblk, src)?;
+                } else {
+                    if fresh {
+                        block.fill(0);
+                    } else {
+                        self.read_block_into(pblk, &mut block)?;
+                    }
+                    block[in_block..in_block + count].copy_from_slice(src);
+                    self.write_block(pblk, &block)?;
+                }
+                done += count;
+            }
+            Ok(())
+        };
+        let res = write_blocks();
+
+        if let Some(list) = extents {
+            self.store_extents(ino, &mut inode, list)?;
+        }
+        if offset + done as u64 > inode.size() {
+            inode.set_size(offset + done as u64);
+        }
+        self.write_inode(ino, &mut inode)?;
+        self.flush()?;
+        match res {
+            Err(e) if done == 0 => Err(e),
+            _ => Ok(done),
+        }
+    }
+
+    pub fn truncate_file(&mut self, ino: u32, size: u64) -> VfsResult {
+        self.check_writable()?;
+        let mut inode = self.read_inode(ino)?;
+        self.truncate_inode(ino, &mut inode, size)?;
+        self.write_inode(ino, &mut inode)?;
+        self.flush()
+    }
+
+    /// Frees the blocks after `size`, and updates the size of the inode.
+    pub fn truncate_inode(&mut self, ino: u32, inode: &mut Inode, size: u64) -> VfsResult {
+        if inode.has_flag(INODE_FLAG_INLINE_DATA) {
+            return Err(VfsError::Unsupported);
+        }
+        if inode.file_type() == S_IFLNK && inode.size() < 60 {
+            // fast symlink, the target is stored in the inode
+            inode.block_area_mut().fill(0);
+            inode.set_size(0);
+            return Ok(());
+        }
+        let bs = self.block_size as u64;
+        if size < inode.size() && !size.is_multiple_of(bs) {
+            // zero the tail of the last block, in case the file is extended
+            if let Some(pblk) = self.map_block(inode, (size / bs) as u32)? {
+                let mut block = self.read_block(pblk)?;
+                block[(size % bs) as usize..].fill(0);
+                self.write_block(pblk, &block)?;
+            }
+        }
+        let keep = size.div_ceil(bs);
+        if inode.has_flag(INODE_FLAG_EXTENTS) {
+            let mut list = self.load_extents(inode)?;
+            let mut kept = Vec::new();
+            for ext in core::mem::take(&mut list.extents) {
+                let end = ext.lblk as u64 + ext.len as u64;
+                if end <= keep {
+                    kept.push(ext);
+                    continue;
+                }
+                let len = keep.saturating_sub(ext.lblk as u64) as u32;
+                self.free_blocks(ext.pblk + len as u64, (ext.len - len) as u64)?;
+                self.add_inode_blocks(inode, -((ext.len - len) as i64));
+                if len > 0 {
+                    kept.push(Extent { len, ..ext });
+                }
+                list.changed = true;
+            }
+            list.extents = kept;
+            self.store_extents(ino, inode, list)?;
+        } else {
+            self.truncate_indirect(inode, keep)?;
+        }
+        inode.set_size(size);
+        Ok(())
+    }
+
+    fn truncate_indirect(&mut self, inode: &mut Inode, keep: u64) -> VfsResult {
+        let ppb = (self.block_size / 4) as u64;
+        let mut freed = 0;
+        let mut base = DIRECT_BLOCKS as u64;
+        let mut span = 1;
+        for idx in 0..15 {
+            let blk = inode.block_ptr(idx) as u64;
+            let (level, start) = if idx < DIRECT_BLOCKS as usize {
+                (0, idx as u64)
+            } else {
+                span *= ppb;
+                let start = base;
+                base += span;
+                (idx as u32 - DIRECT_BLOCKS + 1, start)
+            };
+            if blk != 0 && self.truncate_tree(blk, level, start, keep, &mut freed)? {
+                inode.set_block_ptr(idx, 0);
+            }
+        }
+        self.add_inode_blocks(inode, -(freed as i64));
+        Ok(())
+    }
+
+    /// Frees the data blocks not less than `keep` in the tree of indirect
+    /// blocks at `level`, whose first data block is `base`. Returns whether
+    /// `blk` itself is freed.
+    fn truncate_tree(
+        &mut self,
+        blk: u64,
+        level: u32,
+        base: u64,
+        keep: u64,
+        freed: &mut u64,
+    ) -> VfsResult<bool> {
+        if level == 0 {
+            if base >= keep {
+                self.free_blocks(blk, 1)?;
+                *freed += 1;
+                return Ok(true);
+            }
+            return Ok(false);
+        }
+        let ppb = (self.block_size / 4) as u64;
+        let span = ppb.pow(level - 1);
+        let mut node = self.read_block(blk)?;
+        let mut changed = false;
+        let mut empty = true;
+        for i in 0..ppb as usize {
+            let child = le32(&node, i * 4) as u64;
+            if child == 0 {
+                continue;
+            }
+            let child_base = base + i as u64 * span;
+            if child_base + span > keep
+                && self.truncate_tree(child, level - 1, child_base, keep, freed)?
+            {
+                put_le32(&mut node, i * 4, 0);
+                changed = true;
+            } else {
+                empty = false;
+            }
+        }
+        if empty {
+            self.free_blocks(blk, 1)?;
+            *freed += 1;
+            Ok(true)
+        } else {
+            if changed {
+                self.write_block(blk, &node)?;
+            }
+            Ok(false)
+        }
+    }
+
+    /// Drops a reference to the extended attribute block of the inode.
+    pub fn release_xattr_block(&mut self, inode: &mut Inode) -> VfsResult {
+        const XATTR_MAGIC: u32 = 0xea02_0000;
+        let blk = inode.file_acl();
+        if blk == 0 {
+            return Ok(());
+        }
+        let mut block = self.read_block(blk)?;
+        let refcount = le32(&block, 4);
+        if le32(&block, 0) != XATTR_MAGIC || refcount <= 1 {
+            self.free_blocks(blk, 1)?;
+        } else {
+            put_le32(&mut block, 4, refcount - 1);
+            if self.has_metadata_csum() {
+                let mut crc = crc32c(self.csum_seed, &blk.to_le_bytes());
+                crc = crc32c(crc, &block[..0x10]);
+                crc = crc32c(crc, &[0; 4]);
+                crc = crc32c(crc, &block[0x14..]);
+                put_le32(&mut block, 0x10, crc);
+            }
+            self.write_block(blk, &block)?;
+        }
+        self.add_inode_blocks(inode, -1);
+        put_le32(&mut inode.raw, 0x68, 0);
+        put_le16(&mut inode.raw, 0x76, 0);
+        Ok(())
+    }
+}
@@ -0,0 +1,745 @@
+//! On-disk structures of ext2/3/4, and their checksums.
+//!
+//! Structures are kept as raw little-endian bytes and accessed by offsets, so
+//! that fields we don't know about are written back unchanged.
+
+use alloc::{vec, vec::Vec};
+
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const EXT4_MAGIC: u16 = 0xef53;
+pub const ROOT_INO: u32 = 2;
+pub const GOOD_OLD_INODE_SIZE: usize = 128;
+pub const GOOD_OLD_FIRST_INO: u32 = 11;
+pub const MAX_LOG_BLOCK_SIZE: u32 = 6;
+
+pub const INCOMPAT_FILETYPE: u32 = 0x2;
+pub const INCOMPAT_RECOVER: u32 = 0x4;
+pub const INCOMPAT_EXTENTS: u32 = 0x40;
+pub const INCOMPAT_64BIT: u32 = 0x80;
+pub const INCOMPAT_FLEX_BG: u32 = 0x200;
+pub const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+pub const INCOMPAT_LARGEDIR: u32 = 0x4000;
+/// Incompatible features we can read.
+pub const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE
+    | INCOMPAT_RECOVER
+    | INCOMPAT_EXTENTS
+    | INCOMPAT_64BIT
+    | INCOMPAT_FLEX_BG
+    | INCOMPAT_CSUM_SEED
+    | INCOMPAT_LARGEDIR;
+
+pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+pub const RO_COMPAT_BTREE_DIR: u32 = 0x4;
+pub const RO_COMPAT_HUGE_FILE: u32 = 0x8;
+pub const RO_COMPAT_GDT_CSUM: u32 = 0x10;
+pub const RO_COMPAT_DIR_NLINK: u32 = 0x20;
+pub const RO_COMPAT_EXTRA_ISIZE: u32 = 0x40;
+pub const RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+/// Read-only compatible features we can write.
+pub const RO_COMPAT_SUPPORTED: u32 = RO_COMPAT_SPARSE_SUPER
+    | RO_COMPAT_LARGE_FILE
+    | RO_COMPAT_BTREE_DIR
+    | RO_COMPAT_HUGE_FILE
+    | RO_COMPAT_GDT_CSUM
+    | RO_COMPAT_DIR_NLINK
+    | RO_COMPAT_EXTRA_ISIZE
+    | RO_COMPAT_METADATA_CSUM;
+
+pub const BG_INODE_UNINIT: u16 = 0x1;
+pub const BG_BLOCK_UNINIT: u16 = 0x2;
+
+pub const INODE_FLAG_INDEX: u32 = 0x1000;
+pub const INODE_FLAG_HUGE_FILE: u32 = 0x4_0000;
+pub const INODE_FLAG_EXTENTS: u32 = 0x8_0000;
+pub const INODE_FLAG_INLINE_DATA: u32 = 0x1000_0000;
+
+pub const S_IFMT: u16 = 0o170000;
+pub const S_IFIFO: u16 = 0o010000;
+pub const S_IFCHR: u16 = 0o020000;
+pub const S_IFDIR: u16 = 0o040000;
+pub const S_IFBLK: u16 = 0o060000;
+pub const S_IFREG: u16 = 0o100000;
+pub const S_IFLNK: u16 = 0o120000;
+pub const S_IFSOCK: u16 = 0o140000;
+
+pub const EXTENT_MAGIC: u16 = 0xf30a;
+pub const EXTENT_HEADER_SIZE: usize = 12;
+pub const EXTENT_ENTRY_SIZE: usize = 12;
+/// The maximum depth of an extent tree, the same limit as Linux.
+pub const EXTENT_MAX_DEPTH: u16 = 5;
+/// Extents longer than this are uninitialized (preallocated but unwritten).
+pub const EXTENT_MAX_INIT_LEN: u32 = 32768;
+
+pub const DIRENT_HEADER_SIZE: usize = 8;
+pub const DIRENT_TAIL_SIZE: usize = 12;
+pub const DIRENT_TAIL_FT: u8 = 0xde;
+
+pub fn le16(buf: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([buf[off], buf[off + 1]])
+}
+
+pub fn le32(buf: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(buf[off..off + 4].try_into().unwrap())
+}
+
+pub fn put_le16(buf: &mut [u8], off: usize, val: u16) {
+    buf[off..off + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+pub fn put_le32(buf: &mut [u8], off: usize, val: u32) {
+    buf[off..off + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+/// Updates a raw CRC32C (Castagnoli) without the final inversion, as ext4
+/// does for metadata checksums.
+pub fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// Updates a CRC16 (polynomial 0x8005, reflected), used by group descriptor
+/// checksums without `metadata_csum`.
+pub fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// The superblock.
+pub struct SuperBlock {
+    pub raw: Vec<u8>,
+}
+
+impl SuperBlock {
+    pub fn new(raw: Vec<u8>) -> Self {
+        Self { raw }
+    }
+
+    pub fn inodes_count(&self) -> u32 {
+        le32(&self.raw, 0x0)
+    }
+
+    pub fn blocks_count(&self) -> u64 {
+        self.lo_hi(0x4, 0x150)
+    }
+
+    pub fn free_blocks_count(&self) -> u64 {
+        self.lo_hi(0xc, 0x158)
+    }
+
+    pub fn set_free_blocks_count(&mut self, count: u64) {
+        put_le32(&mut self.raw, 0xc, count as u32);
+        if self.is_64bit() {
+            put_le32(&mut self.raw, 0x158, (count >> 32) as u32);
+        }
+    }
+
+    pub fn free_inodes_count(&self) -> u32 {
+        le32(&self.raw, 0x10)
+    }
+
+    pub fn set_free_inodes_count(&mut self, count: u32) {
+        put_le32(&mut self.raw, 0x10, count);
+    }
+
+    pub fn first_data_block(&self) -> u32 {
+        le32(&self.raw, 0x14)
+    }
+
+    /// `log2(block_size) - 10`, to be checked before [`Self::block_size`].
+    pub fn log_block_size(&self) -> u32 {
+        le32(&self.raw, 0x18)
+    }
+
+    pub fn log_cluster_size(&self) -> u32 {
+        le32(&self.raw, 0x1c)
+    }
+
+    pub fn block_size(&self) -> usize {
+        1024 << self.log_block_size()
+    }
+
+    pub fn blocks_per_group(&self) -> u32 {
+        le32(&self.raw, 0x20)
+    }
+
+    pub fn inodes_per_group(&self) -> u32 {
+        le32(&self.raw, 0x28)
+    }
+
+    pub fn magic(&self) -> u16 {
+        le16(&self.raw, 0x38)
+    }
+
+    /// The last write time, in seconds since the epoch.
+    pub fn write_time(&self) -> u32 {
+        le32(&self.raw, 0x30)
+    }
+
+    pub fn rev_level(&self) -> u32 {
+        le32(&self.raw, 0x4c)
+    }
+
+    pub fn first_ino(&self) -> u32 {
+        if self.rev_level() == 0 {
+            GOOD_OLD_FIRST_INO
+        } else {
+            le32(&self.raw, 0x54)
+        }
+    }
+
+    pub fn inode_size(&self) -> usize {
+        if self.rev_level() == 0 {
+            GOOD_OLD_INODE_SIZE
+        } else {
+            le16(&self.raw, 0x58) as usize
+        }
+    }
+
+    pub fn feature_incompat(&self) -> u32 {
+        le32(&self.raw, 0x60)
+    }
+
+    pub fn feature_ro_compat(&self) -> u32 {
+        le32(&self.raw, 0x64)
+    }
+
+    pub fn has_incompat(&self, feature: u32) -> bool {
+        self.feature_incompat() & feature != 0
+    }
+
+    pub fn has_ro_compat(&self, feature: u32) -> bool {
+        self.feature_ro_compat() & feature != 0
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.has_incompat(INCOMPAT_64BIT)
+    }
+
+    pub fn uuid(&self) -> &[u8] {
+        &self.raw[0x68..0x78]
+    }
+
+    pub fn reserved_gdt_blocks(&self) -> u32 {
+        le16(&self.raw, 0xce) as u32
+    }
+
+    pub fn desc_size(&self) -> usize {
+        if self.is_64bit() {
+            le16(&self.raw, 0xfe) as usize
+        } else {
+            32
+        }
+    }
+
+    pub fn want_extra_isize(&self) -> u16 {
+        le16(&self.raw, 0x15e)
+    }
+
+    pub fn checksum_seed(&self) -> u32 {
+        le32(&self.raw, 0x270)
+    }
+
+    pub fn update_checksum(&mut self) {
+        if self.has_ro_compat(RO_COMPAT_METADATA_CSUM) {
+            let csum = crc32c(!0, &self.raw[..0x3fc]);
+            put_le32(&mut self.raw, 0x3fc, csum);
+        }
+    }
+
+    fn lo_hi(&self, lo: usize, hi: usize) -> u64 {
+        let mut val = le32(&self.raw, lo) as u64;
+        if self.is_64bit() {
+            val |= (le32(&self.raw, hi) as u64) << 32;
+        }
+        val
+    }
+}
+
+/// A block group descriptor.
+pub struct GroupDesc {
+    pub raw: Vec<u8>,
+}
+
+impl GroupDesc {
+    const CHECKSUM_OFFSET: usize = 0x1e;
+
+    pub fn block_bitmap(&self) -> u64 {
+        self.lo_hi32(0x0, 0x20)
+    }
+
+    pub fn inode_bitmap(&self) -> u64 {
+        self.lo_hi32(0x4, 0x24)
+    }
+
+    pub fn inode_table(&self) -> u64 {
+        self.lo_hi32(0x8, 0x28)
+    }
+
+    pub fn free_blocks_count(&self) -> u32 {
+        self.lo_hi16(0xc, 0x2c)
+    }
+
+    pub fn set_free_blocks_count(&mut self, count: u32) {
+        self.set_lo_hi16(0xc, 0x2c, count);
+    }
+
+    pub fn free_inodes_count(&self) -> u32 {
+        self.lo_hi16(0xe, 0x2e)
+    }
+
+    pub fn set_free_inodes_count(&mut self, count: u32) {
+        self.set_lo_hi16(0xe, 0x2e, count);
+    }
+
+    pub fn used_dirs_count(&self) -> u32 {
+        self.lo_hi16(0x10, 0x30)
+    }
+
+    pub fn set_used_dirs_count(&mut self, count: u32) {
+        self.set_lo_hi16(0x10, 0x30, count);
+    }
+
+    pub fn flags(&self) -> u16 {
+        le16(&self.raw, 0x12)
+    }
+
+    pub fn set_flags(&mut self, flags: u16) {
+        put_le16(&mut self.raw, 0x12, flags);
+    }
+
+    pub fn itable_unused(&self) -> u32 {
+        self.lo_hi16(0x1c, 0x32)
+    }
+
+    pub fn set_itable_unused(&mut self, count: u32) {
+        self.set_lo_hi16(0x1c, 0x32, count);
+    }
+
+    pub fn set_block_bitmap_csum(&mut self, csum: u32) {
+        self.set_lo_hi16(0x18, 0x38, csum);
+    }
+
+    pub fn set_inode_bitmap_csum(&mut self, csum: u32) {
+        self.set_lo_hi16(0x1a, 0x3a, csum);
+    }
+
+    /// Computes the descriptor checksum of group `group`, using CRC32C with
+    /// `metadata_csum` or CRC16 with `gdt_csum`.
+    pub fn update_checksum(&mut self, sb: &SuperBlock, csum_seed: u32, group: u32) {
+        let off = Self::CHECKSUM_OFFSET;
+        let group = group.to_le_bytes();
+        let csum = if sb.has_ro_compat(RO_COMPAT_METADATA_CSUM) {
+            let mut crc = crc32c(csum_seed, &group);
+            crc = crc32c(crc, &self.raw[..off]);
+            crc = crc32c(crc, &[0; 2]);
+            crc = crc32c(crc, &self.raw[off + 2..]);
+            crc as u16
+        } else if sb.has_ro_compat(RO_COMPAT_GDT_CSUM) {
+            let mut crc = crc16(!0, sb.uuid());
+            crc = crc16(crc, &group);
+            crc = crc16(crc, &self.raw[..off]);
+            crc16(crc, &self.raw[off + 2..])
+        } else {
+            return;
+        };
+        put_le16(&mut self.raw, off, csum);
+    }
+
+    fn lo_hi32(&self, lo: usize, hi: usize) -> u64 {
+        let mut val = le32(&self.raw, lo) as u64;
+        if self.raw.len() > hi {
+            val |= (le32(&self.raw, hi) as u64) << 32;
+        }
+        val
+    }
+
+    fn lo_hi16(&self, lo: usize, hi: usize) -> u32 {
+        let mut val = le16(&self.raw, lo) as u32;
+        if self.raw.len() > hi {
+            val |= (le16(&self.raw, hi) as u32) << 16;
+        }
+        val
+    }
+
+    fn set_lo_hi16(&mut self, lo: usize, hi: usize, val: u32) {
+        put_le16(&mut self.raw, lo, val as u16);
+        if self.raw.len() > hi {
+            put_le16(&mut self.raw, hi, (val >> 16) as u16);
+        }
+    }
+}
+
+/// An inode.
+#[derive(Clone)]
+pub struct Inode {
+    pub raw: Vec<u8>,
+}
+
+impl Inode {
+    const CHECKSUM_LO_OFFSET: usize = 0x7c;
+    const CHECKSUM_HI_OFFSET: usize = 0x82;
+
+    pub fn new(inode_size: usize) -> Self {
+        Self {
+            raw: vec![0; inode_size],
+        }
+    }
+
+    pub fn mode(&self) -> u16 {
+        le16(&self.raw, 0x0)
+    }
+
+    pub fn set_mode(&mut self, mode: u16) {
+        put_le16(&mut self.raw, 0x0, mode);
+    }
+
+    pub fn file_type(&self) -> u16 {
+        self.mode() & S_IFMT
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == S_IFDIR
+    }
+
+    pub fn size(&self) -> u64 {
+        le32(&self.raw, 0x4) as u64 | (le32(&self.raw, 0x6c) as u64) << 32
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        put_le32(&mut self.raw, 0x4, size as u32);
+        put_le32(&mut self.raw, 0x6c, (size >> 32) as u32);
+    }
+
+    pub fn set_dtime(&mut self, time: u32) {
+        put_le32(&mut self.raw, 0x14, time);
+    }
+
+    pub fn links_count(&self) -> u16 {
+        le16(&self.raw, 0x1a)
+    }
+
+    pub fn set_links_count(&mut self, count: u16) {
+        put_le16(&mut self.raw, 0x1a, count);
+    }
+
+    /// Number of 512-byte sectors used, or blocks with the huge file flag.
+    pub fn blocks(&self) -> u64 {
+        le32(&self.raw, 0x1c) as u64 | (le16(&self.raw, 0x74) as u64) << 32
+    }
+
+    pub fn set_blocks(&mut self, blocks: u64) {
+        put_le32(&mut self.raw, 0x1c, blocks as u32);
+        put_le16(&mut self.raw, 0x74, (blocks >> 32) as u16);
+    }
+
+    pub fn flags(&self) -> u32 {
+        le32(&self.raw, 0x20)
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        put_le32(&mut self.raw, 0x20, flags);
+    }
+
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags() & flag != 0
+    }
+
+    /// The 60-byte `i_block` area, holding block pointers, the extent tree
+    /// root, or the target of a fast symlink.
+    pub fn block_area(&self) -> &[u8] {
+        &self.raw[0x28..0x64]
+    }
+
+    pub fn block_area_mut(&mut self) -> &mut [u8] {
+        &mut self.raw[0x28..0x64]
+    }
+
+    pub fn block_ptr(&self, idx: usize) -> u32 {
+        le32(self.block_area(), idx * 4)
+    }
+
+    pub fn set_block_ptr(&mut self, idx: usize, blk: u32) {
+        put_le32(self.block_area_mut(), idx * 4, blk);
+    }
+
+    pub fn generation(&self) -> u32 {
+        le32(&self.raw, 0x64)
+    }
+
+    pub fn file_acl(&self) -> u64 {
+        le32(&self.raw, 0x68) as u64 | (le16(&self.raw, 0x76) as u64) << 32
+    }
+
+    pub fn extra_isize(&self) -> usize {
+        if self.raw.len() > GOOD_OLD_INODE_SIZE {
+            le16(&self.raw, 0x80) as usize
+        } else {
+            0
+        }
+    }
+
+    pub fn set_extra_isize(&mut self, size: u16) {
+        if self.raw.len() > GOOD_OLD_INODE_SIZE {
+            put_le16(&mut self.raw, 0x80, size);
+        }
+    }
+
+    /// The seed of checksums of this inode and its blocks.
+    pub fn csum_seed(&self, fs_seed: u32, ino: u32) -> u32 {
+        let crc = crc32c(fs_seed, &ino.to_le_bytes());
+        crc32c(crc, &self.generation().to_le_bytes())
+    }
+
+    pub fn update_checksum(&mut self, seed: u32) {
+        let lo = Self::CHECKSUM_LO_OFFSET;
+        let hi = Self::CHECKSUM_HI_OFFSET;
+        let has_hi = self.raw.len() > GOOD_OLD_INODE_SIZE
+            && GOOD_OLD_INODE_SIZE + self.extra_isize() >= hi + 2;
+        let mut crc = crc32c(seed, &self.raw[..lo]);
+        crc = crc32c(crc, &[0; 2]);
+        crc = crc32c(crc, &self.raw[lo + 2..GOOD_OLD_INODE_SIZE]);
+        if self.raw.len() > GOOD_OLD_INODE_SIZE {
+            crc = crc32c(crc, &self.raw[GOOD_OLD_INODE_SIZE..hi]);
+            let rest = if has_hi {
+                crc = crc32c(crc, &[0; 2]);
+                hi + 2
+            } else {
+                hi
+            };
+            crc = crc32c(crc, &self.raw[rest..]);
+        }
+        put_le16(&mut self.raw, lo, crc as u16);
+        if has_hi {
+            put_le16(&mut self.raw, hi, (crc >> 16) as u16);
+        }
+    }
+}
+
+/// A directory entry in a linear directory block.
+pub struct DirEntry<'a> {
+    pub inode: u32,
+    pub rec_len: usize,
+    pub file_type: u8,
+    pub name: &'a [u8],
+}
+
+impl<'a> DirEntry<'a> {
+    /// Parses the entry at `off` of `block`. Returns `None` if it is corrupted.
+    pub fn parse(block: &'a [u8], off: usize) -> Option<Self> {
+        if off + DIRENT_HEADER_SIZE > block.len() {
+            return None;
+        }
+        let rec_len = decode_rec_len(le16(block, off + 4), block.len());
+        let name_len = block[off + 6] as usize;
+        if rec_len < DIRENT_HEADER_SIZE
+            || off + rec_len > block.len()
+            || DIRENT_HEADER_SIZE + name_len > rec_len
+        {
+            return None;
+        }
+        Some(Self {
+            inode: le32(block, off),
+            rec_len,
+            file_type: block[off + 7],
+            name: &block[off + 8..off + 8 + name_len],
+        })
+    }
+
+    /// Whether it is the checksum tail of the block.
+    pub fn is_tail(&self) -> bool {
+        self.inode == 0
+            && self.rec_len == DIRENT_TAIL_SIZE
+            && self.name.is_empty()
+            && self.file_type == DIRENT_TAIL_FT
+    }
+}
+
+/// Bytes taken by an entry with a name of `name_len` bytes.
+pub const fn dirent_size(name_len: usize) -> usize {
+    (DIRENT_HEADER_SIZE + name_len + 3) & !3
+}
+
+fn decode_rec_len(len: u16, block_size: usize) -> usize {
+    if block_size < 65536 {
+        len as usize
+    } else if len == 65535 || len == 0 {
+        65536
+    } else {
+        (len as usize & 65532) | ((len as usize & 3) << 16)
+    }
+}
+
+fn encode_rec_len(len: usize) -> u16 {
+    if len < 65536 {
+        len as u16
+    } else if len == 65536 {
+        65535
+    } else {
+        ((len & 65532) | ((len >> 16) & 3)) as u16
+    }
+}
+
+/// Writes a directory entry at `off` of `block`.
+pub fn write_dirent(
+    block: &mut [u8],
+    off: usize,
+    inode: u32,
+    rec_len: usize,
+    file_type: u8,
+    name: &[u8],
+) {
+    put_le32(block, off, inode);
+    put_le16(block, off + 4, encode_rec_len(rec_len));
+    block[off + 6] = name.len() as u8;
+    block[off + 7] = file_type;
+    block[off + 8..off + 8 + name.len()].copy_from_slice(name);
+}
+
+/// Writes the checksum tail of a directory block.
+pub fn write_dirent_tail(block: &mut [u8], seed: u32) {
+    let off = block.len() - DIRENT_TAIL_SIZE;
+    write_dirent(block, off, 0, DIRENT_TAIL_SIZE, DIRENT_TAIL_FT, &[]);
+    let csum = crc32c(seed, &block[..off]);
+    put_le32(block, off + 8, csum);
+}
+
+/// Converts the type bits of an inode mode to the file type in directory
+/// entries.
+pub fn mode_to_dirent_type(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => 1,
+        S_IFDIR => 2,
+        S_IFCHR => 3,
+        S_IFBLK => 4,
+        S_IFIFO => 5,
+        S_IFSOCK => 6,
+        S_IFLNK => 7,
+        _ => 0,
+    }
+}
+
+/// The header of an extent tree node.
+pub struct ExtentHeader {
+    pub entries: u16,
+    pub max: u16,
+    pub depth: u16,
+}
+
+impl ExtentHeader {
+    pub fn parse(node: &[u8]) -> Option<Self> {
+        if le16(node, 0) != EXTENT_MAGIC {
+            return None;
+        }
+        let hdr = Self {
+            entries: le16(node, 2),
+            max: le16(node, 4),
+            depth: le16(node, 6),
+        };
+        let capacity = (node.len() - EXTENT_HEADER_SIZE) / EXTENT_ENTRY_SIZE;
+        (hdr.entries <= hdr.max && hdr.max as usize <= capacity).then_some(hdr)
+    }
+
+    pub fn write(&self, node: &mut [u8]) {
+        put_le16(node, 0, EXTENT_MAGIC);
+        put_le16(node, 2, self.entries);
+        put_le16(node, 4, self.max);
+        put_le16(node, 6, self.depth);
+        put_le32(node, 8, 0);
+    }
+}
+
+/// A leaf entry of an extent tree, mapping contiguous logical blocks to
+/// physical blocks.
+#[derive(Debug, Clone, Copy)]
+pub struct Extent {
+    pub lblk: u32,
+    pub len: u32,
+    pub pblk: u64,
+    pub uninit: bool,
+}
+
+impl Extent {
+    pub fn parse(entry: &[u8]) -> Self {
+        let raw_len = le16(entry, 4) as u32;
+        let (len, uninit) = if raw_len > EXTENT_MAX_INIT_LEN {
+            (raw_len - EXTENT_MAX_INIT_LEN, true)
+        } else {
+            (raw_len, false)
+        };
+        Self {
+            lblk: le32(entry, 0),
+            len,
+            pblk: le32(entry, 8) as u64 | (le16(entry, 6) as u64) << 32,
+            uninit,
+        }
+    }
+
+    pub fn write(&self, entry: &mut [u8]) {
+        let len = if self.uninit {
+            self.len + EXTENT_MAX_INIT_LEN
+        } else {
+            self.len
+        };
+        put_le32(entry, 0, self.lblk);
+        put_le16(entry, 4, len as u16);
+        put_le16(entry, 6, (self.pblk >> 32) as u16);
+        put_le32(entry, 8, self.pblk as u32);
+    }
+}
+
+/// An index entry of an extent tree, pointing to a lower node.
+pub struct ExtentIndex {
+    pub lblk: u32,
+    pub child: u64,
+}
+
+impl ExtentIndex {
+    pub fn parse(entry: &[u8]) -> Self {
+        Self {
+            lblk: le32(entry, 0),
+            child: le32(entry, 4) as u64 | (le16(entry, 8) as u64) << 32,
+        }
+    }
+
+    pub fn write(&self, entry: &mut [u8]) {
+        put_le32(entry, 0, self.lblk);
+        put_le32(entry, 4, self.child as u32);
+        put_le16(entry, 8, (self.child >> 32) as u16);
+        put_le16(entry, 10, 0);
+    }
+}
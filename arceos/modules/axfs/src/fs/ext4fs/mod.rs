@@ -0,0 +1,176 @@
+//! The ext2/3/4 filesystem.
+//!
+//! Volumes are accessed directly on the disk, with extent trees, 64-bit
+//! block numbers, flexible block groups and metadata checksums. The journal
+//! is not used: changes are written in place like ext2, and volumes whose
+//! journal needs recovery are mounted read-only.
+
+mod bitmap;
+mod dir;
+mod inode;
+mod layout;
+mod volume;
+
+use alloc::sync::Arc;
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsOps, VfsResult};
+use axsync::Mutex;
+
+use self::layout::ROOT_INO;
+use self::volume::Volume;
+use crate::dev::Disk;
+
+pub struct Ext4FileSystem {
+    vol: Arc<Mutex<Volume>>,
+}
+
+/// A directory.
+pub struct DirNode {
+    vol: Arc<Mutex<Volume>>,
+    ino: u32,
+}
+
+/// A regular file, or other kinds of nodes except directories.
+pub struct FileNode {
+    vol: Arc<Mutex<Volume>>,
+    ino: u32,
+}
+
+impl Ext4FileSystem {
+    pub fn new(disk: Disk) -> VfsResult<Self> {
+        let vol = Volume::new(disk)?;
+        Ok(Self {
+            vol: Arc::new(Mutex::new(vol)),
+        })
+    }
+}
+
+fn new_node(vol: &Arc<Mutex<Volume>>, ino: u32) -> VfsResult<VfsNodeRef> {
+    let vol = vol.clone();
+    if vol.lock().read_inode(ino)?.is_dir() {
+        Ok(Arc::new(DirNode { vol, ino }))
+    } else {
+        Ok(Arc::new(FileNode { vol, ino }))
+    }
+}
+
+fn node_attr(vol: &Mutex<Volume>, ino: u32) -> VfsResult<VfsNodeAttr> {
+    let mut vol = vol.lock();
+    let inode = vol.read_inode(ino)?;
+    let perm = VfsNodePerm::from_bits_truncate(inode.mode() & 0o777);
+    let ty = dir::mode_to_node_type(inode.mode());
+    Ok(VfsNodeAttr::new(
+        perm,
+        ty,
+        inode.size(),
+        vol.inode_sectors(&inode),
+    ))
+}
+
+impl DirNode {
+    /// Walks `path` from this directory, returns the inode number.
+    fn walk(&self, vol: &mut Volume, path: &str) -> VfsResult<u32> {
+        path.split('/')
+            .filter(|name| !name.is_empty() && *name != ".")
+            .try_fold(self.ino, |ino, name| vol.lookup(ino, name))
+    }
+
+    /// Walks to the parent of `path`, returns its inode number and the last
+    /// component of `path`.
+    fn walk_parent<'a>(&self, vol: &mut Volume, path: &'a str) -> VfsResult<(u32, &'a str)> {
+        let path = path.trim_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        Ok((self.walk(vol, parent)?, name))
+    }
+}
+
+impl VfsNodeOps for FileNode {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        node_attr(&self.vol, self.ino)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        self.vol.lock().read_file(self.ino, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.vol.lock().write_file(self.ino, offset, buf)
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        self.vol.lock().truncate_file(self.ino, size)
+    }
+}
+
+impl VfsNodeOps for DirNode {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        node_attr(&self.vol, self.ino)
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        let ino = self.vol.lock().lookup(self.ino, "..").ok()?;
+        Some(Arc::new(DirNode {
+            vol: self.vol.clone(),
+            ino,
+        }))
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        debug!("lookup at ext4fs: {}", path);
+        let ino = self.walk(&mut self.vol.lock(), path)?;
+        new_node(&self.vol, ino)
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        debug!("create {:?} at ext4fs: {}", ty, path);
+        let path = path.trim_matches('/');
+        if path.is_empty() || path == "." {
+            return Ok(());
+        }
+        let mut vol = self.vol.lock();
+        let (dir, name) = self.walk_parent(&mut vol, path)?;
+        vol.create(dir, name, ty)
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        debug!("remove at ext4fs: {}", path);
+        let mut vol = self.vol.lock();
+        let (dir, name) = self.walk_parent(&mut vol, path)?;
+        vol.unlink(dir, name)
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let entries = self.vol.lock().read_dir(self.ino)?;
+        let mut count = 0;
+        for ((name, ty), out_entry) in entries.iter().skip(start_idx).zip(dirents.iter_mut()) {
+            *out_entry = VfsDirEntry::new(name, *ty);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        // `src_path` and `dst_path` should in the same mounted fs
+        debug!(
+            "rename at ext4fs, src_path: {}, dst_path: {}",
+            src_path, dst_path
+        );
+        let mut vol = self.vol.lock();
+        let (src_dir, src_name) = self.walk_parent(&mut vol, src_path)?;
+        let (dst_dir, dst_name) = self.walk_parent(&mut vol, dst_path)?;
+        vol.rename(src_dir, src_name, dst_dir, dst_name)
+    }
+}
+
+impl VfsOps for Ext4FileSystem {
+    fn root_dir(&self) -> VfsNodeRef {
+        Arc::new(DirNode {
+            vol: self.vol.clone(),
+            ino: ROOT_INO,
+        })
+    }
+}
@@ -0,0 +1,256 @@
+//! Raw access to the device: the superblock, group descriptors and inodes.
+
+use alloc::{vec, vec::Vec};
+use axfs_vfs::{VfsError, VfsResult};
+
+use super::layout::*;
+use crate::dev::Disk;
+
+/// An opened ext2/3/4 volume.
+pub struct Volume {
+    disk: Disk,
+    pub(super) sb: SuperBlock,
+    pub(super) groups: Vec<GroupDesc>,
+    pub(super) block_size: usize,
+    pub(super) group_count: u32,
+    pub(super) csum_seed: u32,
+    pub(super) read_only: bool,
+    sb_dirty: bool,
+}
+
+impl Volume {
+    pub fn new(mut disk: Disk) -> VfsResult<Self> {
+        let mut raw = vec![0; SUPERBLOCK_SIZE];
+        read_disk(&mut disk, SUPERBLOCK_OFFSET, &mut raw)?;
+        let sb = SuperBlock::new(raw);
+        if sb.magic() != EXT4_MAGIC {
+            warn!("ext4: bad magic number {:#x}", sb.magic());
+            return Err(VfsError::InvalidData);
+        }
+        let unsupported = sb.feature_incompat() & !INCOMPAT_SUPPORTED;
+        if unsupported != 0 {
+            warn!("ext4: unsupported incompatible features {:#x}", unsupported);
+            return Err(VfsError::Unsupported);
+        }
+
+        let group_count = check_geometry(&sb).map_err(|msg| {
+            warn!("ext4: {}", msg);
+            VfsError::InvalidData
+        })?;
+
+        let mut read_only = false;
+        if sb.has_incompat(INCOMPAT_RECOVER) {
+            warn!("ext4: the journal needs recovery, mount read-only");
+            read_only = true;
+        }
+        let unsupported = sb.feature_ro_compat() & !RO_COMPAT_SUPPORTED;
+        if unsupported != 0 || sb.log_cluster_size() != sb.log_block_size() {
+            warn!(
+                "ext4: unsupported read-only compatible features {:#x}, mount read-only",
+                unsupported
+            );
+            read_only = true;
+        }
+
+        let block_size = sb.block_size();
+        let desc_size = sb.desc_size();
+        let mut gdt = vec![0; group_count as usize * desc_size];
+        let gdt_pos = (sb.first_data_block() as u64 + 1) * block_size as u64;
+        read_disk(&mut disk, gdt_pos, &mut gdt)?;
+        let groups = gdt
+            .chunks(desc_size)
+            .map(|raw| GroupDesc { raw: raw.to_vec() })
+            .collect();
+
+        let csum_seed = if sb.has_incompat(INCOMPAT_CSUM_SEED) {
+            sb.checksum_seed()
+        } else {
+            crc32c(!0, sb.uuid())
+        };
+
+        info!(
+            "ext4: {} blocks of {} bytes, {} groups, features {:#x}/{:#x}",
+            sb.blocks_count(),
+            block_size,
+            group_count,
+            sb.feature_incompat(),
+            sb.feature_ro_compat(),
+        );
+        Ok(Self {
+            disk,
+            sb,
+            groups,
+            block_size,
+            group_count,
+            csum_seed,
+            read_only,
+            sb_dirty: false,
+        })
+    }
+
+    pub fn check_writable(&self) -> VfsResult {
+        if self.read_only {
+            Err(VfsError::PermissionDenied)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn has_metadata_csum(&self) -> bool {
+        self.sb.has_ro_compat(RO_COMPAT_METADATA_CSUM)
+    }
+
+    pub fn read_bytes(&mut self, pos: u64, buf: &mut [u8]) -> VfsResult {
+        read_disk(&mut self.disk, pos, buf)
+    }
+
+    pub fn write_bytes(&mut self, pos: u64, buf: &[u8]) -> VfsResult {
+        self.disk.set_position(pos);
+        let mut buf = buf;
+        while !buf.is_empty() {
+            let n = self.disk.write_one(buf).map_err(|_| VfsError::Io)?;
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+
+    pub fn read_block(&mut self, blk: u64) -> VfsResult<Vec<u8>> {
+        let mut buf = vec![0; self.block_size];
+        self.read_block_into(blk, &mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn read_block_into(&mut self, blk: u64, buf: &mut [u8]) -> VfsResult {
+        if blk >= self.sb.blocks_count() {
+            warn!("ext4: block {} out of range", blk);
+            return Err(VfsError::InvalidData);
+        }
+        self.read_bytes(blk * self.block_size as u64, buf)
+    }
+
+    pub fn write_block(&mut self, blk: u64, buf: &[u8]) -> VfsResult {
+        if blk >= self.sb.blocks_count() {
+            warn!("ext4: block {} out of range", blk);
+            return Err(VfsError::InvalidData);
+        }
+        self.write_bytes(blk * self.block_size as u64, buf)
+    }
+
+    /// Marks the superblock to be written by [`Volume::flush`].
+    pub fn mark_super_dirty(&mut self) {
+        self.sb_dirty = true;
+    }
+
+    /// Writes the superblock back if it was changed.
+    pub fn flush(&mut self) -> VfsResult {
+        if self.sb_dirty {
+            self.sb.update_checksum();
+            let raw = core::mem::take(&mut self.sb.raw);
+            let res = self.write_bytes(SUPERBLOCK_OFFSET, &raw);
+            self.sb.raw = raw;
+            res?;
+            self.sb_dirty = false;
+        }
+        Ok(())
+    }
+
+    pub fn write_group(&mut self, group: u32) -> VfsResult {
+        let desc = self
+            .groups
+            .get_mut(group as usize)
+            .ok_or(VfsError::InvalidData)?;
+        desc.update_checksum(&self.sb, self.csum_seed, group);
+        let raw = desc.raw.clone();
+        let gdt_pos = (self.sb.first_data_block() as u64 + 1) * self.block_size as u64;
+        let pos = gdt_pos + group as u64 * self.sb.desc_size() as u64;
+        self.write_bytes(pos, &raw)
+    }
+
+    fn inode_pos(&self, ino: u32) -> VfsResult<u64> {
+        if ino == 0 || ino > self.sb.inodes_count() {
+            warn!("ext4: inode {} out of range", ino);
+            return Err(VfsError::InvalidData);
+        }
+        let ipg = self.sb.inodes_per_group();
+        let group = (ino - 1) / ipg;
+        let index = (ino - 1) % ipg;
+        let Some(desc) = self.groups.get(group as usize) else {
+            warn!("ext4: inode {} in missing group {}", ino, group);
+            return Err(VfsError::InvalidData);
+        };
+        let table = desc.inode_table();
+        Ok(table * self.block_size as u64 + index as u64 * self.sb.inode_size() as u64)
+    }
+
+    pub fn read_inode(&mut self, ino: u32) -> VfsResult<Inode> {
+        let pos = self.inode_pos(ino)?;
+        let mut inode = Inode::new(self.sb.inode_size());
+        self.read_bytes(pos, &mut inode.raw)?;
+        Ok(inode)
+    }
+
+    pub fn write_inode(&mut self, ino: u32, inode: &mut Inode) -> VfsResult {
+        if self.has_metadata_csum() {
+            let seed = inode.csum_seed(self.csum_seed, ino);
+            inode.update_checksum(seed);
+        }
+        let pos = self.inode_pos(ino)?;
+        self.write_bytes(pos, &inode.raw)
+    }
+
+    /// Returns the checksum seed of blocks owned by the inode.
+    pub fn inode_seed(&self, ino: u32, inode: &Inode) -> u32 {
+        inode.csum_seed(self.csum_seed, ino)
+    }
+}
+
+/// Checks the geometry described by the superblock, so that a corrupted
+/// image is rejected here instead of overflowing or indexing out of bounds
+/// later. Returns the number of block groups.
+fn check_geometry(sb: &SuperBlock) -> Result<u32, &'static str> {
+    if sb.log_block_size() > MAX_LOG_BLOCK_SIZE {
+        return Err("bad block size");
+    }
+    let block_size = sb.block_size();
+    let bits_per_block = block_size as u32 * 8;
+    // with bigalloc, the volume is read-only and the bitmaps are not used
+    let bigalloc = sb.log_cluster_size() != sb.log_block_size();
+    let bpg = sb.blocks_per_group();
+    if bpg == 0 || (bpg > bits_per_block && !bigalloc) {
+        return Err("bad number of blocks per group");
+    }
+    let ipg = sb.inodes_per_group();
+    if ipg == 0 || ipg > bits_per_block {
+        return Err("bad number of inodes per group");
+    }
+    let data_blocks = sb
+        .blocks_count()
+        .checked_sub(sb.first_data_block() as u64)
+        .filter(|&n| n > 0)
+        .ok_or("bad number of blocks")?;
+    let group_count =
+        u32::try_from(data_blocks.div_ceil(bpg as u64)).map_err(|_| "too many block groups")?;
+    if sb.inodes_count() as u64 > group_count as u64 * ipg as u64 {
+        return Err("bad number of inodes");
+    }
+    let desc_size = sb.desc_size();
+    if desc_size < 32 || desc_size > block_size || !desc_size.is_power_of_two() {
+        return Err("bad group descriptor size");
+    }
+    let inode_size = sb.inode_size();
+    if inode_size < GOOD_OLD_INODE_SIZE || inode_size > block_size || !inode_size.is_power_of_two()
+    {
+        return Err("bad inode size");
+    }
+    Ok(group_count)
+}
+
+fn read_disk(disk: &mut Disk, pos: u64, buf: &mut [u8]) -> VfsResult {
+    disk.set_position(pos);
+    let mut buf = buf;
+    while !buf.is_empty() {
+        let n = disk.read_one(buf).map_err(|_| VfsError::Io)?;
+        buf = &mut buf[n..];
+    }
+    Ok(())
+}
@@ -1,6 +1,8 @@
 cfg_if::cfg_if! {
     if #[cfg(feature = "myfs")] {
         pub mod myfs;
+    } else if #[cfg(feature = "ext4fs")] {
+        pub mod ext4fs;
     } else if #[cfg(feature = "fatfs")] {
         pub mod fatfs;
     }
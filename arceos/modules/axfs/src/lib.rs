@@ -2,21 +2,33 @@
 //!
 //! It provides unified filesystem operations for various filesystems.
 //!
+//! Filesystems on the disk access it through a write-back block cache, which
+//! is flushed by [`api::sync`] or [`File::flush`](fops::File::flush), and by
+//! [`sync_on_shutdown`] when the system is shut down.
+//!
 //! # Cargo Features
 //!
 //! - `fatfs`: Use [FAT] as the main filesystem and mount it on `/`. This feature
 //!    is **enabled** by default.
+//! - `ext4fs`: Use [ext2/ext4] as the main filesystem and mount it on `/`. The
+//!    disk must already be formatted, e.g. by `mkfs.ext4`. This feature is
+//!    **disabled** by default, but it will override `fatfs` if both are enabled.
 //! - `devfs`: Mount [`axfs_devfs::DeviceFileSystem`] on `/dev`. This feature is
 //!    **enabled** by default.
 //! - `ramfs`: Mount [`axfs_ramfs::RamFileSystem`] on `/tmp`. This feature is
 //!    **enabled** by default.
+//! - `procfs`: Mount a ramfs on `/proc`, with files generated when read,
+//!    added by [`register_proc_file`]. This feature is **enabled** by default.
 //! - `myfs`: Allow users to define their custom filesystems to override the
 //!    default. In this case, [`MyFileSystemIf`] is required to be implemented
 //!    to create and initialize other filesystems. This feature is **disabled** by
 //!    by default, but it will override other filesystem selection features if
 //!    both are enabled.
+//! - `alloc-tag`: Attribute the heap allocations of the block cache to the
+//!    `"fs cache"` allocation tag.
 //!
 //! [FAT]: https://en.wikipedia.org/wiki/File_Allocation_Table
+//! [ext2/ext4]: https://en.wikipedia.org/wiki/Ext4
 //! [`MyFileSystemIf`]: fops::MyFileSystemIf
 
 #![cfg_attr(all(not(test), not(doc)), no_std)]
@@ -26,16 +38,35 @@
 extern crate log;
 extern crate alloc;
 
+mod cache;
 mod dev;
 mod fs;
 mod mounts;
+#[cfg(feature = "procfs")]
+mod procfs;
 mod root;
 
 pub mod api;
 pub mod fops;
 
+#[cfg(feature = "procfs")]
+pub use self::procfs::register_proc_file;
+
 use axdriver::{prelude::*, AxDeviceContainer};
 
+/// Writes the dirty blocks in the block caches back to the disks, before the
+/// system is shut down.
+///
+/// Unlike [`api::sync`], it can be called at any time, e.g., on a panic. It's
+/// skipped if a cache is in use.
+pub fn sync_on_shutdown() {
+    match cache::try_sync_all() {
+        Some(Ok(())) => {}
+        Some(Err(e)) => warn!("failed to sync filesystems: {:?}", e),
+        None => warn!("block cache in use, dirty blocks are not written back"),
+    }
+}
+
 /// Initializes filesystems by block devices.
 pub fn init_filesystems(mut blk_devs: AxDeviceContainer<AxBlockDevice>) {
     info!("Initialize filesystems...");
@@ -22,7 +22,7 @@ pub(crate) fn ramfs() -> Arc<fs::ramfs::RamFileSystem> {
 }
 
 #[cfg(feature = "procfs")]
-pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
+pub(crate) fn procfs() -> VfsResult<Arc<crate::procfs::ProcFileSystem>> {
     let procfs = fs::ramfs::RamFileSystem::new();
     let proc_root = procfs.root_dir();
 
@@ -44,7 +44,7 @@ pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
     proc_root.create("self", VfsNodeType::Dir)?;
     proc_root.create("self/stat", VfsNodeType::File)?;
 
-    Ok(Arc::new(procfs))
+    Ok(Arc::new(crate::procfs::ProcFileSystem::new(procfs)))
 }
 
 #[cfg(feature = "sysfs")]
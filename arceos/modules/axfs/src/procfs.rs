@@ -0,0 +1,143 @@
+//! Files in procfs whose contents are generated when read, e.g.,
+//! `/proc/meminfo`.
+//!
+//! The other files of procfs are ordinary ramfs files, the generated files
+//! are placed in the root directory of it.
+
+use alloc::{collections::BTreeMap, string::String, sync::Arc};
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsError, VfsOps, VfsResult};
+use axsync::Mutex;
+
+use crate::fs;
+
+static PROC_FILES: Mutex<BTreeMap<&'static str, fn() -> String>> = Mutex::new(BTreeMap::new());
+
+/// Adds a file to the root directory of procfs (`/proc`), whose content is
+/// generated by `generate` each time it is read.
+///
+/// It can be called before or after the filesystems are initialized. A file
+/// with the same name is replaced.
+pub fn register_proc_file(name: &'static str, generate: fn() -> String) {
+    PROC_FILES.lock().insert(name, generate);
+}
+
+/// Returns the generator of the file at `path` relative to the procfs root.
+fn proc_file(path: &str) -> Option<fn() -> String> {
+    let path = path.trim_matches('/');
+    let name = path.strip_prefix("./").unwrap_or(path);
+    PROC_FILES.lock().get(name).copied()
+}
+
+/// A read-only file whose content is generated when read.
+struct ProcFile(fn() -> String);
+
+impl VfsNodeOps for ProcFile {
+    axfs_vfs::impl_vfs_non_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let size = (self.0)().len() as u64;
+        let perm = VfsNodePerm::from_bits_truncate(0o444);
+        Ok(VfsNodeAttr::new(perm, VfsNodeType::File, size, 0))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let content = (self.0)();
+        let content = content.as_bytes();
+        let start = content.len().min(offset as usize);
+        let len = buf.len().min(content.len() - start);
+        buf[..len].copy_from_slice(&content[start..start + len]);
+        Ok(len)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> VfsResult<usize> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Err(VfsError::PermissionDenied)
+    }
+}
+
+/// The root directory of procfs, which adds the generated files to the root
+/// directory of the ramfs.
+struct ProcRootDir(VfsNodeRef);
+
+impl VfsNodeOps for ProcRootDir {
+    axfs_vfs::impl_vfs_dir_default! {}
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.0.get_attr()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.0.parent()
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let path = path.trim_matches('/');
+        if path.is_empty() || path == "." {
+            return Ok(self.clone());
+        }
+        if let Some(rest) = path.strip_prefix("./") {
+            return self.lookup(rest);
+        }
+        if let Some(generate) = proc_file(path) {
+            return Ok(Arc::new(ProcFile(generate)));
+        }
+        self.0.clone().lookup(path)
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        if proc_file(path).is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+        self.0.create(path, ty)
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        if proc_file(path).is_some() {
+            return Err(VfsError::PermissionDenied);
+        }
+        self.0.remove(path)
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        // The generated files come first.
+        let files = PROC_FILES.lock();
+        let mut count = 0;
+        for (name, out_entry) in files.keys().skip(start_idx).zip(dirents.iter_mut()) {
+            *out_entry = VfsDirEntry::new(name, VfsNodeType::File);
+            count += 1;
+        }
+        let inner_idx = start_idx.saturating_sub(files.len());
+        Ok(count + self.0.read_dir(inner_idx, &mut dirents[count..])?)
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        self.0.rename(src_path, dst_path)
+    }
+}
+
+/// The procfs, a ramfs with generated files.
+pub(crate) struct ProcFileSystem {
+    ramfs: fs::ramfs::RamFileSystem,
+    root: Arc<ProcRootDir>,
+}
+
+impl ProcFileSystem {
+    pub fn new(ramfs: fs::ramfs::RamFileSystem) -> Self {
+        let root = Arc::new(ProcRootDir(ramfs.root_dir()));
+        Self { ramfs, root }
+    }
+}
+
+impl VfsOps for ProcFileSystem {
+    fn mount(&self, path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        self.ramfs.mount(path, mount_point)
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+}
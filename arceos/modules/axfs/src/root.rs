@@ -1,11 +1,13 @@
-//! Root directory of the filesystem
+//! Root directory of the filesystem, and the mount table.
 //!
-//! TODO: it doesn't work very well if the mount points have containment relationships.
+//! Mount points are kept in a trie indexed by path components, so a path is
+//! dispatched to the filesystem with the longest matched mount point.
 
-use alloc::{string::String, sync::Arc, vec::Vec};
-use axerrno::{ax_err, AxError, AxResult};
-use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps, VfsResult};
+use alloc::{collections::BTreeMap, string::String, sync::Arc, vec::Vec};
+use axerrno::{ax_err, ax_err_type, AxError, AxResult};
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps, VfsResult};
 use axsync::Mutex;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use lazyinit::LazyInit;
 
 use crate::{api::FileType, fs, mounts};
@@ -13,21 +15,81 @@ use crate::{api::FileType, fs, mounts};
 static CURRENT_DIR_PATH: Mutex<String> = Mutex::new(String::new());
 static CURRENT_DIR: LazyInit<Mutex<VfsNodeRef>> = LazyInit::new();
 
+/// Options of a mounted filesystem, shown in the mount table.
+#[derive(Debug, Clone)]
+pub struct MountOptions {
+    /// Where the filesystem comes from, e.g. a device name.
+    pub source: String,
+    /// Name of the filesystem type, e.g. `fatfs`.
+    pub fs_type: String,
+    /// Comma-separated mount flags, e.g. `rw`.
+    pub flags: String,
+}
+
+/// An entry of the mount table, returned by [`mounts`](crate::api::mounts).
+#[derive(Debug, Clone)]
+pub struct MountInfo {
+    /// Absolute path of the mount point.
+    pub path: String,
+    /// Options given when the filesystem was mounted.
+    pub options: MountOptions,
+    /// Number of files and directories currently open on the filesystem.
+    pub open_files: usize,
+}
+
 struct MountPoint {
-    path: &'static str,
+    path: String,
     fs: Arc<dyn VfsOps>,
+    options: MountOptions,
+    open_files: AtomicUsize,
+}
+
+/// A node of the mount trie. Nodes without a mount point and children are
+/// removed, so a node with children always has mount points below it.
+#[derive(Default)]
+struct MountTrie {
+    mount: Option<Arc<MountPoint>>,
+    children: BTreeMap<String, MountTrie>,
 }
 
+/// Holds a mounted filesystem busy while a file or directory on it is open.
+pub(crate) struct MountRef(Arc<MountPoint>);
+
 struct RootDirectory {
     main_fs: Arc<dyn VfsOps>,
-    mounts: Vec<MountPoint>,
+    mounts: Mutex<MountTrie>,
 }
 
 static ROOT_DIR: LazyInit<Arc<RootDirectory>> = LazyInit::new();
 
+impl MountOptions {
+    /// Creates options for a filesystem of type `fs_type`, using the type
+    /// name as the source and `rw` as the flags.
+    pub fn new(fs_type: &str) -> Self {
+        Self {
+            source: fs_type.into(),
+            fs_type: fs_type.into(),
+            flags: "rw".into(),
+        }
+    }
+}
+
 impl MountPoint {
-    pub fn new(path: &'static str, fs: Arc<dyn VfsOps>) -> Self {
-        Self { path, fs }
+    pub fn new(path: &str, fs: Arc<dyn VfsOps>, options: MountOptions) -> Self {
+        Self {
+            path: path.into(),
+            fs,
+            options,
+            open_files: AtomicUsize::new(0),
+        }
+    }
+
+    fn info(&self) -> MountInfo {
+        MountInfo {
+            path: self.path.clone(),
+            options: self.options.clone(),
+            open_files: self.open_files.load(Ordering::Acquire),
+        }
     }
 }
 
@@ -37,37 +99,173 @@ impl Drop for MountPoint {
     }
 }
 
+impl MountRef {
+    fn new(mp: Arc<MountPoint>) -> Self {
+        mp.open_files.fetch_add(1, Ordering::AcqRel);
+        Self(mp)
+    }
+}
+
+impl Clone for MountRef {
+    fn clone(&self) -> Self {
+        Self::new(self.0.clone())
+    }
+}
+
+impl Drop for MountRef {
+    fn drop(&mut self) {
+        self.0.open_files.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Splits the first component from `path`, the rest keeps the leading '/'.
+fn split_component(path: &str) -> (&str, &str) {
+    let path = path.trim_start_matches('/');
+    match path.find('/') {
+        Some(idx) => (&path[..idx], &path[idx..]),
+        None => (path, ""),
+    }
+}
+
+impl MountTrie {
+    /// Returns the node of the normalized absolute `path`.
+    fn get(&self, path: &str) -> Option<&MountTrie> {
+        path.split('/')
+            .filter(|name| !name.is_empty())
+            .try_fold(self, |node, name| node.children.get(name))
+    }
+
+    fn insert(&mut self, path: &str, mp: Arc<MountPoint>) -> AxResult {
+        let node = path
+            .split('/')
+            .filter(|name| !name.is_empty())
+            .fold(self, |node, name| {
+                node.children.entry(name.into()).or_default()
+            });
+        if node.mount.is_some() {
+            return ax_err!(AlreadyExists, "mount point already exists");
+        }
+        node.mount = Some(mp);
+        Ok(())
+    }
+
+    fn remove(&mut self, path: &str) -> Option<Arc<MountPoint>> {
+        let (name, rest) = split_component(path);
+        if name.is_empty() {
+            return self.mount.take();
+        }
+        let child = self.children.get_mut(name)?;
+        let mp = child.remove(rest);
+        if child.mount.is_none() && child.children.is_empty() {
+            self.children.remove(name);
+        }
+        mp
+    }
+
+    /// Finds the mount point with the longest match of `path`. Returns it and
+    /// the rest of the path relative to it.
+    ///
+    /// Matching stops at the first `..`, which is resolved by the filesystem
+    /// of the mount point matched so far.
+    fn lookup<'a>(&self, path: &'a str) -> Option<(&Arc<MountPoint>, &'a str)> {
+        let mut found = self.mount.as_ref().map(|mp| (mp, path));
+        let mut node = self;
+        let mut rest = path;
+        loop {
+            let (name, next) = split_component(rest);
+            if name.is_empty() {
+                break;
+            } else if name != "." {
+                match node.children.get(name) {
+                    Some(child) => node = child,
+                    None => break,
+                }
+                if let Some(mp) = &node.mount {
+                    found = Some((mp, next));
+                }
+            }
+            rest = next;
+        }
+        found
+    }
+
+    fn for_each(&self, f: &mut impl FnMut(&MountPoint)) {
+        if let Some(mp) = &self.mount {
+            f(mp);
+        }
+        for child in self.children.values() {
+            child.for_each(f);
+        }
+    }
+}
+
 impl RootDirectory {
-    pub const fn new(main_fs: Arc<dyn VfsOps>) -> Self {
+    pub fn new(main_fs: Arc<dyn VfsOps>, options: MountOptions) -> Self {
+        let mut mounts = MountTrie::default();
+        mounts.mount = Some(Arc::new(MountPoint::new("/", main_fs.clone(), options)));
         Self {
             main_fs,
-            mounts: Vec::new(),
+            mounts: Mutex::new(mounts),
         }
     }
 
-    pub fn mount(&mut self, path: &'static str, fs: Arc<dyn VfsOps>) -> AxResult {
-        if path == "/" {
+    pub fn mount(&self, path: &str, fs: Arc<dyn VfsOps>, options: MountOptions) -> AxResult {
+        let path = path.trim_end_matches('/');
+        if path.is_empty() {
             return ax_err!(InvalidInput, "cannot mount root filesystem");
         }
         if !path.starts_with('/') {
             return ax_err!(InvalidInput, "mount path must start with '/'");
         }
-        if self.mounts.iter().any(|mp| mp.path == path) {
+        let mut mounts = self.mounts.lock();
+        let (parent, rest) = mounts.lookup(path).unwrap();
+        if rest.is_empty() {
             return ax_err!(InvalidInput, "mount point already exists");
         }
-        // create the mount point in the main filesystem if it does not exist
-        self.main_fs.root_dir().create(path, FileType::Dir)?;
-        fs.mount(path, self.main_fs.root_dir().lookup(path)?)?;
-        self.mounts.push(MountPoint::new(path, fs));
-        Ok(())
+        // create the mount point in its parent filesystem if it does not exist
+        let parent_dir = parent.fs.root_dir();
+        match parent_dir.create(rest, FileType::Dir) {
+            Ok(()) | Err(AxError::AlreadyExists) => {}
+            Err(e) => return Err(e),
+        }
+        let mount_point = parent_dir.lookup(rest)?;
+        if !mount_point.get_attr()?.is_dir() {
+            return ax_err!(NotADirectory);
+        }
+        fs.mount(path, mount_point)?;
+        mounts.insert(path, Arc::new(MountPoint::new(path, fs, options)))
     }
 
-    pub fn _umount(&mut self, path: &str) {
-        self.mounts.retain(|mp| mp.path != path);
+    pub fn umount(&self, path: &str) -> AxResult {
+        let path = path.trim_end_matches('/');
+        if path.is_empty() {
+            return ax_err!(InvalidInput, "cannot unmount root filesystem");
+        }
+        let mut mounts = self.mounts.lock();
+        let (mp, children) = mounts
+            .get(path)
+            .and_then(|node| Some((node.mount.as_ref()?, &node.children)))
+            .ok_or_else(|| ax_err_type!(InvalidInput, "not a mount point"))?;
+        if !children.is_empty() {
+            return ax_err!(ResourceBusy, "other filesystems are mounted on it");
+        }
+        if mp.open_files.load(Ordering::Acquire) > 0 {
+            return ax_err!(ResourceBusy, "filesystem has open files");
+        }
+        let cwd = CURRENT_DIR_PATH.lock();
+        if cwd.starts_with(path) && cwd[path.len()..].starts_with('/') {
+            return ax_err!(ResourceBusy, "filesystem is the current directory");
+        }
+        drop(cwd);
+        mounts.remove(path); // `MountPoint::drop` unmounts the filesystem
+        Ok(())
     }
 
     pub fn contains(&self, path: &str) -> bool {
-        self.mounts.iter().any(|mp| mp.path == path)
+        self.mounts
+            .lock()
+            .get(path)
+            .is_some_and(|node| node.mount.is_some())
     }
 
     fn lookup_mounted_fs<F, T>(&self, path: &str, f: F) -> AxResult<T>
@@ -80,24 +278,13 @@ impl RootDirectory {
             return self.lookup_mounted_fs(rest, f);
         }
 
-        let mut idx = 0;
-        let mut max_len = 0;
-
-        // Find the filesystem that has the longest mounted path match
-        // TODO: more efficient, e.g. trie
-        for (i, mp) in self.mounts.iter().enumerate() {
-            // skip the first '/'
-            if path.starts_with(&mp.path[1..]) && mp.path.len() - 1 > max_len {
-                max_len = mp.path.len() - 1;
-                idx = i;
-            }
-        }
-
-        if max_len == 0 {
-            f(self.main_fs.clone(), path) // not matched any mount point
-        } else {
-            f(self.mounts[idx].fs.clone(), &path[max_len..]) // matched at `idx`
-        }
+        // the lock is not held while accessing the filesystem
+        let (fs, rest) = {
+            let mounts = self.mounts.lock();
+            let (mp, rest) = mounts.lookup(path).unwrap();
+            (mp.fs.clone(), rest)
+        };
+        f(fs, rest)
     }
 }
 
@@ -109,9 +296,18 @@ impl VfsNodeOps for RootDirectory {
     }
 
     fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        if path.trim_matches('/').is_empty() {
+            // keep "/" on the root directory, so paths relative to it cross
+            // mount points the same way as absolute paths do
+            return Ok(self);
+        }
         self.lookup_mounted_fs(path, |fs, rest_path| fs.root_dir().lookup(rest_path))
     }
 
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        self.main_fs.root_dir().read_dir(start_idx, dirents)
+    }
+
     fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
         self.lookup_mounted_fs(path, |fs, rest_path| {
             if rest_path.is_empty() {
@@ -147,38 +343,52 @@ pub(crate) fn init_rootfs(disk: crate::dev::Disk) {
     cfg_if::cfg_if! {
         if #[cfg(feature = "myfs")] { // override the default filesystem
             let main_fs = fs::myfs::new_myfs(disk);
+            let main_opts = MountOptions::new("myfs");
+        } else if #[cfg(feature = "ext4fs")] {
+            let main_fs = Arc::new(
+                fs::ext4fs::Ext4FileSystem::new(disk).expect("failed to mount ext4fs at /"),
+            );
+            let main_opts = MountOptions::new("ext4fs");
         } else if #[cfg(feature = "fatfs")] {
             static FAT_FS: LazyInit<Arc<fs::fatfs::FatFileSystem>> = LazyInit::new();
             FAT_FS.init_once(Arc::new(fs::fatfs::FatFileSystem::new(disk)));
             FAT_FS.init();
             let main_fs = FAT_FS.clone();
+            let main_opts = MountOptions::new("fatfs");
         }
     }
 
-    let mut root_dir = RootDirectory::new(main_fs);
+    let root_dir = RootDirectory::new(main_fs, main_opts);
 
     #[cfg(feature = "devfs")]
     root_dir
-        .mount("/dev", mounts::devfs())
+        .mount("/dev", mounts::devfs(), MountOptions::new("devfs"))
         .expect("failed to mount devfs at /dev");
 
     #[cfg(feature = "ramfs")]
     root_dir
-        .mount("/tmp", mounts::ramfs())
+        .mount("/tmp", mounts::ramfs(), MountOptions::new("ramfs"))
         .expect("failed to mount ramfs at /tmp");
 
     // Mount another ramfs as procfs
     #[cfg(feature = "procfs")]
     root_dir // should not fail
-        .mount("/proc", mounts::procfs().unwrap())
+        .mount(
+            "/proc",
+            mounts::procfs().unwrap(),
+            MountOptions::new("procfs"),
+        )
         .expect("fail to mount procfs at /proc");
 
     // Mount another ramfs as sysfs
     #[cfg(feature = "sysfs")]
     root_dir // should not fail
-        .mount("/sys", mounts::sysfs().unwrap())
+        .mount("/sys", mounts::sysfs().unwrap(), MountOptions::new("sysfs"))
         .expect("fail to mount sysfs at /sys");
 
+    #[cfg(feature = "procfs")]
+    crate::procfs::register_proc_file("mounts", proc_mounts);
+
     ROOT_DIR.init_once(Arc::new(root_dir));
     CURRENT_DIR.init_once(Mutex::new(ROOT_DIR.clone()));
     *CURRENT_DIR_PATH.lock() = "/".into();
@@ -192,6 +402,49 @@ fn parent_node_of(dir: Option<&VfsNodeRef>, path: &str) -> VfsNodeRef {
     }
 }
 
+pub(crate) fn mount(fs: Arc<dyn VfsOps>, path: &str, options: MountOptions) -> AxResult {
+    ROOT_DIR.mount(&absolute_path(path)?, fs, options)
+}
+
+pub(crate) fn umount(path: &str) -> AxResult {
+    ROOT_DIR.umount(&absolute_path(path)?)
+}
+
+pub(crate) fn mount_table() -> Vec<MountInfo> {
+    let mut table = Vec::new();
+    ROOT_DIR
+        .mounts
+        .lock()
+        .for_each(&mut |mp| table.push(mp.info()));
+    table
+}
+
+/// Generates `/proc/mounts`.
+#[cfg(feature = "procfs")]
+fn proc_mounts() -> String {
+    use core::fmt::Write;
+    let mut s = String::new();
+    for info in mount_table() {
+        let opts = &info.options;
+        writeln!(
+            s,
+            "{} {} {} {} 0 0",
+            opts.source, info.path, opts.fs_type, opts.flags
+        )
+        .ok();
+    }
+    s
+}
+
+/// Returns the mount point that `path` belongs to, and holds it busy until
+/// the returned [`MountRef`] is dropped.
+pub(crate) fn mount_ref(path: &str) -> AxResult<MountRef> {
+    let path = absolute_path(path)?;
+    let mounts = ROOT_DIR.mounts.lock();
+    let (mp, _) = mounts.lookup(&path).unwrap();
+    Ok(MountRef::new(mp.clone()))
+}
+
 pub(crate) fn absolute_path(path: &str) -> AxResult<String> {
     if path.starts_with('/') {
         Ok(axfs_vfs::path::canonicalize(path))
@@ -0,0 +1,113 @@
+#![cfg(not(any(feature = "myfs", feature = "ext4fs")))]
+
+//! Compares the filesystem performance with and without the block cache, on
+//! the FAT image in a ramdisk. Run it with `--release -- --nocapture` to see
+//! meaningful numbers.
+
+use std::time::{Duration, Instant};
+
+use axdriver::AxDeviceContainer;
+use axdriver_block::ramdisk::RamDisk;
+use axfs::api::{self as fs, CacheStats, File};
+use axio::{Read, Result, Write};
+
+const IMG_PATH: &str = "resources/fat16.img";
+const FILE_SIZE: usize = 256 * 1024;
+const CHUNK_SIZE: usize = 4096;
+const READ_PASSES: usize = 4;
+
+fn make_disk() -> std::io::Result<RamDisk> {
+    let path = std::env::current_dir()?.join(IMG_PATH);
+    println!("Loading disk image from {:?} ...", path);
+    let data = std::fs::read(path)?;
+    println!("size = {} bytes", data.len());
+    Ok(RamDisk::from(&data))
+}
+
+/// Writes a file, reads it sequentially several times, looks up some paths,
+/// then removes the file.
+fn workload(path: &str, data: &[u8], capacity: usize) -> Result<Duration> {
+    let start = Instant::now();
+    let mut file = File::create(path)?;
+    for chunk in data.chunks(CHUNK_SIZE) {
+        file.write_all(chunk)?;
+    }
+    drop(file);
+    // start reading with an empty cache
+    fs::set_cache_capacity(0)?;
+    fs::set_cache_capacity(capacity)?;
+
+    let mut buf = vec![0; FILE_SIZE];
+    for _ in 0..READ_PASSES {
+        let mut file = File::open(path)?;
+        let mut pos = 0;
+        while pos < FILE_SIZE {
+            let n = file.read(&mut buf[pos..(pos + CHUNK_SIZE).min(FILE_SIZE)])?;
+            assert!(n > 0);
+            pos += n;
+        }
+        assert!(buf == data);
+    }
+    for _ in 0..100 {
+        fs::metadata("/very/long/path/test.txt")?;
+        fs::metadata("/very-long-dir-name/very-long-file-name.txt")?;
+    }
+
+    fs::remove_file(path)?;
+    fs::sync()?;
+    Ok(start.elapsed())
+}
+
+fn run(name: &str, capacity: usize, data: &[u8]) -> (Duration, CacheStats) {
+    fs::set_cache_capacity(capacity).unwrap();
+    let before = fs::cache_stats();
+    let time = workload("/bench.bin", data, capacity).expect("workload failed");
+    let after = fs::cache_stats();
+    let stats = CacheStats {
+        hits: after.hits - before.hits,
+        misses: after.misses - before.misses,
+        readahead: after.readahead - before.readahead,
+        dev_reads: after.dev_reads - before.dev_reads,
+        dev_writes: after.dev_writes - before.dev_writes,
+        ..after
+    };
+    println!(
+        "{:>8}: {:>10.3?}, {} hits, {} misses, {} blocks read ahead, {} reads and {} writes to the device",
+        name, time, stats.hits, stats.misses, stats.readahead, stats.dev_reads, stats.dev_writes
+    );
+    (time, stats)
+}
+
+#[test]
+fn test_block_cache() {
+    println!("Benchmarking the block cache with ramdisk ...");
+
+    let disk = make_disk().expect("failed to load disk image");
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    axfs::init_filesystems(AxDeviceContainer::from_one(disk));
+
+    let capacity = fs::cache_stats().capacity;
+    let data: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    let (uncached_time, uncached) = run("uncached", 0, &data);
+    let (cached_time, cached) = run("cached", capacity, &data);
+    println!(
+        "speed-up: {:.2}x",
+        uncached_time.as_secs_f64() / cached_time.as_secs_f64()
+    );
+
+    assert_eq!(uncached.hits, 0);
+    assert!(cached.hits > cached.misses);
+    assert!(cached.readahead > 0);
+    assert!(cached.dev_reads < uncached.dev_reads);
+    assert!(cached.dev_writes < uncached.dev_writes);
+    assert_eq!(cached.dirty, 0);
+    assert!(cached.cached <= capacity);
+
+    // Syncing a file opened read-only writes back the whole cache.
+    File::create("/sync.txt")
+        .and_then(|mut file| file.write_all(b"dirty"))
+        .unwrap();
+    assert!(fs::cache_stats().dirty > 0);
+    File::open("/sync.txt").unwrap().sync_all().unwrap();
+    assert_eq!(fs::cache_stats().dirty, 0);
+}
@@ -1,7 +1,11 @@
+use std::sync::Arc;
+
 use axfs::api as fs;
+use axfs::fops;
+use axfs_ramfs::RamFileSystem;
 use axio as io;
 
-use fs::{File, FileType, OpenOptions};
+use fs::{File, FileType, MountOptions, OpenOptions};
 use io::{prelude::*, Error, Result};
 
 macro_rules! assert_err {
@@ -252,6 +256,64 @@ fn test_devfs_ramfs() -> Result<()> {
     Ok(())
 }
 
+fn test_mount_umount() -> Result<()> {
+    println!("test mount and umount:");
+    let ramfs = || Arc::new(RamFileSystem::new());
+
+    let table = fs::mounts();
+    assert_eq!(table[0].path, "/");
+    assert!(table.iter().any(|mp| mp.path == "/dev"));
+
+    // mount a new ramfs
+    fs::mount(ramfs(), "/mnt", MountOptions::new("ramfs"))?;
+    assert_err!(
+        fs::mount(ramfs(), "//mnt/", MountOptions::new("ramfs")),
+        InvalidInput
+    );
+    assert_eq!(fs::write("/mnt/test.txt", "mounted"), Ok(()));
+    assert_eq!(fs::read_to_string("mnt/./test.txt")?, "mounted");
+    assert!(fs::read_to_string("/proc/mounts")?.contains("ramfs /mnt ramfs rw"));
+
+    // nested mount points
+    fs::mount(ramfs(), "/mnt/inner", MountOptions::new("ramfs"))?;
+    assert_eq!(fs::write("/mnt/inner/test.txt", "inner"), Ok(()));
+    assert_eq!(fs::read_to_string("/mnt/test.txt")?, "mounted");
+    assert_err!(fs::umount("/mnt"), ResourceBusy);
+    assert_eq!(fs::umount("/mnt/inner"), Ok(()));
+    assert_err!(fs::metadata("/mnt/inner/test.txt"), NotFound);
+
+    // busy with open files
+    let file = File::open("/mnt/test.txt")?;
+    assert_err!(fs::umount("/mnt"), ResourceBusy);
+    drop(file);
+
+    // busy with files opened relative to the root directory
+    let mut opts = fops::OpenOptions::new();
+    opts.read(true);
+    let root = fops::Directory::open_dir("/", &opts)?;
+    let file = root.open_file_at("mnt/test.txt", &opts)?;
+    assert_err!(fs::umount("/mnt"), ResourceBusy);
+    drop(file);
+    let dir = root.open_dir_at("./mnt", &opts)?;
+    assert_err!(fs::umount("/mnt"), ResourceBusy);
+    drop((dir, root));
+    assert_eq!(fs::umount("/mnt/"), Ok(()));
+    assert_err!(fs::metadata("/mnt/test.txt"), NotFound);
+    assert!(!fs::mounts().iter().any(|mp| mp.path == "/mnt"));
+
+    // error cases
+    assert_err!(fs::umount("/mnt"), InvalidInput);
+    assert_err!(fs::umount("/"), InvalidInput);
+    assert_err!(
+        fs::mount(ramfs(), "/", MountOptions::new("ramfs")),
+        InvalidInput
+    );
+    assert_eq!(fs::remove_dir("/mnt"), Ok(()));
+
+    println!("test_mount_umount() OK!");
+    Ok(())
+}
+
 pub fn test_all() {
     test_read_write_file().expect("test_read_write_file() failed");
     test_read_dir().expect("test_read_dir() failed");
@@ -259,4 +321,5 @@ pub fn test_all() {
     test_create_file_dir().expect("test_create_file_dir() failed");
     test_remove_file_dir().expect("test_remove_file_dir() failed");
     test_devfs_ramfs().expect("test_devfs_ramfs() failed");
+    test_mount_umount().expect("test_mount_umount() failed");
 }
@@ -8,6 +8,8 @@ use memory_addr::{PhysAddr, VirtAddr};
 use tock_registers::interfaces::{Readable, Writeable};
 
 pub use self::context::{FpState, TaskContext, TrapFrame};
+pub(crate) use self::trap::init_overflow_stack;
+pub use self::trap::set_kernel_stack_limit;
 
 /// Allows the current CPU to respond to interrupts.
 #[inline]
@@ -6,7 +6,49 @@ use tock_registers::interfaces::Readable;
 
 use super::TrapFrame;
 
-global_asm!(include_str!("trap.S"));
+global_asm!(
+    include_str!("trap.S"),
+    trapframe_size = const core::mem::size_of::<TrapFrame>(),
+    stack_info = sym __PERCPU_TRAP_STACK_INFO,
+);
+
+#[cfg(feature = "uspace")]
+global_asm!(include_str!("uaccess.S"));
+
+/// Per-CPU states for the trap entry to detect kernel stack overflows.
+#[repr(C)]
+struct TrapStackInfo {
+    /// The trap frame must be pushed above this address, 0 if not checked.
+    stack_limit: usize,
+    /// The top of the overflow stack of this CPU.
+    overflow_stack_top: usize,
+}
+
+#[percpu::def_percpu]
+static TRAP_STACK_INFO: TrapStackInfo = TrapStackInfo {
+    stack_limit: 0,
+    overflow_stack_top: 0,
+};
+
+/// Sets up the overflow stack of the current CPU.
+pub(crate) fn init_overflow_stack() {
+    unsafe {
+        TRAP_STACK_INFO.current_ref_mut_raw().overflow_stack_top =
+            crate::trap::overflow_stack_top();
+    }
+}
+
+/// Sets the lowest address of the current kernel stack.
+///
+/// If a trap frame would be pushed below it at EL1, i.e., the kernel stack
+/// has overflowed into the guard page, the synchronous exception raised by
+/// the push is handled on a per-CPU overflow stack instead, so that the
+/// overflow can be reported. 0 disables the check.
+pub fn set_kernel_stack_limit(limit: usize) {
+    let _guard = kernel_guard::IrqSave::new();
+    unsafe { TRAP_STACK_INFO.current_ref_mut_raw().stack_limit = limit };
+    crate::trap::set_stack_limit(limit);
+}
 
 #[repr(u8)]
 #[derive(Debug)]
@@ -41,7 +83,7 @@ fn handle_irq_exception(_tf: &TrapFrame) {
     handle_trap!(IRQ, 0);
 }
 
-fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
+fn handle_instruction_abort(tf: &mut TrapFrame, iss: u64, is_user: bool) {
     let mut access_flags = MappingFlags::EXECUTE;
     if is_user {
         access_flags |= MappingFlags::USER;
@@ -64,7 +106,7 @@ fn handle_instruction_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
     }
 }
 
-fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
+fn handle_data_abort(tf: &mut TrapFrame, iss: u64, is_user: bool) {
     let wnr = (iss & (1 << 6)) != 0; // WnR: Write not Read
     let cm = (iss & (1 << 8)) != 0; // CM: Cache maintenance
     let mut access_flags = if wnr & !cm {
@@ -76,11 +118,21 @@ fn handle_data_abort(tf: &TrapFrame, iss: u64, is_user: bool) {
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(FAR_EL1.get() as usize);
+    if !is_user {
+        crate::trap::check_stack_overflow(vaddr);
+    }
 
     // Only handle Translation fault and Permission fault
     if !matches!(iss & 0b111100, 0b0100 | 0b1100) // IFSC or DFSC bits
         || !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user)
     {
+        #[cfg(feature = "uspace")]
+        if !is_user {
+            if let Some(fixup) = crate::uaccess::fixup_exception(tf.elr as usize) {
+                tf.elr = fixup as u64;
+                return;
+            }
+        }
         panic!(
             "Unhandled {} Data Abort @ {:#x}, fault_vaddr={:#x}, ISS=0b{:08b} ({:?}):\n{:#x?}",
             if is_user { "EL0" } else { "EL1" },
@@ -11,6 +11,8 @@ use riscv::register::{satp, sstatus, stvec};
 #[cfg(feature = "uspace")]
 pub use self::context::UspaceContext;
 pub use self::context::{GeneralRegisters, TaskContext, TrapFrame};
+pub(crate) use self::trap::init_overflow_stack;
+pub use self::trap::set_kernel_stack_limit;
 
 /// Allows the current CPU to respond to interrupts.
 #[inline]
@@ -9,19 +9,71 @@ include_asm_marcos!();
 core::arch::global_asm!(
     include_str!("trap.S"),
     trapframe_size = const core::mem::size_of::<TrapFrame>(),
+    stack_info = sym __PERCPU_TRAP_STACK_INFO,
 );
 
+/// Per-CPU states for the trap entry to detect kernel stack overflows.
+#[repr(C)]
+struct TrapStackInfo {
+    /// Saves `t0` and `t1` while checking the stack.
+    scratch: [usize; 2],
+    /// The trap frame must be pushed above this address, 0 if not checked.
+    stack_limit: usize,
+    /// The top of the overflow stack of this CPU.
+    overflow_stack_top: usize,
+}
+
+#[percpu::def_percpu]
+static TRAP_STACK_INFO: TrapStackInfo = TrapStackInfo {
+    scratch: [0; 2],
+    stack_limit: 0,
+    overflow_stack_top: 0,
+};
+
+/// Sets up the overflow stack of the current CPU.
+pub(crate) fn init_overflow_stack() {
+    unsafe {
+        TRAP_STACK_INFO.current_ref_mut_raw().overflow_stack_top =
+            crate::trap::overflow_stack_top();
+    }
+}
+
+/// Sets the lowest address of the current kernel stack.
+///
+/// If a trap frame would be pushed below it in the supervisor mode, i.e., the
+/// kernel stack has overflowed into the guard page, the trap is handled on a
+/// per-CPU overflow stack instead, so that the overflow can be reported. 0
+/// disables the check.
+pub fn set_kernel_stack_limit(limit: usize) {
+    let _guard = kernel_guard::IrqSave::new();
+    unsafe { TRAP_STACK_INFO.current_ref_mut_raw().stack_limit = limit };
+    crate::trap::set_stack_limit(limit);
+}
+
+#[cfg(feature = "uspace")]
+core::arch::global_asm!(include_str!("uaccess.S"));
+
 fn handle_breakpoint(sepc: &mut usize) {
     debug!("Exception(Breakpoint) @ {:#x} ", sepc);
     *sepc += 2
 }
 
-fn handle_page_fault(tf: &TrapFrame, mut access_flags: MappingFlags, is_user: bool) {
+fn handle_page_fault(tf: &mut TrapFrame, mut access_flags: MappingFlags, is_user: bool) {
     if is_user {
         access_flags |= MappingFlags::USER;
     }
     let vaddr = va!(stval::read());
+    if !is_user {
+        crate::trap::check_stack_overflow(vaddr);
+    }
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, is_user) {
+        #[cfg(feature = "uspace")]
+        if !is_user {
+            if let Some(fixup) = crate::uaccess::fixup_exception(tf.sepc) {
+                tf.sepc = fixup;
+                return;
+            }
+        }
         panic!(
             "Unhandled {} Page Fault @ {:#x}, fault_vaddr={:#x} ({:?}):\n{:#x?}",
             if is_user { "User" } else { "Supervisor" },
@@ -1,5 +1,6 @@
 use core::fmt;
 
+use x86::irq::DOUBLE_FAULT_VECTOR;
 use x86_64::addr::VirtAddr;
 use x86_64::structures::idt::{Entry, HandlerFunc, InterruptDescriptorTable};
 use x86_64::structures::DescriptorTablePointer;
@@ -13,6 +14,15 @@ pub struct IdtStruct {
 }
 
 impl IdtStruct {
+    /// Index of the interrupt stack table (IST) entry in the TSS, the double
+    /// fault (#DF) handler runs on that stack.
+    ///
+    /// When the kernel stack overflows into its guard page, the page fault
+    /// (#PF) can't be delivered, which raises a #DF. Running the #DF handler
+    /// on its own stack lets it report the overflow. The #PF handler is not
+    /// moved, as it may block, which is not allowed on a per-CPU stack.
+    pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
+
     /// Constructs a new IDT struct that filled with entries from
     /// `trap_handler_table`.
     #[allow(clippy::new_without_default)]
@@ -33,7 +43,10 @@ impl IdtStruct {
         };
         for i in 0..NUM_INT {
             #[allow(clippy::missing_transmute_annotations)]
-            entries[i].set_handler_fn(unsafe { core::mem::transmute(ENTRIES[i]) });
+            let opts = entries[i].set_handler_fn(unsafe { core::mem::transmute(ENTRIES[i]) });
+            if i == DOUBLE_FAULT_VECTOR as usize {
+                unsafe { opts.set_stack_index(Self::DOUBLE_FAULT_IST_INDEX) };
+            }
         }
         idt
     }
@@ -76,6 +76,16 @@ pub unsafe fn write_page_table_root(root_paddr: PhysAddr) {
     }
 }
 
+/// Sets the lowest address of the current kernel stack, 0 if unknown.
+///
+/// Page faults in the guard region below it are reported as kernel stack
+/// overflows. A page fault raised by pushing the trap frame there turns into
+/// a double fault, which is always handled on the overflow stack of the CPU.
+#[inline]
+pub fn set_kernel_stack_limit(limit: usize) {
+    crate::trap::set_stack_limit(limit);
+}
+
 /// Flushes the TLB.
 ///
 /// If `vaddr` is [`None`], flushes the entire TLB. Otherwise, flushes the TLB
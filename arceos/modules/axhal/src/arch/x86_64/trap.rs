@@ -6,14 +6,27 @@ use super::context::TrapFrame;
 
 core::arch::global_asm!(include_str!("trap.S"));
 
+#[cfg(feature = "uspace")]
+core::arch::global_asm!(include_str!("uaccess.S"));
+
 const IRQ_VECTOR_START: u8 = 0x20;
 const IRQ_VECTOR_END: u8 = 0xff;
 
-fn handle_page_fault(tf: &TrapFrame) {
+fn handle_page_fault(tf: &mut TrapFrame) {
     let access_flags = err_code_to_flags(tf.error_code)
         .unwrap_or_else(|e| panic!("Invalid #PF error code: {:#x}", e));
     let vaddr = va!(unsafe { cr2() });
+    if !tf.is_user() {
+        crate::trap::check_stack_overflow(vaddr);
+    }
     if !handle_trap!(PAGE_FAULT, vaddr, access_flags, tf.is_user()) {
+        #[cfg(feature = "uspace")]
+        if !tf.is_user() {
+            if let Some(fixup) = crate::uaccess::fixup_exception(tf.rip as usize) {
+                tf.rip = fixup as u64;
+                return;
+            }
+        }
         panic!(
             "Unhandled {} #PF @ {:#x}, fault_vaddr={:#x}, error_code={:#x} ({:?}):\n{:#x?}",
             if tf.is_user() { "user" } else { "kernel" },
@@ -26,11 +39,24 @@ fn handle_page_fault(tf: &TrapFrame) {
     }
 }
 
+/// A kernel stack overflow raises a #DF, as the #PF in the guard page can't be
+/// pushed to the stack. It's handled on the overflow stack, and CR2 is still
+/// the fault address, so the overflow can be reported.
+fn handle_double_fault(tf: &TrapFrame) {
+    let vaddr = va!(unsafe { cr2() });
+    crate::trap::check_stack_overflow(vaddr);
+    panic!(
+        "#DF @ {:#x}, fault_vaddr={:#x}, error_code={:#x}:\n{:#x?}",
+        tf.rip, vaddr, tf.error_code, tf
+    );
+}
+
 #[no_mangle]
-fn x86_trap_handler(tf: &TrapFrame) {
+fn x86_trap_handler(tf: &mut TrapFrame) {
     match tf.vector as u8 {
         PAGE_FAULT_VECTOR => handle_page_fault(tf),
         BREAKPOINT_VECTOR => debug!("#BP @ {:#x} ", tf.rip),
+        DOUBLE_FAULT_VECTOR => handle_double_fault(tf),
         GENERAL_PROTECTION_FAULT_VECTOR => {
             panic!(
                 "#GP @ {:#x}, error_code={:#x}:\n{:#x?}",
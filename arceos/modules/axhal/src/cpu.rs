@@ -81,6 +81,12 @@ pub(crate) fn init_primary(cpu_id: usize) {
         CPU_ID.write_current_raw(cpu_id);
         IS_BSP.write_current_raw(true);
     }
+    #[cfg(any(
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+        target_arch = "aarch64"
+    ))]
+    crate::arch::init_overflow_stack();
 }
 
 #[allow(dead_code)]
@@ -90,4 +96,10 @@ pub(crate) fn init_secondary(cpu_id: usize) {
         CPU_ID.write_current_raw(cpu_id);
         IS_BSP.write_current_raw(false);
     }
+    #[cfg(any(
+        target_arch = "riscv32",
+        target_arch = "riscv64",
+        target_arch = "aarch64"
+    ))]
+    crate::arch::init_overflow_stack();
 }
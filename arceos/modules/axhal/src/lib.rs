@@ -20,6 +20,8 @@
 //! - `fp_simd`: Enable floating-point and SIMD support.
 //! - `paging`: Enable page table manipulation.
 //! - `irq`: Enable interrupt handling support.
+//! - `uspace`: Enable user space support, including fault-tolerant access to
+//!    user memory by [`uaccess`].
 //!
 //! [ArceOS]: https://github.com/arceos-org/arceos
 //! [cargo test]: https://doc.rust-lang.org/cargo/guide/tests.html
@@ -58,6 +60,9 @@ pub mod irq;
 #[cfg(feature = "paging")]
 pub mod paging;
 
+#[cfg(feature = "uspace")]
+pub mod uaccess;
+
 /// Console input and output.
 pub mod console {
     pub use super::platform::console::*;
@@ -7,7 +7,7 @@ use page_table_multiarch::PagingHandler;
 use crate::mem::{phys_to_virt, virt_to_phys, MemRegionFlags, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 
 #[doc(no_inline)]
-pub use page_table_multiarch::{MappingFlags, PageSize, PagingError, PagingResult};
+pub use page_table_multiarch::{GenericPTE, MappingFlags, PageSize, PagingError, PagingResult};
 
 impl From<MemRegionFlags> for MappingFlags {
     fn from(f: MemRegionFlags) -> Self {
@@ -57,12 +57,24 @@ cfg_if::cfg_if! {
     if #[cfg(target_arch = "x86_64")] {
         /// The architecture-specific page table.
         pub type PageTable = page_table_multiarch::x86_64::X64PageTable<PagingHandlerImpl>;
+        /// The architecture-specific page table entry.
+        pub type PageTableEntry = page_table_entry::x86_64::X64PTE;
+        /// The number of levels of the page table.
+        pub const PAGE_TABLE_LEVELS: usize = 4;
     } else if #[cfg(any(target_arch = "riscv32", target_arch = "riscv64"))] {
         /// The architecture-specific page table.
         pub type PageTable = page_table_multiarch::riscv::Sv39PageTable<PagingHandlerImpl>;
+        /// The architecture-specific page table entry.
+        pub type PageTableEntry = page_table_entry::riscv::Rv64PTE;
+        /// The number of levels of the page table.
+        pub const PAGE_TABLE_LEVELS: usize = 3;
     } else if #[cfg(target_arch = "aarch64")]{
         /// The architecture-specific page table.
         pub type PageTable = page_table_multiarch::aarch64::A64PageTable<PagingHandlerImpl>;
+        /// The architecture-specific page table entry.
+        pub type PageTableEntry = page_table_entry::aarch64::A64PTE;
+        /// The number of levels of the page table.
+        pub const PAGE_TABLE_LEVELS: usize = 4;
     }
 }
 
@@ -1,7 +1,11 @@
 //! Description tables (per-CPU GDT, per-CPU ISS, IDT)
+//!
+//! The TSS of each CPU points the interrupt stack table entry of double
+//! faults to the overflow stack of the CPU.
 
 use crate::arch::{GdtStruct, IdtStruct, TaskStateSegment};
 use lazyinit::LazyInit;
+use x86_64::VirtAddr;
 
 static IDT: LazyInit<IdtStruct> = LazyInit::new();
 
@@ -16,7 +20,10 @@ fn init_percpu() {
         IDT.load();
         let tss = TSS.current_ref_mut_raw();
         let gdt = GDT.current_ref_mut_raw();
-        tss.init_once(TaskStateSegment::new());
+        let mut new_tss = TaskStateSegment::new();
+        new_tss.interrupt_stack_table[IdtStruct::DOUBLE_FAULT_IST_INDEX as usize] =
+            VirtAddr::new(crate::trap::overflow_stack_top() as u64);
+        tss.init_once(new_tss);
         gdt.init_once(GdtStruct::new(tss));
         gdt.load();
         gdt.load_tss();
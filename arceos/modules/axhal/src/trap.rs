@@ -1,7 +1,7 @@
 //! Trap handling.
 
 use linkme::distributed_slice as def_trap_handler;
-use memory_addr::VirtAddr;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
 use page_table_entry::MappingFlags;
 
 #[cfg(feature = "uspace")]
@@ -38,6 +38,75 @@ macro_rules! handle_trap {
     }}
 }
 
+/// The size of the unmapped guard region below each kernel stack.
+pub const KERNEL_STACK_GUARD_SIZE: usize = PAGE_SIZE_4K;
+
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "aarch64"
+))]
+pub(crate) use self::stack_overflow::{check_stack_overflow, overflow_stack_top, set_stack_limit};
+
+/// Detection of kernel stack overflows, for kernel stacks with guard pages.
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "riscv32",
+    target_arch = "riscv64",
+    target_arch = "aarch64"
+))]
+mod stack_overflow {
+    use memory_addr::VirtAddr;
+
+    use super::KERNEL_STACK_GUARD_SIZE;
+
+    /// Size of the per-CPU stack that traps switch to when the kernel stack
+    /// overflows, so that the overflow can still be reported.
+    const OVERFLOW_STACK_SIZE: usize = 0x4000;
+
+    #[repr(C, align(16))]
+    struct OverflowStack([u8; OVERFLOW_STACK_SIZE]);
+
+    static mut OVERFLOW_STACKS: [OverflowStack; axconfig::SMP] =
+        [const { OverflowStack([0; OVERFLOW_STACK_SIZE]) }; axconfig::SMP];
+
+    /// The lowest address of the current kernel stack of each CPU, 0 if unknown.
+    #[percpu::def_percpu]
+    static KERNEL_STACK_LIMIT: usize = 0;
+
+    /// Returns the top of the overflow stack of the current CPU.
+    pub(crate) fn overflow_stack_top() -> usize {
+        let cpu_id = crate::cpu::this_cpu_id();
+        unsafe { core::ptr::addr_of!(OVERFLOW_STACKS[cpu_id]) as usize + OVERFLOW_STACK_SIZE }
+    }
+
+    /// Saves the lowest address of the current kernel stack, for
+    /// [`check_stack_overflow`].
+    pub(crate) fn set_stack_limit(limit: usize) {
+        let _guard = kernel_guard::IrqSave::new();
+        unsafe { KERNEL_STACK_LIMIT.write_current_raw(limit) };
+    }
+
+    /// Panics if the kernel page fault at `vaddr` is in the guard region below
+    /// the current kernel stack, i.e., the kernel stack has overflowed.
+    ///
+    /// It must be called on the overflow stack if the fault is raised by
+    /// pushing the trap frame, see [`crate::arch::set_kernel_stack_limit`].
+    pub(crate) fn check_stack_overflow(vaddr: VirtAddr) {
+        let limit = unsafe { KERNEL_STACK_LIMIT.read_current_raw() };
+        if limit != 0
+            && vaddr.as_usize() < limit
+            && vaddr.as_usize() >= limit - KERNEL_STACK_GUARD_SIZE
+        {
+            panic!(
+                "kernel stack overflow (stack_bottom={:#x}, fault_vaddr={:#x})",
+                limit, vaddr
+            );
+        }
+    }
+}
+
 /// Call the external syscall handler.
 #[cfg(feature = "uspace")]
 pub(crate) fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
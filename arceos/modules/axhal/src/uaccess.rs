@@ -0,0 +1,61 @@
+//! Fault-tolerant access to user memory.
+//!
+//! The instructions that may fault when accessing user memory are recorded
+//! in an exception table (the `.extable` section), along with the addresses
+//! to continue at. When a page fault from the kernel is not handled by the
+//! registered handlers, and the faulting instruction is in the table, the
+//! trap handler resumes at the fixup address instead of panicking.
+
+/// An entry of the exception table, the addresses are relative to the fields
+/// themselves.
+#[repr(C)]
+struct ExceptionTableEntry {
+    insn: i32,
+    fixup: i32,
+}
+
+impl ExceptionTableEntry {
+    fn insn(&self) -> usize {
+        (&self.insn as *const i32 as isize + self.insn as isize) as usize
+    }
+
+    fn fixup(&self) -> usize {
+        (&self.fixup as *const i32 as isize + self.fixup as isize) as usize
+    }
+}
+
+extern "C" {
+    fn _sextable();
+    fn _eextable();
+    fn __copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize;
+}
+
+fn exception_table() -> &'static [ExceptionTableEntry] {
+    let start = _sextable as usize;
+    let len = (_eextable as usize - start) / core::mem::size_of::<ExceptionTableEntry>();
+    unsafe { core::slice::from_raw_parts(start as *const ExceptionTableEntry, len) }
+}
+
+/// Returns the address to continue at if the instruction at `pc` is allowed
+/// to fault.
+pub(crate) fn fixup_exception(pc: usize) -> Option<usize> {
+    exception_table()
+        .iter()
+        .find(|entry| entry.insn() == pc)
+        .map(|entry| entry.fixup())
+}
+
+/// Copies `len` bytes from `src` to `dst`, where either of them may be a user
+/// address.
+///
+/// Unlike [`core::ptr::copy_nonoverlapping`], a page fault that is not
+/// handled by the registered handlers stops the copy rather than panicking.
+/// Returns the number of bytes not copied, i.e., 0 on success.
+///
+/// # Safety
+///
+/// The kernel part of `src` and `dst` must be valid, and they must not
+/// overlap.
+pub unsafe fn copy_user(dst: *mut u8, src: *const u8, len: usize) -> usize {
+    __copy_user(dst, src, len)
+}
@@ -1,24 +1,37 @@
+use core::cell::Cell;
 use core::fmt;
 
 use axerrno::{ax_err, AxError, AxResult};
 use axhal::{
     mem::phys_to_virt,
-    paging::{MappingFlags, PageTable},
+    paging::{GenericPTE, MappingFlags, PageSize, PageTable, PAGE_TABLE_LEVELS},
 };
 use memory_addr::{
-    is_aligned_4k, pa, MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K,
+    is_aligned_4k, pa, va, MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K,
 };
 use memory_set::{MemoryArea, MemorySet};
-use crate::backend::Backend;
+use crate::backend::{split_huge_page_at, Backend};
 use crate::paging_err_to_ax_err;
 use crate::mapping_err_to_ax_err;
+use crate::AreaInfo;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
 use alloc::vec::Vec;
+#[cfg(feature = "fs")]
+use alloc::sync::Arc;
+#[cfg(feature = "fs")]
+use axfs::fops::File;
 
 /// The virtual memory address space.
 pub struct AddrSpace {
     va_range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pt: PageTable,
+    /// Names of address ranges, keyed by the start address, with the end
+    /// address and the name.
+    names: BTreeMap<VirtAddr, (VirtAddr, String)>,
+    #[cfg(feature = "swap")]
+    swap: crate::swap::SwapSpace,
 }
 
 impl AddrSpace {
@@ -59,6 +72,9 @@ impl AddrSpace {
             va_range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pt: PageTable::try_new().map_err(|_| AxError::NoMemory)?,
+            names: BTreeMap::new(),
+            #[cfg(feature = "swap")]
+            swap: Default::default(),
         })
     }
 
@@ -77,6 +93,66 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Creates a copy of this address space, with the allocated frames shared
+    /// between them by copy-on-write.
+    ///
+    /// The mapped pages of allocation areas (added by [`map_alloc`]) and private
+    /// file mappings are made read-only in both address spaces, and each
+    /// address space gets a private copy of a page on the first write to it
+    /// (see [`handle_page_fault`]). Other areas are mapped to the same
+    /// physical memory. If this is a user address space,
+    /// the kernel mappings are copied as [`new_user_aspace`] does.
+    ///
+    /// [`write`] also gives the shared pages it writes to private copies
+    /// first. Pages that have been swapped out are read back before sharing.
+    ///
+    /// [`map_alloc`]: Self::map_alloc
+    /// [`handle_page_fault`]: Self::handle_page_fault
+    /// [`write`]: Self::write
+    /// [`new_user_aspace`]: crate::new_user_aspace
+    pub fn clone_cow(&mut self) -> AxResult<Self> {
+        #[cfg(feature = "swap")]
+        {
+            // Swapped-out pages can't be shared, read them back first.
+            let areas = &self.areas;
+            let flags_of = |vaddr| areas.find(vaddr).unwrap().flags();
+            self.swap.swap_in_all(flags_of, &mut self.pt)?;
+        }
+
+        let mut new = Self::new_empty(self.base(), self.size())?;
+        let kernel_range = VirtAddrRange::from_start_size(
+            va!(axconfig::KERNEL_ASPACE_BASE),
+            axconfig::KERNEL_ASPACE_SIZE,
+        );
+        if !self.va_range.overlaps(kernel_range) {
+            new.copy_mappings_from(&crate::kernel_aspace().lock())?;
+        }
+
+        for area in self.areas.iter() {
+            let backend = match area.backend() {
+                // Mapped lazily first, then the populated pages are shared.
+                Backend::Alloc { .. } => Backend::new_alloc(false),
+                backend => backend.clone(),
+            };
+            let new_area = MemoryArea::new(area.start(), area.size(), area.flags(), backend);
+            new.areas
+                .map(new_area, &mut new.pt, false)
+                .map_err(mapping_err_to_ax_err)?;
+            if !area
+                .backend()
+                .clone_mapping(area.start(), area.size(), &mut self.pt, &mut new.pt)
+            {
+                return ax_err!(BadState, "failed to share pages");
+            }
+        }
+        new.names = self.names.clone();
+        #[cfg(feature = "swap")]
+        {
+            new.swap = self.swap.fork();
+        }
+        Ok(new)
+    }
+
     /// Finds a free area that can accommodate the given size.
     ///
     /// The search starts from the given hint address, and the area should be within the given limit range.
@@ -94,7 +170,8 @@ impl AddrSpace {
     /// Add a new linear mapping.
     ///
     /// The mapping is linear, i.e., `start_vaddr` is mapped to `start_paddr`,
-    /// and `start_vaddr + size` is mapped to `start_paddr + size`.
+    /// and `start_vaddr + size` is mapped to `start_paddr + size`. Huge pages
+    /// are used where the addresses and size are aligned.
     ///
     /// The `flags` parameter indicates the mapping permissions and attributes.
     ///
@@ -121,7 +198,7 @@ impl AddrSpace {
                 |va| pa!(va.as_usize() - offset),
                 size,
                 flags,
-                false, // allow_huge
+                true,  // allow_huge
                 false, // flush_tlb_by_page
             )
             .map_err(paging_err_to_ax_err)?
@@ -131,7 +208,9 @@ impl AddrSpace {
 
     /// Add a new allocation mapping.
     ///
-    /// See [`Backend`] for more details about the mapping backends.
+    /// See [`Backend`] for more details about the mapping backends. Populated
+    /// mappings use huge pages where the address and size are aligned, and
+    /// contiguous physical memory is available.
     ///
     /// The `flags` parameter indicates the mapping permissions and attributes.
     ///
@@ -158,10 +237,101 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Add a new file mapping.
+    ///
+    /// `size` bytes of `file` starting from `offset` are mapped to `start`.
+    /// The pages are read from the file on demand (by handling page faults),
+    /// and the part beyond the end of the file is filled with zeros.
+    ///
+    /// If `shared` is `true` (`MAP_SHARED`), writes to the mapping are written
+    /// back to the file by [`msync`](Self::msync) and on unmapping, but the
+    /// file is not extended. Otherwise (`MAP_PRIVATE`), writes are private to
+    /// this address space.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned, or if the file is not opened for reading (and for writing if
+    /// the mapping is shared and writable).
+    #[cfg(feature = "fs")]
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        file: Arc<File>,
+        offset: u64,
+        shared: bool,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) || !is_aligned_4k(offset as usize) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+        if !file.is_readable() {
+            return ax_err!(PermissionDenied, "file not opened for reading");
+        }
+        if shared && flags.contains(MappingFlags::WRITE) && !file.is_writable() {
+            return ax_err!(PermissionDenied, "file not opened for writing");
+        }
+
+        let backend = Backend::new_file(file, start, offset, shared);
+        let area = MemoryArea::new(start, size, flags, backend);
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Writes the shared file mappings within the specified virtual address
+    /// range back to the files.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned, if any page in the range is not mapped by an area, or if
+    /// failed to write back to the files.
+    #[cfg(feature = "fs")]
+    pub fn msync(&self, start: VirtAddr, size: usize) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let end = start + size;
+        let mut vaddr = start;
+        while vaddr < end {
+            let Some(area) = self.areas.find(vaddr) else {
+                return ax_err!(NoMemory, "address not mapped");
+            };
+            let sync_end = area.end().min(end);
+            let sync_size = sync_end.as_usize() - vaddr.as_usize();
+            area.backend().sync(vaddr, sync_size, &self.pt)?;
+            vaddr = sync_end;
+        }
+        Ok(())
+    }
+
+    /// Writes the shared file mappings overlapping `[start, end)` back to the
+    /// files.
+    #[cfg(feature = "fs")]
+    fn sync_overlapping(&self, start: VirtAddr, end: VirtAddr) -> AxResult {
+        for area in self.areas.iter() {
+            let sync_start = area.start().max(start);
+            let sync_end = area.end().min(end);
+            if sync_start < sync_end {
+                let sync_size = sync_end.as_usize() - sync_start.as_usize();
+                area.backend().sync(sync_start, sync_size, &self.pt)?;
+            }
+        }
+        Ok(())
+    }
+
     /// Removes mappings within the specified virtual address range.
     ///
+    /// Shared file mappings in the range are written back to the files first.
+    ///
     /// Returns an error if the address range is out of the address space or not
-    /// aligned.
+    /// aligned, or if failed to write back to the files.
     pub fn unmap(&mut self, start: VirtAddr, size: usize) -> AxResult {
         if !self.contains_range(start, size) {
             return ax_err!(InvalidInput, "address out of range");
@@ -169,14 +339,126 @@ impl AddrSpace {
         if !start.is_aligned_4k() || !is_aligned_4k(size) {
             return ax_err!(InvalidInput, "address not aligned");
         }
+        #[cfg(feature = "fs")]
+        self.sync_overlapping(start, start + size)?;
 
-        self.pt
-            .unmap_region(start, size, true)
-            .map_err(paging_err_to_ax_err)?
-            .ignore();
+        self.split_huge_pages(start, size)?;
+        self.unmap_linear(start, start + size);
+        #[cfg(feature = "swap")]
+        self.swap.unmap(start, size);
+        self.areas
+            .unmap(start, size, &mut self.pt)
+            .map_err(mapping_err_to_ax_err)?;
+        self.remove_names(start, start + size);
         Ok(())
     }
 
+    /// Unmaps the pages in `[start, end)` that are not in any area, i.e., the
+    /// linear mappings added by [`map_linear`](Self::map_linear).
+    fn unmap_linear(&mut self, start: VirtAddr, end: VirtAddr) {
+        let mut vaddr = start;
+        while vaddr < end {
+            if let Some(area) = self.areas.find(vaddr) {
+                vaddr = area.end();
+                continue;
+            }
+            match self.pt.unmap(vaddr) {
+                Ok((_, page_size, tlb)) => {
+                    tlb.flush();
+                    vaddr = vaddr.align_down(page_size) + page_size.into();
+                }
+                Err(_) => vaddr += PAGE_SIZE_4K,
+            }
+        }
+    }
+
+    /// Names the areas within the specified virtual address range, e.g.,
+    /// `[stack]`, `[heap]`, or the path of the mapped ELF file.
+    ///
+    /// The name is shown by [`areas`] for the areas starting in the range,
+    /// and is removed when the range is unmapped.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    ///
+    /// [`areas`]: Self::areas
+    pub fn set_area_name(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        name: impl Into<String>,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let end = start + size;
+        self.remove_names(start, end);
+        self.names.insert(start, (end, name.into()));
+        Ok(())
+    }
+
+    /// Removes the names within `[start, end)`, the named ranges across the
+    /// boundaries are truncated.
+    fn remove_names(&mut self, start: VirtAddr, end: VirtAddr) {
+        let overlapped: Vec<_> = self
+            .names
+            .range(..end)
+            .rev()
+            .take_while(|(_, (name_end, _))| *name_end > start)
+            .map(|(&name_start, _)| name_start)
+            .collect();
+        for name_start in overlapped {
+            let (name_end, name) = self.names.remove(&name_start).unwrap();
+            if name_start < start {
+                self.names.insert(name_start, (start, name.clone()));
+            }
+            if name_end > end {
+                self.names.insert(end, (name_end, name));
+            }
+        }
+    }
+
+    /// Returns the name of the area starting at `start`.
+    fn area_name(&self, start: VirtAddr) -> Option<&str> {
+        self.names
+            .range(..=start)
+            .next_back()
+            .filter(|(_, (end, _))| *end > start)
+            .map(|(_, (_, name))| name.as_str())
+    }
+
+    /// Returns an iterator over the information of the mapped areas, in
+    /// ascending order of the start address.
+    ///
+    /// Each [`AreaInfo`] is displayed as a line of `/proc/self/maps`.
+    pub fn areas(&self) -> impl Iterator<Item = AreaInfo<'_>> {
+        self.areas.iter().map(|area| self.area_info(area))
+    }
+
+    /// Returns the information of the area containing the given address.
+    pub fn area_at(&self, vaddr: VirtAddr) -> Option<AreaInfo<'_>> {
+        self.areas.find(vaddr).map(|area| self.area_info(area))
+    }
+
+    fn area_info(&self, area: &MemoryArea<Backend>) -> AreaInfo<'_> {
+        let [pages_4k, pages_2m, pages_1g] = self.count_area_pages(area.start(), area.end());
+        let pages_per = |page_size: PageSize| usize::from(page_size) / PAGE_SIZE_4K;
+        AreaInfo {
+            start: area.start(),
+            size: area.size(),
+            flags: area.flags(),
+            kind: area.backend().kind(area.start()),
+            name: self.area_name(area.start()),
+            resident_pages: pages_4k
+                + pages_2m * pages_per(PageSize::Size2M)
+                + pages_1g * pages_per(PageSize::Size1G),
+        }
+    }
+
     /// To process data in this area with the given function.
     ///
     /// Now it supports reading and writing data in the given interval.
@@ -229,16 +511,49 @@ impl AddrSpace {
     ///
     /// * `start_vaddr` - The start virtual address to write.
     /// * `buf` - The buffer to write to the address space.
-    pub fn write(&self, start: VirtAddr, buf: &[u8]) -> AxResult {
+    ///
+    /// Pages shared by copy-on-write (see [`clone_cow`](Self::clone_cow)) get
+    /// private copies first, so other address spaces are not affected. This
+    /// changes the page table, hence it takes `&mut self`.
+    pub fn write(&mut self, start: VirtAddr, buf: &[u8]) -> AxResult {
+        if !self.contains_range(start, buf.len()) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        self.break_cow(start, buf.len())?;
         self.process_area_data(start, buf.len(), |dst, offset, write_size| unsafe {
             core::ptr::copy_nonoverlapping(buf.as_ptr().add(offset), dst.as_mut_ptr(), write_size);
         })
     }
 
+    /// Gives the pages in `[start, start + size)` that are shared by
+    /// copy-on-write private copies of the frames.
+    fn break_cow(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        let end = (start + size).align_up_4k();
+        for vaddr in PageIter4K::new(start.align_down_4k(), end).unwrap() {
+            let Some(area) = self.areas.find(vaddr) else {
+                continue;
+            };
+            if !area.backend().is_cow() {
+                continue;
+            }
+            if let Ok((frame, flags, PageSize::Size4K)) = self.pt.query(vaddr) {
+                if !flags.is_empty()
+                    && !flags.contains(MappingFlags::WRITE)
+                    && crate::backend::is_shared_frame(frame)
+                    && !crate::backend::handle_cow_fault(vaddr, frame, area.flags(), &mut self.pt)
+                {
+                    return ax_err!(NoMemory, "failed to copy a shared page");
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Updates mapping within the specified virtual address range.
     ///
     /// Returns an error if the address range is out of the address space or not
-    /// aligned.
+    /// aligned, or if it makes a shared mapping of a file not opened for
+    /// writing writable.
     pub fn protect(&mut self, start: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
         if !self.contains_range(start, size) {
             return ax_err!(InvalidInput, "address out of range");
@@ -246,30 +561,230 @@ impl AddrSpace {
         if !start.is_aligned_4k() || !is_aligned_4k(size) {
             return ax_err!(InvalidInput, "address not aligned");
         }
+        if flags.contains(MappingFlags::WRITE) {
+            let end = start + size;
+            for area in self.areas.iter() {
+                let overlaps = area.start() < end && start < area.end();
+                if overlaps && !area.backend().can_write() {
+                    return ax_err!(PermissionDenied, "file not opened for writing");
+                }
+            }
+        }
 
-        self.pt
-            .protect_region(start, size, flags, true)
-            .map_err(paging_err_to_ax_err)?
-            .ignore();
+        self.split_huge_pages(start, size)?;
+        // The areas are updated as well, so that the pages faulted in later
+        // get the new flags. Pages shared by copy-on-write stay read-only.
+        self.areas
+            .protect(start, size, |_| Some(flags), &mut self.pt)
+            .map_err(mapping_err_to_ax_err)?;
         Ok(())
     }
 
+    /// Splits the huge pages across the boundaries of the given range.
+    fn split_huge_pages(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        if !split_huge_page_at(&mut self.pt, start)
+            || !split_huge_page_at(&mut self.pt, start + size)
+        {
+            return ax_err!(NoMemory, "failed to split huge pages");
+        }
+        Ok(())
+    }
+
+    /// Returns the number of frames used by the page table, including the
+    /// root but not the tables shared with the kernel.
+    pub fn page_table_frames(&self) -> usize {
+        self.count_pages().1
+    }
+
+    /// Walks the page table, returns the number of mapped 4K, 2M and 1G pages
+    /// and the number of page table frames in the address space.
+    ///
+    /// Unlike [`count_area_pages`](Self::count_area_pages), it also counts the
+    /// pages not in any area, i.e., the linear mappings.
+    fn count_pages(&self) -> ([usize; 3], usize) {
+        let pages: [Cell<usize>; 3] = Default::default();
+        let tables = Cell::new(1);
+        self.pt
+            .walk(
+                usize::MAX,
+                Some(&|level, _, vaddr, entry| {
+                    if !entry.is_present() || !self.va_range.contains(vaddr) {
+                        return;
+                    }
+                    if level == PAGE_TABLE_LEVELS - 1 || entry.is_huge() {
+                        let idx = PAGE_TABLE_LEVELS - 1 - level;
+                        pages[idx].set(pages[idx].get() + 1);
+                    } else {
+                        tables.set(tables.get() + 1);
+                    }
+                }),
+                None,
+            )
+            .ok();
+        (pages.map(Cell::into_inner), tables.get())
+    }
+
+    /// Returns the number of mapped 4K, 2M and 1G pages in `[start, end)`.
+    fn count_area_pages(&self, start: VirtAddr, end: VirtAddr) -> [usize; 3] {
+        let mut counts = [0; 3];
+        let mut vaddr = start;
+        while vaddr < end {
+            match self.pt.query(vaddr) {
+                Ok((_, flags, page_size)) if !flags.is_empty() => {
+                    let idx = match page_size {
+                        PageSize::Size4K => 0,
+                        PageSize::Size2M => 1,
+                        PageSize::Size1G => 2,
+                    };
+                    counts[idx] += 1;
+                    vaddr = vaddr.align_down(page_size) + page_size.into();
+                }
+                _ => vaddr += PAGE_SIZE_4K,
+            }
+        }
+        counts
+    }
+
     /// Handles a page fault at the given address.
     ///
     /// `access_flags` indicates the access type that caused the page fault.
     ///
     /// Returns `true` if the page fault is handled successfully (not a real
-    /// fault).
+    /// fault). Otherwise, the reason and the area at the address are logged.
+    ///
+    /// With the `swap` feature, it may read or write the swap file in place.
+    /// Page fault handlers should use [`handle_page_fault_unlocked`] to do it
+    /// without holding the lock of the address space.
+    ///
+    /// [`handle_page_fault_unlocked`]: crate::handle_page_fault_unlocked
     pub fn handle_page_fault(&mut self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
+        #[cfg(not(feature = "swap"))]
+        let handled = self.handle_page_fault_inner(vaddr, access_flags);
+        #[cfg(feature = "swap")]
+        let handled = loop {
+            match self.try_handle_page_fault(vaddr, access_flags) {
+                Ok(handled) => break handled,
+                Err(io) => {
+                    if !io.run() {
+                        break false;
+                    }
+                }
+            }
+        };
+        if !handled {
+            self.report_page_fault(vaddr, access_flags);
+        }
+        handled
+    }
+
+    /// Logs the reason of a page fault that failed to be handled.
+    ///
+    /// It's only a debug message, since it's up to the caller to report it,
+    /// e.g., a segmentation fault of a user process.
+    pub(crate) fn report_page_fault(&self, vaddr: VirtAddr, access_flags: MappingFlags) {
+        match self.area_at(vaddr) {
+            Some(area) if !area.flags.contains(access_flags) => debug!(
+                "page fault at {:#x} ({:?}): permission denied by area {}",
+                vaddr, access_flags, area
+            ),
+            Some(area) => debug!(
+                "page fault at {:#x} ({:?}): failed to map page in area {}",
+                vaddr, access_flags, area
+            ),
+            None => debug!(
+                "page fault at {:#x} ({:?}): address not mapped",
+                vaddr, access_flags
+            ),
+        }
+    }
+
+    /// Handles a page fault, but returns the swap I/O to do instead of doing
+    /// it, then the page fault should be handled again.
+    #[cfg(feature = "swap")]
+    pub(crate) fn try_handle_page_fault(
+        &mut self,
+        vaddr: VirtAddr,
+        access_flags: MappingFlags,
+    ) -> Result<bool, crate::swap::SwapIo> {
+        if let Some(area) = self.areas.find(vaddr) {
+            let orig_flags = area.flags();
+            if self.va_range.contains(vaddr)
+                && orig_flags.contains(access_flags)
+                && crate::swap::is_swappable(area.backend(), orig_flags)
+            {
+                return self.swap.handle_page_fault(
+                    vaddr,
+                    access_flags,
+                    orig_flags,
+                    area.backend(),
+                    &mut self.pt,
+                );
+            }
+        }
+        Ok(self.handle_page_fault_inner(vaddr, access_flags))
+    }
+
+    /// Returns the number of pages of the address space that are swapped out.
+    #[cfg(feature = "swap")]
+    pub fn swapped_pages(&self) -> usize {
+        self.swap.swapped_pages()
+    }
+
+    /// Checks that `[start, end)` is covered by contiguous areas that allow
+    /// `access` for the user, without mapping any page.
+    #[cfg(feature = "uspace")]
+    pub(crate) fn check_user_range(
+        &self,
+        mut start: VirtAddr,
+        end: VirtAddr,
+        access: MappingFlags,
+    ) -> AxResult {
+        let access = access | MappingFlags::USER;
+        while start < end {
+            match self.areas.find(start) {
+                Some(area) if area.flags().contains(access) => start = area.end(),
+                _ => return ax_err!(BadAddress, "bad user address"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the user page containing `vaddr` allows `access`, and
+    /// maps it if it's not mapped yet, or write-protected for copy-on-write.
+    #[cfg(feature = "uspace")]
+    pub(crate) fn fault_in_user_page(&mut self, vaddr: VirtAddr, access: MappingFlags) -> AxResult {
+        let access = access | MappingFlags::USER;
+        match self.areas.find(vaddr) {
+            Some(area) if area.flags().contains(access) => {}
+            _ => return ax_err!(BadAddress, "bad user address"),
+        }
+        let mapped = matches!(self.pt.query(vaddr), Ok((_, flags, _)) if flags.contains(access));
+        if !mapped && !self.handle_page_fault(vaddr, access) {
+            return ax_err!(BadAddress, "bad user address");
+        }
+        Ok(())
+    }
+
+    fn handle_page_fault_inner(&mut self, vaddr: VirtAddr, access_flags: MappingFlags) -> bool {
         if !self.va_range.contains(vaddr) {
             return false;
         }
         if let Some(area) = self.areas.find(vaddr) {
             let orig_flags = area.flags();
             if orig_flags.contains(access_flags) {
-                return area
-                    .backend()
-                    .handle_page_fault(vaddr, orig_flags, &mut self.pt);
+                if matches!(self.pt.query(vaddr), Ok((_, flags, _)) if flags.contains(access_flags))
+                {
+                    // Spurious, e.g., the huge page was being split, or the
+                    // TLB entry is stale.
+                    axhal::arch::flush_tlb(Some(vaddr));
+                    return true;
+                }
+                return area.backend().handle_page_fault(
+                    vaddr,
+                    access_flags,
+                    orig_flags,
+                    &mut self.pt,
+                );
             }
         }
         false
@@ -326,9 +841,29 @@ impl AddrSpace {
 
 impl fmt::Debug for AddrSpace {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ([pages_4k, pages_2m, pages_1g], page_table_frames) = self.count_pages();
         f.debug_struct("AddrSpace")
             .field("va_range", &self.va_range)
             .field("page_table_root", &self.pt.root_paddr())
+            .field("page_table_frames", &page_table_frames)
+            .field("pages_4k", &pages_4k)
+            .field("pages_2m", &pages_2m)
+            .field("pages_1g", &pages_1g)
             .finish()
     }
 }
+
+impl Drop for AddrSpace {
+    fn drop(&mut self) {
+        // Writes back the shared file mappings, errors can only be logged here.
+        #[cfg(feature = "fs")]
+        for area in self.areas.iter() {
+            if let Err(e) = area.backend().sync(area.start(), area.size(), &self.pt) {
+                warn!("failed to write back {:#x?}: {:?}", area.va_range(), e);
+            }
+        }
+        // Deallocates the frames of the areas, or drops the references to
+        // those shared by copy-on-write.
+        self.areas.clear(&mut self.pt).ok();
+    }
+}
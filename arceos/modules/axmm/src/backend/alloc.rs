@@ -1,11 +1,46 @@
+use alloc::collections::BTreeMap;
+
 use axalloc::global_allocator;
 use axhal::mem::{phys_to_virt, virt_to_phys};
 use axhal::paging::{MappingFlags, PageSize, PageTable};
-use memory_addr::{PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
 
 use super::Backend;
 
-fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
+/// Reference counts of the frames shared by copy-on-write mappings. Frames
+/// not in the table are owned by a single mapping.
+///
+/// A frame stays in the table after the other mappings are gone, until the
+/// last mapping takes it back on a write fault, as its page is still mapped
+/// read-only.
+static FRAME_REFS: SpinNoIrq<BTreeMap<PhysAddr, usize>> = SpinNoIrq::new(BTreeMap::new());
+
+pub(super) fn share_frame(frame: PhysAddr) {
+    *FRAME_REFS.lock().entry(frame).or_insert(1) += 1;
+}
+
+/// Returns whether the frame is shared by copy-on-write, i.e., its pages are
+/// write-protected until copied or taken back by [`handle_cow_fault`].
+pub(crate) fn is_shared_frame(frame: PhysAddr) -> bool {
+    FRAME_REFS.lock().contains_key(&frame)
+}
+
+/// Removes the frame from the table if the caller holds its last reference.
+///
+/// Returns `false` if the frame is still shared by other mappings.
+fn take_frame(frame: PhysAddr) -> bool {
+    let mut refs = FRAME_REFS.lock();
+    match refs.get(&frame) {
+        Some(&count) if count > 1 => false,
+        _ => {
+            refs.remove(&frame);
+            true
+        }
+    }
+}
+
+pub(crate) fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
     let vaddr = VirtAddr::from(global_allocator().alloc_pages(1, PAGE_SIZE_4K).ok()?);
     if zeroed {
         unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, PAGE_SIZE_4K) };
@@ -14,11 +49,108 @@ fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
     Some(paddr)
 }
 
-fn dealloc_frame(frame: PhysAddr) {
+/// Allocates zeroed frames for the page at `vaddr`, with the largest page size
+/// that `vaddr` is aligned to and not larger than `size`. Falls back to
+/// smaller pages if there is no contiguous memory for huge pages.
+fn alloc_page_frames(vaddr: VirtAddr, size: usize) -> Option<(PhysAddr, PageSize)> {
+    for page_size in [PageSize::Size1G, PageSize::Size2M] {
+        let bytes: usize = page_size.into();
+        if !vaddr.is_aligned(bytes) || size < bytes {
+            continue;
+        }
+        if let Ok(vaddr) = global_allocator().alloc_pages(bytes / PAGE_SIZE_4K, bytes) {
+            unsafe { core::ptr::write_bytes(vaddr as *mut u8, 0, bytes) };
+            return Some((virt_to_phys(vaddr.into()), page_size));
+        }
+    }
+    alloc_frame(true).map(|frame| (frame, PageSize::Size4K))
+}
+
+/// Deallocates the frames of a page, dropping the reference for 4K pages.
+fn dealloc_page_frames(frame: PhysAddr, page_size: PageSize) {
+    if page_size.is_huge() {
+        let bytes: usize = page_size.into();
+        global_allocator().dealloc_pages(phys_to_virt(frame).as_usize(), bytes / PAGE_SIZE_4K);
+    } else {
+        dealloc_frame(frame);
+    }
+}
+
+/// Drops a reference to the frame, and deallocates it if it's the last one.
+pub(crate) fn dealloc_frame(frame: PhysAddr) {
+    {
+        let mut refs = FRAME_REFS.lock();
+        if let Some(count) = refs.get_mut(&frame) {
+            *count -= 1;
+            if *count > 0 {
+                return;
+            }
+            refs.remove(&frame);
+        }
+    }
     let vaddr = phys_to_virt(frame);
     global_allocator().dealloc_pages(vaddr.as_usize(), 1);
 }
 
+/// Shares the mapped frames in `[start, start + size)` of `pt` with
+/// `new_pt`, which has the same area mapped lazily.
+///
+/// If `cow` is `true`, the pages are made read-only in both page tables, and
+/// copied on write faults. Otherwise, they are shared with the same flags.
+pub(super) fn share_frames(
+    start: VirtAddr,
+    size: usize,
+    pt: &mut PageTable,
+    new_pt: &mut PageTable,
+    cow: bool,
+) -> bool {
+    debug!(
+        "share_frames: [{:#x}, {:#x}) (cow={})",
+        start,
+        start + size,
+        cow
+    );
+    for addr in PageIter4K::new(start, start + size).unwrap() {
+        if matches!(pt.query(addr), Ok((_, _, page_size)) if page_size.is_huge()) {
+            // Frames are shared and copied in 4K pages.
+            if !super::split_to_4k(pt, addr) {
+                return false;
+            }
+        }
+        let (frame, mut flags, _) = match pt.query(addr) {
+            Ok(entry) if !entry.1.is_empty() => entry,
+            _ => continue, // not populated yet
+        };
+        if cow && flags.contains(MappingFlags::WRITE) {
+            flags -= MappingFlags::WRITE;
+            match pt.protect(addr, flags) {
+                Ok((_, tlb)) => tlb.flush(),
+                Err(_) => return false,
+            }
+        }
+        match new_pt.remap(addr, frame, flags) {
+            Ok((_, tlb)) => tlb.ignore(), // not the current page table
+            Err(_) => return false,
+        }
+        share_frame(frame);
+    }
+    true
+}
+
+/// Removes the write permission of the pages in `[start, start + size)` whose
+/// frames are shared by copy-on-write.
+pub(crate) fn write_protect_shared(start: VirtAddr, size: usize, pt: &mut PageTable) {
+    for addr in PageIter4K::new(start, start + size).unwrap() {
+        if let Ok((frame, flags, _)) = pt.query(addr) {
+            if flags.contains(MappingFlags::WRITE) && is_shared_frame(frame) {
+                if let Ok((_, tlb)) = pt.protect(addr, flags - MappingFlags::WRITE) {
+                    tlb.flush();
+                }
+            }
+        }
+    }
+}
+
 impl Backend {
     /// Creates a new allocation mapping backend.
     pub const fn new_alloc(populate: bool) -> Self {
@@ -41,15 +173,23 @@ impl Backend {
             populate
         );
         if populate {
-            // allocate all possible physical frames for populated mapping.
-            for addr in PageIter4K::new(start, start + size).unwrap() {
-                if let Some(frame) = alloc_frame(true) {
-                    if let Ok(tlb) = pt.map(addr, frame, PageSize::Size4K, flags) {
-                        tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
-                    } else {
-                        return false;
-                    }
+            // allocate all possible physical frames for populated mapping, in
+            // huge pages if possible.
+            let end = start + size;
+            let mut addr = start;
+            while addr < end {
+                let remaining = end.as_usize() - addr.as_usize();
+                let Some((frame, page_size)) = alloc_page_frames(addr, remaining) else {
+                    addr += PAGE_SIZE_4K;
+                    continue;
+                };
+                if let Ok(tlb) = pt.map(addr, frame, page_size, flags) {
+                    tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
+                } else {
+                    dealloc_page_frames(frame, page_size);
+                    return false;
                 }
+                addr += usize::from(page_size);
             }
             true
         } else {
@@ -69,17 +209,19 @@ impl Backend {
         _populate: bool,
     ) -> bool {
         debug!("unmap_alloc: [{:#x}, {:#x})", start, start + size);
-        for addr in PageIter4K::new(start, start + size).unwrap() {
+        // Huge pages across the boundaries have been split by the caller.
+        let end = start + size;
+        let mut addr = start;
+        while addr < end {
             if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
                 // Deallocate the physical frame if there is a mapping in the
                 // page table.
-                if page_size.is_huge() {
-                    return false;
-                }
                 tlb.flush();
-                dealloc_frame(frame);
+                dealloc_page_frames(frame, page_size);
+                addr = addr.align_down(page_size) + page_size.into();
             } else {
                 // Deallocation is needn't if the page is not mapped.
+                addr += PAGE_SIZE_4K;
             }
         }
         true
@@ -88,10 +230,23 @@ impl Backend {
     pub(crate) fn handle_page_fault_alloc(
         &self,
         vaddr: VirtAddr,
+        access_flags: MappingFlags,
         orig_flags: MappingFlags,
         pt: &mut PageTable,
         populate: bool,
     ) -> bool {
+        if let Ok((frame, flags, page_size)) = pt.query(vaddr) {
+            if !flags.is_empty() {
+                // The page is present, so it can only be a write to a page
+                // shared by copy-on-write, in an area that allows writes.
+                return access_flags.contains(MappingFlags::WRITE)
+                    && orig_flags.contains(MappingFlags::WRITE)
+                    && !flags.contains(MappingFlags::WRITE)
+                    && !page_size.is_huge()
+                    && is_shared_frame(frame)
+                    && handle_cow_fault(vaddr, frame, orig_flags, pt);
+            }
+        }
         if populate {
             false // Populated mappings should not trigger page faults.
         } else if let Some(frame) = alloc_frame(true) {
@@ -106,3 +261,34 @@ impl Backend {
         }
     }
 }
+
+/// Gives the faulting page a private copy of the shared frame, or reuses the
+/// frame if it's no longer shared, and restores the write permission.
+///
+/// `orig_flags` must allow writes, the caller checks it against the area.
+pub(crate) fn handle_cow_fault(
+    vaddr: VirtAddr,
+    frame: PhysAddr,
+    orig_flags: MappingFlags,
+    pt: &mut PageTable,
+) -> bool {
+    let frame = if !take_frame(frame) {
+        let Some(new_frame) = alloc_frame(false) else {
+            return false;
+        };
+        unsafe {
+            core::ptr::copy_nonoverlapping(
+                phys_to_virt(frame).as_ptr(),
+                phys_to_virt(new_frame).as_mut_ptr(),
+                PAGE_SIZE_4K,
+            )
+        };
+        dealloc_frame(frame);
+        new_frame
+    } else {
+        frame
+    };
+    pt.remap(vaddr, frame, orig_flags)
+        .map(|(_, tlb)| tlb.flush())
+        .is_ok()
+}
@@ -0,0 +1,177 @@
+use alloc::sync::Arc;
+
+use axerrno::{AxError, AxResult};
+use axfs::fops::File;
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageTable};
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use super::alloc::{alloc_frame, dealloc_frame, handle_cow_fault};
+use super::Backend;
+
+/// Returns the page of the frame as a byte slice.
+fn frame_bytes(frame: PhysAddr) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) }
+}
+
+/// Reads a page from the file at `offset`, the part beyond the end of the
+/// file is left unchanged.
+fn read_page(file: &File, offset: u64, buf: &mut [u8]) -> bool {
+    let mut read = 0;
+    while read < buf.len() {
+        match file.read_at(offset + read as u64, &mut buf[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) => {
+                warn!("failed to read mapped file at {:#x}: {:?}", offset, e);
+                return false;
+            }
+        }
+    }
+    true
+}
+
+/// Writes a page back to the file at `offset`, without extending the file.
+fn write_page(file: &File, offset: u64, buf: &[u8]) -> AxResult {
+    let file_size = file.get_attr()?.size();
+    if offset >= file_size {
+        return Ok(());
+    }
+    let len = buf.len().min((file_size - offset) as usize);
+    let mut written = 0;
+    while written < len {
+        match file.write_at(offset + written as u64, &buf[written..len])? {
+            0 => return Err(AxError::WriteZero),
+            n => written += n,
+        }
+    }
+    Ok(())
+}
+
+impl Backend {
+    /// Creates a new file mapping backend.
+    ///
+    /// The virtual address `start` is mapped to `offset` in the file. If
+    /// `shared` is `true`, writes to the mapping are written back to the
+    /// file, otherwise they are private to the mapping.
+    pub fn new_file(file: Arc<File>, start: VirtAddr, offset: u64, shared: bool) -> Self {
+        Self::File {
+            file,
+            start,
+            offset,
+            shared,
+        }
+    }
+
+    /// Returns the offset in the file that `vaddr` is mapped to.
+    fn file_offset(&self, vaddr: VirtAddr) -> u64 {
+        match *self {
+            Self::File { start, offset, .. } => offset + (vaddr.as_usize() - start.as_usize()) as u64,
+            _ => unreachable!(),
+        }
+    }
+
+    pub(crate) fn map_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        debug!(
+            "map_file: [{:#x}, {:#x}) {:?} (offset={:#x})",
+            start,
+            start + size,
+            flags,
+            self.file_offset(start)
+        );
+        // Map to empty entries, the pages are read from the file on demand.
+        pt.map_region(
+            start,
+            |_| 0.into(),
+            size,
+            MappingFlags::empty(),
+            false,
+            false,
+        )
+        .map(|tlb| tlb.ignore())
+        .is_ok()
+    }
+
+    /// Unmaps the pages, which should have been written back by the caller
+    /// for shared mappings.
+    pub(crate) fn unmap_file(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        debug!("unmap_file: [{:#x}, {:#x})", start, start + size);
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
+                if page_size.is_huge() {
+                    return false;
+                }
+                tlb.flush();
+                dealloc_frame(frame);
+            }
+        }
+        true
+    }
+
+    /// Writes the mapped pages in `range` back to the file.
+    pub(crate) fn sync_file(&self, range: VirtAddrRange, pt: &PageTable, file: &File) -> AxResult {
+        for addr in PageIter4K::new(range.start, range.end).unwrap() {
+            if let Ok((frame, flags, _)) = pt.query(addr) {
+                if !flags.is_empty() {
+                    let offset = self.file_offset(addr);
+                    if let Err(e) = write_page(file, offset, frame_bytes(frame)) {
+                        warn!("failed to write back mapped file at {:#x}: {:?}", offset, e);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn handle_page_fault_file(
+        &self,
+        vaddr: VirtAddr,
+        access_flags: MappingFlags,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+        file: &File,
+        shared: bool,
+    ) -> bool {
+        if let Ok((frame, flags, page_size)) = pt.query(vaddr) {
+            if !flags.is_empty() {
+                // The page is present, so it can only be a write to a page
+                // shared by `clone_cow`, in an area that allows writes.
+                if !access_flags.contains(MappingFlags::WRITE)
+                    || !orig_flags.contains(MappingFlags::WRITE)
+                    || flags.contains(MappingFlags::WRITE)
+                    || page_size.is_huge()
+                {
+                    return false;
+                }
+                return if shared {
+                    pt.protect(vaddr, orig_flags)
+                        .map(|(_, tlb)| tlb.flush())
+                        .is_ok()
+                } else {
+                    handle_cow_fault(vaddr, frame, orig_flags, pt)
+                };
+            }
+        }
+
+        // Read the page from the file, the part beyond the end of the file is
+        // filled with zeros.
+        let vaddr = vaddr.align_down_4k();
+        let Some(frame) = alloc_frame(true) else {
+            return false;
+        };
+        if !read_page(file, self.file_offset(vaddr), frame_bytes(frame)) {
+            dealloc_frame(frame);
+            return false;
+        }
+        pt.remap(vaddr, frame, orig_flags)
+            .map(|(_, tlb)| tlb.flush())
+            .is_ok()
+    }
+}
@@ -1,21 +1,38 @@
 //! Memory mapping backends.
 #![allow(dead_code)]
 
-use axhal::paging::{MappingFlags, PageTable};
-use memory_addr::VirtAddr;
+#[cfg(feature = "fs")]
+use ::alloc::sync::Arc;
+use axalloc::global_allocator;
+use axhal::mem::{phys_to_virt, virt_to_phys};
+use axhal::paging::{
+    GenericPTE, MappingFlags, PageSize, PageTable, PageTableEntry, PAGE_TABLE_LEVELS,
+};
+use memory_addr::{va, MemoryAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
 use memory_set::MappingBackend;
 
+use crate::BackendKind;
+
 mod alloc;
+#[cfg(feature = "fs")]
+mod file;
 mod linear;
 
+#[cfg(feature = "swap")]
+pub(crate) use self::alloc::{alloc_frame, dealloc_frame};
+pub(crate) use self::alloc::{handle_cow_fault, is_shared_frame, write_protect_shared};
+
 /// A unified enum type for different memory mapping backends.
 ///
-/// Currently, two backends are implemented:
+/// Currently, the following backends are implemented:
 ///
 /// - **Linear**: used for linear mappings. The target physical frames are
 ///   contiguous and their addresses should be known when creating the mapping.
 /// - **Allocation**: used in general, or for lazy mappings. The target physical
-///   frames are obtained from the global allocator.
+///   frames are obtained from the global allocator. They can be shared between
+///   address spaces by [`AddrSpace::clone_cow`](crate::AddrSpace::clone_cow).
+/// - **File**: used for memory-mapped files (requires the `fs` feature). The
+///   pages are read from the file on demand.
 #[derive(Clone)]
 pub enum Backend {
     /// Linear mapping backend.
@@ -37,6 +54,30 @@ pub enum Backend {
         /// Whether to populate the physical frames when creating the mapping.
         populate: bool,
     },
+    /// File mapping backend.
+    ///
+    /// The physical frames are allocated on demand, and filled with the file
+    /// content. The virtual address `start` is mapped to `offset` in the file,
+    /// which keep unchanged when the area is split.
+    ///
+    /// If `shared` is `true` (`MAP_SHARED`), the pages are written back to the
+    /// file when unmapped or synced by [`AddrSpace::msync`]. As there is no page
+    /// cache, shared mappings of the same file in different address spaces do
+    /// not see each other's writes until then. Otherwise (`MAP_PRIVATE`),
+    /// writes are private to the mapping.
+    ///
+    /// [`AddrSpace::msync`]: crate::AddrSpace::msync
+    #[cfg(feature = "fs")]
+    File {
+        /// The mapped file.
+        file: Arc<axfs::fops::File>,
+        /// The virtual address mapped to `offset`.
+        start: VirtAddr,
+        /// The offset in the file that `start` is mapped to.
+        offset: u64,
+        /// Whether writes are written back to the file.
+        shared: bool,
+    },
 }
 
 impl MappingBackend for Backend {
@@ -47,6 +88,8 @@ impl MappingBackend for Backend {
         match *self {
             Self::Linear { pa_va_offset } => self.map_linear(start, size, flags, pt, pa_va_offset),
             Self::Alloc { populate } => self.map_alloc(start, size, flags, pt, populate),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self.map_file(start, size, flags, pt),
         }
     }
 
@@ -54,6 +97,8 @@ impl MappingBackend for Backend {
         match *self {
             Self::Linear { pa_va_offset } => self.unmap_linear(start, size, pt, pa_va_offset),
             Self::Alloc { populate } => self.unmap_alloc(start, size, pt, populate),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self.unmap_file(start, size, pt),
         }
     }
 
@@ -64,25 +109,255 @@ impl MappingBackend for Backend {
         new_flags: Self::Flags,
         page_table: &mut Self::PageTable,
     ) -> bool {
-        page_table
+        let ok = page_table
             .protect_region(start, size, new_flags, true)
             .map(|tlb| tlb.ignore())
-            .is_ok()
+            .is_ok();
+        if ok && new_flags.contains(MappingFlags::WRITE) {
+            write_protect_shared(start, size, page_table);
+        }
+        ok
     }
 }
 
 impl Backend {
+    /// Returns the kind of the backend, for an area starting at `start`.
+    pub(crate) fn kind(&self, start: VirtAddr) -> BackendKind {
+        match *self {
+            Self::Linear { .. } => BackendKind::Linear,
+            Self::Alloc { .. } => BackendKind::Alloc,
+            #[cfg(feature = "fs")]
+            Self::File {
+                start: file_start,
+                offset,
+                shared,
+                ..
+            } => BackendKind::File {
+                offset: offset + (start.as_usize() - file_start.as_usize()) as u64,
+                shared,
+            },
+        }
+    }
+
+    /// Returns `true` if the mapped frames are shared by copy-on-write when
+    /// cloned, instead of shared.
+    pub(crate) fn is_cow(&self) -> bool {
+        match *self {
+            Self::Linear { .. } => false,
+            Self::Alloc { .. } => true,
+            #[cfg(feature = "fs")]
+            Self::File { shared, .. } => !shared,
+        }
+    }
+
+    /// Returns `true` if the mapping can be made writable, i.e., it's not a
+    /// shared mapping of a file not opened for writing.
+    pub(crate) fn can_write(&self) -> bool {
+        match *self {
+            #[cfg(feature = "fs")]
+            Self::File {
+                ref file,
+                shared: true,
+                ..
+            } => file.is_writable(),
+            _ => true,
+        }
+    }
+
+    /// Handles a page fault at `vaddr` caused by an access of
+    /// `access_flags`, in an area with `orig_flags`.
     pub(crate) fn handle_page_fault(
         &self,
         vaddr: VirtAddr,
+        access_flags: MappingFlags,
         orig_flags: MappingFlags,
         page_table: &mut PageTable,
     ) -> bool {
         match *self {
             Self::Linear { .. } => false, // Linear mappings should not trigger page faults.
             Self::Alloc { populate } => {
-                self.handle_page_fault_alloc(vaddr, orig_flags, page_table, populate)
+                self.handle_page_fault_alloc(vaddr, access_flags, orig_flags, page_table, populate)
+            }
+            #[cfg(feature = "fs")]
+            Self::File {
+                ref file, shared, ..
+            } => self.handle_page_fault_file(
+                vaddr,
+                access_flags,
+                orig_flags,
+                page_table,
+                file,
+                shared,
+            ),
+        }
+    }
+
+    /// Maps the pages in `[start, start + size)` of `pt` to the same frames
+    /// in `new_pt`, where the area has been mapped by this backend.
+    ///
+    /// The allocated frames are shared by copy-on-write, except those of
+    /// shared file mappings.
+    pub(crate) fn clone_mapping(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        new_pt: &mut PageTable,
+    ) -> bool {
+        match *self {
+            Self::Linear { .. } => true, // Already mapped to the same frames.
+            Self::Alloc { .. } => self::alloc::share_frames(start, size, pt, new_pt, true),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self::alloc::share_frames(start, size, pt, new_pt, self.is_cow()),
+        }
+    }
+
+    /// Writes the mapped pages in `[start, start + size)` back to the file,
+    /// if this is a shared file mapping.
+    #[cfg(feature = "fs")]
+    pub(crate) fn sync(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        page_table: &PageTable,
+    ) -> axerrno::AxResult {
+        if let Self::File {
+            ref file,
+            shared: true,
+            ..
+        } = *self
+        {
+            let range = memory_addr::VirtAddrRange::from_start_size(start, size);
+            self.sync_file(range, page_table, file)?;
+        }
+        Ok(())
+    }
+}
+
+/// The number of entries in a page table.
+const ENTRY_COUNT: usize = 512;
+
+/// Returns the entry of the huge page of `page_size` containing `vaddr`.
+fn huge_page_entry(
+    pt: &mut PageTable,
+    vaddr: VirtAddr,
+    page_size: PageSize,
+) -> &mut PageTableEntry {
+    let leaf_level = match page_size {
+        PageSize::Size1G => PAGE_TABLE_LEVELS - 3,
+        PageSize::Size2M => PAGE_TABLE_LEVELS - 2,
+        PageSize::Size4K => PAGE_TABLE_LEVELS - 1,
+    };
+    let mut table_paddr = pt.root_paddr();
+    let mut level = 0;
+    loop {
+        let shift = 12 + 9 * (PAGE_TABLE_LEVELS - 1 - level);
+        let idx = (vaddr.as_usize() >> shift) & (ENTRY_COUNT - 1);
+        let table = phys_to_virt(table_paddr).as_mut_ptr() as *mut PageTableEntry;
+        // SAFETY: the tables on the path to a mapped page are valid, and
+        // exclusively borrowed through `pt`.
+        let entry = unsafe { &mut *table.add(idx) };
+        if level == leaf_level {
+            return entry;
+        }
+        table_paddr = entry.paddr();
+        level += 1;
+    }
+}
+
+/// Splits the huge page containing `vaddr` into pages of the next smaller
+/// size, which are mapped to the same frames with the same flags.
+///
+/// The huge page entry is replaced with break-before-make: it is cleared and
+/// flushed from the TLB before the table of the smaller pages is installed,
+/// as aarch64 requires when changing the page size. Accesses from other CPUs
+/// in between fault, and are handled as spurious after the split.
+///
+/// Huge pages of the kernel are shared by all CPUs, and may map the running
+/// code, stacks and page tables, so they can't be unmapped even briefly. They
+/// are replaced in place on x86_64 and riscv64, which is allowed as the
+/// translations don't change, and are never split on aarch64.
+fn split_huge_page(pt: &mut PageTable, vaddr: VirtAddr) -> bool {
+    let page_size = match pt.query(vaddr) {
+        Ok((_, _, page_size)) => page_size,
+        Err(_) => return false,
+    };
+    let small_size = match page_size {
+        PageSize::Size1G => PageSize::Size2M,
+        PageSize::Size2M => PageSize::Size4K,
+        PageSize::Size4K => return true,
+    };
+    let vaddr_base = vaddr.align_down(page_size);
+    let kernel_range = VirtAddrRange::from_start_size(
+        va!(axconfig::KERNEL_ASPACE_BASE),
+        axconfig::KERNEL_ASPACE_SIZE,
+    );
+    let in_place = kernel_range.contains(vaddr_base);
+    if in_place && cfg!(target_arch = "aarch64") {
+        warn!(
+            "can not split huge page {:#x} of the kernel without break-before-make",
+            vaddr_base
+        );
+        return false;
+    }
+    debug!(
+        "split huge page: {:#x} ({:?}) -> {:?}",
+        vaddr_base, page_size, small_size
+    );
+    let Ok(table_vaddr) = global_allocator().alloc_pages(1, PAGE_SIZE_4K) else {
+        return false;
+    };
+    let entry = huge_page_entry(pt, vaddr_base, page_size);
+    let (paddr_base, flags) = (entry.paddr(), entry.flags());
+    let table = table_vaddr as *mut PageTableEntry;
+    let step = usize::from(small_size);
+    for i in 0..ENTRY_COUNT {
+        let small_entry =
+            PageTableEntry::new_page(paddr_base + i * step, flags, small_size.is_huge());
+        // SAFETY: the table is a newly allocated frame of `ENTRY_COUNT` entries.
+        unsafe { table.add(i).write(small_entry) };
+    }
+    if !in_place {
+        let mut empty = *entry;
+        empty.clear();
+        // SAFETY: the entry is valid, and a single aligned store clears it.
+        unsafe { core::ptr::write_volatile(entry, empty) };
+        // A flush at any address in the huge page drops its whole TLB entry,
+        // and it is broadcast to all CPUs on aarch64.
+        axhal::arch::flush_tlb(Some(vaddr_base));
+    }
+    let table_paddr = virt_to_phys(va!(table_vaddr));
+    // SAFETY: the entry is valid, and a single aligned store replaces it.
+    unsafe { core::ptr::write_volatile(entry, PageTableEntry::new_table(table_paddr)) };
+    axhal::arch::flush_tlb(Some(vaddr_base));
+    true
+}
+
+/// Splits the huge page containing `vaddr` if `vaddr` is not at its start,
+/// so that the mappings before and after `vaddr` can be changed separately.
+pub(crate) fn split_huge_page_at(pt: &mut PageTable, vaddr: VirtAddr) -> bool {
+    loop {
+        match pt.query(vaddr) {
+            Ok((_, _, page_size)) if page_size.is_huge() && !vaddr.is_aligned(page_size) => {
+                if !split_huge_page(pt, vaddr) {
+                    return false;
+                }
+            }
+            _ => return true,
+        }
+    }
+}
+
+/// Splits the huge page containing `vaddr` until `vaddr` is in a 4K page.
+pub(crate) fn split_to_4k(pt: &mut PageTable, vaddr: VirtAddr) -> bool {
+    loop {
+        match pt.query(vaddr) {
+            Ok((_, _, page_size)) if page_size.is_huge() => {
+                if !split_huge_page(pt, vaddr) {
+                    return false;
+                }
             }
+            _ => return true,
         }
     }
 }
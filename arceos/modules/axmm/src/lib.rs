@@ -1,4 +1,11 @@
 //! [ArceOS](https://github.com/arceos-org/arceos) memory management module.
+//!
+//! # Cargo Features
+//!
+//! - `fs`: Enable file-backed mappings by [`AddrSpace::map_file`].
+//! - `swap`: Enable swapping user pages to a swap file, see [`swap_on`].
+//! - `uspace`: Enable fault-tolerant access to user memory, e.g.,
+//!   [`copy_from_user`] and [`UserPtr`].
 
 #![no_std]
 
@@ -8,20 +15,36 @@ extern crate alloc;
 
 mod aspace;
 mod backend;
+mod maps;
+#[cfg(feature = "swap")]
+mod swap;
+#[cfg(feature = "uspace")]
+mod uaccess;
 
 pub use self::aspace::AddrSpace;
+pub use self::maps::{AreaInfo, BackendKind};
+#[cfg(feature = "swap")]
+pub use self::swap::{handle_page_fault_unlocked, swap_on, swap_usage};
+#[cfg(feature = "uspace")]
+pub use self::uaccess::{check_user_range, copy_from_user, copy_to_user, read_user_cstr, UserPtr};
 
 use axerrno::{AxError, AxResult};
 use axhal::mem::phys_to_virt;
-use axhal::paging::PagingError;
+use axhal::paging::{MappingFlags, PagingError, PAGE_TABLE_LEVELS};
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
-use memory_addr::{va, PhysAddr, VirtAddr};
+use memory_addr::{align_down, align_down_4k, va, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
 use memory_set::MappingError;
 
 const USER_ASPACE_BASE: usize = 0x0000;
 const USER_ASPACE_SIZE: usize = 0x40_0000_0000;
 
+#[doc(no_inline)]
+pub use axhal::trap::KERNEL_STACK_GUARD_SIZE;
+
+/// The size of the address range mapped by an entry of the root page table.
+const ROOT_ENTRY_SPAN: usize = PAGE_SIZE_4K << (9 * (PAGE_TABLE_LEVELS - 1));
+
 static KERNEL_ASPACE: LazyInit<SpinNoIrq<AddrSpace>> = LazyInit::new();
 
 fn mapping_err_to_ax_err(err: MappingError) -> AxError {
@@ -74,6 +97,56 @@ pub fn kernel_page_table_root() -> PhysAddr {
     KERNEL_ASPACE.lock().page_table_root()
 }
 
+/// Returns the region of the kernel address space where kernel stacks are
+/// placed, which is away from the linear mapping of the physical memory.
+///
+/// It's the part of the upper half of the kernel address space that is in the
+/// range of the last entry of the root page table. User address spaces copy
+/// the root entries of the kernel when created, so the entry is created
+/// beforehand by [`init_memory_management`], and the stacks allocated later
+/// are still mapped in all address spaces.
+pub fn kernel_stack_region() -> VirtAddrRange {
+    let end = axconfig::KERNEL_ASPACE_BASE + axconfig::KERNEL_ASPACE_SIZE;
+    let half = align_down_4k(axconfig::KERNEL_ASPACE_SIZE / 2);
+    let start = (axconfig::KERNEL_ASPACE_BASE + half).max(align_down(end - 1, ROOT_ENTRY_SPAN));
+    VirtAddrRange::new(va!(start), va!(end))
+}
+
+/// Allocates a kernel stack of `size` bytes in the [kernel stack region],
+/// with an unmapped guard region of [`KERNEL_STACK_GUARD_SIZE`] bytes below
+/// it.
+///
+/// The stack is populated when allocated. Returns the lowest address of the
+/// stack, and accessing the addresses below it triggers page faults.
+///
+/// [kernel stack region]: kernel_stack_region
+pub fn alloc_kernel_stack(size: usize) -> AxResult<VirtAddr> {
+    let total_size = KERNEL_STACK_GUARD_SIZE + size;
+    let region = kernel_stack_region();
+    let mut aspace = KERNEL_ASPACE.lock();
+    let start = aspace
+        .find_free_area(region.start, total_size, region)
+        .ok_or(AxError::NoMemory)?;
+    // Also keep the guard region as an area (with no access permissions) in
+    // the address space, so that it won't be reused by other stacks.
+    aspace.map_alloc(start, KERNEL_STACK_GUARD_SIZE, MappingFlags::empty(), false)?;
+    let bottom = start + KERNEL_STACK_GUARD_SIZE;
+    if let Err(e) = aspace.map_alloc(bottom, size, MappingFlags::READ | MappingFlags::WRITE, true) {
+        aspace.unmap(start, KERNEL_STACK_GUARD_SIZE)?;
+        return Err(e);
+    }
+    Ok(bottom)
+}
+
+/// Deallocates a kernel stack allocated by [`alloc_kernel_stack`], including
+/// its guard region.
+pub fn dealloc_kernel_stack(bottom: VirtAddr, size: usize) -> AxResult {
+    KERNEL_ASPACE.lock().unmap(
+        bottom - KERNEL_STACK_GUARD_SIZE,
+        KERNEL_STACK_GUARD_SIZE + size,
+    )
+}
+
 /// Initializes virtual memory management.
 ///
 /// It mainly sets up the kernel virtual memory address space and recreate a
@@ -81,7 +154,14 @@ pub fn kernel_page_table_root() -> PhysAddr {
 pub fn init_memory_management() {
     info!("Initialize virtual memory management...");
 
-    let kernel_aspace = new_kernel_aspace().expect("failed to initialize kernel address space");
+    let mut kernel_aspace = new_kernel_aspace().expect("failed to initialize kernel address space");
+    // Mapping a page creates the page tables of the kernel stack region, and
+    // unmapping it doesn't free them.
+    let stack_start = kernel_stack_region().start;
+    kernel_aspace
+        .map_alloc(stack_start, PAGE_SIZE_4K, MappingFlags::READ, true)
+        .and_then(|_| kernel_aspace.unmap(stack_start, PAGE_SIZE_4K))
+        .expect("failed to create page tables of the kernel stack region");
     debug!("kernel address space init OK: {:#x?}", kernel_aspace);
     KERNEL_ASPACE.init_once(SpinNoIrq::new(kernel_aspace));
     axhal::paging::set_kernel_page_table_root(kernel_page_table_root());
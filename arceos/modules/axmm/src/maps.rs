@@ -0,0 +1,83 @@
+//! Information of the mapped areas in an address space.
+
+use core::fmt;
+
+use axhal::paging::MappingFlags;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+/// The kind of the backend of a mapped area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    /// Linear mapping to contiguous physical memory.
+    Linear,
+    /// Mapping to frames allocated from the global allocator.
+    Alloc,
+    /// File mapping, with the file offset of the area start, and whether it
+    /// is shared.
+    #[cfg(feature = "fs")]
+    File {
+        /// The offset in the file that the area start is mapped to.
+        offset: u64,
+        /// Whether writes are written back to the file.
+        shared: bool,
+    },
+}
+
+/// Information of a mapped area, returned by
+/// [`AddrSpace::areas`](crate::AddrSpace::areas).
+///
+/// Its [`Display`](fmt::Display) implementation formats it as a line of
+/// `/proc/self/maps`.
+#[derive(Debug, Clone)]
+pub struct AreaInfo<'a> {
+    /// The start address of the area.
+    pub start: VirtAddr,
+    /// The size of the area in bytes.
+    pub size: usize,
+    /// The mapping flags of the area.
+    pub flags: MappingFlags,
+    /// The kind of the mapping backend.
+    pub kind: BackendKind,
+    /// The name of the area, e.g., `[stack]`, `[heap]`, or the file path.
+    pub name: Option<&'a str>,
+    /// Number of 4K pages that are mapped to physical memory.
+    pub resident_pages: usize,
+}
+
+impl AreaInfo<'_> {
+    /// Returns the end address of the area (exclusive).
+    pub fn end(&self) -> VirtAddr {
+        self.start + self.size
+    }
+
+    /// Returns the number of 4K pages reserved by the area.
+    pub fn reserved_pages(&self) -> usize {
+        self.size / PAGE_SIZE_4K
+    }
+}
+
+impl fmt::Display for AreaInfo<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let perm = |flag, c| if self.flags.contains(flag) { c } else { '-' };
+        let (offset, shared) = match self.kind {
+            #[cfg(feature = "fs")]
+            BackendKind::File { offset, shared } => (offset, shared),
+            _ => (0, false),
+        };
+        write!(
+            f,
+            "{:08x}-{:08x} {}{}{}{} {:08x} 00:00 0",
+            self.start.as_usize(),
+            self.end().as_usize(),
+            perm(MappingFlags::READ, 'r'),
+            perm(MappingFlags::WRITE, 'w'),
+            perm(MappingFlags::EXECUTE, 'x'),
+            if shared { 's' } else { 'p' },
+            offset,
+        )?;
+        if let Some(name) = self.name {
+            write!(f, "    {}", name)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,644 @@
+//! Swapping user pages to a swap file, enabled by the `swap` feature.
+//!
+//! Swapping applies to lazy allocation areas of user address spaces. Each
+//! address space keeps some of its resident pages inactive, i.e., unmapped
+//! but still in memory, chosen by a clock over its mapped pages. When a page
+//! fault fails to allocate a frame, an inactive page of any address space is
+//! evicted to the swap file, and is read back on the next access.
+//!
+//! References are detected by minor faults instead of the accessed bits of
+//! page tables: the hardware does not always maintain them (aarch64 without
+//! FEAT_HAFDBS raises access flag faults, and so does riscv with Svade), so
+//! clearing them needs the fault path anyway. Unmapping also makes inactive
+//! pages reclaimable without touching the page tables of their address
+//! spaces, which may be in use on other CPUs.
+//!
+//! The swap file is never accessed with the lock of an address space held:
+//! a page fault that needs swap I/O returns it as [`SwapIo`], and the I/O is
+//! done after the lock is released, see [`handle_page_fault_unlocked`].
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::ops::DerefMut;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axerrno::{ax_err, AxResult};
+use axfs::fops::File;
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageSize, PageTable};
+use kspin::SpinNoIrq;
+use lazyinit::LazyInit;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use crate::backend::{alloc_frame, dealloc_frame, is_shared_frame, Backend};
+use crate::AddrSpace;
+
+/// One in this many resident pages of an address space are kept inactive.
+const INACTIVE_RATIO: usize = 4;
+
+static SWAP_FILE: LazyInit<File> = LazyInit::new();
+static SWAP_SLOTS: SpinNoIrq<SwapSlots> = SpinNoIrq::new(SwapSlots {
+    total: 0,
+    next: 0,
+    free: Vec::new(),
+});
+
+/// The swap states of all address spaces, to reclaim inactive pages from.
+static SWAP_SPACES: SpinNoIrq<Vec<Weak<SharedSwapState>>> = SpinNoIrq::new(Vec::new());
+/// The index in [`SWAP_SPACES`] to start the next reclaim from.
+static RECLAIM_HAND: AtomicUsize = AtomicUsize::new(0);
+
+/// Allocator of page-sized slots in the swap file.
+struct SwapSlots {
+    total: usize,
+    /// Slots from `next` to `total` have never been used.
+    next: usize,
+    free: Vec<usize>,
+}
+
+impl SwapSlots {
+    fn alloc(&mut self) -> Option<usize> {
+        if let Some(slot) = self.free.pop() {
+            Some(slot)
+        } else if self.next < self.total {
+            self.next += 1;
+            Some(self.next - 1)
+        } else {
+            None
+        }
+    }
+
+    fn dealloc(&mut self, slot: usize) {
+        self.free.push(slot);
+    }
+
+    fn used(&self) -> usize {
+        self.next - self.free.len()
+    }
+}
+
+/// Enables swapping to the given file.
+///
+/// The number of swap slots is the file size in pages. The file must be
+/// opened for reading and writing, and it can only be called once.
+pub fn swap_on(file: File) -> AxResult {
+    if SWAP_FILE.is_inited() {
+        return ax_err!(AlreadyExists, "swap is already enabled");
+    }
+    let slots = file.get_attr()?.size() as usize / PAGE_SIZE_4K;
+    info!("swap on: {} slots", slots);
+    SWAP_FILE.init_once(file);
+    SWAP_SLOTS.lock().total = slots;
+    Ok(())
+}
+
+/// Returns the number of used and total slots in the swap file.
+pub fn swap_usage() -> (usize, usize) {
+    let slots = SWAP_SLOTS.lock();
+    (slots.used(), slots.total)
+}
+
+fn is_swap_on() -> bool {
+    SWAP_FILE.is_inited()
+}
+
+fn frame_bytes(frame: PhysAddr) -> &'static mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) }
+}
+
+/// Writes the frame to the swap slot.
+fn write_slot(slot: usize, frame: PhysAddr) -> bool {
+    match SWAP_FILE.write_at((slot * PAGE_SIZE_4K) as u64, frame_bytes(frame)) {
+        Ok(PAGE_SIZE_4K) => true,
+        res => {
+            warn!("failed to write swap slot {}: {:?}", slot, res);
+            false
+        }
+    }
+}
+
+/// Reads the swap slot to the frame.
+fn read_slot(slot: usize, frame: PhysAddr) -> bool {
+    match SWAP_FILE.read_at((slot * PAGE_SIZE_4K) as u64, frame_bytes(frame)) {
+        Ok(PAGE_SIZE_4K) => true,
+        res => {
+            warn!("failed to read swap slot {}: {:?}", slot, res);
+            false
+        }
+    }
+}
+
+/// Handles a page fault of the address space behind a lock, like
+/// [`AddrSpace::handle_page_fault`], but releases the lock during the swap
+/// I/O, so other tasks can use the address space meanwhile.
+///
+/// `lock` locks the address space and returns the guard. It's called again
+/// after each I/O, until the page fault is handled or fails.
+pub fn handle_page_fault_unlocked<F, G>(
+    lock: F,
+    vaddr: VirtAddr,
+    access_flags: MappingFlags,
+) -> bool
+where
+    F: Fn() -> G,
+    G: DerefMut<Target = AddrSpace>,
+{
+    loop {
+        let res = lock().try_handle_page_fault(vaddr, access_flags);
+        match res {
+            Ok(true) => return true,
+            Ok(false) => {
+                lock().report_page_fault(vaddr, access_flags);
+                return false;
+            }
+            Err(io) => {
+                if !io.run() {
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+/// Returns whether the pages of the area can be swapped out.
+pub(crate) fn is_swappable(backend: &Backend, flags: MappingFlags) -> bool {
+    matches!(backend, Backend::Alloc { populate: false }) && flags.contains(MappingFlags::USER)
+}
+
+/// The state of a page that is not mapped, but belongs to a swappable area.
+enum PageState {
+    /// Unmapped to detect references, and evicted if not referenced. The
+    /// sequence number orders the inactive pages by the deactivation time.
+    Inactive(PhysAddr, u64),
+    /// Being written to the swap slot, then the frame is freed. If the page
+    /// is accessed meanwhile, `cancelled` is set and the frame is kept.
+    SwappingOut {
+        frame: PhysAddr,
+        slot: usize,
+        cancelled: bool,
+    },
+    /// Swapped out to the slot.
+    Swapped(usize),
+    /// Being read from the swap slot into the frame.
+    SwappingIn { frame: PhysAddr, slot: usize },
+    /// Read back from the swap file, to be mapped.
+    SwappedIn(PhysAddr),
+}
+
+/// Swap states of the unmapped pages of an address space.
+///
+/// It's shared with other address spaces to reclaim the inactive pages, so
+/// it's never locked with the page table being modified.
+#[derive(Default)]
+pub(crate) struct SwapState {
+    pages: BTreeMap<VirtAddr, PageState>,
+    /// Inactive pages in the order to evict, keyed by the sequence numbers.
+    inactive: BTreeMap<u64, VirtAddr>,
+    next_seq: u64,
+}
+
+pub(crate) type SharedSwapState = SpinNoIrq<SwapState>;
+
+impl SwapState {
+    fn deactivate(&mut self, vaddr: VirtAddr, frame: PhysAddr) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.pages.insert(vaddr, PageState::Inactive(frame, seq));
+        self.inactive.insert(seq, vaddr);
+    }
+
+    /// Removes the state of the page, which is going to be mapped or
+    /// forgotten.
+    fn remove(&mut self, vaddr: VirtAddr) -> Option<PageState> {
+        let state = self.pages.remove(&vaddr)?;
+        if let PageState::Inactive(_, seq) = state {
+            self.inactive.remove(&seq);
+        }
+        Some(state)
+    }
+
+    /// Starts evicting the least recently deactivated page.
+    fn start_swap_out(state: &Arc<SharedSwapState>) -> Option<SwapIo> {
+        let mut this = state.lock();
+        let (_, &vaddr) = this.inactive.first_key_value()?;
+        let Some(&PageState::Inactive(frame, _)) = this.pages.get(&vaddr) else {
+            unreachable!("inactive page {:#x} not found", vaddr);
+        };
+        // Swap is full if no slots.
+        let slot = SWAP_SLOTS.lock().alloc()?;
+        this.remove(vaddr);
+        this.pages.insert(
+            vaddr,
+            PageState::SwappingOut {
+                frame,
+                slot,
+                cancelled: false,
+            },
+        );
+        Some(SwapIo::Out {
+            state: state.clone(),
+            vaddr,
+            frame,
+            slot,
+        })
+    }
+
+    /// Finishes the eviction started by [`start_swap_out`]. Returns `false`
+    /// if failed to write the swap slot.
+    ///
+    /// [`start_swap_out`]: Self::start_swap_out
+    fn finish_swap_out(&mut self, vaddr: VirtAddr, frame: PhysAddr, slot: usize, ok: bool) -> bool {
+        match self.pages.get(&vaddr) {
+            Some(&PageState::SwappingOut {
+                frame: f,
+                cancelled,
+                ..
+            }) if f == frame => {
+                if ok && !cancelled {
+                    debug!("swap out {:#x} to slot {}", vaddr, slot);
+                    self.pages.insert(vaddr, PageState::Swapped(slot));
+                    dealloc_frame(frame);
+                } else {
+                    // Accessed again or failed to write, keep it in memory.
+                    self.deactivate(vaddr, frame);
+                    SWAP_SLOTS.lock().dealloc(slot);
+                }
+            }
+            // Unmapped meanwhile.
+            _ => {
+                dealloc_frame(frame);
+                SWAP_SLOTS.lock().dealloc(slot);
+            }
+        }
+        ok
+    }
+
+    /// Finishes reading a swapped-out page. Returns `false` if failed to read
+    /// the swap slot.
+    fn finish_swap_in(&mut self, vaddr: VirtAddr, frame: PhysAddr, slot: usize, ok: bool) -> bool {
+        match self.pages.get(&vaddr) {
+            Some(&PageState::SwappingIn { frame: f, .. }) if f == frame => {
+                if ok {
+                    self.pages.insert(vaddr, PageState::SwappedIn(frame));
+                    SWAP_SLOTS.lock().dealloc(slot);
+                } else {
+                    self.pages.insert(vaddr, PageState::Swapped(slot));
+                    dealloc_frame(frame);
+                }
+            }
+            // Unmapped meanwhile.
+            _ => {
+                dealloc_frame(frame);
+                SWAP_SLOTS.lock().dealloc(slot);
+            }
+        }
+        ok
+    }
+
+    /// Forgets the state of a page that is being unmapped. The frame and slot
+    /// of a page in I/O are freed when the I/O finishes.
+    fn forget(&mut self, vaddr: VirtAddr) {
+        match self.remove(vaddr) {
+            Some(PageState::Inactive(frame, _) | PageState::SwappedIn(frame)) => {
+                dealloc_frame(frame)
+            }
+            Some(PageState::Swapped(slot)) => SWAP_SLOTS.lock().dealloc(slot),
+            _ => {}
+        }
+    }
+}
+
+/// Swap I/O needed to handle a page fault, which should be done without the
+/// lock of the address space held.
+pub(crate) enum SwapIo {
+    /// Writes an inactive page to the swap slot, to free its frame.
+    Out {
+        state: Arc<SharedSwapState>,
+        vaddr: VirtAddr,
+        frame: PhysAddr,
+        slot: usize,
+    },
+    /// Reads a swapped-out page from the swap slot.
+    In {
+        state: Arc<SharedSwapState>,
+        vaddr: VirtAddr,
+        frame: PhysAddr,
+        slot: usize,
+    },
+    /// Waits for the I/O of the page started by another page fault.
+    Wait,
+}
+
+impl SwapIo {
+    /// Does the I/O, then the page fault should be handled again. Returns
+    /// `false` if the I/O failed.
+    pub(crate) fn run(self) -> bool {
+        match self {
+            Self::Out {
+                state,
+                vaddr,
+                frame,
+                slot,
+            } => {
+                let ok = write_slot(slot, frame);
+                state.lock().finish_swap_out(vaddr, frame, slot, ok)
+            }
+            Self::In {
+                state,
+                vaddr,
+                frame,
+                slot,
+            } => {
+                let ok = read_slot(slot, frame);
+                state.lock().finish_swap_in(vaddr, frame, slot, ok)
+            }
+            Self::Wait => {
+                core::hint::spin_loop();
+                true
+            }
+        }
+    }
+}
+
+/// Starts evicting an inactive page of any address space, in turn.
+fn reclaim_inactive() -> Option<SwapIo> {
+    let states: Vec<_> = {
+        let mut spaces = SWAP_SPACES.lock();
+        spaces.retain(|state| state.strong_count() > 0);
+        spaces.iter().filter_map(Weak::upgrade).collect()
+    };
+    let hand = RECLAIM_HAND.load(Ordering::Relaxed);
+    for i in 0..states.len() {
+        let idx = (hand + i) % states.len();
+        if let Some(io) = SwapState::start_swap_out(&states[idx]) {
+            RECLAIM_HAND.store(idx + 1, Ordering::Relaxed);
+            return Some(io);
+        }
+    }
+    None
+}
+
+/// Swap states of the pages in an address space.
+pub(crate) struct SwapSpace {
+    /// Mapped pages in the clock order, the clock hand is at the front.
+    clock: VecDeque<VirtAddr>,
+    /// States of the pages not mapped.
+    state: Arc<SharedSwapState>,
+}
+
+impl Default for SwapSpace {
+    fn default() -> Self {
+        let state = Arc::new(SpinNoIrq::new(SwapState::default()));
+        SWAP_SPACES.lock().push(Arc::downgrade(&state));
+        Self {
+            clock: VecDeque::new(),
+            state,
+        }
+    }
+}
+
+impl SwapSpace {
+    /// Handles a page fault in a swappable area.
+    ///
+    /// Returns the swap I/O to do if the page is swapped out, or no memory is
+    /// available to map it.
+    pub(crate) fn handle_page_fault(
+        &mut self,
+        vaddr: VirtAddr,
+        access_flags: MappingFlags,
+        orig_flags: MappingFlags,
+        backend: &Backend,
+        pt: &mut PageTable,
+    ) -> Result<bool, SwapIo> {
+        let vaddr = vaddr.align_down_4k();
+        let mut state = self.state.lock();
+        let frame = match state.pages.get_mut(&vaddr) {
+            None => None,
+            Some(&mut PageState::Inactive(frame, _) | &mut PageState::SwappedIn(frame)) => {
+                state.remove(vaddr);
+                Some(frame)
+            }
+            Some(&mut PageState::Swapped(slot)) => {
+                let Some(frame) = alloc_frame(false) else {
+                    drop(state);
+                    return self.reclaim(vaddr, pt);
+                };
+                state
+                    .pages
+                    .insert(vaddr, PageState::SwappingIn { frame, slot });
+                return Err(SwapIo::In {
+                    state: self.state.clone(),
+                    vaddr,
+                    frame,
+                    slot,
+                });
+            }
+            Some(PageState::SwappingOut { cancelled, .. }) => {
+                *cancelled = true;
+                return Err(SwapIo::Wait);
+            }
+            Some(PageState::SwappingIn { .. }) => return Err(SwapIo::Wait),
+        };
+        drop(state);
+
+        if let Some(frame) = frame {
+            // Referenced again or read back, map it.
+            if pt
+                .map(vaddr, frame, PageSize::Size4K, orig_flags)
+                .map(|tlb| tlb.flush())
+                .is_err()
+            {
+                self.state.lock().deactivate(vaddr, frame);
+                return Ok(false);
+            }
+        } else {
+            let is_new = !matches!(pt.query(vaddr), Ok((_, flags, _)) if !flags.is_empty());
+            if !backend.handle_page_fault(vaddr, access_flags, orig_flags, pt) {
+                if matches!(pt.query(vaddr), Ok((_, flags, _)) if flags.contains(access_flags)) {
+                    // Not a fault caused by no memory.
+                    return Ok(false);
+                }
+                return self.reclaim(vaddr, pt);
+            }
+            if !is_new {
+                return Ok(true);
+            }
+        }
+        if is_swap_on() {
+            self.clock.push_back(vaddr);
+            self.balance(vaddr, pt);
+        }
+        Ok(true)
+    }
+
+    /// Starts evicting a page to free memory for the page at `vaddr`.
+    fn reclaim(&mut self, vaddr: VirtAddr, pt: &mut PageTable) -> Result<bool, SwapIo> {
+        if !is_swap_on() {
+            return Ok(false);
+        }
+        if let Some(io) = reclaim_inactive() {
+            return Err(io);
+        }
+        // No inactive pages anywhere, deactivate one of this address space.
+        if self.deactivate_one(vaddr, pt) {
+            if let Some(io) = reclaim_inactive() {
+                return Err(io);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Deactivates pages by the clock, until the inactive pages are at least
+    /// `1 / INACTIVE_RATIO` of the resident pages.
+    fn balance(&mut self, excluded: VirtAddr, pt: &mut PageTable) {
+        loop {
+            let nr_inactive = self.state.lock().inactive.len();
+            if self.clock.len() + nr_inactive > nr_inactive * INACTIVE_RATIO
+                && self.deactivate_one(excluded, pt)
+            {
+                continue;
+            }
+            break;
+        }
+    }
+
+    /// Deactivates the first page other than `excluded` in the clock order,
+    /// i.e., unmaps it but keeps it in memory.
+    ///
+    /// Returns `false` if no pages can be deactivated.
+    fn deactivate_one(&mut self, excluded: VirtAddr, pt: &mut PageTable) -> bool {
+        for _ in 0..self.clock.len() {
+            let Some(vaddr) = self.clock.pop_front() else {
+                break;
+            };
+            if vaddr == excluded {
+                self.clock.push_back(vaddr);
+                continue;
+            }
+            match pt.query(vaddr) {
+                Ok((frame, flags, page_size))
+                    if !flags.is_empty() && !page_size.is_huge() && !is_shared_frame(frame) =>
+                {
+                    if let Ok((_, _, tlb)) = pt.unmap(vaddr) {
+                        tlb.flush();
+                        self.state.lock().deactivate(vaddr, frame);
+                        return true;
+                    }
+                    self.clock.push_back(vaddr);
+                }
+                // Shared by copy-on-write, skip it for now.
+                Ok((_, flags, _)) if !flags.is_empty() => self.clock.push_back(vaddr),
+                // No longer mapped.
+                _ => {}
+            }
+        }
+        false
+    }
+
+    /// Returns the number of pages that are swapped out.
+    pub(crate) fn swapped_pages(&self) -> usize {
+        let state = self.state.lock();
+        state
+            .pages
+            .values()
+            .filter(|page| {
+                matches!(
+                    page,
+                    PageState::Swapped(_)
+                        | PageState::SwappingOut {
+                            cancelled: false,
+                            ..
+                        }
+                )
+            })
+            .count()
+    }
+
+    /// Reads all pages of the address space back to memory, and maps them.
+    ///
+    /// Unlike page faults, it reads the swap file in place, since it's only
+    /// used when cloning the address space.
+    ///
+    /// `flags_of` returns the mapping flags of the area containing the page.
+    pub(crate) fn swap_in_all<F>(&mut self, flags_of: F, pt: &mut PageTable) -> AxResult
+    where
+        F: Fn(VirtAddr) -> MappingFlags,
+    {
+        loop {
+            let mut state = self.state.lock();
+            let Some((&vaddr, page)) = state.pages.iter_mut().next() else {
+                return Ok(());
+            };
+            let io = match page {
+                PageState::Inactive(frame, _) | PageState::SwappedIn(frame) => {
+                    let frame = *frame;
+                    state.remove(vaddr);
+                    drop(state);
+                    let _ = pt
+                        .map(vaddr, frame, PageSize::Size4K, flags_of(vaddr))
+                        .map(|tlb| tlb.flush());
+                    self.clock.push_back(vaddr);
+                    continue;
+                }
+                &mut PageState::Swapped(slot) => {
+                    let Some(frame) = alloc_frame(false) else {
+                        return ax_err!(NoMemory, "failed to swap in pages");
+                    };
+                    *page = PageState::SwappingIn { frame, slot };
+                    SwapIo::In {
+                        state: self.state.clone(),
+                        vaddr,
+                        frame,
+                        slot,
+                    }
+                }
+                PageState::SwappingOut { cancelled, .. } => {
+                    *cancelled = true;
+                    SwapIo::Wait
+                }
+                PageState::SwappingIn { .. } => SwapIo::Wait,
+            };
+            drop(state);
+            if !io.run() {
+                return ax_err!(Io, "failed to swap in pages");
+            }
+        }
+    }
+
+    /// Returns the swap states of a copy of the address space, which has all
+    /// pages in memory.
+    pub(crate) fn fork(&self) -> Self {
+        Self {
+            clock: self.clock.clone(),
+            ..Default::default()
+        }
+    }
+
+    /// Forgets the pages in `[start, start + size)`, which are being unmapped.
+    pub(crate) fn unmap(&mut self, start: VirtAddr, size: usize) {
+        let range = VirtAddrRange::from_start_size(start, size);
+        self.clock.retain(|vaddr| !range.contains(*vaddr));
+        let mut state = self.state.lock();
+        let vaddrs: Vec<_> = state
+            .pages
+            .range(start..range.end)
+            .map(|(&v, _)| v)
+            .collect();
+        for vaddr in vaddrs {
+            state.forget(vaddr);
+        }
+    }
+}
+
+impl Drop for SwapSpace {
+    fn drop(&mut self) {
+        SWAP_SPACES
+            .lock()
+            .retain(|state| !core::ptr::eq(state.as_ptr(), Arc::as_ptr(&self.state)));
+        let mut state = self.state.lock();
+        while let Some((&vaddr, _)) = state.pages.first_key_value() {
+            state.forget(vaddr);
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Access to user memory, enabled by the `uspace` feature.
+//!
+//! The user address range is validated against the areas of the address
+//! space page by page, and lazily mapped pages are faulted in before they are
+//! accessed. The copy itself is done by [`axhal::uaccess::copy_user`], so a
+//! bad user pointer results in [`AxError::BadAddress`] (`EFAULT`) rather than
+//! a kernel panic.
+//!
+//! The address space must be the one in use on the current CPU, i.e., the
+//! address space of the current task.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::marker::PhantomData;
+use core::mem::size_of;
+
+use axerrno::{ax_err, AxError, AxResult};
+use axhal::paging::MappingFlags;
+use axhal::uaccess::copy_user;
+use memory_addr::{align_down_4k, VirtAddr, PAGE_SIZE_4K};
+
+use crate::AddrSpace;
+
+/// Calls `f` with each part of `[start, start + len)` within a page, after
+/// the page is checked and faulted in for `access`. Stops early if `f`
+/// returns `Ok(false)`.
+fn for_each_user_page<F>(
+    aspace: &mut AddrSpace,
+    start: VirtAddr,
+    len: usize,
+    access: MappingFlags,
+    mut f: F,
+) -> AxResult
+where
+    F: FnMut(VirtAddr, usize) -> AxResult<bool>,
+{
+    let end = start
+        .as_usize()
+        .checked_add(len)
+        .ok_or(AxError::BadAddress)?;
+    let mut vaddr = start.as_usize();
+    while vaddr < end {
+        let part_end = (align_down_4k(vaddr) + PAGE_SIZE_4K).min(end);
+        aspace.fault_in_user_page(VirtAddr::from(vaddr), access)?;
+        if !f(VirtAddr::from(vaddr), part_end - vaddr)? {
+            break;
+        }
+        vaddr = part_end;
+    }
+    Ok(())
+}
+
+fn copy_part(dst: *mut u8, src: *const u8, len: usize) -> AxResult {
+    if unsafe { copy_user(dst, src, len) } != 0 {
+        return ax_err!(BadAddress, "bad user address");
+    }
+    Ok(())
+}
+
+/// Copies `len` bytes from the user address `src` to `dst`.
+fn copy_in(aspace: &mut AddrSpace, dst: *mut u8, src: VirtAddr, len: usize) -> AxResult {
+    let mut copied = 0;
+    for_each_user_page(aspace, src, len, MappingFlags::READ, |vaddr, part_len| {
+        copy_part(unsafe { dst.add(copied) }, vaddr.as_ptr(), part_len)?;
+        copied += part_len;
+        Ok(true)
+    })
+}
+
+/// Copies `len` bytes from `src` to the user address `dst`.
+fn copy_out(aspace: &mut AddrSpace, dst: VirtAddr, src: *const u8, len: usize) -> AxResult {
+    let mut copied = 0;
+    for_each_user_page(aspace, dst, len, MappingFlags::WRITE, |vaddr, part_len| {
+        copy_part(vaddr.as_mut_ptr(), unsafe { src.add(copied) }, part_len)?;
+        copied += part_len;
+        Ok(true)
+    })
+}
+
+/// Copies bytes from the user address `src` to `dst`.
+///
+/// Returns [`AxError::BadAddress`] if any part of the user range is not
+/// mapped readable for the user.
+pub fn copy_from_user(aspace: &mut AddrSpace, dst: &mut [u8], src: VirtAddr) -> AxResult {
+    copy_in(aspace, dst.as_mut_ptr(), src, dst.len())
+}
+
+/// Copies bytes from `src` to the user address `dst`.
+///
+/// Returns [`AxError::BadAddress`] if any part of the user range is not
+/// mapped writable for the user.
+pub fn copy_to_user(aspace: &mut AddrSpace, dst: VirtAddr, src: &[u8]) -> AxResult {
+    copy_out(aspace, dst, src.as_ptr(), src.len())
+}
+
+/// Checks that the user range `[start, start + len)` is mapped with `access`,
+/// e.g., before a buffer is filled by a read that can't be undone.
+///
+/// Pages are not faulted in, so it's cheap for large ranges. Returns
+/// [`AxError::BadAddress`] if any part of the range doesn't allow `access`.
+pub fn check_user_range(
+    aspace: &AddrSpace,
+    start: VirtAddr,
+    len: usize,
+    access: MappingFlags,
+) -> AxResult {
+    let end = start
+        .as_usize()
+        .checked_add(len)
+        .ok_or(AxError::BadAddress)?;
+    aspace.check_user_range(start, VirtAddr::from(end), access)
+}
+
+/// Reads a nul-terminated string at the user address `ptr`, with at most
+/// `max_len` bytes excluding the nul.
+///
+/// Returns [`AxError::BadAddress`] if the string is not readable,
+/// [`AxError::InvalidInput`] if it is too long, or [`AxError::InvalidData`]
+/// if it is not valid UTF-8.
+pub fn read_user_cstr(aspace: &mut AddrSpace, ptr: VirtAddr, max_len: usize) -> AxResult<String> {
+    let mut bytes = Vec::new();
+    let mut terminated = false;
+    let len = max_len.saturating_add(1);
+    for_each_user_page(aspace, ptr, len, MappingFlags::READ, |vaddr, part_len| {
+        let old_len = bytes.len();
+        bytes.resize(old_len + part_len, 0);
+        copy_part(bytes[old_len..].as_mut_ptr(), vaddr.as_ptr(), part_len)?;
+        if let Some(pos) = bytes[old_len..].iter().position(|&b| b == 0) {
+            bytes.truncate(old_len + pos);
+            terminated = true;
+            return Ok(false);
+        }
+        Ok(true)
+    })?;
+    if !terminated {
+        return ax_err!(InvalidInput, "user string too long");
+    }
+    String::from_utf8(bytes).map_err(|_| AxError::InvalidData)
+}
+
+/// A pointer to a value of type `T` in user space.
+///
+/// It is not dereferenced directly, but read or written through the address
+/// space. `T` should be valid for any bit pattern, e.g., integers and
+/// `#[repr(C)]` structures of them, as the value read is provided by the
+/// user.
+#[repr(transparent)]
+pub struct UserPtr<T> {
+    addr: usize,
+    _phantom: PhantomData<*mut T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> From<usize> for UserPtr<T> {
+    fn from(addr: usize) -> Self {
+        Self::new(addr)
+    }
+}
+
+impl<T> UserPtr<T> {
+    /// Creates a user pointer from the address.
+    pub const fn new(addr: usize) -> Self {
+        Self {
+            addr,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the address of the pointer.
+    pub const fn addr(self) -> VirtAddr {
+        VirtAddr::from_usize(self.addr)
+    }
+
+    /// Whether the pointer is null.
+    pub const fn is_null(self) -> bool {
+        self.addr == 0
+    }
+
+    /// Returns the pointer to the `count`-th value after it.
+    pub const fn add(self, count: usize) -> Self {
+        Self::new(self.addr.wrapping_add(count.wrapping_mul(size_of::<T>())))
+    }
+}
+
+impl<T: Copy> UserPtr<T> {
+    /// Reads the value from user space.
+    pub fn read(self, aspace: &mut AddrSpace) -> AxResult<T> {
+        let mut value = core::mem::MaybeUninit::<T>::uninit();
+        copy_in(
+            aspace,
+            value.as_mut_ptr().cast(),
+            self.addr(),
+            size_of::<T>(),
+        )?;
+        Ok(unsafe { value.assume_init() })
+    }
+
+    /// Writes the value to user space.
+    pub fn write(self, aspace: &mut AddrSpace, value: T) -> AxResult {
+        copy_out(
+            aspace,
+            self.addr(),
+            (&value as *const T).cast(),
+            size_of::<T>(),
+        )
+    }
+
+    /// Reads `len` consecutive values from user space.
+    pub fn read_array(self, aspace: &mut AddrSpace, len: usize) -> AxResult<Vec<T>> {
+        let size = len.checked_mul(size_of::<T>()).ok_or(AxError::BadAddress)?;
+        let mut values = Vec::with_capacity(len);
+        copy_in(aspace, values.as_mut_ptr().cast(), self.addr(), size)?;
+        unsafe { values.set_len(len) };
+        Ok(values)
+    }
+}
@@ -14,6 +14,14 @@
 //!
 //! - `smoltcp`: Use [smoltcp] as the underlying network stack. This is enabled
 //!   by default.
+//! - `async`: Provide async versions of the blocking [`TcpSocket`] methods,
+//!   such as [`TcpSocket::recv_async`]. They register wakers to the sockets
+//!   instead of polling, and a background task is started to poll the network
+//!   stack on the RX interrupts of the NIC and when smoltcp's timers expire.
+//!   The futures can be run by [`axtask::Executor`]. It also enables the `irq`
+//!   features of `axtask` and `axhal`.
+//! - `alloc-tag`: Attribute the socket buffers to the `"net buffers"`
+//!   allocation tag.
 //!
 //! [smoltcp]: https://github.com/smoltcp-rs/smoltcp
 
@@ -36,13 +44,16 @@ pub use self::net_impl::UdpSocket;
 pub use self::net_impl::{bench_receive, bench_transmit};
 pub use self::net_impl::{dns_query, poll_interfaces};
 
-use axdriver::{prelude::*, AxDeviceContainer};
+use axdriver::{prelude::*, AxDeviceContainer, VirtIoMmioIrq};
 
 /// Initializes the network subsystem by NIC devices.
-pub fn init_network(mut net_devs: AxDeviceContainer<AxNetDevice>) {
+///
+/// `irq` is the interrupt of the first NIC, if it's known. With the `async`
+/// feature, it wakes up the background poller when packets are received.
+pub fn init_network(mut net_devs: AxDeviceContainer<AxNetDevice>, irq: Option<VirtIoMmioIrq>) {
     info!("Initialize network subsystem...");
 
     let dev = net_devs.take_one().expect("No NIC device found!");
     info!("  use NIC 0: {:?}", dev.device_name());
-    net_impl::init(dev);
+    net_impl::init(dev, irq);
 }
@@ -1,5 +1,7 @@
 use alloc::{boxed::Box, collections::VecDeque};
 use core::ops::{Deref, DerefMut};
+#[cfg(feature = "async")]
+use core::task::Waker;
 
 use axerrno::{ax_err, AxError, AxResult};
 use axsync::Mutex;
@@ -14,6 +16,9 @@ const PORT_NUM: usize = 65536;
 struct ListenTableEntry {
     listen_endpoint: IpListenEndpoint,
     syn_queue: VecDeque<SocketHandle>,
+    /// Woken up when a new connection is coming.
+    #[cfg(feature = "async")]
+    accept_waker: Option<Waker>,
 }
 
 impl ListenTableEntry {
@@ -21,6 +26,8 @@ impl ListenTableEntry {
         Self {
             listen_endpoint,
             syn_queue: VecDeque::with_capacity(LISTEN_QUEUE_SIZE),
+            #[cfg(feature = "async")]
+            accept_waker: None,
         }
     }
 
@@ -110,6 +117,33 @@ impl ListenTable {
         }
     }
 
+    /// Registers a waker to be woken up when a connection on `port` may be
+    /// accepted.
+    #[cfg(feature = "async")]
+    pub fn register_accept_waker(&self, port: u16, waker: &Waker) -> AxResult {
+        let syn_queue: VecDeque<SocketHandle> =
+            if let Some(entry) = self.tcp[port as usize].lock().deref_mut() {
+                entry.accept_waker = Some(waker.clone());
+                entry.syn_queue.clone()
+            } else {
+                return ax_err!(InvalidInput, "socket accept() failed: not listen");
+            };
+        // Pending connections are woken up when they are established. The
+        // entry is unlocked first, as it's locked with `SOCKET_SET` locked
+        // when polling.
+        for handle in syn_queue {
+            let connected = SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+                socket.register_recv_waker(waker);
+                !matches!(socket.state(), State::Listen | State::SynReceived)
+            });
+            if connected {
+                waker.wake_by_ref();
+                break;
+            }
+        }
+        Ok(())
+    }
+
     pub fn incoming_tcp_packet(
         &self,
         src: IpEndpoint,
@@ -134,6 +168,10 @@ impl ListenTable {
                     handle, src, entry.listen_endpoint
                 );
                 entry.syn_queue.push_back(handle);
+                #[cfg(feature = "async")]
+                if let Some(waker) = entry.accept_waker.take() {
+                    waker.wake();
+                }
             }
         }
     }
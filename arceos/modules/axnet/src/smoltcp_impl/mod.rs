@@ -2,6 +2,8 @@ mod addr;
 mod bench;
 mod dns;
 mod listen_table;
+#[cfg(feature = "async")]
+mod poller;
 mod tcp;
 mod udp;
 
@@ -10,6 +12,7 @@ use core::cell::RefCell;
 use core::ops::DerefMut;
 
 use axdriver::prelude::*;
+use axdriver::VirtIoMmioIrq;
 use axdriver_net::{DevError, NetBufPtr};
 use axhal::time::{wall_time_nanos, NANOS_PER_MICROS};
 use axsync::Mutex;
@@ -54,6 +57,14 @@ static LISTEN_TABLE: LazyInit<ListenTable> = LazyInit::new();
 static SOCKET_SET: LazyInit<SocketSetWrapper> = LazyInit::new();
 static ETH0: LazyInit<InterfaceWrapper> = LazyInit::new();
 
+/// Runs `f` with its heap allocations attributed to network buffers.
+fn with_net_buf_tag<R>(f: impl FnOnce() -> R) -> R {
+    #[cfg(feature = "alloc-tag")]
+    return axtask::with_alloc_tag("net buffers", f);
+    #[cfg(not(feature = "alloc-tag"))]
+    return f();
+}
+
 struct SocketSetWrapper<'a>(Mutex<SocketSet<'a>>);
 
 struct DeviceWrapper {
@@ -73,21 +84,25 @@ impl<'a> SocketSetWrapper<'a> {
     }
 
     pub fn new_tcp_socket() -> socket::tcp::Socket<'a> {
-        let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_RX_BUF_LEN]);
-        let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_TX_BUF_LEN]);
-        socket::tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer)
+        with_net_buf_tag(|| {
+            let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_RX_BUF_LEN]);
+            let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_TX_BUF_LEN]);
+            socket::tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer)
+        })
     }
 
     pub fn new_udp_socket() -> socket::udp::Socket<'a> {
-        let udp_rx_buffer = socket::udp::PacketBuffer::new(
-            vec![socket::udp::PacketMetadata::EMPTY; 8],
-            vec![0; UDP_RX_BUF_LEN],
-        );
-        let udp_tx_buffer = socket::udp::PacketBuffer::new(
-            vec![socket::udp::PacketMetadata::EMPTY; 8],
-            vec![0; UDP_TX_BUF_LEN],
-        );
-        socket::udp::Socket::new(udp_rx_buffer, udp_tx_buffer)
+        with_net_buf_tag(|| {
+            let udp_rx_buffer = socket::udp::PacketBuffer::new(
+                vec![socket::udp::PacketMetadata::EMPTY; 8],
+                vec![0; UDP_RX_BUF_LEN],
+            );
+            let udp_tx_buffer = socket::udp::PacketBuffer::new(
+                vec![socket::udp::PacketMetadata::EMPTY; 8],
+                vec![0; UDP_TX_BUF_LEN],
+            );
+            socket::udp::Socket::new(udp_rx_buffer, udp_tx_buffer)
+        })
     }
 
     pub fn new_dns_socket() -> socket::dns::Socket<'a> {
@@ -123,6 +138,13 @@ impl<'a> SocketSetWrapper<'a> {
         ETH0.poll(&self.0);
     }
 
+    /// Returns how long to wait before the sockets need to be polled again,
+    /// or [`None`] if they're idle.
+    #[cfg(feature = "async")]
+    pub fn poll_delay(&self) -> Option<core::time::Duration> {
+        ETH0.poll_delay(&self.0)
+    }
+
     pub fn remove(&self, handle: SocketHandle) {
         self.0.lock().remove(handle);
         debug!("socket {}: destroyed", handle);
@@ -177,6 +199,14 @@ impl InterfaceWrapper {
         let timestamp = Self::current_time();
         iface.poll(timestamp, dev.deref_mut(), &mut sockets);
     }
+
+    #[cfg(feature = "async")]
+    pub fn poll_delay(&self, sockets: &Mutex<SocketSet>) -> Option<core::time::Duration> {
+        let mut iface = self.iface.lock();
+        let sockets = sockets.lock();
+        let delay = iface.poll_delay(Self::current_time(), &sockets)?;
+        Some(core::time::Duration::from_micros(delay.total_micros()))
+    }
 }
 
 impl DeviceWrapper {
@@ -310,7 +340,7 @@ pub fn bench_receive() {
     ETH0.dev.lock().bench_receive_bandwidth();
 }
 
-pub(crate) fn init(net_dev: AxNetDevice) {
+pub(crate) fn init(net_dev: AxNetDevice, irq: Option<VirtIoMmioIrq>) {
     let ether_addr = EthernetAddress(net_dev.mac_address().0);
     let eth0 = InterfaceWrapper::new("eth0", net_dev, ether_addr);
 
@@ -327,4 +357,12 @@ pub(crate) fn init(net_dev: AxNetDevice) {
     info!("  ether:    {}", ETH0.ethernet_address());
     info!("  ip:       {}/{}", ip, IP_PREFIX);
     info!("  gateway:  {}", gateway);
+
+    #[cfg(feature = "async")]
+    if let Some(irq) = irq {
+        poller::init_rx_irq(irq);
+    }
+    // The blocking operations poll the network stack by themselves.
+    #[cfg(not(feature = "async"))]
+    let _ = irq;
 }
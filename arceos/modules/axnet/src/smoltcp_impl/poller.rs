@@ -0,0 +1,107 @@
+//! The background task polling the network stack for the async socket
+//! operations.
+//!
+//! The task polls the network stack, so the wakers registered by the async
+//! operations can be woken up, and then sleeps until smoltcp's next timer
+//! (e.g., retransmission) expires. It's woken up early by the socket
+//! operations that have queued packets or registered wakers, and by the RX
+//! interrupt of the NIC. If the NIC has no interrupt, received packets are
+//! checked every [`RX_POLL_INTERVAL`] while any async operation is pending.
+//! The task is parked when no operation is pending and smoltcp is idle.
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use core::time::Duration;
+
+use axdriver::VirtIoMmioIrq;
+use axtask::WaitQueue;
+use lazyinit::LazyInit;
+
+use super::SOCKET_SET;
+
+/// The interval to check received packets while async operations are
+/// pending, if the NIC does not interrupt on receiving.
+const RX_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+/// Whether the network stack should be polled again without sleeping.
+static POLL_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// The number of pending async operations, see [`Waiting`].
+static WAITERS: AtomicUsize = AtomicUsize::new(0);
+static POLLER_WQ: WaitQueue = WaitQueue::new();
+
+static RX_IRQ: LazyInit<VirtIoMmioIrq> = LazyInit::new();
+static RX_IRQ_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Wakes up the poller on the interrupts of the NIC.
+pub(super) fn init_rx_irq(irq: VirtIoMmioIrq) {
+    let irq_num = irq.irq_num();
+    RX_IRQ.init_once(irq);
+    let registered = axhal::irq::register_handler(irq_num, || {
+        RX_IRQ.ack();
+        if STARTED.load(Ordering::Acquire) {
+            POLL_REQUESTED.store(true, Ordering::Release);
+            POLLER_WQ.notify_one(true);
+        }
+    });
+    if registered {
+        RX_IRQ_ENABLED.store(true, Ordering::Release);
+    } else {
+        warn!("failed to register the NIC interrupt {}", irq_num);
+    }
+}
+
+/// Wakes up the poller to poll the network stack, and starts it if not yet.
+pub(super) fn wake() {
+    POLL_REQUESTED.store(true, Ordering::Release);
+    if !STARTED.swap(true, Ordering::AcqRel) {
+        axtask::spawn(run);
+    } else {
+        POLLER_WQ.notify_one(false);
+    }
+}
+
+fn run() {
+    loop {
+        POLL_REQUESTED.store(false, Ordering::Release);
+        SOCKET_SET.poll_interfaces();
+        let mut delay = SOCKET_SET.poll_delay();
+        if WAITERS.load(Ordering::Acquire) > 0 && !RX_IRQ_ENABLED.load(Ordering::Acquire) {
+            delay = Some(delay.map_or(RX_POLL_INTERVAL, |d| d.min(RX_POLL_INTERVAL)));
+        }
+        let requested = || POLL_REQUESTED.load(Ordering::Acquire);
+        match delay {
+            Some(delay) if delay.is_zero() => {}
+            Some(delay) => {
+                POLLER_WQ.wait_timeout_until(delay, requested);
+            }
+            None => POLLER_WQ.wait_until(requested),
+        }
+    }
+}
+
+/// Counts an async operation as pending while it's waiting for its wakers,
+/// so that the poller keeps checking received packets for it.
+pub(super) struct Waiting(bool);
+
+impl Waiting {
+    pub const fn new() -> Self {
+        Self(false)
+    }
+
+    pub fn set(&mut self, waiting: bool) {
+        if waiting != self.0 {
+            self.0 = waiting;
+            if waiting {
+                WAITERS.fetch_add(1, Ordering::AcqRel);
+            } else {
+                WAITERS.fetch_sub(1, Ordering::AcqRel);
+            }
+        }
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        self.set(false);
+    }
+}
@@ -1,6 +1,10 @@
 use core::cell::UnsafeCell;
+#[cfg(feature = "async")]
+use core::future::poll_fn;
 use core::net::SocketAddr;
 use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+#[cfg(feature = "async")]
+use core::task::{Context, Poll};
 
 use axerrno::{ax_err, ax_err_type, AxError, AxResult};
 use axio::PollState;
@@ -119,57 +123,13 @@ impl TcpSocket {
     ///
     /// The local port is generated automatically.
     pub fn connect(&self, remote_addr: SocketAddr) -> AxResult {
-        self.update_state(STATE_CLOSED, STATE_CONNECTING, || {
-            // SAFETY: no other threads can read or write these fields.
-            let handle = unsafe { self.handle.get().read() }
-                .unwrap_or_else(|| SOCKET_SET.add(SocketSetWrapper::new_tcp_socket()));
-
-            // TODO: check remote addr unreachable
-            let remote_endpoint = from_core_sockaddr(remote_addr);
-            let bound_endpoint = self.bound_endpoint()?;
-            let iface = &ETH0.iface;
-            let (local_endpoint, remote_endpoint) = SOCKET_SET
-                .with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
-                    socket
-                        .connect(iface.lock().context(), remote_endpoint, bound_endpoint)
-                        .or_else(|e| match e {
-                            ConnectError::InvalidState => {
-                                ax_err!(BadState, "socket connect() failed")
-                            }
-                            ConnectError::Unaddressable => {
-                                ax_err!(ConnectionRefused, "socket connect() failed")
-                            }
-                        })?;
-                    Ok((
-                        socket.local_endpoint().unwrap(),
-                        socket.remote_endpoint().unwrap(),
-                    ))
-                })?;
-            unsafe {
-                // SAFETY: no other threads can read or write these fields as we
-                // have changed the state to `BUSY`.
-                self.local_addr.get().write(local_endpoint);
-                self.peer_addr.get().write(remote_endpoint);
-                self.handle.get().write(Some(handle));
-            }
-            Ok(())
-        })
-        .unwrap_or_else(|_| ax_err!(AlreadyExists, "socket connect() failed: already connected"))?; // EISCONN
+        self.start_connect(remote_addr)?;
 
         // Here our state must be `CONNECTING`, and only one thread can run here.
         if self.is_nonblocking() {
             Err(AxError::WouldBlock)
         } else {
-            self.block_on(|| {
-                let PollState { writable, .. } = self.poll_connect()?;
-                if !writable {
-                    Err(AxError::WouldBlock)
-                } else if self.get_state() == STATE_CONNECTED {
-                    Ok(())
-                } else {
-                    ax_err!(ConnectionRefused, "socket connect() failed")
-                }
-            })
+            self.block_on(|| self.finish_connect())
         }
     }
 
@@ -280,25 +240,8 @@ impl TcpSocket {
         // SAFETY: `self.handle` should be initialized in a connected socket.
         let handle = unsafe { self.handle.get().read().unwrap() };
         self.block_on(|| {
-            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
-                if !socket.is_active() {
-                    // not open
-                    ax_err!(ConnectionRefused, "socket recv() failed")
-                } else if !socket.may_recv() {
-                    // connection closed
-                    Ok(0)
-                } else if socket.recv_queue() > 0 {
-                    // data available
-                    // TODO: use socket.recv(|buf| {...})
-                    let len = socket
-                        .recv_slice(buf)
-                        .map_err(|_| ax_err_type!(BadState, "socket recv() failed"))?;
-                    Ok(len)
-                } else {
-                    // no more data
-                    Err(AxError::WouldBlock)
-                }
-            })
+            SOCKET_SET
+                .with_socket_mut::<tcp::Socket, _, _>(handle, |socket| Self::recv_impl(socket, buf))
         })
     }
 
@@ -313,22 +256,8 @@ impl TcpSocket {
         // SAFETY: `self.handle` should be initialized in a connected socket.
         let handle = unsafe { self.handle.get().read().unwrap() };
         self.block_on(|| {
-            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
-                if !socket.is_active() || !socket.may_send() {
-                    // closed by remote
-                    ax_err!(ConnectionReset, "socket send() failed")
-                } else if socket.can_send() {
-                    // connected, and the tx buffer is not full
-                    // TODO: use socket.send(|buf| {...})
-                    let len = socket
-                        .send_slice(buf)
-                        .map_err(|_| ax_err_type!(BadState, "socket send() failed"))?;
-                    Ok(len)
-                } else {
-                    // tx buffer is full
-                    Err(AxError::WouldBlock)
-                }
-            })
+            SOCKET_SET
+                .with_socket_mut::<tcp::Socket, _, _>(handle, |socket| Self::send_impl(socket, buf))
         })
     }
 
@@ -346,6 +275,107 @@ impl TcpSocket {
     }
 }
 
+/// Async methods
+#[cfg(feature = "async")]
+impl TcpSocket {
+    /// Connects to the given address and port asynchronously.
+    ///
+    /// It's the async version of [`connect`](Self::connect).
+    pub async fn connect_async(&self, remote_addr: SocketAddr) -> AxResult {
+        self.start_connect(remote_addr)?;
+
+        // SAFETY: `self.handle` should be initialized in a connecting socket.
+        let handle = unsafe { self.handle.get().read().unwrap() };
+        self.async_on(|cx| match self.finish_connect() {
+            Err(AxError::WouldBlock) => {
+                // The send waker is woken up when the connection is
+                // established or fails.
+                SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+                    socket.register_send_waker(cx.waker())
+                });
+                Err(AxError::WouldBlock)
+            }
+            res => res,
+        })
+        .await
+    }
+
+    /// Accepts a new connection asynchronously.
+    ///
+    /// It's the async version of [`accept`](Self::accept), which registers
+    /// the waker of the current future instead of blocking the calling
+    /// thread.
+    pub async fn accept_async(&self) -> AxResult<TcpSocket> {
+        if !self.is_listening() {
+            return ax_err!(InvalidInput, "socket accept() failed: not listen");
+        }
+
+        // SAFETY: `self.local_addr` should be initialized after `bind()`.
+        let local_port = unsafe { self.local_addr.get().read().port };
+        self.async_on(|cx| match LISTEN_TABLE.accept(local_port) {
+            Ok((handle, (local_addr, peer_addr))) => {
+                debug!("TCP socket accepted a new connection {}", peer_addr);
+                Ok(TcpSocket::new_connected(handle, local_addr, peer_addr))
+            }
+            Err(AxError::WouldBlock) => {
+                LISTEN_TABLE.register_accept_waker(local_port, cx.waker())?;
+                Err(AxError::WouldBlock)
+            }
+            Err(e) => Err(e),
+        })
+        .await
+    }
+
+    /// Receives data from the socket asynchronously, stores it in the given
+    /// buffer.
+    ///
+    /// It's the async version of [`recv`](Self::recv).
+    pub async fn recv_async(&self, buf: &mut [u8]) -> AxResult<usize> {
+        if self.is_connecting() {
+            return Err(AxError::WouldBlock);
+        } else if !self.is_connected() {
+            return ax_err!(NotConnected, "socket recv() failed");
+        }
+
+        // SAFETY: `self.handle` should be initialized in a connected socket.
+        let handle = unsafe { self.handle.get().read().unwrap() };
+        self.async_on(|cx| {
+            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+                let res = Self::recv_impl(socket, buf);
+                if matches!(res, Err(AxError::WouldBlock)) {
+                    socket.register_recv_waker(cx.waker());
+                }
+                res
+            })
+        })
+        .await
+    }
+
+    /// Transmits data in the given buffer asynchronously.
+    ///
+    /// It's the async version of [`send`](Self::send).
+    pub async fn send_async(&self, buf: &[u8]) -> AxResult<usize> {
+        if self.is_connecting() {
+            return Err(AxError::WouldBlock);
+        } else if !self.is_connected() {
+            return ax_err!(NotConnected, "socket send() failed");
+        }
+
+        // SAFETY: `self.handle` should be initialized in a connected socket.
+        let handle = unsafe { self.handle.get().read().unwrap() };
+        self.async_on(|cx| {
+            SOCKET_SET.with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+                let res = Self::send_impl(socket, buf);
+                if matches!(res, Err(AxError::WouldBlock)) {
+                    socket.register_send_waker(cx.waker());
+                }
+                res
+            })
+        })
+        .await
+    }
+}
+
 /// Private methods
 impl TcpSocket {
     #[inline]
@@ -419,6 +449,61 @@ impl TcpSocket {
         Ok(IpListenEndpoint { addr, port })
     }
 
+    /// Starts connecting to the given address, and moves to the `CONNECTING`
+    /// state.
+    fn start_connect(&self, remote_addr: SocketAddr) -> AxResult {
+        self.update_state(STATE_CLOSED, STATE_CONNECTING, || {
+            // SAFETY: no other threads can read or write these fields.
+            let handle = unsafe { self.handle.get().read() }
+                .unwrap_or_else(|| SOCKET_SET.add(SocketSetWrapper::new_tcp_socket()));
+
+            // TODO: check remote addr unreachable
+            let remote_endpoint = from_core_sockaddr(remote_addr);
+            let bound_endpoint = self.bound_endpoint()?;
+            let iface = &ETH0.iface;
+            let (local_endpoint, remote_endpoint) = SOCKET_SET
+                .with_socket_mut::<tcp::Socket, _, _>(handle, |socket| {
+                    socket
+                        .connect(iface.lock().context(), remote_endpoint, bound_endpoint)
+                        .or_else(|e| match e {
+                            ConnectError::InvalidState => {
+                                ax_err!(BadState, "socket connect() failed")
+                            }
+                            ConnectError::Unaddressable => {
+                                ax_err!(ConnectionRefused, "socket connect() failed")
+                            }
+                        })?;
+                    Ok((
+                        socket.local_endpoint().unwrap(),
+                        socket.remote_endpoint().unwrap(),
+                    ))
+                })?;
+            unsafe {
+                // SAFETY: no other threads can read or write these fields as we
+                // have changed the state to `BUSY`.
+                self.local_addr.get().write(local_endpoint);
+                self.peer_addr.get().write(remote_endpoint);
+                self.handle.get().write(Some(handle));
+            }
+            Ok(())
+        })
+        .unwrap_or_else(|_| ax_err!(AlreadyExists, "socket connect() failed: already connected"))
+        // EISCONN
+    }
+
+    /// Checks whether the connection is established, returns
+    /// [`Err(WouldBlock)`](AxError::WouldBlock) if it's in progress.
+    fn finish_connect(&self) -> AxResult {
+        let PollState { writable, .. } = self.poll_connect()?;
+        if !writable {
+            Err(AxError::WouldBlock)
+        } else if self.get_state() == STATE_CONNECTED {
+            Ok(())
+        } else {
+            ax_err!(ConnectionRefused, "socket connect() failed")
+        }
+    }
+
     fn poll_connect(&self) -> AxResult<PollState> {
         // SAFETY: `self.handle` should be initialized above.
         let handle = unsafe { self.handle.get().read().unwrap() };
@@ -469,6 +554,43 @@ impl TcpSocket {
         })
     }
 
+    fn recv_impl(socket: &mut tcp::Socket, buf: &mut [u8]) -> AxResult<usize> {
+        if !socket.is_active() {
+            // not open
+            ax_err!(ConnectionRefused, "socket recv() failed")
+        } else if !socket.may_recv() {
+            // connection closed
+            Ok(0)
+        } else if socket.recv_queue() > 0 {
+            // data available
+            // TODO: use socket.recv(|buf| {...})
+            let len = socket
+                .recv_slice(buf)
+                .map_err(|_| ax_err_type!(BadState, "socket recv() failed"))?;
+            Ok(len)
+        } else {
+            // no more data
+            Err(AxError::WouldBlock)
+        }
+    }
+
+    fn send_impl(socket: &mut tcp::Socket, buf: &[u8]) -> AxResult<usize> {
+        if !socket.is_active() || !socket.may_send() {
+            // closed by remote
+            ax_err!(ConnectionReset, "socket send() failed")
+        } else if socket.can_send() {
+            // connected, and the tx buffer is not full
+            // TODO: use socket.send(|buf| {...})
+            let len = socket
+                .send_slice(buf)
+                .map_err(|_| ax_err_type!(BadState, "socket send() failed"))?;
+            Ok(len)
+        } else {
+            // tx buffer is full
+            Err(AxError::WouldBlock)
+        }
+    }
+
     /// Block the current thread until the given function completes or fails.
     ///
     /// If the socket is non-blocking, it calls the function once and returns
@@ -491,6 +613,35 @@ impl TcpSocket {
             }
         }
     }
+
+    /// Wait asynchronously until the given function completes or fails.
+    ///
+    /// Like [`block_on`](Self::block_on), but if the function returns
+    /// [`Err(WouldBlock)`](AxError::WouldBlock), it should have registered
+    /// the waker in the given context, and the future returns pending until
+    /// the waker is woken up by the network poller.
+    #[cfg(feature = "async")]
+    async fn async_on<F, T>(&self, mut f: F) -> AxResult<T>
+    where
+        F: FnMut(&mut Context<'_>) -> AxResult<T>,
+    {
+        let nonblock = self.is_nonblocking();
+        let mut waiting = super::poller::Waiting::new();
+        poll_fn(|cx| {
+            SOCKET_SET.poll_interfaces();
+            let res = f(cx);
+            let pending = matches!(res, Err(AxError::WouldBlock)) && !nonblock;
+            waiting.set(pending);
+            // Transmits the queued packets, or waits for the registered wakers.
+            super::poller::wake();
+            if pending {
+                Poll::Pending
+            } else {
+                Poll::Ready(res)
+            }
+        })
+        .await
+    }
 }
 
 impl Drop for TcpSocket {
@@ -0,0 +1,62 @@
+//! Follows the requests of the host to the memory balloon.
+//!
+//! The host changes the target size of the balloon in the config space, and
+//! raises the configuration change interrupt. With `multitask`, the interrupt
+//! is masked and the balloon is updated in a task, as inflating it may take a
+//! while. Otherwise, it's updated in the interrupt handler.
+
+use axdriver::VirtIoBalloonDev;
+
+#[cfg(feature = "multitask")]
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+#[cfg(feature = "multitask")]
+static BALLOON_IRQ: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "multitask")]
+static CONFIG_CHANGED: AtomicBool = AtomicBool::new(false);
+#[cfg(feature = "multitask")]
+static BALLOON_WQ: axtask::WaitQueue = axtask::WaitQueue::new();
+
+#[cfg(not(feature = "multitask"))]
+static BALLOON: kspin::SpinNoIrq<Option<VirtIoBalloonDev>> = kspin::SpinNoIrq::new(None);
+
+pub(crate) fn init_balloon(mut balloon: VirtIoBalloonDev) {
+    info!("Initialize memory balloon...");
+    balloon.update();
+
+    let Some(irq_num) = balloon.irq_num() else {
+        warn!("no interrupt for the memory balloon, it won't follow the host");
+        // The device still uses the virtqueues and holds the pages.
+        core::mem::forget(balloon);
+        return;
+    };
+
+    #[cfg(feature = "multitask")]
+    {
+        BALLOON_IRQ.store(irq_num, Ordering::Relaxed);
+        axtask::spawn_raw(
+            move || loop {
+                BALLOON_WQ.wait_until(|| CONFIG_CHANGED.swap(false, Ordering::Acquire));
+                balloon.update();
+                axhal::irq::set_enable(irq_num, true);
+            },
+            "balloon".into(),
+            axconfig::TASK_STACK_SIZE,
+        );
+        axhal::irq::register_handler(irq_num, || {
+            // Masked until the task has acknowledged the interrupt.
+            axhal::irq::set_enable(BALLOON_IRQ.load(Ordering::Relaxed), false);
+            CONFIG_CHANGED.store(true, Ordering::Release);
+            BALLOON_WQ.notify_one(true);
+        });
+    }
+    #[cfg(not(feature = "multitask"))]
+    {
+        *BALLOON.lock() = Some(balloon);
+        axhal::irq::register_handler(irq_num, || {
+            if let Some(balloon) = BALLOON.lock().as_mut() {
+                balloon.update();
+            }
+        });
+    }
+}
@@ -6,11 +6,21 @@
 //! # Cargo Features
 //!
 //! - `alloc`: Enable global memory allocator.
+//! - `alloc-tag`: Attribute heap allocations to the current task, or the
+//!   subsystem set by `axtask::with_alloc_tag`.
+//! - `alloc-debug`: Check heap allocations for overflows, use-after-free and
+//!   double free, reporting the task that made the allocation.
+//! - `alloc-early`: Allocate from a static early heap from the beginning of
+//!   the boot, until the global allocator is initialized with the memory
+//!   regions.
 //! - `paging`: Enable page table manipulation support.
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
 //! - `fs`: Enable filesystem support.
+//! - `balloon`: Enable the VirtIO memory balloon, which gives free memory to
+//!   the host or takes it back on the host's requests. It follows the requests
+//!   on the interrupts of the device, so it also enables `irq`.
 //! - `net`: Enable networking support.
 //! - `display`: Enable graphics support.
 //!
@@ -25,6 +35,8 @@ extern crate axlog;
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
 
+#[cfg(feature = "balloon")]
+mod balloon;
 #[cfg(feature = "smp")]
 mod mp;
 
@@ -48,6 +60,67 @@ extern "C" {
 
 struct LogIfImpl;
 
+#[cfg(feature = "alloc-tag")]
+struct AllocTagIfImpl;
+
+#[cfg(feature = "alloc-tag")]
+#[crate_interface::impl_interface]
+impl axalloc::AllocTagIf for AllocTagIfImpl {
+    fn current_tag() -> axalloc::AllocTag {
+        #[cfg(feature = "multitask")]
+        if is_init_ok() {
+            if let Some(curr) = axtask::current_may_uninit() {
+                return match curr.alloc_tag() {
+                    Some(name) => axalloc::AllocTag::Named(name),
+                    None => axalloc::AllocTag::Task(curr.id().as_u64()),
+                };
+            }
+        }
+        axalloc::AllocTag::Untagged
+    }
+}
+
+#[cfg(feature = "alloc-debug")]
+struct DebugAllocIfImpl;
+
+#[cfg(feature = "alloc-debug")]
+#[crate_interface::impl_interface]
+impl axalloc::DebugAllocIf for DebugAllocIfImpl {
+    fn current_task_name(buf: &mut [u8]) -> usize {
+        #[cfg(feature = "multitask")]
+        if is_init_ok() {
+            if let Some(curr) = axtask::current_may_uninit() {
+                let name = curr.name();
+                let mut len = name.len().min(buf.len());
+                while !name.is_char_boundary(len) {
+                    len -= 1;
+                }
+                buf[..len].copy_from_slice(&name.as_bytes()[..len]);
+                return len;
+            }
+        }
+        let name = b"main";
+        let len = name.len().min(buf.len());
+        buf[..len].copy_from_slice(&name[..len]);
+        len
+    }
+}
+
+#[cfg(all(feature = "paging", feature = "multitask"))]
+struct KernelStackIfImpl;
+
+#[cfg(all(feature = "paging", feature = "multitask"))]
+#[crate_interface::impl_interface]
+impl axtask::KernelStackIf for KernelStackIfImpl {
+    fn alloc_kernel_stack(size: usize) -> Option<axhal::mem::VirtAddr> {
+        axmm::alloc_kernel_stack(size).ok()
+    }
+
+    fn dealloc_kernel_stack(bottom: axhal::mem::VirtAddr, size: usize) {
+        axmm::dealloc_kernel_stack(bottom, size).expect("failed to deallocate kernel stack");
+    }
+}
+
 #[crate_interface::impl_interface]
 impl axlog::LogIf for LogIfImpl {
     fn console_write_str(s: &str) {
@@ -102,6 +175,9 @@ fn is_init_ok() -> bool {
 /// and the secondary CPUs call [`rust_main_secondary`].
 #[cfg_attr(not(test), no_mangle)]
 pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
+    #[cfg(feature = "alloc-early")]
+    init_early_allocator();
+
     ax_println!("{}", LOGO);
     ax_println!(
         "\
@@ -153,22 +229,35 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     #[cfg(feature = "multitask")]
     axtask::init_scheduler();
 
-    #[cfg(any(feature = "fs", feature = "net", feature = "display", feature = "input"))]
+    #[cfg(any(
+        feature = "fs",
+        feature = "net",
+        feature = "display",
+        feature = "input",
+        feature = "balloon"
+    ))]
     {
         #[allow(unused_variables)]
         let all_devices = axdriver::init_drivers();
 
         #[cfg(feature = "fs")]
         axfs::init_filesystems(all_devices.block);
+        #[cfg(all(feature = "fs", feature = "alloc"))]
+        axfs::register_proc_file("meminfo", axalloc::meminfo);
 
         #[cfg(feature = "net")]
-        axnet::init_network(all_devices.net);
+        axnet::init_network(all_devices.net, all_devices.net_irq);
 
         #[cfg(feature = "display")]
         axdisplay::init_display(all_devices.display);
 
         #[cfg(feature = "input")]
         axinput::init_input(all_devices.input);
+
+        #[cfg(feature = "balloon")]
+        if let Some(balloon) = all_devices.balloon {
+            balloon::init_balloon(balloon);
+        }
     }
 
     #[cfg(feature = "smp")]
@@ -196,14 +285,42 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
     unsafe { main() };
 
     #[cfg(feature = "multitask")]
-    axtask::exit(0);
+    {
+        #[cfg(feature = "fs")]
+        axfs::sync_on_shutdown();
+        axtask::exit(0);
+    }
     #[cfg(not(feature = "multitask"))]
     {
         debug!("main task exited: exit_code={}", 0);
-        axhal::misc::terminate();
+        terminate();
     }
 }
 
+/// Shuts down the whole system, after writing the dirty blocks of the
+/// filesystems back to the disk.
+///
+/// Applications and the panic handler should call it rather than
+/// [`axhal::misc::terminate`], or the data in the block cache is lost.
+pub fn terminate() -> ! {
+    #[cfg(feature = "fs")]
+    axfs::sync_on_shutdown();
+    axhal::misc::terminate()
+}
+
+#[cfg(feature = "alloc-early")]
+fn init_early_allocator() {
+    const EARLY_HEAP_SIZE: usize = 0x40000; // 256 K
+
+    #[repr(align(4096))]
+    struct EarlyHeap([u8; EARLY_HEAP_SIZE]);
+
+    static mut EARLY_HEAP: EarlyHeap = EarlyHeap([0; EARLY_HEAP_SIZE]);
+
+    let start = core::ptr::addr_of_mut!(EARLY_HEAP) as usize;
+    axalloc::global_early_init(start, EARLY_HEAP_SIZE);
+}
+
 #[cfg(feature = "alloc")]
 fn init_allocator() {
     use axhal::mem::{memory_regions, phys_to_virt, MemRegionFlags};
@@ -256,8 +373,14 @@ fn init_allocator() {
     }
     for r in memory_regions() {
         if r.flags.contains(MemRegionFlags::FREE) && r.paddr != max_region_paddr {
-            alt_axalloc::global_add_memory(phys_to_virt(r.paddr).as_usize(), r.size)
-                .expect("add heap memory region failed");
+            // The early allocator only manages the largest region.
+            if alt_axalloc::global_add_memory(phys_to_virt(r.paddr).as_usize(), r.size).is_err() {
+                warn!(
+                    "  ignore memory region [{:x?}, {:x?})",
+                    r.paddr,
+                    r.paddr + r.size
+                );
+            }
         }
     }
 }
@@ -267,12 +390,15 @@ fn init_interrupt() {
     use axhal::time::TIMER_IRQ_NUM;
 
     // Setup timer interrupt handler
+    #[cfg(not(feature = "multitask"))]
     const PERIODIC_INTERVAL_NANOS: u64 =
         axhal::time::NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
 
+    #[cfg(not(feature = "multitask"))]
     #[percpu::def_percpu]
     static NEXT_DEADLINE: u64 = 0;
 
+    #[cfg(not(feature = "multitask"))]
     fn update_timer() {
         let now_ns = axhal::time::monotonic_time_nanos();
         // Safety: we have disabled preemption in IRQ handler.
@@ -285,9 +411,12 @@ fn init_interrupt() {
     }
 
     axhal::irq::register_handler(TIMER_IRQ_NUM, || {
-        update_timer();
+        // The task manager programs the timer by itself, according to the
+        // earliest timed event.
         #[cfg(feature = "multitask")]
         axtask::on_timer_tick();
+        #[cfg(not(feature = "multitask"))]
+        update_timer();
     });
 
     // Enable IRQs before starting app
@@ -0,0 +1,169 @@
+//! A barrier to synchronize multiple tasks.
+
+use core::fmt;
+
+use axtask::WaitQueue;
+use kspin::SpinNoIrq;
+
+/// A barrier enables multiple tasks to synchronize the beginning of some
+/// computation, similar to
+/// [`std::sync::Barrier`](https://doc.rust-lang.org/std/sync/struct.Barrier.html).
+pub struct Barrier {
+    state: SpinNoIrq<BarrierState>,
+    wq: WaitQueue,
+    num_tasks: usize,
+}
+
+struct BarrierState {
+    count: usize,
+    generation_id: usize,
+}
+
+/// A `BarrierWaitResult` is returned by [`Barrier::wait()`] when all tasks
+/// in the [`Barrier`] have rendezvoused.
+pub struct BarrierWaitResult(bool);
+
+impl Barrier {
+    /// Creates a new barrier that can block a given number of tasks.
+    ///
+    /// A barrier will block `n`-1 tasks which call [`wait()`] and then wake
+    /// up all tasks at once when the `n`th task calls [`wait()`].
+    ///
+    /// [`wait()`]: Barrier::wait
+    pub const fn new(n: usize) -> Self {
+        Self {
+            state: SpinNoIrq::new(BarrierState {
+                count: 0,
+                generation_id: 0,
+            }),
+            wq: WaitQueue::new(),
+            num_tasks: n,
+        }
+    }
+
+    /// Blocks the current task until all tasks have rendezvoused here.
+    ///
+    /// Barriers are re-usable after all tasks have rendezvoused once, and
+    /// can be used continuously.
+    ///
+    /// A single (arbitrary) task will receive a [`BarrierWaitResult`] that
+    /// returns `true` from [`BarrierWaitResult::is_leader()`] when returning
+    /// from this function, and all other tasks will receive a result that
+    /// will return `false` from [`BarrierWaitResult::is_leader()`].
+    ///
+    /// A task killed while waiting is not counted as arrived any more.
+    pub fn wait(&self) -> BarrierWaitResult {
+        let mut state = self.state.lock();
+        let local_gen = state.generation_id;
+        state.count += 1;
+        if state.count < self.num_tasks {
+            drop(state);
+            if !self
+                .wq
+                .wait_until_or_killed(|| self.state.lock().generation_id != local_gen)
+            {
+                let mut state = self.state.lock();
+                if state.generation_id == local_gen {
+                    state.count -= 1;
+                }
+                drop(state);
+                axtask::exit(axtask::KILLED_EXIT_CODE);
+            }
+            BarrierWaitResult(false)
+        } else {
+            state.count = 0;
+            state.generation_id = state.generation_id.wrapping_add(1);
+            drop(state);
+            self.wq.notify_all(true);
+            BarrierWaitResult(true)
+        }
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Barrier").finish_non_exhaustive()
+    }
+}
+
+impl BarrierWaitResult {
+    /// Returns `true` if this task is the "leader task" for the call to
+    /// [`Barrier::wait()`].
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl fmt::Debug for BarrierWaitResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BarrierWaitResult")
+            .field("is_leader", &self.is_leader())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Barrier;
+    use axtask as thread;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn rendezvous() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 10;
+        const NUM_ROUNDS: usize = 10;
+        static BARRIER: Barrier = Barrier::new(NUM_TASKS + 1);
+        static ARRIVED: AtomicUsize = AtomicUsize::new(0);
+        static LEADERS: AtomicUsize = AtomicUsize::new(0);
+
+        for _ in 0..NUM_TASKS {
+            thread::spawn(|| {
+                for _ in 0..NUM_ROUNDS {
+                    ARRIVED.fetch_add(1, Ordering::Relaxed);
+                    if BARRIER.wait().is_leader() {
+                        LEADERS.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            });
+        }
+        for round in 1..=NUM_ROUNDS {
+            if BARRIER.wait().is_leader() {
+                LEADERS.fetch_add(1, Ordering::Relaxed);
+            }
+            // all tasks have arrived before anyone leaves.
+            assert!(ARRIVED.load(Ordering::Relaxed) >= NUM_TASKS * round);
+        }
+        assert_eq!(LEADERS.load(Ordering::Relaxed), NUM_ROUNDS);
+    }
+
+    #[test]
+    fn killed_waiter() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        static BARRIER: Barrier = Barrier::new(2);
+        static LEADERS: AtomicUsize = AtomicUsize::new(0);
+
+        let killed = thread::spawn(|| {
+            BARRIER.wait();
+        });
+        thread::yield_now();
+        assert!(killed.kill());
+        assert_eq!(killed.join(), Some(thread::KILLED_EXIT_CODE));
+
+        // The killed task has left, two more tasks are needed.
+        let task = thread::spawn(|| {
+            if BARRIER.wait().is_leader() {
+                LEADERS.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        if BARRIER.wait().is_leader() {
+            LEADERS.fetch_add(1, Ordering::Relaxed);
+        }
+        assert_eq!(task.join(), Some(0));
+        assert_eq!(LEADERS.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,157 @@
+//! A sleeping condition variable.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use axtask::WaitQueue;
+
+use crate::MutexGuard;
+
+/// A type indicating whether a timed wait on a condition variable returned
+/// due to a time out or not.
+///
+/// It is returned by the [`wait_timeout`] method.
+///
+/// [`wait_timeout`]: Condvar::wait_timeout
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait was known to have timed out.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// A condition variable, similar to
+/// [`std::sync::Condvar`](https://doc.rust-lang.org/std/sync/struct.Condvar.html).
+///
+/// It is used with [`Mutex`](crate::Mutex) to block a task until some
+/// condition becomes true. Like in `std`, waiting tasks may be woken up
+/// spuriously, so the condition should be checked in a loop, or use
+/// [`wait_while`](Self::wait_while) instead.
+pub struct Condvar {
+    wq: WaitQueue,
+    /// Increased by every notification, so tasks that are going to wait
+    /// won't miss the notifications sent after they unlock the mutex.
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    /// Creates a new condition variable which is ready to be waited on and
+    /// notified.
+    pub const fn new() -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    /// Blocks the current task until this condition variable receives a
+    /// notification.
+    ///
+    /// The mutex of `guard` is unlocked while waiting, and locked again
+    /// before returning.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Acquire);
+        drop(guard);
+        self.wq
+            .wait_until(|| self.seq.load(Ordering::Acquire) != seq);
+        mutex.lock()
+    }
+
+    /// Blocks the current task as long as `condition` returns `true`.
+    pub fn wait_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> MutexGuard<'a, T>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard);
+        }
+        guard
+    }
+
+    /// Blocks the current task until this condition variable receives a
+    /// notification, or the given duration has elapsed.
+    #[cfg(feature = "irq")]
+    #[doc(cfg(feature = "irq"))]
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: core::time::Duration,
+    ) -> (MutexGuard<'a, T>, WaitTimeoutResult) {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Acquire);
+        drop(guard);
+        let timed_out = self
+            .wq
+            .wait_timeout_until(dur, || self.seq.load(Ordering::Acquire) != seq);
+        (mutex.lock(), WaitTimeoutResult(timed_out))
+    }
+
+    /// Wakes up one task blocked on this condition variable.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+
+    /// Wakes up all tasks blocked on this condition variable.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        self.wq.notify_all(true);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Condvar { .. }")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Condvar, Mutex};
+    use axtask as thread;
+
+    #[test]
+    fn producer_consumer() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        const NUM_ITEMS: usize = 1000;
+        static QUEUE: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+        static NOT_EMPTY: Condvar = Condvar::new();
+
+        thread::spawn(|| {
+            for i in 0..NUM_ITEMS {
+                QUEUE.lock().push(i);
+                NOT_EMPTY.notify_one();
+                if i % 7 == 0 {
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut sum = 0;
+        let mut received = 0;
+        while received < NUM_ITEMS {
+            let mut queue = NOT_EMPTY.wait_while(QUEUE.lock(), |q| q.is_empty());
+            for i in queue.drain(..) {
+                sum += i;
+                received += 1;
+            }
+        }
+        assert_eq!(sum, NUM_ITEMS * (NUM_ITEMS - 1) / 2);
+    }
+}
@@ -2,27 +2,83 @@
 //!
 //! Currently supported primitives:
 //!
-//! - [`Mutex`]: A mutual exclusion primitive.
-//! - mod [`spin`]: spinlocks imported from the [`kspin`] crate.
+//! - [`Mutex`]: A mutual exclusion primitive, optionally with priority
+//!   inheritance.
+//! - [`RwLock`]: A reader-writer lock.
+//! - [`Condvar`]: A condition variable, used with [`Mutex`].
+//! - [`Semaphore`]: A counting semaphore.
+//! - [`Barrier`]: A barrier to synchronize multiple tasks.
+//! - mod [`spin`]: spinlocks imported from the [`kspin`] crate, wrapped to be
+//!   tracked with the `lockdep` feature.
 //!
 //! # Cargo Features
 //!
 //! - `multitask`: For use in the multi-threaded environments. If the feature is
-//!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`]. This
-//!   feature is enabled by default.
+//!   not enabled, [`Mutex`] will be an alias of [`spin::SpinNoIrq`], and other
+//!   primitives are not available. This feature is enabled by default.
+//! - `irq`: Interrupts are enabled. Timed waits such as
+//!   [`Condvar::wait_timeout`] can be used.
+//! - `lockdep`: Check the acquisition order of [`Mutex`]es, [`RwLock`]s and
+//!   the spinlocks of [`spin`] at runtime, and report possible deadlocks and
+//!   sleeping while holding spinlocks, see [`lockdep`]. It also enables the
+//!   `multitask` feature.
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
 
+#[cfg(feature = "multitask")]
+extern crate alloc;
+
+#[cfg(feature = "lockdep")]
+#[macro_use]
+extern crate log;
+
+#[cfg(not(feature = "lockdep"))]
 pub use kspin as spin;
 
+#[cfg(feature = "multitask")]
+mod barrier;
+#[cfg(feature = "multitask")]
+mod condvar;
+#[cfg(feature = "lockdep")]
+#[doc(cfg(feature = "lockdep"))]
+pub mod lockdep;
 #[cfg(feature = "multitask")]
 mod mutex;
+#[cfg(feature = "multitask")]
+mod pi;
+#[cfg(feature = "multitask")]
+mod rwlock;
+#[cfg(feature = "multitask")]
+mod semaphore;
+#[cfg(feature = "lockdep")]
+pub mod spin;
 
+#[cfg(feature = "multitask")]
+#[doc(cfg(feature = "multitask"))]
+pub use self::barrier::{Barrier, BarrierWaitResult};
+#[cfg(feature = "multitask")]
+#[doc(cfg(feature = "multitask"))]
+pub use self::condvar::{Condvar, WaitTimeoutResult};
 #[cfg(feature = "multitask")]
 #[doc(cfg(feature = "multitask"))]
 pub use self::mutex::{Mutex, MutexGuard};
+#[cfg(feature = "multitask")]
+#[doc(cfg(feature = "multitask"))]
+pub use self::rwlock::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(feature = "multitask")]
+#[doc(cfg(feature = "multitask"))]
+pub use self::semaphore::{Semaphore, SemaphoreGuard};
 
 #[cfg(not(feature = "multitask"))]
 #[doc(cfg(not(feature = "multitask")))]
 pub use kspin::{SpinNoIrq as Mutex, SpinNoIrqGuard as MutexGuard};
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, Once};
+
+    /// The scheduler is shared by all tests, so they can not run in parallel.
+    pub static SERIAL: Mutex<()> = Mutex::new(());
+    pub static INIT: Once = Once::new();
+}
@@ -0,0 +1,250 @@
+//! A lock dependency checker for the locks of this crate, enabled by the
+//! `lockdep` feature.
+//!
+//! [`Mutex`](crate::Mutex)es, [`RwLock`](crate::RwLock)s and the spinlocks of
+//! [`spin`](crate::spin) are tracked. Each lock belongs to a lock class, which
+//! is the location where it is created. The checker records the locks held by
+//! each task, and whenever a task acquires a lock while holding others, an
+//! order "held class -> new class" is added to a global dependency graph. If
+//! the new order closes a cycle in the graph, some tasks may deadlock by
+//! acquiring the locks in opposite orders, which is reported the first time it
+//! is observed.
+//!
+//! Readers and writers of a [`RwLock`](crate::RwLock) are not distinguished,
+//! since writers are preferred, a new reader may wait for a writer that waits
+//! for other readers. Locks acquired in interrupt handlers are attributed to
+//! the interrupted task.
+//!
+//! It also reports acquiring a sleeping lock in an atomic context, e.g., when
+//! holding a [`SpinNoIrq`](crate::spin::SpinNoIrq): with IRQs disabled, which
+//! requires the `irq` feature, or with preemption disabled, which requires
+//! the `preempt` feature of `axtask`. Without both, it's not checked.
+//!
+//! Other lock implementations, e.g., the ones of `axstd`, are checked by
+//! calling [`check_acquire`], [`on_acquire`] and [`on_release`]. Spinlocks
+//! used directly from [`kspin`] are not tracked.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::panic::Location;
+
+use axtask::TaskId;
+use kspin::SpinNoIrq;
+
+/// A lock class, identified by the location where the lock is created.
+pub type LockClass = &'static Location<'static>;
+
+struct LockDep {
+    /// Lock classes held by each task, in acquisition order.
+    held: BTreeMap<u64, Vec<LockClass>>,
+    /// `deps[a][b]` means `b` has been acquired while holding `a`, which is
+    /// the name of the first task that did so.
+    deps: BTreeMap<LockClass, BTreeMap<LockClass, String>>,
+    /// Inversions that have been reported.
+    reported: BTreeSet<(LockClass, LockClass)>,
+}
+
+static LOCKDEP: SpinNoIrq<LockDep> = SpinNoIrq::new(LockDep {
+    held: BTreeMap::new(),
+    deps: BTreeMap::new(),
+    reported: BTreeSet::new(),
+});
+
+impl LockDep {
+    fn has_dep(&self, from: LockClass, to: LockClass) -> bool {
+        self.deps.get(from).is_some_and(|d| d.contains_key(to))
+    }
+
+    /// Finds a path `from -> ... -> to` in the dependency graph, returns the
+    /// task name of its first edge.
+    fn find_path(&self, from: LockClass, to: LockClass) -> Option<&str> {
+        let mut visited = BTreeSet::new();
+        let mut stack = Vec::new();
+        for (next, name) in self.deps.get(from).into_iter().flatten() {
+            stack.push((*next, name.as_str()));
+        }
+        while let Some((class, name)) = stack.pop() {
+            if class == to {
+                return Some(name);
+            }
+            if visited.insert(class) {
+                for next in self.deps.get(class).into_iter().flat_map(|d| d.keys()) {
+                    stack.push((*next, name));
+                }
+            }
+        }
+        None
+    }
+
+    /// Removes the locks held by tasks that have exited, or been killed,
+    /// without releasing them.
+    fn remove_dead_holders(&mut self) {
+        self.held.retain(|&id, _| {
+            axtask::find_task(TaskId::from_u64(id)).is_some_and(|t| !t.is_killed())
+        });
+    }
+}
+
+/// Returns `true` if the current task can't sleep.
+fn in_atomic() -> bool {
+    axtask::preempt_disabled() || (cfg!(feature = "irq") && !axhal::arch::irqs_enabled())
+}
+
+/// Checks the acquisition of `class` by the current task, before it may
+/// block on the lock.
+pub fn check_acquire(class: LockClass) {
+    if in_atomic() {
+        error!(
+            "lockdep: task {} may sleep on lock {} in an atomic context (holding a spinlock?)",
+            axtask::current().id_name(),
+            class
+        );
+    }
+    check_order(class);
+}
+
+/// Checks the order of acquiring `class` against the locks held by the
+/// current task, without the atomic context check, for spinlocks.
+pub(crate) fn check_order(class: LockClass) {
+    // Spinlocks may be used before the scheduler is initialized.
+    let Some(curr) = axtask::current_may_uninit() else {
+        return;
+    };
+    let mut lockdep = LOCKDEP.lock();
+    let held = match lockdep.held.get(&curr.id().as_u64()) {
+        Some(held) => held.clone(),
+        None => return,
+    };
+    for prev in held {
+        // Nested locks of the same class are not tracked.
+        if prev == class || lockdep.has_dep(prev, class) {
+            continue;
+        }
+        if let Some(other) = lockdep.find_path(class, prev).map(String::from) {
+            if lockdep.reported.insert((prev, class)) {
+                error!(
+                    "lockdep: possible deadlock: task {} acquires {} while holding {}, \
+                     but task {} acquired them in the opposite order",
+                    curr.id_name(),
+                    class,
+                    prev,
+                    other
+                );
+            }
+        } else {
+            let deps = lockdep.deps.entry(prev).or_default();
+            deps.insert(class, curr.id_name());
+        }
+    }
+}
+
+/// Records that `class` is held by the current task.
+pub fn on_acquire(class: LockClass) {
+    let Some(curr) = axtask::current_may_uninit() else {
+        return;
+    };
+    let id = curr.id().as_u64();
+    let mut lockdep = LOCKDEP.lock();
+    if !lockdep.held.contains_key(&id) {
+        // Killed tasks exit without releasing their locks.
+        lockdep.remove_dead_holders();
+    }
+    lockdep.held.entry(id).or_default().push(class);
+}
+
+/// Records that `class` is released by the current task.
+pub fn on_release(class: LockClass) {
+    let Some(curr) = axtask::current_may_uninit() else {
+        return;
+    };
+    let id = curr.id().as_u64();
+    let mut lockdep = LOCKDEP.lock();
+    if let Some(held) = lockdep.held.get_mut(&id) {
+        if let Some(pos) = held.iter().rposition(|c| *c == class) {
+            held.remove(pos);
+        }
+        if held.is_empty() {
+            lockdep.held.remove(&id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LOCKDEP;
+    use crate::spin::SpinRaw;
+    use crate::{Mutex, RwLock};
+    use axtask as thread;
+
+    #[test]
+    fn lock_inversion() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        static A: Mutex<()> = Mutex::new(());
+        static B: Mutex<()> = Mutex::new(());
+
+        let task = thread::spawn(|| {
+            let _a = A.lock();
+            let _b = B.lock();
+        });
+        assert_eq!(task.join(), Some(0));
+        assert!(LOCKDEP.lock().has_dep(A.class, B.class));
+
+        // No deadlock actually happens, but the inversion is reported.
+        let _b = B.lock();
+        let _a = A.lock();
+        assert!(LOCKDEP.lock().reported.contains(&(B.class, A.class)));
+        assert!(!LOCKDEP.lock().has_dep(B.class, A.class));
+    }
+
+    #[test]
+    fn rwlock_and_spinlock() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        static L: RwLock<()> = RwLock::new(());
+        static S: SpinRaw<()> = SpinRaw::new(());
+
+        {
+            let _r = L.read();
+            let _s = S.lock();
+        }
+        assert!(LOCKDEP.lock().has_dep(L.class, S.class));
+        let id = thread::current().id().as_u64();
+        assert!(!LOCKDEP.lock().held.contains_key(&id));
+
+        // Writers are tracked the same as readers.
+        let _s = S.lock();
+        let _w = L.write();
+        assert!(LOCKDEP.lock().reported.contains(&(S.class, L.class)));
+    }
+
+    #[test]
+    fn killed_holder() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        static M: Mutex<()> = Mutex::new(());
+        static N: Mutex<()> = Mutex::new(());
+        static WQ: thread::WaitQueue = thread::WaitQueue::new();
+
+        let task = thread::spawn(|| {
+            let _m = M.lock();
+            WQ.wait();
+        });
+        while !M.is_locked() {
+            thread::yield_now();
+        }
+        let id = task.id().as_u64();
+        assert!(LOCKDEP.lock().held.contains_key(&id));
+
+        // The killed task exits without releasing `M`.
+        assert!(task.kill());
+        assert_eq!(task.join(), Some(thread::KILLED_EXIT_CODE));
+        assert!(M.is_locked());
+        let _n = N.lock();
+        assert!(!LOCKDEP.lock().held.contains_key(&id));
+    }
+}
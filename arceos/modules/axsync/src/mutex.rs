@@ -13,9 +13,16 @@ use axtask::{current, WaitQueue};
 /// When the mutex is locked, the current task will block and be put into the
 /// wait queue. When the mutex is unlocked, all tasks waiting on the queue
 /// will be woken up.
+///
+/// A mutex created by [`Mutex::new_pi`] uses priority inheritance: while
+/// tasks are blocked on it, the owner runs with the highest priority of them,
+/// so a low-priority owner can not be starved by tasks of medium priorities.
 pub struct Mutex<T: ?Sized> {
     wq: WaitQueue,
     owner_id: AtomicU64,
+    pi: bool,
+    #[cfg(feature = "lockdep")]
+    pub(crate) class: crate::lockdep::LockClass,
     data: UnsafeCell<T>,
 }
 
@@ -34,10 +41,31 @@ unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
 impl<T> Mutex<T> {
     /// Creates a new [`Mutex`] wrapping the supplied data.
     #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
             wq: WaitQueue::new(),
             owner_id: AtomicU64::new(0),
+            pi: false,
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Creates a new [`Mutex`] with priority inheritance enabled.
+    ///
+    /// The priorities are set by [`axtask::set_priority`], it has no effect
+    /// if the scheduler does not support priorities.
+    #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new_pi(data: T) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            owner_id: AtomicU64::new(0),
+            pi: true,
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
             data: UnsafeCell::new(data),
         }
     }
@@ -70,6 +98,8 @@ impl<T: ?Sized> Mutex<T> {
     /// and the lock will be dropped when the guard falls out of scope.
     pub fn lock(&self) -> MutexGuard<T> {
         let current_id = current().id().as_u64();
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::check_acquire(self.class);
         loop {
             // Can fail to lock even if the spinlock is not locked. May be more efficient than `try_lock`
             // when called in a loop.
@@ -88,10 +118,21 @@ impl<T: ?Sized> Mutex<T> {
                         current().id_name()
                     );
                     // Wait until the lock looks unlocked before retrying
-                    self.wq.wait_until(|| !self.is_locked());
+                    if self.pi {
+                        crate::pi::wait_on(&self.owner_id, owner_id, || {
+                            self.wq.wait_until(|| !self.is_locked())
+                        });
+                    } else {
+                        self.wq.wait_until(|| !self.is_locked());
+                    }
                 }
             }
         }
+        if self.pi {
+            crate::pi::on_acquire(&self.owner_id);
+        }
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::on_acquire(self.class);
         MutexGuard {
             lock: self,
             data: unsafe { &mut *self.data.get() },
@@ -109,6 +150,11 @@ impl<T: ?Sized> Mutex<T> {
             .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
+            if self.pi {
+                crate::pi::on_acquire(&self.owner_id);
+            }
+            #[cfg(feature = "lockdep")]
+            crate::lockdep::on_acquire(self.class);
             Some(MutexGuard {
                 lock: self,
                 data: unsafe { &mut *self.data.get() },
@@ -126,13 +172,19 @@ impl<T: ?Sized> Mutex<T> {
     /// thread. However, this can be useful in some instances for exposing
     /// the lock to FFI that doesn’t know how to deal with RAII.
     pub unsafe fn force_unlock(&self) {
-        let owner_id = self.owner_id.swap(0, Ordering::Release);
+        let owner_id = if self.pi {
+            crate::pi::release(&self.owner_id)
+        } else {
+            self.owner_id.swap(0, Ordering::Release)
+        };
         assert_eq!(
             owner_id,
             current().id().as_u64(),
             "{} tried to release mutex it doesn't own",
             current().id_name()
         );
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::on_release(self.class);
         self.wq.notify_one(true);
     }
 
@@ -151,6 +203,7 @@ impl<T: ?Sized> Mutex<T> {
 
 impl<T: ?Sized + Default> Default for Mutex<T> {
     #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     fn default() -> Self {
         Self::new(Default::default())
     }
@@ -167,6 +220,14 @@ impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
     }
 }
 
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Returns the mutex that the guard is created from.
+    #[inline(always)]
+    pub(crate) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
 impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
     type Target = T;
     #[inline(always)]
@@ -199,11 +260,19 @@ impl<'a, T: ?Sized> Drop for MutexGuard<'a, T> {
 
 #[cfg(test)]
 mod tests {
+    use crate::tests::{INIT, SERIAL};
     use crate::Mutex;
     use axtask as thread;
-    use std::sync::Once;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
-    static INIT: Once = Once::new();
+    /// The priority tests are ignored by default, they run with
+    /// `--features axtask/sched_cfs -- --include-ignored`.
+    fn check_priorities_supported() {
+        assert!(
+            thread::set_priority(0),
+            "priorities not supported, enable `axtask/sched_cfs`"
+        );
+    }
 
     fn may_interrupt() {
         // simulate interrupts
@@ -214,6 +283,7 @@ mod tests {
 
     #[test]
     fn lots_and_lots() {
+        let _lock = SERIAL.lock();
         INIT.call_once(thread::init_scheduler);
 
         const NUM_TASKS: u32 = 10;
@@ -249,4 +319,110 @@ mod tests {
         assert_eq!(*M.lock(), NUM_ITERS * NUM_TASKS * 3);
         println!("Mutex test OK");
     }
+
+    #[test]
+    #[ignore = "needs a scheduler with priorities"]
+    fn priority_inheritance() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+        check_priorities_supported();
+
+        static M1: Mutex<()> = Mutex::new_pi(());
+        static M2: Mutex<()> = Mutex::new_pi(());
+        static MID_WAITING: AtomicBool = AtomicBool::new(false);
+        static HIGH_WAITING: AtomicBool = AtomicBool::new(false);
+
+        // The main task has a low priority, and holds `M2`.
+        assert!(thread::set_priority(10));
+        let low = thread::current().as_task_ref().clone();
+        let guard = M2.lock();
+
+        // The medium task holds `M1`, and waits for `M2`.
+        let mid = thread::spawn(|| {
+            let _g1 = M1.lock();
+            MID_WAITING.store(true, Ordering::Release);
+            let _g2 = M2.lock();
+        });
+        while !MID_WAITING.load(Ordering::Acquire) {
+            thread::yield_now();
+        }
+        assert_eq!(low.base_priority(), 10);
+        assert_eq!(low.priority(), 0);
+
+        // The high task waits for `M1`, its priority is passed along the
+        // chain to the low task.
+        let high = thread::spawn(|| {
+            assert!(thread::set_priority(-10));
+            HIGH_WAITING.store(true, Ordering::Release);
+            let _g1 = M1.lock();
+        });
+        while !HIGH_WAITING.load(Ordering::Acquire) {
+            thread::yield_now();
+        }
+        assert_eq!(mid.priority(), -10);
+        assert_eq!(low.priority(), -10);
+
+        // The low task drops the inherited priority after unlocking.
+        drop(guard);
+        assert_eq!(low.priority(), 10);
+        assert_eq!(mid.join(), Some(0));
+        assert_eq!(high.join(), Some(0));
+        assert_eq!(mid.priority(), 0);
+
+        assert!(thread::set_priority(0));
+        println!("Priority inheritance test OK");
+    }
+
+    #[test]
+    #[ignore = "needs a scheduler with priorities"]
+    fn priority_inheritance_no_starvation() {
+        let _lock = SERIAL.lock();
+        INIT.call_once(thread::init_scheduler);
+        check_priorities_supported();
+
+        const WORK: usize = 100;
+        const MAX_HOG_RUNS: usize = 100_000;
+        static M: Mutex<()> = Mutex::new_pi(());
+        static HIGH_WAITING: AtomicBool = AtomicBool::new(false);
+        static HIGH_DONE: AtomicBool = AtomicBool::new(false);
+        static HOG_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        // The main task has a low priority, and holds `M`.
+        assert!(thread::set_priority(10));
+        let guard = M.lock();
+
+        let high = thread::spawn(|| {
+            assert!(thread::set_priority(-10));
+            HIGH_WAITING.store(true, Ordering::Release);
+            let _g = M.lock();
+            HIGH_DONE.store(true, Ordering::Release);
+        });
+        while !HIGH_WAITING.load(Ordering::Acquire) {
+            thread::yield_now();
+        }
+
+        // A medium-priority task keeps the CPU busy until the high task gets
+        // the mutex.
+        let hog = thread::spawn(|| {
+            while !HIGH_DONE.load(Ordering::Acquire) {
+                let runs = HOG_RUNS.fetch_add(1, Ordering::Relaxed);
+                assert!(runs < MAX_HOG_RUNS, "high-priority waiter starved");
+                thread::yield_now();
+            }
+        });
+
+        // The low task competes with the hog by the priority of the high
+        // task, so it can finish its work and release the mutex.
+        assert_eq!(thread::current().priority(), -10);
+        for _ in 0..WORK {
+            thread::yield_now();
+        }
+        drop(guard);
+        assert_eq!(high.join(), Some(0));
+        assert_eq!(hog.join(), Some(0));
+        assert!(HOG_RUNS.load(Ordering::Relaxed) < MAX_HOG_RUNS);
+
+        assert!(thread::set_priority(0));
+        println!("Priority inheritance starvation test OK");
+    }
 }
@@ -0,0 +1,180 @@
+//! Priority inheritance for [`Mutex`](crate::Mutex).
+//!
+//! The owner of a contended mutex inherits the highest priority of the tasks
+//! waiting for it. If the owner is itself waiting for another mutex, the
+//! priority is passed along the chain of owners.
+//!
+//! All the bookkeeping is kept in a global table protected by one lock, so a
+//! chain can be walked without locking each mutex on it. The table keeps a
+//! copy of the owner of each contended mutex, and never dereferences the
+//! mutexes, as a waiter killed while blocked leaves its entry behind after
+//! the mutex may have been dropped.
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axtask::{AxTaskRef, TaskId};
+use kspin::SpinNoIrq;
+
+static PI_TABLE: SpinNoIrq<PiTable> = SpinNoIrq::new(PiTable::new());
+
+/// The tasks waiting for a mutex.
+struct PiWaiters {
+    /// ID of the task owning the mutex, or 0 if it's unlocked. It's updated
+    /// with the table locked when the mutex is acquired or released.
+    owner: u64,
+    /// IDs of the waiting tasks. Tasks killed while waiting are not removed
+    /// until they are found dead.
+    task_ids: Vec<u64>,
+}
+
+struct PiTable {
+    /// Contended mutexes (indexed by the address of their owner ID fields)
+    /// and the tasks waiting for them.
+    waiters: BTreeMap<usize, PiWaiters>,
+    /// The mutex each waiting task is blocked on, indexed by task IDs.
+    blocked_on: BTreeMap<u64, usize>,
+}
+
+fn find_task(id: u64) -> Option<AxTaskRef> {
+    axtask::find_task(TaskId::from_u64(id)).filter(|t| !t.is_killed())
+}
+
+impl PiTable {
+    const fn new() -> Self {
+        Self {
+            waiters: BTreeMap::new(),
+            blocked_on: BTreeMap::new(),
+        }
+    }
+
+    fn owner_of(&self, lock: usize) -> Option<AxTaskRef> {
+        match self.waiters.get(&lock)?.owner {
+            0 => None,
+            owner_id => axtask::find_task(TaskId::from_u64(owner_id)),
+        }
+    }
+
+    /// Returns the highest priority of the tasks waiting for the mutexes
+    /// held by `owner`.
+    fn top_waiter_priority(&self, owner: &AxTaskRef) -> Option<isize> {
+        let owner_id = owner.id().as_u64();
+        self.waiters
+            .values()
+            .filter(|w| w.owner == owner_id)
+            .flat_map(|w| w.task_ids.iter())
+            .filter_map(|&id| find_task(id))
+            .map(|t| t.priority())
+            .min()
+    }
+
+    /// Recomputes the inherited priority of `task` from its waiters, and
+    /// passes the change along the chain of owners it's blocked on.
+    fn propagate(&self, mut task: AxTaskRef) {
+        // Each mutex appears at most once in a chain, unless there is a
+        // deadlock.
+        for _ in 0..=self.waiters.len() {
+            let old = task.priority();
+            let inherited = self
+                .top_waiter_priority(&task)
+                .filter(|&prio| prio < task.base_priority());
+            axtask::set_inherited_priority(&task, inherited);
+            if task.priority() == old {
+                return;
+            }
+            match self
+                .blocked_on
+                .get(&task.id().as_u64())
+                .and_then(|&lock| self.owner_of(lock))
+            {
+                Some(owner) => task = owner,
+                None => return,
+            }
+        }
+    }
+
+    /// Removes the tasks that were killed while waiting.
+    fn remove_dead_waiters(&mut self) {
+        self.blocked_on.retain(|&id, _| find_task(id).is_some());
+        for waiters in self.waiters.values_mut() {
+            waiters.task_ids.retain(|&id| find_task(id).is_some());
+        }
+        self.waiters.retain(|_, w| !w.task_ids.is_empty());
+    }
+}
+
+/// Calls `wait` to block the current task until the mutex is released, while
+/// lending its priority to the owner of the mutex.
+///
+/// Returns immediately without calling `wait` if the owner is not
+/// `expected_owner` any more.
+pub(crate) fn wait_on(owner_id: &AtomicU64, expected_owner: u64, wait: impl FnOnce()) {
+    let lock = owner_id as *const _ as usize;
+    let curr_id = axtask::current().id().as_u64();
+    {
+        let mut table = PI_TABLE.lock();
+        // The owner releases the mutex with the table locked, recheck here so
+        // we won't boost a task that is not the owner.
+        if owner_id.load(Ordering::Acquire) != expected_owner {
+            return;
+        }
+        table.remove_dead_waiters();
+        // The entry may be left by a dropped mutex at the same address, whose
+        // waiters were all killed, so the owner is always refreshed.
+        let waiters = table.waiters.entry(lock).or_insert_with(|| PiWaiters {
+            owner: expected_owner,
+            task_ids: Vec::new(),
+        });
+        waiters.owner = expected_owner;
+        waiters.task_ids.push(curr_id);
+        table.blocked_on.insert(curr_id, lock);
+        if let Some(owner) = table.owner_of(lock) {
+            table.propagate(owner);
+        }
+    }
+
+    wait();
+
+    let mut table = PI_TABLE.lock();
+    table.blocked_on.remove(&curr_id);
+    if let Some(waiters) = table.waiters.get_mut(&lock) {
+        waiters.task_ids.retain(|&id| id != curr_id);
+        // The owner may have changed, recompute its priority without us.
+        if let Some(owner) = table.owner_of(lock) {
+            table.propagate(owner);
+        }
+        if table.waiters[&lock].task_ids.is_empty() {
+            table.waiters.remove(&lock);
+        }
+    }
+}
+
+/// The current task has acquired the mutex, inherits the priority of the
+/// remaining waiters.
+pub(crate) fn on_acquire(owner_id: &AtomicU64) {
+    let lock = owner_id as *const _ as usize;
+    let mut table = PI_TABLE.lock();
+    if let Some(waiters) = table.waiters.get_mut(&lock) {
+        waiters.owner = owner_id.load(Ordering::Acquire);
+        table.propagate(axtask::current().as_task_ref().clone());
+    }
+}
+
+/// Releases the mutex held by the current task, and drops the priority
+/// inherited from its waiters.
+///
+/// Returns the previous owner ID.
+pub(crate) fn release(owner_id: &AtomicU64) -> u64 {
+    let lock = owner_id as *const _ as usize;
+    let mut table = PI_TABLE.lock();
+    let prev_owner = owner_id.swap(0, Ordering::Release);
+    if let Some(waiters) = table.waiters.get_mut(&lock) {
+        waiters.owner = 0;
+    }
+    let curr = axtask::current();
+    if curr.priority() != curr.base_priority() {
+        table.propagate(curr.as_task_ref().clone());
+    }
+    prev_owner
+}
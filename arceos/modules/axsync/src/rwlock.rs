@@ -0,0 +1,358 @@
+//! A naïve sleeping reader-writer lock.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+/// The lock state when it is held by a writer, otherwise the state is the
+/// number of readers.
+const WRITER: usize = usize::MAX;
+
+/// A reader-writer lock, similar to
+/// [`std::sync::RwLock`](https://doc.rust-lang.org/std/sync/struct.RwLock.html).
+///
+/// It allows multiple readers or at most one writer at any point in time.
+/// Tasks that can not get the lock block and are put into the wait queue.
+/// Writers are preferred: new readers wait if there are writers waiting.
+pub struct RwLock<T: ?Sized> {
+    wq: WaitQueue,
+    state: AtomicUsize,
+    writers_waiting: AtomicUsize,
+    #[cfg(feature = "lockdep")]
+    pub(crate) class: crate::lockdep::LockClass,
+    data: UnsafeCell<T>,
+}
+
+/// Counts a writer waiting for the [`RwLock`] while it's alive, which keeps
+/// new readers off.
+///
+/// The count is dropped after the writer gets the lock, or is killed while
+/// waiting, otherwise the readers would wait forever.
+struct WaitingWriter<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+/// A guard that provides immutable data access.
+///
+/// When the guard falls out of scope it will release the shared read access.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    data: *const T,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the exclusive write
+/// access.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    data: *mut T,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new [`RwLock`] wrapping the supplied data.
+    #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this [`RwLock`] and unwraps the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        let RwLock { data, .. } = self;
+        data.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn can_read(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != WRITER
+            && self.writers_waiting.load(Ordering::Relaxed) == 0
+    }
+
+    /// Locks this [`RwLock`] with shared read access, blocking the current
+    /// task until it can be acquired.
+    pub fn read(&self) -> RwLockReadGuard<T> {
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::check_acquire(self.class);
+        loop {
+            if let Some(guard) = self.try_read() {
+                return guard;
+            }
+            self.wq.wait_until(|| self.can_read());
+        }
+    }
+
+    /// Attempts to acquire this [`RwLock`] with shared read access, returns
+    /// [`None`] if it is held by a writer or there are writers waiting.
+    pub fn try_read(&self) -> Option<RwLockReadGuard<T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        while self.can_read() {
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    #[cfg(feature = "lockdep")]
+                    crate::lockdep::on_acquire(self.class);
+                    return Some(RwLockReadGuard {
+                        lock: self,
+                        data: self.data.get(),
+                    });
+                }
+                Err(s) => state = s,
+            }
+        }
+        None
+    }
+
+    /// Locks this [`RwLock`] with exclusive write access, blocking the
+    /// current task until it can be acquired.
+    pub fn write(&self) -> RwLockWriteGuard<T> {
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::check_acquire(self.class);
+        if let Some(guard) = self.try_write() {
+            return guard;
+        }
+        let waiting = WaitingWriter::new(self);
+        loop {
+            if !self
+                .wq
+                .wait_until_or_killed(|| self.state.load(Ordering::Relaxed) == 0)
+            {
+                drop(waiting);
+                axtask::exit(axtask::KILLED_EXIT_CODE);
+            }
+            if let Some(guard) = self.try_write() {
+                return guard;
+            }
+        }
+    }
+
+    /// Attempts to acquire this [`RwLock`] with exclusive write access,
+    /// returns [`None`] if it is held by others.
+    pub fn try_write(&self) -> Option<RwLockWriteGuard<T>> {
+        if self
+            .state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            #[cfg(feature = "lockdep")]
+            crate::lockdep::on_acquire(self.class);
+            Some(RwLockWriteGuard {
+                lock: self,
+                data: self.data.get(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Returns `true` if the lock is currently held by a writer.
+    ///
+    /// # Safety
+    ///
+    /// This function provides no synchronization guarantees and so its result
+    /// should be considered 'out of date' the instant it is called.
+    #[inline(always)]
+    pub fn is_write_locked(&self) -> bool {
+        self.state.load(Ordering::Relaxed) == WRITER
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`RwLock`] mutably, no actual locking needs
+    /// to take place.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data.get() }
+    }
+
+    fn read_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::on_release(self.class);
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            // the last reader, wake up writers.
+            self.wq.notify_all(true);
+        }
+    }
+
+    fn write_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        crate::lockdep::on_release(self.class);
+        self.state.store(0, Ordering::Release);
+        self.wq.notify_all(true);
+    }
+}
+
+impl<T: ?Sized + Default> Default for RwLock<T> {
+    #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_read() {
+            Some(guard) => write!(f, "RwLock {{ data: ")
+                .and_then(|()| (*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "RwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> WaitingWriter<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> Self {
+        lock.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        Self { lock }
+    }
+}
+
+impl<T: ?Sized> Drop for WaitingWriter<'_, T> {
+    fn drop(&mut self) {
+        let lock = self.lock;
+        let last = lock.writers_waiting.fetch_sub(1, Ordering::Relaxed) == 1;
+        if last && !lock.is_write_locked() {
+            // no writers are waiting or holding the lock, wake up readers.
+            lock.wq.notify_all(true);
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::RwLock;
+    use axtask as thread;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn readers_and_writers() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 10;
+        const NUM_ITERS: usize = 1000;
+        static LOCK: RwLock<(usize, usize)> = RwLock::new((0, 0));
+        static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+        for i in 0..NUM_TASKS {
+            thread::spawn(move || {
+                for _ in 0..NUM_ITERS {
+                    if i % 2 == 0 {
+                        let mut val = LOCK.write();
+                        val.0 += 1;
+                        thread::yield_now();
+                        val.1 += 1;
+                    } else {
+                        let val = LOCK.read();
+                        thread::yield_now();
+                        assert_eq!(val.0, val.1);
+                    }
+                }
+                FINISHED.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        while FINISHED.load(Ordering::Relaxed) < NUM_TASKS {
+            thread::yield_now();
+        }
+        assert_eq!(*LOCK.read(), (NUM_ITERS * 5, NUM_ITERS * 5));
+    }
+
+    #[test]
+    fn killed_writer() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        static LOCK: RwLock<usize> = RwLock::new(0);
+
+        let guard = LOCK.read();
+        let writer = thread::spawn(|| {
+            *LOCK.write() += 1;
+        });
+        thread::yield_now();
+        // The waiting writer keeps new readers off.
+        assert!(LOCK.try_read().is_none());
+        let reader = thread::spawn(|| {
+            assert_eq!(*LOCK.read(), 0);
+        });
+        thread::yield_now();
+
+        // Readers can go on after the writer is killed.
+        assert!(writer.kill());
+        assert_eq!(writer.join(), Some(thread::KILLED_EXIT_CODE));
+        assert_eq!(reader.join(), Some(0));
+        assert!(LOCK.try_read().is_some());
+        drop(guard);
+        assert!(LOCK.try_write().is_some());
+    }
+}
@@ -0,0 +1,139 @@
+//! A sleeping counting semaphore.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axtask::WaitQueue;
+
+/// A counting semaphore.
+///
+/// It maintains a number of permits. [`acquire`] takes a permit, blocking
+/// the current task until one is available, and [`release`] gives a permit
+/// back and wakes up a waiting task.
+///
+/// [`acquire`]: Semaphore::acquire
+/// [`release`]: Semaphore::release
+pub struct Semaphore {
+    wq: WaitQueue,
+    count: AtomicUsize,
+}
+
+/// An RAII guard which will release a permit of the semaphore when dropped.
+pub struct SemaphoreGuard<'a> {
+    sem: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with the initial number of permits.
+    pub const fn new(count: usize) -> Self {
+        Self {
+            wq: WaitQueue::new(),
+            count: AtomicUsize::new(count),
+        }
+    }
+
+    /// Returns the number of permits currently available.
+    pub fn available_permits(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Acquires a permit, blocking the current task until one is available.
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            self.wq.wait_until(|| self.available_permits() > 0);
+        }
+    }
+
+    /// Acquires a permit if one is available, returns `true` on success.
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Releases a permit, and wakes up a task waiting for it.
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+
+    /// Acquires a permit, and returns a guard that releases it when dropped.
+    pub fn access(&self) -> SemaphoreGuard {
+        self.acquire();
+        SemaphoreGuard { sem: self }
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("count", &self.available_permits())
+            .finish()
+    }
+}
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        self.sem.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Semaphore;
+    use axtask as thread;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn limits_concurrency() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        const NUM_TASKS: usize = 10;
+        const NUM_PERMITS: usize = 3;
+        static SEM: Semaphore = Semaphore::new(NUM_PERMITS);
+        static INSIDE: AtomicUsize = AtomicUsize::new(0);
+        static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+        for _ in 0..NUM_TASKS {
+            thread::spawn(|| {
+                for _ in 0..100 {
+                    let _guard = SEM.access();
+                    let n = INSIDE.fetch_add(1, Ordering::Relaxed);
+                    assert!(n < NUM_PERMITS);
+                    thread::yield_now();
+                    INSIDE.fetch_sub(1, Ordering::Relaxed);
+                }
+                FINISHED.fetch_add(1, Ordering::Relaxed);
+            });
+        }
+        while FINISHED.load(Ordering::Relaxed) < NUM_TASKS {
+            thread::yield_now();
+        }
+        assert_eq!(SEM.available_permits(), NUM_PERMITS);
+        assert!(SEM.try_acquire());
+        SEM.release();
+    }
+
+    #[test]
+    fn killed_waiter() {
+        let _lock = crate::tests::SERIAL.lock();
+        crate::tests::INIT.call_once(thread::init_scheduler);
+
+        static SEM: Semaphore = Semaphore::new(0);
+
+        let killed = thread::spawn(|| SEM.acquire());
+        let waiter = thread::spawn(|| SEM.acquire());
+        thread::yield_now();
+
+        // The killed task is woken up for the permit but does not take it,
+        // which goes to the other waiter.
+        SEM.release();
+        assert!(killed.kill());
+        assert_eq!(killed.join(), Some(thread::KILLED_EXIT_CODE));
+        assert_eq!(waiter.join(), Some(0));
+        assert_eq!(SEM.available_permits(), 0);
+    }
+}
@@ -0,0 +1,163 @@
+//! Spinlocks of the [`kspin`] crate, tracked by the [`lockdep`](crate::lockdep)
+//! checker.
+//!
+//! The locks have the same interface as the ones of [`kspin`], which are
+//! re-exported as this module without the `lockdep` feature.
+
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+
+use kernel_guard::{BaseGuard, NoOp, NoPreempt, NoPreemptIrqSave};
+
+use crate::lockdep::{self, LockClass};
+
+/// A spinlock with the guard `G`, wrapping [`kspin::BaseSpinLock`].
+pub struct BaseSpinLock<G: BaseGuard, T: ?Sized> {
+    pub(crate) class: LockClass,
+    inner: kspin::BaseSpinLock<G, T>,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the lock.
+pub struct BaseSpinLockGuard<'a, G: BaseGuard, T: ?Sized + 'a> {
+    class: LockClass,
+    inner: kspin::BaseSpinLockGuard<'a, G, T>,
+}
+
+/// A spin lock that disables kernel preemption while trying to lock, and
+/// re-enables it after unlocking.
+pub type SpinNoPreempt<T> = BaseSpinLock<NoPreempt, T>;
+/// A guard that provides mutable data access for [`SpinNoPreempt`].
+pub type SpinNoPreemptGuard<'a, T> = BaseSpinLockGuard<'a, NoPreempt, T>;
+
+/// A spin lock that disables kernel preemption and local IRQs while trying
+/// to lock, and re-enables it after unlocking.
+pub type SpinNoIrq<T> = BaseSpinLock<NoPreemptIrqSave, T>;
+/// A guard that provides mutable data access for [`SpinNoIrq`].
+pub type SpinNoIrqGuard<'a, T> = BaseSpinLockGuard<'a, NoPreemptIrqSave, T>;
+
+/// A raw spin lock that does nothing while trying to lock.
+pub type SpinRaw<T> = BaseSpinLock<NoOp, T>;
+/// A guard that provides mutable data access for [`SpinRaw`].
+pub type SpinRawGuard<'a, T> = BaseSpinLockGuard<'a, NoOp, T>;
+
+impl<G: BaseGuard, T> BaseSpinLock<G, T> {
+    /// Creates a new [`BaseSpinLock`] wrapping the supplied data.
+    #[inline(always)]
+    #[track_caller]
+    pub const fn new(data: T) -> Self {
+        Self {
+            class: Location::caller(),
+            inner: kspin::BaseSpinLock::new(data),
+        }
+    }
+
+    /// Consumes this [`BaseSpinLock`] and unwraps the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> T {
+        self.inner.into_inner()
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> BaseSpinLock<G, T> {
+    /// Locks the [`BaseSpinLock`] and returns a guard that permits access to
+    /// the inner data.
+    #[inline(always)]
+    pub fn lock(&self) -> BaseSpinLockGuard<G, T> {
+        lockdep::check_order(self.class);
+        let inner = self.inner.lock();
+        lockdep::on_acquire(self.class);
+        BaseSpinLockGuard {
+            class: self.class,
+            inner,
+        }
+    }
+
+    /// Returns `true` if the lock is currently held.
+    ///
+    /// # Safety
+    ///
+    /// This function provides no synchronization guarantees and so its result
+    /// should be considered 'out of date' the instant it is called.
+    #[inline(always)]
+    pub fn is_locked(&self) -> bool {
+        self.inner.is_locked()
+    }
+
+    /// Force unlock this [`BaseSpinLock`].
+    ///
+    /// # Safety
+    ///
+    /// The same as [`kspin::BaseSpinLock::force_unlock`].
+    #[inline(always)]
+    pub unsafe fn force_unlock(&self) {
+        lockdep::on_release(self.class);
+        self.inner.force_unlock()
+    }
+
+    /// Try to lock this [`BaseSpinLock`], returning a lock guard if
+    /// successful.
+    #[inline(always)]
+    pub fn try_lock(&self) -> Option<BaseSpinLockGuard<G, T>> {
+        let inner = self.inner.try_lock()?;
+        lockdep::on_acquire(self.class);
+        Some(BaseSpinLockGuard {
+            class: self.class,
+            inner,
+        })
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`BaseSpinLock`] mutably, no actual
+    /// locking needs to take place.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + Default> Default for BaseSpinLock<G, T> {
+    #[inline(always)]
+    #[track_caller]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + fmt::Debug> fmt::Debug for BaseSpinLock<G, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Deref for BaseSpinLockGuard<'_, G, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> DerefMut for BaseSpinLockGuard<'_, G, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized + fmt::Debug> fmt::Debug for BaseSpinLockGuard<'_, G, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<G: BaseGuard, T: ?Sized> Drop for BaseSpinLockGuard<'_, G, T> {
+    #[inline(always)]
+    fn drop(&mut self) {
+        // the inner guard releases the lock after this.
+        lockdep::on_release(self.class);
+    }
+}
@@ -2,10 +2,22 @@
 
 use alloc::{string::String, sync::Arc};
 
-pub(crate) use crate::run_queue::{AxRunQueue, RUN_QUEUE};
+pub(crate) use crate::run_queue::{current_run_queue, select_run_queue};
 
 #[doc(cfg(feature = "multitask"))]
-pub use crate::task::{CurrentTask, TaskId, TaskInner};
+pub use crate::cpumask::CpuMask;
+#[doc(cfg(feature = "multitask"))]
+pub use crate::executor::{block_on, Executor};
+#[cfg(feature = "sched_rt")]
+#[doc(cfg(feature = "sched_rt"))]
+pub use crate::rt::RtParams;
+#[doc(cfg(feature = "multitask"))]
+pub use crate::stats::{find_task, for_each_task, task_stats, TaskStats};
+#[doc(cfg(feature = "multitask"))]
+pub use crate::task::{CurrentTask, TaskId, TaskInner, KILLED_EXIT_CODE};
+#[cfg(feature = "paging")]
+#[doc(cfg(feature = "paging"))]
+pub use crate::task::KernelStackIf;
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task_ext::{TaskExtMut, TaskExtRef};
 #[doc(cfg(feature = "multitask"))]
@@ -63,6 +75,18 @@ pub fn current() -> CurrentTask {
     CurrentTask::get()
 }
 
+/// Returns `true` if preemption is disabled for the current task, e.g., it
+/// is holding a [`kspin::SpinNoIrq`] or a [`kernel_guard::NoPreempt`] guard.
+///
+/// It always returns `false` if the `preempt` feature is not enabled.
+pub fn preempt_disabled() -> bool {
+    #[cfg(feature = "preempt")]
+    if let Some(curr) = current_may_uninit() {
+        return !curr.can_preempt(0);
+    }
+    false
+}
+
 /// Initializes the task scheduler (for the primary CPU).
 pub fn init_scheduler() {
     info!("Initialize scheduling...");
@@ -79,20 +103,40 @@ pub fn init_scheduler_secondary() {
     crate::run_queue::init_secondary();
 }
 
-/// Handles periodic timer ticks for the task manager.
+/// Handles timer interrupts for the task manager.
 ///
-/// For example, advance scheduler states, checks timed events, etc.
+/// It fires the expired timed events, advances scheduler states if a
+/// scheduler tick has elapsed, and programs the timer hardware for the next
+/// event or tick. So the caller should not program the timer again.
 #[cfg(feature = "irq")]
 #[doc(cfg(feature = "irq"))]
 pub fn on_timer_tick() {
-    crate::timers::check_events();
-    RUN_QUEUE.lock().scheduler_timer_tick();
+    if crate::timers::on_timer_irq() {
+        current_run_queue().scheduler_timer_tick();
+    }
+}
+
+/// Runs `f` with the heap allocations of the current task attributed to the
+/// subsystem `tag`, e.g., `"net buffers"` or `"fs cache"`.
+///
+/// The allocations freed in `f` are still attributed to the tags that they
+/// were allocated with.
+#[cfg(feature = "alloc-tag")]
+#[doc(cfg(feature = "alloc-tag"))]
+pub fn with_alloc_tag<R>(tag: &'static str, f: impl FnOnce() -> R) -> R {
+    let Some(curr) = current_may_uninit() else {
+        return f();
+    };
+    let prev = curr.set_alloc_tag(Some(tag));
+    let ret = f();
+    curr.set_alloc_tag(prev);
+    ret
 }
 
 /// Adds the given task to the run queue, returns the task reference.
 pub fn spawn_task(task: TaskInner) -> AxTaskRef {
     let task_ref = task.into_arc();
-    RUN_QUEUE.lock().add_task(task_ref.clone());
+    select_run_queue(&task_ref).add_task(task_ref.clone());
     task_ref
 }
 
@@ -129,13 +173,96 @@ where
 ///
 /// [CFS]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
 pub fn set_priority(prio: isize) -> bool {
-    RUN_QUEUE.lock().set_current_priority(prio)
+    current_run_queue().set_current_priority(prio)
+}
+
+/// Lends the priority `prio` to `task`, or takes back the lent priority if
+/// `prio` is [`None`].
+///
+/// The effective priority of `task` is the higher one of its base priority
+/// and `prio`, see [`TaskInner::priority`]. It's used by synchronization
+/// primitives to implement priority inheritance.
+///
+/// Returns `false` if the underlying scheduler does not support priorities,
+/// or `prio` is out of range.
+pub fn set_inherited_priority(task: &AxTaskRef, prio: Option<isize>) -> bool {
+    current_run_queue().set_inherited_priority(task, prio)
+}
+
+/// Spawns a new task in the real-time class with the given parameters.
+///
+/// Returns [`None`] if the parameters are invalid, or the total bandwidth of
+/// real-time tasks would exceed 100% with this task admitted.
+#[cfg(feature = "sched_rt")]
+#[doc(cfg(feature = "sched_rt"))]
+pub fn spawn_rt<F>(f: F, name: String, stack_size: usize, params: RtParams) -> Option<AxTaskRef>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let task = TaskInner::new(f, name, stack_size);
+    if task
+        .rt()
+        .set_params(&params, axhal::time::wall_time_nanos())
+    {
+        Some(spawn_task(task))
+    } else {
+        None
+    }
+}
+
+/// Moves the current task into the real-time class with the given
+/// parameters, its first job is released immediately.
+///
+/// Real-time tasks are scheduled by the earliest deadline first, and always
+/// preempt the tasks of the fair class.
+///
+/// Returns `false` if the parameters are invalid, or the total bandwidth of
+/// real-time tasks would exceed 100% with this task admitted. In this case,
+/// the scheduling class of the current task is not changed.
+#[cfg(feature = "sched_rt")]
+#[doc(cfg(feature = "sched_rt"))]
+pub fn set_rt_params(params: RtParams) -> bool {
+    current_run_queue().set_current_rt_params(&params)
+}
+
+/// Moves the current task back to the fair class, and releases its reserved
+/// bandwidth.
+///
+/// If some real-time tasks are ready, the current task is preempted by them
+/// before this function returns.
+#[cfg(feature = "sched_rt")]
+#[doc(cfg(feature = "sched_rt"))]
+pub fn clear_rt_params() {
+    current_run_queue().clear_current_rt_params();
+}
+
+/// Current real-time task finishes its job, and sleeps until the next period
+/// begins.
+///
+/// # Panics
+///
+/// Panics if the current task is not in the real-time class.
+#[cfg(all(feature = "sched_rt", feature = "irq"))]
+#[doc(cfg(all(feature = "sched_rt", feature = "irq")))]
+pub fn wait_next_period() {
+    current_run_queue().wait_next_period();
+    TaskInner::current_check_killed();
+}
+
+/// Sets the CPU affinity of the current task.
+///
+/// If the current CPU is not in `cpumask`, the current task is migrated to
+/// one of the allowed CPUs immediately.
+///
+/// Returns `false` if none of the CPUs in `cpumask` is online.
+pub fn set_current_affinity(cpumask: CpuMask) -> bool {
+    current_run_queue().set_current_affinity(cpumask)
 }
 
 /// Current task gives up the CPU time voluntarily, and switches to another
 /// ready task.
 pub fn yield_now() {
-    RUN_QUEUE.lock().yield_current();
+    current_run_queue().yield_current();
 }
 
 /// Current task is going to sleep for the given duration.
@@ -150,14 +277,17 @@ pub fn sleep(dur: core::time::Duration) {
 /// If the feature `irq` is not enabled, it uses busy-wait instead.
 pub fn sleep_until(deadline: axhal::time::TimeValue) {
     #[cfg(feature = "irq")]
-    RUN_QUEUE.lock().sleep_until(deadline);
+    {
+        current_run_queue().sleep_until(deadline);
+        TaskInner::current_check_killed();
+    }
     #[cfg(not(feature = "irq"))]
     axhal::time::busy_wait_until(deadline);
 }
 
 /// Exits the current task.
 pub fn exit(exit_code: i32) -> ! {
-    RUN_QUEUE.lock().exit_current(exit_code)
+    current_run_queue().exit_current(exit_code)
 }
 
 /// The idle task routine.
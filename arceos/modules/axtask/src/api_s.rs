@@ -20,3 +20,10 @@ pub fn sleep(dur: core::time::Duration) {
 pub fn sleep_until(deadline: axhal::time::TimeValue) {
     axhal::time::busy_wait_until(deadline);
 }
+
+/// For single-task situation, there are no tasks to attribute allocations to,
+/// so we just run `f`.
+#[cfg(feature = "alloc-tag")]
+pub fn with_alloc_tag<R>(_tag: &'static str, f: impl FnOnce() -> R) -> R {
+    f()
+}
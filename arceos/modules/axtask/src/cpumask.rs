@@ -0,0 +1,84 @@
+//! CPU affinity masks.
+
+use core::fmt;
+
+use axconfig::SMP;
+
+const _: () = assert!(SMP <= 64, "`CpuMask` supports at most 64 CPUs");
+
+/// A set of CPUs, used as the CPU affinity of tasks.
+///
+/// Bit `i` is set if the CPU with ID `i` is in the set.
+#[derive(Clone, Copy, Eq, PartialEq)]
+pub struct CpuMask(u64);
+
+impl CpuMask {
+    /// Creates a mask with no CPU in it.
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Creates a mask with all CPUs in it.
+    pub const fn full() -> Self {
+        Self(u64::MAX >> (64 - SMP))
+    }
+
+    /// Creates a mask with only the given CPU in it, or an empty mask if the
+    /// CPU does not exist.
+    pub const fn one(cpu_id: usize) -> Self {
+        if cpu_id < SMP {
+            Self(1 << cpu_id)
+        } else {
+            Self::empty()
+        }
+    }
+
+    /// Creates a mask from raw bits. Bits of CPUs that do not exist are
+    /// ignored.
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits & Self::full().0)
+    }
+
+    /// Returns the raw bits of the mask.
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// Returns `true` if the given CPU is in the mask.
+    pub const fn contains(&self, cpu_id: usize) -> bool {
+        cpu_id < SMP && self.0 & (1 << cpu_id) != 0
+    }
+
+    /// Returns `true` if no CPU is in the mask.
+    pub const fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Adds or removes the given CPU.
+    pub fn set(&mut self, cpu_id: usize, value: bool) {
+        assert!(cpu_id < SMP, "invalid CPU ID: {}", cpu_id);
+        if value {
+            self.0 |= 1 << cpu_id;
+        } else {
+            self.0 &= !(1 << cpu_id);
+        }
+    }
+
+    /// Returns an iterator over IDs of the CPUs in the mask.
+    pub fn iter(&self) -> impl Iterator<Item = usize> {
+        let bits = self.0;
+        (0..SMP).filter(move |&i| bits & (1 << i) != 0)
+    }
+}
+
+impl Default for CpuMask {
+    fn default() -> Self {
+        Self::full()
+    }
+}
+
+impl fmt::Debug for CpuMask {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
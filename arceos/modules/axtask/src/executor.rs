@@ -0,0 +1,248 @@
+//! Running futures on tasks, with wakers backed by [`WaitQueue`]s.
+
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use alloc::task::Wake;
+use core::cell::UnsafeCell;
+use core::future::Future;
+use core::pin::{pin, Pin};
+use core::sync::atomic::{AtomicBool, AtomicU8, AtomicUsize, Ordering};
+use core::task::{Context, Poll, Waker};
+
+use kspin::SpinNoIrq;
+
+use crate::WaitQueue;
+
+/// A waker that wakes up the task blocked in [`block_on`].
+struct BlockOnWaker {
+    woken: AtomicBool,
+    wq: WaitQueue,
+}
+
+impl Wake for BlockOnWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.wq.notify_one(true);
+    }
+}
+
+/// Runs a future to completion on the current task.
+///
+/// The current task is blocked while the future is pending, until the
+/// future's waker is woken up.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let signal = Arc::new(BlockOnWaker {
+        woken: AtomicBool::new(false),
+        wq: WaitQueue::new(),
+    });
+    let waker = Waker::from(signal.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        signal
+            .wq
+            .wait_until(|| signal.woken.swap(false, Ordering::AcqRel));
+    }
+}
+
+// State transitions of an async task:
+// SCHEDULED -(poll)-> RUNNING -(pending)-> IDLE -(wake)-> SCHEDULED
+//                            |
+//                            |-(wake)-> NOTIFIED -(pending)-> SCHEDULED
+//                            |
+//                             -(ready)-> DONE
+const STATE_IDLE: u8 = 0;
+const STATE_SCHEDULED: u8 = 1;
+const STATE_RUNNING: u8 = 2;
+const STATE_NOTIFIED: u8 = 3;
+const STATE_DONE: u8 = 4;
+
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// A future spawned on an [`Executor`], which is also its own waker.
+struct AsyncTask {
+    future: UnsafeCell<Option<BoxFuture>>,
+    state: AtomicU8,
+    executor: Weak<ExecutorInner>,
+}
+
+// The future is only accessed by the worker that moves the task from
+// `SCHEDULED` to `RUNNING`.
+unsafe impl Sync for AsyncTask {}
+
+impl Wake for AsyncTask {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            let new_state = match state {
+                STATE_IDLE => STATE_SCHEDULED,
+                STATE_RUNNING => STATE_NOTIFIED,
+                _ => return, // already scheduled or done
+            };
+            match self.state.compare_exchange_weak(
+                state,
+                new_state,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(s) => state = s,
+            }
+        }
+        if state == STATE_IDLE {
+            if let Some(executor) = self.executor.upgrade() {
+                executor.schedule(self.clone());
+            }
+        }
+    }
+}
+
+struct ExecutorInner {
+    ready_queue: SpinNoIrq<VecDeque<Arc<AsyncTask>>>,
+    /// Workers waiting for ready async tasks.
+    wq: WaitQueue,
+    /// Number of spawned futures that are not completed.
+    pending: AtomicUsize,
+}
+
+impl ExecutorInner {
+    fn schedule(&self, task: Arc<AsyncTask>) {
+        self.ready_queue.lock().push_back(task);
+        self.wq.notify_one(true);
+    }
+
+    fn run_task(&self, task: Arc<AsyncTask>) {
+        task.state.store(STATE_RUNNING, Ordering::Release);
+        let waker = Waker::from(task.clone());
+        let mut cx = Context::from_waker(&waker);
+        // SAFETY: only this worker can access the future in `RUNNING` state.
+        let future = unsafe { &mut *task.future.get() };
+        if future.as_mut().unwrap().as_mut().poll(&mut cx).is_ready() {
+            *future = None;
+            task.state.store(STATE_DONE, Ordering::Release);
+            if self.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+                // All futures are completed, let the workers return.
+                self.wq.notify_all(true);
+            }
+        } else if task
+            .state
+            .compare_exchange(
+                STATE_RUNNING,
+                STATE_IDLE,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_err()
+        {
+            // Woken up while being polled, poll it again later.
+            task.state.store(STATE_SCHEDULED, Ordering::Release);
+            self.schedule(task);
+        }
+    }
+}
+
+/// An executor that runs many futures on a few tasks.
+///
+/// Futures are spawned by [`spawn`](Executor::spawn), and polled by tasks
+/// calling [`run`](Executor::run), which are called workers. A pending future
+/// does not occupy a worker, it's polled again after its waker is woken up.
+///
+/// # Examples
+///
+/// ```
+/// use axtask::Executor;
+///
+/// axtask::init_scheduler();
+/// let executor = Executor::new();
+/// for i in 0..100 {
+///     executor.spawn(async move {
+///         assert!(i < 100);
+///     });
+/// }
+/// // Run the futures on two tasks.
+/// let worker = executor.clone();
+/// let task = axtask::spawn(move || worker.run());
+/// executor.run();
+/// task.join();
+/// ```
+#[derive(Clone)]
+pub struct Executor {
+    inner: Arc<ExecutorInner>,
+}
+
+impl Executor {
+    /// Creates a new executor with no futures.
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(ExecutorInner {
+                ready_queue: SpinNoIrq::new(VecDeque::new()),
+                wq: WaitQueue::new(),
+                pending: AtomicUsize::new(0),
+            }),
+        }
+    }
+
+    /// Spawns a future on the executor.
+    ///
+    /// It's polled by one of the workers calling [`run`](Self::run).
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.inner.pending.fetch_add(1, Ordering::AcqRel);
+        let task = Arc::new(AsyncTask {
+            future: UnsafeCell::new(Some(Box::pin(future))),
+            state: AtomicU8::new(STATE_SCHEDULED),
+            executor: Arc::downgrade(&self.inner),
+        });
+        self.inner.schedule(task);
+    }
+
+    /// Returns the number of spawned futures that are not completed.
+    pub fn pending_count(&self) -> usize {
+        self.inner.pending.load(Ordering::Acquire)
+    }
+
+    /// Polls the spawned futures on the current task, until all of them are
+    /// completed.
+    ///
+    /// It can be called on multiple tasks at the same time, to poll the
+    /// futures in parallel. The current task blocks when there are no futures
+    /// ready to be polled.
+    pub fn run(&self) {
+        let inner = &self.inner;
+        loop {
+            let task = loop {
+                if let Some(task) = inner.ready_queue.lock().pop_front() {
+                    break task;
+                }
+                if inner.pending.load(Ordering::Acquire) == 0 {
+                    return;
+                }
+                inner.wq.wait_until(|| {
+                    !inner.ready_queue.lock().is_empty()
+                        || inner.pending.load(Ordering::Acquire) == 0
+                });
+            };
+            inner.run_task(task);
+        }
+    }
+}
+
+impl Default for Executor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -12,7 +12,21 @@
 //! - `irq`: Interrupts are enabled. If this feature is enabled, timer-based
 //!    APIs can be used, such as [`sleep`], [`sleep_until`], and
 //!    [`WaitQueue::wait_timeout`].
+//! - `tickless`: Stop the periodic scheduler tick while a CPU is idle, so
+//!   idle CPUs are only interrupted by timed events. It also enables the
+//!   `irq` feature.
 //! - `preempt`: Enable preemptive scheduling.
+//! - `alloc-tag`: Record the subsystem that heap allocations of each task are
+//!   attributed to, set by [`with_alloc_tag`]. Without the `multitask`
+//!   feature, [`with_alloc_tag`] just runs the closure.
+//! - `paging`: Kernel stacks are mapped in the kernel address space by
+//!   [`KernelStackIf`] with unmapped guard pages below them, so stack
+//!   overflows are caught by page faults, which are reported on per-CPU
+//!   overflow stacks. Otherwise, a canary at the bottom of each stack is
+//!   checked on context switches.
+//! - `smp`: Enable multi-core support. Each CPU has its own run queue, tasks
+//!   are migrated according to their [CPU affinity][`CpuMask`] on wakeup, and
+//!   idle CPUs steal ready tasks from other CPUs.
 //! - `sched_fifo`: Use the [FIFO cooperative scheduler][1]. It also enables the
 //!   `multitask` feature if it is enabled. This feature is enabled by default,
 //!   and it can be overriden by other scheduler features.
@@ -20,10 +34,40 @@
 //!   the `multitask` and `preempt` features if it is enabled.
 //! - `sched_cfs`: Use the [Completely Fair Scheduler][3]. It also enables the
 //!   the `multitask` and `preempt` features if it is enabled.
+//! - `sched_rt`: Add an [earliest-deadline-first][4] real-time class that runs
+//!   alongside the scheduler above. Real-time tasks always preempt other tasks.
+//!   It also enables the `multitask` and `preempt` features if it is enabled.
+//!
+//! # Task Statistics
+//!
+//! With the `multitask` feature, every task keeps CPU-time counters updated
+//! on each context switch. They can be read by [`TaskInner::stats`], or for
+//! all live tasks by [`for_each_task`] and [`task_stats`].
+//!
+//! # Task Cancellation
+//!
+//! A task can be asked to terminate by [`TaskInner::kill`]. It is woken up
+//! if blocked, and exits with [`KILLED_EXIT_CODE`] at the next blocking
+//! operation, which can be observed by the joiner. The stack is not unwound,
+//! so the locks held by the task at that point are never released.
+//!
+//! # Timers
+//!
+//! With the `irq` feature, callbacks can be called at a given deadline or
+//! periodically by [`timers::set_timer`] and [`timers::set_periodic_timer`].
+//! The timer hardware is programmed to the earliest timed event, so they
+//! are not delayed to the next scheduler tick.
+//!
+//! # Async
+//!
+//! Futures can be run by [`block_on`] on the current task, or spawned on an
+//! [`Executor`] to run many of them on a few tasks. Their wakers are backed
+//! by [`WaitQueue`]s, so pending futures do not consume CPU time.
 //!
 //! [1]: scheduler::FifoScheduler
 //! [2]: scheduler::RRScheduler
 //! [3]: scheduler::CFScheduler
+//! [4]: https://en.wikipedia.org/wiki/Earliest_deadline_first_scheduling
 
 #![cfg_attr(not(test), no_std)]
 #![feature(doc_cfg)]
@@ -42,14 +86,20 @@ cfg_if::cfg_if! {
         extern crate log;
         extern crate alloc;
 
+        mod cpumask;
+        mod executor;
         mod run_queue;
+        mod stats;
+        #[cfg(feature = "sched_rt")]
+        mod rt;
         mod task;
         mod task_ext;
         mod api;
         mod wait_queue;
 
         #[cfg(feature = "irq")]
-        mod timers;
+        #[doc(cfg(feature = "irq"))]
+        pub mod timers;
 
         #[doc(cfg(feature = "multitask"))]
         pub use self::api::*;
@@ -57,5 +107,7 @@ cfg_if::cfg_if! {
     } else {
         mod api_s;
         pub use self::api_s::{sleep, sleep_until, yield_now};
+        #[cfg(feature = "alloc-tag")]
+        pub use self::api_s::with_alloc_tag;
     }
 }
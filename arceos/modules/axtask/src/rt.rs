@@ -0,0 +1,224 @@
+//! Earliest-deadline-first (EDF) real-time scheduling class.
+//!
+//! Real-time tasks are kept in a separate ready queue ordered by their
+//! absolute deadlines, and always run before the tasks of the fair class
+//! (the scheduler selected by `sched_fifo`, `sched_rr` or `sched_cfs`).
+
+use alloc::collections::BTreeMap;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::AxTaskRef;
+
+/// The total bandwidth that can be reserved by real-time tasks, in parts per
+/// million (i.e., 100% of one CPU).
+const MAX_BANDWIDTH: u64 = 1_000_000;
+
+/// The bandwidth currently reserved by all real-time tasks, in parts per
+/// million.
+static RESERVED_BANDWIDTH: AtomicU64 = AtomicU64::new(0);
+
+/// Parameters of a periodic real-time task.
+///
+/// In each `period`, the task runs a job that needs at most `runtime` of CPU
+/// time, and must finish within `deadline` after the job is released.
+///
+/// The `runtime` is only used by the admission control, a job running longer
+/// than it is not throttled. It's only stopped when it overruns its deadline,
+/// which is then postponed by one period.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct RtParams {
+    /// The worst-case execution time of each job.
+    pub runtime: Duration,
+    /// The interval between two consecutive job releases.
+    pub period: Duration,
+    /// The relative deadline of each job.
+    pub deadline: Duration,
+}
+
+impl RtParams {
+    /// Creates real-time parameters with an implicit deadline (equal to the
+    /// period).
+    pub const fn new(runtime: Duration, period: Duration) -> Self {
+        Self {
+            runtime,
+            period,
+            deadline: period,
+        }
+    }
+
+    /// Sets a constrained deadline, which should be not greater than the
+    /// period.
+    pub const fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Returns the CPU bandwidth (`runtime / deadline`) required by the task
+    /// in parts per million, or [`None`] if the parameters are invalid.
+    fn bandwidth(&self) -> Option<u64> {
+        let runtime = self.runtime.as_nanos();
+        let deadline = self.deadline.as_nanos();
+        if runtime == 0 || runtime > deadline || self.deadline > self.period {
+            return None;
+        }
+        Some((runtime * MAX_BANDWIDTH as u128).div_ceil(deadline) as u64)
+    }
+}
+
+/// Replaces the reservation of `old` bandwidth with `new`, fails if the total
+/// bandwidth would exceed 100%.
+fn reserve_bandwidth(old: u64, new: u64) -> bool {
+    RESERVED_BANDWIDTH
+        .fetch_update(Ordering::AcqRel, Ordering::Acquire, |used| {
+            let used = used - old + new;
+            (used <= MAX_BANDWIDTH).then_some(used)
+        })
+        .is_ok()
+}
+
+/// The real-time scheduling state of a task.
+///
+/// A task belongs to the real-time class if its period is not zero.
+pub(crate) struct RtTaskState {
+    period_ns: AtomicU64,
+    deadline_ns: AtomicU64,
+    bandwidth: AtomicU64,
+    release_ns: AtomicU64,
+    abs_deadline_ns: AtomicU64,
+}
+
+impl RtTaskState {
+    pub const fn new() -> Self {
+        Self {
+            period_ns: AtomicU64::new(0),
+            deadline_ns: AtomicU64::new(0),
+            bandwidth: AtomicU64::new(0),
+            release_ns: AtomicU64::new(0),
+            abs_deadline_ns: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn is_rt(&self) -> bool {
+        self.period_ns.load(Ordering::Acquire) != 0
+    }
+
+    #[inline]
+    pub fn abs_deadline(&self) -> u64 {
+        self.abs_deadline_ns.load(Ordering::Acquire)
+    }
+
+    /// Moves the task into the real-time class, with the first job released
+    /// at `now`.
+    ///
+    /// Returns `false` if the parameters are invalid or the admission control
+    /// rejects the task.
+    pub fn set_params(&self, params: &RtParams, now: u64) -> bool {
+        let Some(bandwidth) = params.bandwidth() else {
+            return false;
+        };
+        let old = self.bandwidth.load(Ordering::Acquire);
+        if !reserve_bandwidth(old, bandwidth) {
+            return false;
+        }
+        let deadline = params.deadline.as_nanos() as u64;
+        self.bandwidth.store(bandwidth, Ordering::Release);
+        self.deadline_ns.store(deadline, Ordering::Release);
+        self.release_ns.store(now, Ordering::Release);
+        self.abs_deadline_ns
+            .store(now + deadline, Ordering::Release);
+        self.period_ns
+            .store(params.period.as_nanos() as u64, Ordering::Release);
+        true
+    }
+
+    /// Moves the task back to the fair class, and releases its bandwidth.
+    pub fn clear_params(&self) {
+        self.period_ns.store(0, Ordering::Release);
+        let bandwidth = self.bandwidth.swap(0, Ordering::AcqRel);
+        if bandwidth != 0 {
+            RESERVED_BANDWIDTH.fetch_sub(bandwidth, Ordering::AcqRel);
+        }
+    }
+
+    /// Finishes the current job, and returns the release time of the next
+    /// job. Periods that have been missed entirely are skipped.
+    pub fn next_period(&self, now: u64) -> u64 {
+        let period = self.period_ns.load(Ordering::Acquire);
+        let mut release = self.release_ns.load(Ordering::Acquire) + period;
+        if release < now {
+            release += (now - release) / period * period;
+        }
+        self.release_ns.store(release, Ordering::Release);
+        self.abs_deadline_ns.store(
+            release + self.deadline_ns.load(Ordering::Acquire),
+            Ordering::Release,
+        );
+        release
+    }
+
+    /// The current job has overrun its deadline, postpones the deadline by
+    /// one period so it cannot starve other real-time tasks.
+    pub fn postpone(&self) {
+        let period = self.period_ns.load(Ordering::Acquire);
+        self.release_ns.fetch_add(period, Ordering::AcqRel);
+        self.abs_deadline_ns.fetch_add(period, Ordering::AcqRel);
+    }
+}
+
+impl Drop for RtTaskState {
+    fn drop(&mut self) {
+        self.clear_params();
+    }
+}
+
+/// The ready queue of real-time tasks, ordered by absolute deadlines.
+pub(crate) struct RtRunQueue {
+    ready: BTreeMap<(u64, u64), AxTaskRef>,
+}
+
+impl RtRunQueue {
+    pub const fn new() -> Self {
+        Self {
+            ready: BTreeMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ready.is_empty()
+    }
+
+    pub fn add_task(&mut self, task: AxTaskRef) {
+        let key = (task.rt().abs_deadline(), task.id().as_u64());
+        self.ready.insert(key, task);
+    }
+
+    pub fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        self.ready.pop_first().map(|(_, task)| task)
+    }
+
+    #[cfg(feature = "smp")]
+    pub fn peek_next_task(&self) -> Option<&AxTaskRef> {
+        self.ready.first_key_value().map(|(_, task)| task)
+    }
+
+    /// Returns `true` if `task` should preempt the task `curr`.
+    pub fn should_preempt(task: &AxTaskRef, curr: &AxTaskRef) -> bool {
+        task.rt().is_rt()
+            && (!curr.rt().is_rt() || task.rt().abs_deadline() < curr.rt().abs_deadline())
+    }
+
+    /// Handles a timer tick for the running real-time task `curr`, returns
+    /// `true` if it should be preempted.
+    pub fn task_tick(&mut self, curr: &AxTaskRef, now: u64) -> bool {
+        if now >= curr.rt().abs_deadline() {
+            warn!("real-time task overran its deadline: {}", curr.id_name());
+            curr.rt().postpone();
+            return true;
+        }
+        self.ready
+            .first_key_value()
+            .is_some_and(|(&(deadline, _), _)| deadline < curr.rt().abs_deadline())
+    }
+}
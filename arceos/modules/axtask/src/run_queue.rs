@@ -1,14 +1,22 @@
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
+#[cfg(all(feature = "smp", feature = "tickless"))]
+use core::sync::atomic::AtomicBool;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use axconfig::SMP;
+use kernel_guard::NoPreemptIrqSave;
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use scheduler::BaseScheduler;
 
+#[cfg(feature = "sched_rt")]
+use crate::rt::RtRunQueue;
 use crate::task::{CurrentTask, TaskState};
-use crate::{AxTaskRef, Scheduler, TaskInner, WaitQueue};
+use crate::{AxTaskRef, CpuMask, Scheduler, TaskInner, WaitQueue};
 
-// TODO: per-CPU
-pub(crate) static RUN_QUEUE: LazyInit<SpinNoIrq<AxRunQueue>> = LazyInit::new();
+/// Run queues of all CPUs, indexed by CPU IDs.
+static RUN_QUEUES: [LazyInit<AxRunQueue>; SMP] = [const { LazyInit::new() }; SMP];
 
 // TODO: per-CPU
 static EXITED_TASKS: SpinNoIrq<VecDeque<AxTaskRef>> = SpinNoIrq::new(VecDeque::new());
@@ -18,28 +26,354 @@ static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
 #[percpu::def_percpu]
 static IDLE_TASK: LazyInit<AxTaskRef> = LazyInit::new();
 
-pub(crate) struct AxRunQueue {
+/// The task that is being switched out on this CPU, and whether it should be
+/// migrated to another CPU once the switch is finished.
+#[cfg(feature = "smp")]
+#[percpu::def_percpu]
+static PREV_TASK: Option<(AxTaskRef, bool)> = None;
+
+/// Ready tasks of a CPU in all scheduling classes.
+struct ReadyQueue {
     scheduler: Scheduler,
+    #[cfg(feature = "sched_rt")]
+    rt: RtRunQueue,
+    /// The next task of `scheduler`, taken out by
+    /// [`peek_next_task`](ReadyQueue::peek_next_task) but not picked yet.
+    #[cfg(feature = "smp")]
+    peeked: Option<AxTaskRef>,
+}
+
+/// The run queue of a CPU.
+pub(crate) struct AxRunQueue {
+    cpu_id: usize,
+    ready: SpinNoIrq<ReadyQueue>,
+    /// Number of tasks in `ready`, read without locking for load balancing.
+    nr_ready: AtomicUsize,
+    /// Whether the CPU is idle with the scheduler tick stopped, so it won't
+    /// notice the tasks put in `ready` until its next timer event.
+    #[cfg(all(feature = "smp", feature = "tickless"))]
+    tick_stopped: AtomicBool,
+}
+
+/// A reference to the run queue of the current CPU.
+///
+/// IRQs and preemption are disabled while it's alive, so the current task
+/// cannot be migrated to other CPUs.
+pub(crate) struct CurrentRunQueueRef {
+    rq: &'static AxRunQueue,
+    _guard: NoPreemptIrqSave,
+}
+
+/// Returns the run queue of the CPU with the given ID.
+fn run_queue(cpu_id: usize) -> &'static AxRunQueue {
+    &RUN_QUEUES[cpu_id]
+}
+
+/// Returns `true` if the CPU with the given ID has initialized its run queue.
+fn cpu_online(cpu_id: usize) -> bool {
+    cpu_id < SMP && RUN_QUEUES[cpu_id].is_inited()
+}
+
+/// Locks the run queue of the current CPU, with IRQs and preemption disabled.
+pub(crate) fn current_run_queue() -> CurrentRunQueueRef {
+    let guard = NoPreemptIrqSave::new();
+    CurrentRunQueueRef {
+        rq: run_queue(axhal::cpu::this_cpu_id()),
+        _guard: guard,
+    }
+}
+
+/// Selects the run queue to put the ready task in.
+///
+/// With the `smp` feature, it selects the least loaded online CPU allowed by
+/// the CPU affinity of the task. The current CPU is preferred, then the CPU
+/// it ran on last time.
+///
+/// With the `tickless` feature, other CPUs that are idle with the tick
+/// stopped are not selected, as there are no IPIs to wake them up. They're
+/// only selected if the task is not allowed to run anywhere else, then it
+/// waits until the next timer event of that CPU.
+pub(crate) fn select_run_queue(task: &AxTaskRef) -> &'static AxRunQueue {
+    #[cfg(feature = "smp")]
+    {
+        let cpumask = task.cpumask();
+        let this_cpu = axhal::cpu::this_cpu_id();
+        let preferred = [this_cpu, task.stats_counter().last_cpu()];
+        let mut selected: Option<&AxRunQueue> = None;
+        let mut fallback: Option<&AxRunQueue> = None;
+        for cpu_id in preferred.into_iter().chain(0..SMP) {
+            if !cpumask.contains(cpu_id) || !cpu_online(cpu_id) {
+                continue;
+            }
+            let rq = run_queue(cpu_id);
+            if cpu_id != this_cpu && rq.tick_stopped() {
+                fallback.get_or_insert(rq);
+                continue;
+            }
+            if selected.map_or(true, |s| rq.nr_ready() < s.nr_ready()) {
+                selected = Some(rq);
+            }
+        }
+        if let Some(rq) = selected.or(fallback) {
+            return rq;
+        }
+    }
+    #[cfg(not(feature = "smp"))]
+    let _ = task;
+    run_queue(axhal::cpu::this_cpu_id())
+}
+
+impl ReadyQueue {
+    fn new() -> Self {
+        Self {
+            scheduler: Scheduler::new(),
+            #[cfg(feature = "sched_rt")]
+            rt: RtRunQueue::new(),
+            #[cfg(feature = "smp")]
+            peeked: None,
+        }
+    }
+
+    /// Puts a ready task into the queue of its scheduling class.
+    fn add_task(&mut self, task: AxTaskRef) {
+        #[cfg(feature = "sched_rt")]
+        if task.rt().is_rt() {
+            self.rt.add_task(task);
+            return;
+        }
+        self.scheduler.add_task(task);
+    }
+
+    /// Puts the previous running task back into the queue of its scheduling
+    /// class.
+    fn put_prev_task(&mut self, prev: AxTaskRef, preempt: bool) {
+        #[cfg(feature = "sched_rt")]
+        if prev.rt().is_rt() {
+            self.rt.add_task(prev);
+            return;
+        }
+        self.scheduler.put_prev_task(prev, preempt);
+    }
+
+    /// Picks the next task to run, real-time tasks come first.
+    fn pick_next_task(&mut self) -> Option<AxTaskRef> {
+        #[cfg(feature = "sched_rt")]
+        if let Some(task) = self.rt.pick_next_task() {
+            return Some(task);
+        }
+        #[cfg(feature = "smp")]
+        if let Some(task) = self.peeked.take() {
+            return Some(task);
+        }
+        self.scheduler.pick_next_task()
+    }
+
+    /// Returns the task to be picked next, without removing it.
+    #[cfg(feature = "smp")]
+    fn peek_next_task(&mut self) -> Option<&AxTaskRef> {
+        #[cfg(feature = "sched_rt")]
+        if !self.rt.is_empty() {
+            return self.rt.peek_next_task();
+        }
+        if self.peeked.is_none() {
+            // The scheduler can't peek, keep the task here as the next one.
+            self.peeked = self.scheduler.pick_next_task();
+        }
+        self.peeked.as_ref()
+    }
+
+    /// Advances the scheduler states of the running task `curr`, returns
+    /// `true` if it should be preempted.
+    #[cfg(feature = "irq")]
+    fn task_tick(&mut self, curr: &AxTaskRef) -> bool {
+        #[cfg(feature = "sched_rt")]
+        {
+            if curr.rt().is_rt() {
+                return self.rt.task_tick(curr, axhal::time::wall_time_nanos());
+            }
+            if !self.rt.is_empty() {
+                // some real-time tasks are waiting for the CPU.
+                self.scheduler.task_tick(curr);
+                return true;
+            }
+        }
+        self.scheduler.task_tick(curr)
+    }
 }
 
 impl AxRunQueue {
-    pub fn new() -> SpinNoIrq<Self> {
-        let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
-        let mut scheduler = Scheduler::new();
-        scheduler.add_task(gc_task);
-        SpinNoIrq::new(Self { scheduler })
+    fn new(cpu_id: usize) -> Self {
+        Self {
+            cpu_id,
+            ready: SpinNoIrq::new(ReadyQueue::new()),
+            nr_ready: AtomicUsize::new(0),
+            #[cfg(all(feature = "smp", feature = "tickless"))]
+            tick_stopped: AtomicBool::new(false),
+        }
+    }
+
+    #[inline]
+    fn nr_ready(&self) -> usize {
+        self.nr_ready.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "smp")]
+    #[inline]
+    fn tick_stopped(&self) -> bool {
+        #[cfg(feature = "tickless")]
+        return self.tick_stopped.load(Ordering::Acquire);
+        #[cfg(not(feature = "tickless"))]
+        false
     }
 
-    pub fn add_task(&mut self, task: AxTaskRef) {
-        debug!("task spawn: {}", task.id_name());
+    pub fn add_task(&self, task: AxTaskRef) {
+        debug!("task spawn: {} on CPU {}", task.id_name(), self.cpu_id);
         assert!(task.is_ready());
-        self.scheduler.add_task(task);
+        self.enqueue_ready(task);
+    }
+
+    /// Wakes up a blocked task. Returns `false` if the task is not blocked,
+    /// e.g., it has been woken up by a timer or `kill()`.
+    pub fn unblock_task(&self, task: AxTaskRef, resched: bool) -> bool {
+        debug!("task unblock: {}", task.id_name());
+        // Timers and wait queues on different CPUs may wake up the same task
+        // at the same time, only one of them can succeed.
+        if task.transition_state(TaskState::Blocked, TaskState::Ready) {
+            // The task may have not been switched out on another CPU yet.
+            #[cfg(feature = "smp")]
+            while task.on_cpu() {
+                core::hint::spin_loop();
+            }
+            self.enqueue_ready(task); // TODO: priority
+            if resched && self.cpu_id == axhal::cpu::this_cpu_id() {
+                #[cfg(feature = "preempt")]
+                crate::current().set_preempt_pending(true);
+            }
+            true
+        } else {
+            false
+        }
+    }
+
+    fn enqueue_ready(&self, task: AxTaskRef) {
+        task.stats_counter().on_ready(crate::stats::now_ns());
+        #[cfg(feature = "sched_rt")]
+        if self.cpu_id == axhal::cpu::this_cpu_id() {
+            check_rt_preempt(&task);
+        }
+        let mut ready = self.ready.lock();
+        #[cfg(feature = "smp")]
+        task.set_rq_cpu(self.cpu_id);
+        ready.add_task(task);
+        self.nr_ready.fetch_add(1, Ordering::Relaxed);
+        drop(ready);
+        #[cfg(all(feature = "smp", feature = "tickless"))]
+        self.check_tick_stopped();
+    }
+
+    /// Called after a task is put into this run queue of another CPU, which
+    /// may have stopped its tick in the meantime. Moves the next task to the
+    /// current CPU then, otherwise it may wait for long.
+    #[cfg(all(feature = "smp", feature = "tickless"))]
+    fn check_tick_stopped(&self) {
+        let this_cpu = axhal::cpu::this_cpu_id();
+        if self.cpu_id == this_cpu {
+            return;
+        }
+        // Pairs with the fence in `try_stop_tick`.
+        core::sync::atomic::fence(Ordering::SeqCst);
+        if self.tick_stopped() {
+            if let Some(task) = self.try_steal(this_cpu) {
+                run_queue(this_cpu).enqueue_ready(task);
+            }
+        }
+    }
+
+    fn put_prev_task(&self, prev: AxTaskRef, preempt: bool) {
+        let mut ready = self.ready.lock();
+        #[cfg(feature = "smp")]
+        prev.set_rq_cpu(self.cpu_id);
+        ready.put_prev_task(prev, preempt);
+        self.nr_ready.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn pick_next_task(&self) -> Option<AxTaskRef> {
+        loop {
+            let task = self.ready.lock().pick_next_task()?;
+            self.nr_ready.fetch_sub(1, Ordering::Relaxed);
+            #[cfg(feature = "smp")]
+            if !task.cpumask().contains(self.cpu_id) {
+                // The CPU affinity was changed while the task was ready, move
+                // it to an allowed CPU.
+                let rq = select_run_queue(&task);
+                if !core::ptr::eq(rq, self) {
+                    rq.enqueue_ready(task);
+                    continue;
+                }
+            }
+            return Some(task);
+        }
     }
 
+    /// Tries to take a ready task away for the idle CPU `thief_cpu`.
+    ///
+    /// Tasks that are not allowed to run on `thief_cpu`, or have not been
+    /// switched out completely, are not stolen.
+    #[cfg(feature = "smp")]
+    fn try_steal(&self, thief_cpu: usize) -> Option<AxTaskRef> {
+        if self.nr_ready() == 0 {
+            return None;
+        }
+        // Do not spin on a busy run queue, try the next one instead.
+        let mut ready = self.ready.try_lock()?;
+        let task = ready.peek_next_task()?;
+        if !task.cpumask().contains(thief_cpu) || task.on_cpu() {
+            return None;
+        }
+        let task = ready.pick_next_task()?;
+        self.nr_ready.fetch_sub(1, Ordering::Relaxed);
+        debug!(
+            "task stolen: {}, CPU {} -> {}",
+            task.id_name(),
+            self.cpu_id,
+            thief_cpu
+        );
+        Some(task)
+    }
+}
+
+/// Marks the tick of the current idle CPU as stopped, returns `false` and
+/// keeps the tick if any CPU has ready tasks, which may be stolen.
+#[cfg(feature = "tickless")]
+pub(crate) fn try_stop_tick() -> bool {
+    #[cfg(feature = "smp")]
+    {
+        let rq = run_queue(axhal::cpu::this_cpu_id());
+        rq.tick_stopped.store(true, Ordering::Relaxed);
+        // Pairs with the fence in `check_tick_stopped`.
+        core::sync::atomic::fence(Ordering::SeqCst);
+        if (0..SMP).any(|cpu_id| cpu_online(cpu_id) && run_queue(cpu_id).nr_ready() > 0) {
+            rq.tick_stopped.store(false, Ordering::Relaxed);
+            return false;
+        }
+    }
+    true
+}
+
+/// Marks the tick of the current CPU as running.
+#[cfg(feature = "tickless")]
+pub(crate) fn tick_restarted() {
+    #[cfg(feature = "smp")]
+    run_queue(axhal::cpu::this_cpu_id())
+        .tick_stopped
+        .store(false, Ordering::Release);
+}
+
+impl CurrentRunQueueRef {
     #[cfg(feature = "irq")]
     pub fn scheduler_timer_tick(&mut self) {
         let curr = crate::current();
-        if !curr.is_idle() && self.scheduler.task_tick(curr.as_task_ref()) {
+        if !curr.is_idle() && self.rq.ready.lock().task_tick(curr.as_task_ref()) {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
@@ -53,8 +387,52 @@ impl AxRunQueue {
     }
 
     pub fn set_current_priority(&mut self, prio: isize) -> bool {
-        self.scheduler
-            .set_priority(crate::current().as_task_ref(), prio)
+        let curr = crate::current();
+        let mut ready = self.rq.ready.lock();
+        if !ready.scheduler.set_priority(curr.as_task_ref(), prio) {
+            return false;
+        }
+        curr.set_base_priority(prio);
+        if curr.priority() != prio {
+            // Keep the higher priority inherited from other tasks.
+            ready
+                .scheduler
+                .set_priority(curr.as_task_ref(), curr.priority());
+        }
+        true
+    }
+
+    pub fn set_inherited_priority(&mut self, task: &AxTaskRef, prio: Option<isize>) -> bool {
+        task.set_inherited_priority(prio);
+        // Update the task in the run queue it was put in, it may be moved to
+        // another one before the run queue is locked.
+        #[cfg(feature = "smp")]
+        loop {
+            let rq = run_queue(task.rq_cpu());
+            let mut ready = rq.ready.lock();
+            if task.rq_cpu() == rq.cpu_id {
+                return ready.scheduler.set_priority(task, task.priority());
+            }
+        }
+        #[cfg(not(feature = "smp"))]
+        self.rq
+            .ready
+            .lock()
+            .scheduler
+            .set_priority(task, task.priority())
+    }
+
+    pub fn set_current_affinity(&mut self, cpumask: CpuMask) -> bool {
+        if !cpumask.iter().any(cpu_online) {
+            return false;
+        }
+        let curr = crate::current();
+        curr.set_cpumask(cpumask);
+        if !cpumask.contains(self.rq.cpu_id) {
+            // The current task will be migrated after it's switched out.
+            self.resched(false);
+        }
+        true
     }
 
     #[cfg(feature = "preempt")]
@@ -62,11 +440,10 @@ impl AxRunQueue {
         let curr = crate::current();
         assert!(curr.is_running());
 
-        // When we get the mutable reference of the run queue, we must
-        // have held the `SpinNoIrq` lock with both IRQs and preemption
-        // disabled. So we need to set `current_disable_count` to 1 in
-        // `can_preempt()` to obtain the preemption permission before
-        //  locking the run queue.
+        // When we get the reference of the current run queue, we must have
+        // disabled both IRQs and preemption. So we need to set
+        // `current_disable_count` to 1 in `can_preempt()` to obtain the
+        // preemption permission.
         let can_preempt = curr.can_preempt(1);
 
         debug!(
@@ -91,14 +468,22 @@ impl AxRunQueue {
             axhal::misc::terminate();
         } else {
             curr.set_state(TaskState::Exited);
-            curr.notify_exit(exit_code, self);
+            #[cfg(feature = "sched_rt")]
+            curr.rt().clear_params();
+            curr.notify_exit(exit_code);
             EXITED_TASKS.lock().push_back(curr.clone());
-            WAIT_FOR_EXIT.notify_one_locked(false, self);
+            WAIT_FOR_EXIT.notify_one(false);
             self.resched(false);
         }
         unreachable!("task exited!");
     }
 
+    /// Blocks the current task, `wait_queue_push` is called after the task
+    /// state is set to `Blocked`, to put the task into a wait queue or arm a
+    /// timer for it.
+    ///
+    /// If the current task has been killed, it returns immediately without
+    /// calling `wait_queue_push`.
     pub fn block_current<F>(&mut self, wait_queue_push: F)
     where
         F: FnOnce(AxTaskRef),
@@ -112,20 +497,9 @@ impl AxRunQueue {
         #[cfg(feature = "preempt")]
         assert!(curr.can_preempt(1));
 
-        curr.set_state(TaskState::Blocked);
-        wait_queue_push(curr.clone());
-        self.resched(false);
-    }
-
-    pub fn unblock_task(&mut self, task: AxTaskRef, resched: bool) {
-        debug!("task unblock: {}", task.id_name());
-        if task.is_blocked() {
-            task.set_state(TaskState::Ready);
-            self.scheduler.add_task(task); // TODO: priority
-            if resched {
-                #[cfg(feature = "preempt")]
-                crate::current().set_preempt_pending(true);
-            }
+        if curr.try_block() {
+            wait_queue_push(curr.clone());
+            self.resched(false);
         }
     }
 
@@ -137,33 +511,117 @@ impl AxRunQueue {
         assert!(!curr.is_idle());
 
         let now = axhal::time::wall_time();
-        if now < deadline {
+        // Set the state before arming the timer, as it may fire on another
+        // CPU immediately.
+        if now < deadline && curr.try_block() {
             crate::timers::set_alarm_wakeup(deadline, curr.clone());
-            curr.set_state(TaskState::Blocked);
             self.resched(false);
         }
+        cancel_killed_alarm(&curr);
+    }
+
+    #[cfg(feature = "sched_rt")]
+    pub fn set_current_rt_params(&mut self, params: &crate::RtParams) -> bool {
+        let curr = crate::current();
+        let now = axhal::time::wall_time_nanos();
+        curr.rt().set_params(params, now)
+    }
+
+    #[cfg(feature = "sched_rt")]
+    pub fn clear_current_rt_params(&mut self) {
+        let curr = crate::current();
+        curr.rt().clear_params();
+        // Real-time tasks waiting for the CPU run before the fair class now.
+        let rt_ready = !self.rq.ready.lock().rt.is_empty();
+        if rt_ready {
+            self.resched(true);
+        }
+    }
+
+    #[cfg(all(feature = "sched_rt", feature = "irq"))]
+    pub fn wait_next_period(&mut self) {
+        let curr = crate::current();
+        assert!(curr.is_running());
+        assert!(curr.rt().is_rt());
+
+        let now = axhal::time::wall_time_nanos();
+        let release = curr.rt().next_period(now);
+        debug!(
+            "task wait next period: {}, release={}",
+            curr.id_name(),
+            release
+        );
+        if now < release && curr.try_block() {
+            let deadline = axhal::time::TimeValue::from_nanos(release);
+            crate::timers::set_alarm_wakeup(deadline, curr.clone());
+        }
+        // If the next job has been released, it competes with other real-time
+        // tasks by its new deadline.
+        self.resched(false);
+        cancel_killed_alarm(&curr);
     }
 }
 
-impl AxRunQueue {
+/// Cancels the alarm of the current task if it has been killed while
+/// sleeping.
+///
+/// The caller terminates the task by
+/// [`TaskInner::current_check_killed`](crate::TaskInner::current_check_killed)
+/// after releasing the run queue, as the task may have been migrated to
+/// another CPU while sleeping.
+#[cfg(feature = "irq")]
+fn cancel_killed_alarm(curr: &CurrentTask) {
+    if curr.is_killed() && curr.in_timer_list() {
+        crate::timers::cancel_alarm(curr.as_task_ref());
+    }
+}
+
+impl CurrentRunQueueRef {
     /// Common reschedule subroutine. If `preempt`, keep current task's time
     /// slice, otherwise reset it.
     fn resched(&mut self, preempt: bool) {
         let prev = crate::current();
+        let mut migrate = false;
         if prev.is_running() {
             prev.set_state(TaskState::Ready);
             if !prev.is_idle() {
-                self.scheduler.put_prev_task(prev.clone(), preempt);
+                if cfg!(feature = "smp") && !prev.cpumask().contains(self.rq.cpu_id) {
+                    // Not allowed to run on this CPU any more, it will be put
+                    // into another run queue after switched out.
+                    migrate = true;
+                } else {
+                    self.rq.put_prev_task(prev.clone(), preempt);
+                }
             }
         }
-        let next = self.scheduler.pick_next_task().unwrap_or_else(|| unsafe {
+        let next = self.rq.pick_next_task();
+        #[cfg(feature = "smp")]
+        let next = next.or_else(|| self.steal_task());
+        let next = next.unwrap_or_else(|| unsafe {
             // Safety: IRQs must be disabled at this time.
             IDLE_TASK.current_ref_raw().get_unchecked().clone()
         });
-        self.switch_to(prev, next);
+        self.switch_to(prev, next, preempt, migrate);
     }
 
-    fn switch_to(&mut self, prev_task: CurrentTask, next_task: AxTaskRef) {
+    /// Steals a ready task from other CPUs when there is nothing to run on
+    /// this CPU.
+    #[cfg(feature = "smp")]
+    fn steal_task(&self) -> Option<AxTaskRef> {
+        let this_cpu = self.rq.cpu_id;
+        (1..SMP)
+            .map(|i| (this_cpu + i) % SMP)
+            .filter(|&cpu_id| cpu_online(cpu_id))
+            .find_map(|cpu_id| run_queue(cpu_id).try_steal(this_cpu))
+    }
+
+    fn switch_to(
+        &mut self,
+        prev_task: CurrentTask,
+        next_task: AxTaskRef,
+        preempt: bool,
+        migrate: bool,
+    ) {
         trace!(
             "context switch: {} -> {}",
             prev_task.id_name(),
@@ -175,22 +633,82 @@ impl AxRunQueue {
         if prev_task.ptr_eq(&next_task) {
             return;
         }
+        prev_task.check_stack_canary();
+        #[cfg(feature = "tickless")]
+        if prev_task.is_idle() {
+            crate::timers::restart_tick();
+        }
+
+        let now = crate::stats::now_ns();
+        prev_task.stats_counter().on_switch_out(now, preempt);
+        next_task.stats_counter().on_switch_in(now);
 
         unsafe {
             let prev_ctx_ptr = prev_task.ctx_mut_ptr();
             let next_ctx_ptr = next_task.ctx_mut_ptr();
 
+            #[cfg(feature = "smp")]
+            {
+                next_task.set_on_cpu(true);
+                // Safety: IRQs and preemption are disabled at this time.
+                *PREV_TASK.current_ref_mut_raw() = Some((prev_task.clone(), migrate));
+            }
+            #[cfg(not(feature = "smp"))]
+            let _ = migrate;
+
             // The strong reference count of `prev_task` will be decremented by 1,
             // but won't be dropped until `gc_entry()` is called.
             assert!(Arc::strong_count(prev_task.as_task_ref()) > 1);
             assert!(Arc::strong_count(&next_task) >= 1);
 
+            next_task.set_stack_limit();
             CurrentTask::set_current(prev_task, next_task);
             (*prev_ctx_ptr).switch_to(&*next_ctx_ptr);
+
+            #[cfg(feature = "smp")]
+            finish_switch();
+        }
+    }
+}
+
+/// Finishes the context switch on the current CPU.
+///
+/// It's called by the next task right after it's switched in, when the
+/// context of the previous task has been saved completely. Then the previous
+/// task can be run by other CPUs.
+///
+/// # Safety
+///
+/// IRQs and preemption must be disabled.
+#[cfg(feature = "smp")]
+pub(crate) unsafe fn finish_switch() {
+    if let Some((prev, migrate)) = PREV_TASK.current_ref_mut_raw().take() {
+        prev.set_on_cpu(false);
+        if migrate {
+            select_run_queue(&prev).enqueue_ready(prev);
         }
     }
 }
 
+/// Wakes up the blocked task `task` that has been killed.
+///
+/// The task is left in its wait queue, and it removes itself from the queue
+/// and timers after woken up.
+pub(crate) fn wake_killed(task: AxTaskRef) {
+    let _guard = NoPreemptIrqSave::new();
+    select_run_queue(&task).unblock_task(task, true);
+}
+
+/// Marks the current task to be preempted if the ready task `task` belongs to
+/// the real-time class and has an earlier deadline.
+#[cfg(feature = "sched_rt")]
+fn check_rt_preempt(task: &AxTaskRef) {
+    let curr = crate::current();
+    if RtRunQueue::should_preempt(task, curr.as_task_ref()) {
+        curr.set_preempt_pending(true);
+    }
+}
+
 fn gc_entry() {
     loop {
         // Drop all exited tasks and recycle resources.
@@ -214,6 +732,8 @@ fn gc_entry() {
 }
 
 pub(crate) fn init() {
+    let cpu_id = axhal::cpu::this_cpu_id();
+
     // Create the `idle` task (not current task).
     const IDLE_TASK_STACK_SIZE: usize = 4096;
     let idle_task = TaskInner::new(|| crate::run_idle(), "idle".into(), IDLE_TASK_STACK_SIZE);
@@ -226,10 +746,15 @@ pub(crate) fn init() {
     main_task.set_state(TaskState::Running);
     unsafe { CurrentTask::init_current(main_task) };
 
-    RUN_QUEUE.init_once(AxRunQueue::new());
+    RUN_QUEUES[cpu_id].init_once(AxRunQueue::new(cpu_id));
+
+    let gc_task = TaskInner::new(gc_entry, "gc".into(), axconfig::TASK_STACK_SIZE).into_arc();
+    run_queue(cpu_id).add_task(gc_task);
 }
 
 pub(crate) fn init_secondary() {
+    let cpu_id = axhal::cpu::this_cpu_id();
+
     // Put the subsequent execution into the `idle` task.
     let idle_task = TaskInner::new_init("idle".into()).into_arc();
     idle_task.set_state(TaskState::Running);
@@ -237,4 +762,6 @@ pub(crate) fn init_secondary() {
         i.init_once(idle_task.clone());
     });
     unsafe { CurrentTask::init_current(idle_task) }
+
+    RUN_QUEUES[cpu_id].init_once(AxRunQueue::new(cpu_id));
 }
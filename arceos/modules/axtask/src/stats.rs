@@ -0,0 +1,162 @@
+//! Per-task CPU-time accounting and the table of live tasks.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use kspin::SpinNoIrq;
+
+use crate::task::TaskState;
+use crate::{AxTask, AxTaskRef, TaskId, TaskInner};
+
+/// All live tasks, indexed by their IDs.
+///
+/// Entries are inserted when a task is wrapped into an [`AxTaskRef`] and
+/// removed when the [`TaskInner`] is dropped.
+static TASK_TABLE: SpinNoIrq<BTreeMap<u64, Weak<AxTask>>> = SpinNoIrq::new(BTreeMap::new());
+
+/// A snapshot of the runtime statistics of a task.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct TaskStats {
+    /// Total time (in nanoseconds) the task has been running on a CPU.
+    pub run_time_ns: u64,
+    /// Total time (in nanoseconds) the task has been ready but waiting for a
+    /// CPU in the run queue.
+    pub wait_time_ns: u64,
+    /// Number of times the task gave up the CPU by itself (yield, block,
+    /// sleep or exit).
+    pub voluntary_switches: u64,
+    /// Number of times the task was preempted.
+    pub involuntary_switches: u64,
+    /// The CPU on which the task ran last time.
+    pub last_cpu: usize,
+}
+
+/// Runtime counters embedded in [`TaskInner`], updated by the run queue on
+/// every state transition.
+pub(crate) struct TaskStatsCounter {
+    /// Timestamp of the last transition to `Running` or `Ready`.
+    last_stamp_ns: AtomicU64,
+    run_time_ns: AtomicU64,
+    wait_time_ns: AtomicU64,
+    voluntary_switches: AtomicU64,
+    involuntary_switches: AtomicU64,
+    last_cpu: AtomicUsize,
+}
+
+impl TaskStatsCounter {
+    pub fn new() -> Self {
+        Self {
+            last_stamp_ns: AtomicU64::new(now_ns()),
+            run_time_ns: AtomicU64::new(0),
+            wait_time_ns: AtomicU64::new(0),
+            voluntary_switches: AtomicU64::new(0),
+            involuntary_switches: AtomicU64::new(0),
+            last_cpu: AtomicUsize::new(axhal::cpu::this_cpu_id()),
+        }
+    }
+
+    /// Returns the elapsed time since the last transition, and restarts the
+    /// stamp at `now`.
+    fn restart(&self, now: u64) -> u64 {
+        now.saturating_sub(self.last_stamp_ns.swap(now, Ordering::Relaxed))
+    }
+
+    /// The task is put into the run queue (spawned, woken up or switched out
+    /// while still runnable).
+    pub fn on_ready(&self, now: u64) {
+        self.last_stamp_ns.store(now, Ordering::Relaxed);
+    }
+
+    /// The task is picked by the scheduler and starts running.
+    pub fn on_switch_in(&self, now: u64) {
+        let waited = self.restart(now);
+        self.wait_time_ns.fetch_add(waited, Ordering::Relaxed);
+        self.last_cpu
+            .store(axhal::cpu::this_cpu_id(), Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn last_cpu(&self) -> usize {
+        self.last_cpu.load(Ordering::Relaxed)
+    }
+
+    /// The task stops running. `preempted` tells whether it was forced to give
+    /// up the CPU.
+    pub fn on_switch_out(&self, now: u64, preempted: bool) {
+        let ran = self.restart(now);
+        self.run_time_ns.fetch_add(ran, Ordering::Relaxed);
+        if preempted {
+            self.involuntary_switches.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.voluntary_switches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes a snapshot of the counters, including the time elapsed in the
+    /// current state.
+    pub fn snapshot(&self, state: TaskState) -> TaskStats {
+        let mut stats = TaskStats {
+            run_time_ns: self.run_time_ns.load(Ordering::Relaxed),
+            wait_time_ns: self.wait_time_ns.load(Ordering::Relaxed),
+            voluntary_switches: self.voluntary_switches.load(Ordering::Relaxed),
+            involuntary_switches: self.involuntary_switches.load(Ordering::Relaxed),
+            last_cpu: self.last_cpu.load(Ordering::Relaxed),
+        };
+        let elapsed = now_ns().saturating_sub(self.last_stamp_ns.load(Ordering::Relaxed));
+        match state {
+            TaskState::Running => stats.run_time_ns += elapsed,
+            TaskState::Ready => stats.wait_time_ns += elapsed,
+            _ => {}
+        }
+        stats
+    }
+}
+
+#[inline]
+pub(crate) fn now_ns() -> u64 {
+    axhal::time::monotonic_time_nanos()
+}
+
+pub(crate) fn register_task(task: &AxTaskRef) {
+    TASK_TABLE
+        .lock()
+        .insert(task.id().as_u64(), Arc::downgrade(task));
+}
+
+pub(crate) fn unregister_task(task: &TaskInner) {
+    TASK_TABLE.lock().remove(&task.id().as_u64());
+}
+
+/// Returns the live task with the given ID, or [`None`] if no such task
+/// exists.
+pub fn find_task(id: TaskId) -> Option<AxTaskRef> {
+    TASK_TABLE.lock().get(&id.as_u64()).and_then(Weak::upgrade)
+}
+
+/// Calls `f` on every live task, in the order of task IDs.
+///
+/// The task table is not locked while `f` runs, so `f` may spawn, block or
+/// exit tasks. Tasks spawned during the walk are not visited.
+pub fn for_each_task<F>(mut f: F)
+where
+    F: FnMut(&AxTaskRef),
+{
+    // Collect strong references first: the last reference of a task may be
+    // dropped here, and `TaskInner::drop` needs to lock the table.
+    let tasks: Vec<AxTaskRef> = TASK_TABLE
+        .lock()
+        .values()
+        .filter_map(Weak::upgrade)
+        .collect();
+    for task in tasks.iter() {
+        f(task);
+    }
+}
+
+/// Returns the runtime statistics of the live task with the given ID, or
+/// [`None`] if no such task exists.
+pub fn task_stats(id: TaskId) -> Option<TaskStats> {
+    find_task(id).map(|t| t.stats())
+}
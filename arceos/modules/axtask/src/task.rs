@@ -1,19 +1,29 @@
 use alloc::{boxed::Box, string::String, sync::Arc};
 use core::ops::Deref;
-use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
+use core::sync::atomic::{
+    fence, AtomicBool, AtomicI32, AtomicIsize, AtomicU64, AtomicU8, Ordering,
+};
 use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
 
-#[cfg(feature = "preempt")]
+#[cfg(any(feature = "preempt", feature = "smp"))]
 use core::sync::atomic::AtomicUsize;
 
 #[cfg(feature = "tls")]
 use axhal::tls::TlsArea;
+#[cfg(feature = "alloc-tag")]
+use kspin::SpinNoIrq;
 
 use axhal::arch::TaskContext;
 use memory_addr::{align_up_4k, VirtAddr};
 
+#[cfg(feature = "sched_rt")]
+use crate::rt::RtTaskState;
+use crate::stats::{TaskStats, TaskStatsCounter};
 use crate::task_ext::AxTaskExt;
-use crate::{AxRunQueue, AxTask, AxTaskRef, WaitQueue};
+use crate::{AxTask, AxTaskRef, CpuMask, WaitQueue};
+
+/// The exit code of tasks terminated by [`TaskInner::kill`].
+pub const KILLED_EXIT_CODE: i32 = i32::MIN;
 
 /// A unique identifier for a thread.
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
@@ -43,20 +53,46 @@ pub struct TaskInner {
     #[cfg(feature = "irq")]
     in_timer_list: AtomicBool,
 
+    /// CPUs the task is allowed to run on.
+    cpumask: AtomicU64,
+    /// Whether the task is running on a CPU, or its context is not saved
+    /// completely after switched out.
+    #[cfg(feature = "smp")]
+    on_cpu: AtomicBool,
+    /// The CPU whose run queue the task was put in last time.
+    #[cfg(feature = "smp")]
+    rq_cpu: AtomicUsize,
+
+    /// The priority set by [`set_priority`](crate::set_priority).
+    base_prio: AtomicIsize,
+    /// The priority lent by other tasks, [`isize::MAX`] if there is none.
+    inherited_prio: AtomicIsize,
+
     #[cfg(feature = "preempt")]
     need_resched: AtomicBool,
     #[cfg(feature = "preempt")]
     preempt_disable_count: AtomicUsize,
 
+    /// Whether the task has been requested to terminate by [`TaskInner::kill`].
+    killed: AtomicBool,
     exit_code: AtomicI32,
     wait_for_exit: WaitQueue,
 
+    stats: TaskStatsCounter,
+    #[cfg(feature = "sched_rt")]
+    rt: RtTaskState,
+
     kstack: Option<TaskStack>,
     ctx: UnsafeCell<TaskContext>,
     task_ext: AxTaskExt,
 
     #[cfg(feature = "tls")]
     tls: TlsArea,
+
+    /// The subsystem that heap allocations of the task are attributed to,
+    /// set by [`with_alloc_tag`](crate::with_alloc_tag).
+    #[cfg(feature = "alloc-tag")]
+    alloc_tag: SpinNoIrq<Option<&'static str>>,
 }
 
 impl TaskId {
@@ -69,6 +105,11 @@ impl TaskId {
     pub const fn as_u64(&self) -> u64 {
         self.0
     }
+
+    /// Convert a `u64` returned by [`TaskId::as_u64`] back to the task ID.
+    pub const fn from_u64(id: u64) -> Self {
+        Self(id)
+    }
 }
 
 impl From<u8> for TaskState {
@@ -95,6 +136,10 @@ impl TaskInner {
     {
         let mut t = Self::new_common(TaskId::new(), name);
         debug!("new task: {}", t.id_name());
+        #[cfg(feature = "alloc-tag")]
+        let kstack =
+            crate::with_alloc_tag("task stacks", || TaskStack::alloc(align_up_4k(stack_size)));
+        #[cfg(not(feature = "alloc-tag"))]
         let kstack = TaskStack::alloc(align_up_4k(stack_size));
 
         #[cfg(feature = "tls")]
@@ -126,6 +171,19 @@ impl TaskInner {
         alloc::format!("Task({}, {:?})", self.id.as_u64(), self.name)
     }
 
+    /// Gets the subsystem that heap allocations of the task are attributed to,
+    /// or [`None`] if they are attributed to the task itself.
+    #[cfg(feature = "alloc-tag")]
+    pub fn alloc_tag(&self) -> Option<&'static str> {
+        *self.alloc_tag.lock()
+    }
+
+    /// Sets the allocation tag, returns the previous one.
+    #[cfg(feature = "alloc-tag")]
+    pub(crate) fn set_alloc_tag(&self, tag: Option<&'static str>) -> Option<&'static str> {
+        core::mem::replace(&mut *self.alloc_tag.lock(), tag)
+    }
+
     /// Wait for the task to exit, and return the exit code.
     ///
     /// It will return immediately if the task has already exited (but not dropped).
@@ -135,6 +193,93 @@ impl TaskInner {
         Some(self.exit_code.load(Ordering::Acquire))
     }
 
+    /// Requests the task to terminate.
+    ///
+    /// The cancellation is cooperative: the task exits with
+    /// [`KILLED_EXIT_CODE`] at its next cancellation point, which is any
+    /// blocking operation of this crate ([`WaitQueue::wait`] and its variants,
+    /// [`sleep`], [`join`], etc.). If the task is blocked in one of them, it
+    /// is woken up immediately. Tasks that run for a long time without
+    /// blocking should poll [`is_killed`] instead.
+    ///
+    /// Like [`exit`], the task terminates without unwinding: the objects on
+    /// its stack are not dropped. E.g., a `MutexGuard` held across the
+    /// cancellation point is leaked, and the mutex stays locked.
+    ///
+    /// Returns `false` if the task has exited, or it is an idle or init task
+    /// which can not be killed.
+    ///
+    /// [`sleep`]: crate::sleep
+    /// [`join`]: Self::join
+    /// [`is_killed`]: Self::is_killed
+    /// [`exit`]: crate::exit
+    pub fn kill(&self) -> bool {
+        if self.is_idle || self.is_init || self.state() == TaskState::Exited {
+            return false;
+        }
+        debug!("task kill: {}", self.id_name());
+        self.killed.store(true, Ordering::Release);
+        // Pairs with the fence in `try_block()`: either the task sees the
+        // flag before blocking, or we see it blocked here.
+        fence(Ordering::SeqCst);
+        if self.is_blocked() {
+            // The task is held by a wait queue or the timer list, so it must
+            // be alive.
+            if let Some(task) = crate::stats::find_task(self.id) {
+                crate::run_queue::wake_killed(task);
+            }
+        }
+        true
+    }
+
+    /// Returns `true` if the task has been requested to terminate by
+    /// [`kill`](Self::kill).
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Acquire)
+    }
+
+    /// Gets the effective priority of the task, that is the higher one of its
+    /// base priority and the priority inherited from other tasks.
+    ///
+    /// Smaller values mean higher priorities, as the nice values of the [CFS]
+    /// scheduler.
+    ///
+    /// [CFS]: https://en.wikipedia.org/wiki/Completely_Fair_Scheduler
+    pub fn priority(&self) -> isize {
+        self.base_priority()
+            .min(self.inherited_prio.load(Ordering::Acquire))
+    }
+
+    /// Gets the priority set by [`set_priority`](crate::set_priority), which
+    /// is 0 by default.
+    pub fn base_priority(&self) -> isize {
+        self.base_prio.load(Ordering::Acquire)
+    }
+
+    /// Gets the CPUs the task is allowed to run on.
+    pub fn cpumask(&self) -> CpuMask {
+        CpuMask::from_bits(self.cpumask.load(Ordering::Acquire))
+    }
+
+    /// Sets the CPUs the task is allowed to run on.
+    ///
+    /// If the task is ready on a CPU not in the mask, it is moved to an
+    /// allowed CPU when it's picked to run. If it's running on such a CPU, it
+    /// will be migrated at its next reschedule. Use [`set_current_affinity`]
+    /// to migrate the current task immediately.
+    ///
+    /// [`set_current_affinity`]: crate::set_current_affinity
+    pub fn set_cpumask(&self, cpumask: CpuMask) {
+        self.cpumask.store(cpumask.bits(), Ordering::Release);
+    }
+
+    /// Returns a snapshot of the runtime statistics of the task.
+    ///
+    /// The time spent in the current state (running or ready) is included.
+    pub fn stats(&self) -> TaskStats {
+        self.stats.snapshot(self.state())
+    }
+
     /// Returns the pointer to the user-defined task extended data.
     ///
     /// # Safety
@@ -174,17 +319,30 @@ impl TaskInner {
             in_wait_queue: AtomicBool::new(false),
             #[cfg(feature = "irq")]
             in_timer_list: AtomicBool::new(false),
+            cpumask: AtomicU64::new(CpuMask::full().bits()),
+            #[cfg(feature = "smp")]
+            on_cpu: AtomicBool::new(false),
+            #[cfg(feature = "smp")]
+            rq_cpu: AtomicUsize::new(0),
+            base_prio: AtomicIsize::new(0),
+            inherited_prio: AtomicIsize::new(isize::MAX),
             #[cfg(feature = "preempt")]
             need_resched: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             preempt_disable_count: AtomicUsize::new(0),
+            killed: AtomicBool::new(false),
             exit_code: AtomicI32::new(0),
             wait_for_exit: WaitQueue::new(),
+            stats: TaskStatsCounter::new(),
+            #[cfg(feature = "sched_rt")]
+            rt: RtTaskState::new(),
             kstack: None,
             ctx: UnsafeCell::new(TaskContext::new()),
             task_ext: AxTaskExt::empty(),
             #[cfg(feature = "tls")]
             tls: TlsArea::alloc(),
+            #[cfg(feature = "alloc-tag")]
+            alloc_tag: SpinNoIrq::new(None),
         }
     }
 
@@ -199,6 +357,8 @@ impl TaskInner {
     pub(crate) fn new_init(name: String) -> Self {
         let mut t = Self::new_common(TaskId::new(), name);
         t.is_init = true;
+        #[cfg(feature = "smp")]
+        t.set_on_cpu(true);
         if t.name == "idle" {
             t.is_idle = true;
         }
@@ -206,7 +366,9 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
+        let task = Arc::new(AxTask::new(self));
+        crate::stats::register_task(&task);
+        task
     }
 
     #[inline]
@@ -219,6 +381,38 @@ impl TaskInner {
         self.state.store(state as u8, Ordering::Release)
     }
 
+    /// Changes the state from `from` to `to` atomically, returns `false` if
+    /// the current state is not `from`.
+    #[inline]
+    pub(crate) fn transition_state(&self, from: TaskState, to: TaskState) -> bool {
+        self.state
+            .compare_exchange(from as u8, to as u8, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    /// Sets the state of the running task to `Blocked`.
+    ///
+    /// Returns `false` and keeps the task running if it has been killed, then
+    /// it should not be put into any wait queue or timer list.
+    pub(crate) fn try_block(&self) -> bool {
+        self.set_state(TaskState::Blocked);
+        fence(Ordering::SeqCst);
+        // If the state has been changed, `kill()` is waking us up, and we
+        // have to block as usual to let it finish.
+        !(self.is_killed() && self.transition_state(TaskState::Blocked, TaskState::Running))
+    }
+
+    #[inline]
+    pub(crate) fn set_base_priority(&self, prio: isize) {
+        self.base_prio.store(prio, Ordering::Release);
+    }
+
+    #[inline]
+    pub(crate) fn set_inherited_priority(&self, prio: Option<isize>) {
+        self.inherited_prio
+            .store(prio.unwrap_or(isize::MAX), Ordering::Release);
+    }
+
     #[inline]
     pub(crate) fn is_running(&self) -> bool {
         matches!(self.state(), TaskState::Running)
@@ -234,8 +428,9 @@ impl TaskInner {
         matches!(self.state(), TaskState::Blocked)
     }
 
+    /// Whether the task is the main task. Exiting it shuts down the system.
     #[inline]
-    pub(crate) const fn is_init(&self) -> bool {
+    pub const fn is_init(&self) -> bool {
         self.is_init
     }
 
@@ -266,6 +461,30 @@ impl TaskInner {
         self.in_timer_list.store(in_timer_list, Ordering::Release);
     }
 
+    #[inline]
+    #[cfg(feature = "smp")]
+    pub(crate) fn on_cpu(&self) -> bool {
+        self.on_cpu.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    #[cfg(feature = "smp")]
+    pub(crate) fn set_on_cpu(&self, on_cpu: bool) {
+        self.on_cpu.store(on_cpu, Ordering::Release);
+    }
+
+    #[inline]
+    #[cfg(feature = "smp")]
+    pub(crate) fn rq_cpu(&self) -> usize {
+        self.rq_cpu.load(Ordering::Acquire)
+    }
+
+    #[inline]
+    #[cfg(feature = "smp")]
+    pub(crate) fn set_rq_cpu(&self, cpu_id: usize) {
+        self.rq_cpu.store(cpu_id, Ordering::Release);
+    }
+
     #[inline]
     #[cfg(feature = "preempt")]
     pub(crate) fn set_preempt_pending(&self, pending: bool) {
@@ -297,16 +516,35 @@ impl TaskInner {
     fn current_check_preempt_pending() {
         let curr = crate::current();
         if curr.need_resched.load(Ordering::Acquire) && curr.can_preempt(0) {
-            let mut rq = crate::RUN_QUEUE.lock();
+            let mut rq = crate::current_run_queue();
             if curr.need_resched.load(Ordering::Acquire) {
                 rq.preempt_resched();
             }
         }
     }
 
-    pub(crate) fn notify_exit(&self, exit_code: i32, rq: &mut AxRunQueue) {
+    /// Terminates the current task if it has been killed, called at
+    /// cancellation points after the task leaves wait queues and timers.
+    pub(crate) fn current_check_killed() {
+        if crate::current().is_killed() {
+            crate::exit(KILLED_EXIT_CODE);
+        }
+    }
+
+    #[inline]
+    pub(crate) fn stats_counter(&self) -> &TaskStatsCounter {
+        &self.stats
+    }
+
+    #[inline]
+    #[cfg(feature = "sched_rt")]
+    pub(crate) fn rt(&self) -> &RtTaskState {
+        &self.rt
+    }
+
+    pub(crate) fn notify_exit(&self, exit_code: i32) {
         self.exit_code.store(exit_code, Ordering::Release);
-        self.wait_for_exit.notify_all_locked(false, rq);
+        self.wait_for_exit.notify_all(false);
     }
 
     #[inline]
@@ -328,6 +566,28 @@ impl TaskInner {
             None => None,
         }
     }
+
+    /// Panics if the canary at the bottom of the kernel stack has been
+    /// overwritten, i.e., the stack has overflowed.
+    ///
+    /// It does nothing if the stack has a guard page, as the stack overflow
+    /// is caught by the guard page instead.
+    #[inline]
+    pub(crate) fn check_stack_canary(&self) {
+        if self.kstack.as_ref().is_some_and(|s| !s.canary_intact()) {
+            panic!("stack overflow in task {}", self.id_name());
+        }
+    }
+
+    /// Sets the kernel stack limit of the current CPU to the bottom of the
+    /// stack of this task, which is going to run, so that page faults in the
+    /// guard page are reported as stack overflows, and traps after an
+    /// overflow are handled on the overflow stack.
+    #[inline]
+    pub(crate) fn set_stack_limit(&self) {
+        #[cfg(feature = "paging")]
+        axhal::arch::set_kernel_stack_limit(self.kstack.as_ref().map_or(0, |s| s.limit()));
+    }
 }
 
 impl fmt::Debug for TaskInner {
@@ -343,23 +603,101 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
+        crate::stats::unregister_task(self);
     }
 }
 
+/// The interface to map kernel stacks in the kernel address space, which is
+/// used with the `paging` feature.
+#[cfg(feature = "paging")]
+#[crate_interface::def_interface]
+pub trait KernelStackIf {
+    /// Allocates a kernel stack of `size` bytes with an unmapped guard region
+    /// of [`KERNEL_STACK_GUARD_SIZE`] bytes below it, and returns the lowest
+    /// address of the stack.
+    ///
+    /// [`KERNEL_STACK_GUARD_SIZE`]: axhal::trap::KERNEL_STACK_GUARD_SIZE
+    fn alloc_kernel_stack(size: usize) -> Option<VirtAddr>;
+
+    /// Deallocates a kernel stack allocated by `alloc_kernel_stack`.
+    fn dealloc_kernel_stack(bottom: VirtAddr, size: usize);
+}
+
+/// The kernel stack of a task.
+///
+/// With the `paging` feature, it is mapped by [`KernelStackIf`] with an
+/// unmapped guard page below it, so a stack overflow triggers a page fault,
+/// which is reported on the overflow stack of the CPU (see
+/// [`TaskInner::set_stack_limit`]). Otherwise, it is allocated from the heap,
+/// and a canary is written at the bottom of it, which is checked when the task
+/// is switched out.
 struct TaskStack {
     ptr: NonNull<u8>,
     layout: Layout,
 }
 
-impl TaskStack {
-    pub fn alloc(size: usize) -> Self {
-        let layout = Layout::from_size_align(size, 16).unwrap();
-        Self {
-            ptr: NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap(),
-            layout,
+cfg_if::cfg_if! {
+    if #[cfg(feature = "paging")] {
+        use memory_addr::PAGE_SIZE_4K;
+
+        impl TaskStack {
+            pub fn alloc(size: usize) -> Self {
+                let layout = Layout::from_size_align(size, PAGE_SIZE_4K).unwrap();
+                let bottom =
+                    crate_interface::call_interface!(KernelStackIf::alloc_kernel_stack(size))
+                        .expect("failed to allocate kernel stack");
+                Self {
+                    ptr: NonNull::new(bottom.as_mut_ptr()).unwrap(),
+                    layout,
+                }
+            }
+
+            /// The lowest address of the stack, to detect overflows.
+            fn limit(&self) -> usize {
+                self.ptr.as_ptr() as usize
+            }
+
+            fn canary_intact(&self) -> bool {
+                true
+            }
+
+            fn dealloc(&mut self) {
+                crate_interface::call_interface!(KernelStackIf::dealloc_kernel_stack(
+                    VirtAddr::from_ptr_of(self.ptr.as_ptr()),
+                    self.layout.size()
+                ));
+            }
+        }
+    } else {
+        /// The magic number at the bottom of each kernel stack without guard
+        /// pages.
+        const STACK_CANARY: u64 = 0x5a5a_c0de_dead_beef;
+
+        impl TaskStack {
+            pub fn alloc(size: usize) -> Self {
+                let layout = Layout::from_size_align(size, 16).unwrap();
+                let ptr = NonNull::new(unsafe { alloc::alloc::alloc(layout) }).unwrap();
+                unsafe { ptr.cast::<u64>().write(STACK_CANARY) };
+                Self { ptr, layout }
+            }
+
+            /// No guard page to detect overflows by page faults.
+            fn limit(&self) -> usize {
+                0
+            }
+
+            fn canary_intact(&self) -> bool {
+                unsafe { self.ptr.cast::<u64>().read_volatile() == STACK_CANARY }
+            }
+
+            fn dealloc(&mut self) {
+                unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+            }
         }
     }
+}
 
+impl TaskStack {
     pub const fn top(&self) -> VirtAddr {
         unsafe { core::mem::transmute(self.ptr.as_ptr().add(self.layout.size())) }
     }
@@ -367,7 +705,7 @@ impl TaskStack {
 
 impl Drop for TaskStack {
     fn drop(&mut self) {
-        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) }
+        self.dealloc();
     }
 }
 
@@ -429,8 +767,12 @@ impl Deref for CurrentTask {
 }
 
 extern "C" fn task_entry() -> ! {
-    // release the lock that was implicitly held across the reschedule
-    unsafe { crate::RUN_QUEUE.force_unlock() };
+    // Let the previous task run on other CPUs.
+    #[cfg(feature = "smp")]
+    unsafe {
+        crate::run_queue::finish_switch()
+    };
+    // IRQs were disabled by the previous task across the reschedule.
     #[cfg(feature = "irq")]
     axhal::arch::enable_irqs();
     let task = crate::current();
@@ -1,5 +1,5 @@
 use core::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::{Mutex, Once};
+use std::sync::{Arc, Mutex, Once};
 
 use crate::{api as axtask, current, WaitQueue};
 
@@ -128,3 +128,311 @@ fn test_task_join() {
         assert_eq!(tasks[i].join(), Some(i as _));
     }
 }
+
+#[test]
+fn test_task_kill() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_TASKS: usize = 10;
+    static WQ: WaitQueue = WaitQueue::new();
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+    let mut tasks = Vec::with_capacity(NUM_TASKS);
+    for i in 0..NUM_TASKS {
+        tasks.push(axtask::spawn(move || {
+            STARTED.fetch_add(1, Ordering::Relaxed);
+            if i % 2 == 0 {
+                WQ.wait();
+            } else {
+                WQ.wait_until(|| false);
+            }
+            unreachable!("killed task resumed");
+        }));
+    }
+    while STARTED.load(Ordering::Relaxed) < NUM_TASKS {
+        axtask::yield_now();
+    }
+
+    for t in tasks.iter() {
+        assert!(t.kill());
+        assert!(t.is_killed());
+    }
+    for t in tasks.iter() {
+        assert_eq!(t.join(), Some(axtask::KILLED_EXIT_CODE));
+        assert!(!t.kill());
+    }
+    // killed tasks have left the wait queue.
+    assert!(!WQ.notify_one(false));
+
+    // killed before blocking, never blocks.
+    let task = axtask::spawn(|| {
+        assert!(current().is_killed());
+        WQ.wait();
+        unreachable!("killed task resumed");
+    });
+    assert!(task.kill());
+    assert_eq!(task.join(), Some(axtask::KILLED_EXIT_CODE));
+    assert!(!current().kill());
+}
+
+#[test]
+fn test_notify_skips_killed() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ: WaitQueue = WaitQueue::new();
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+    static WOKEN: AtomicUsize = AtomicUsize::new(0);
+
+    let killed = axtask::spawn(|| {
+        STARTED.fetch_add(1, Ordering::Relaxed);
+        WQ.wait();
+        unreachable!("killed task resumed");
+    });
+    let live = axtask::spawn(|| {
+        STARTED.fetch_add(1, Ordering::Relaxed);
+        WQ.wait();
+        WOKEN.fetch_add(1, Ordering::Relaxed);
+    });
+    while STARTED.load(Ordering::Relaxed) < 2 {
+        axtask::yield_now();
+    }
+
+    // The killed task is still in front of the queue until it runs.
+    assert!(killed.kill());
+    assert!(WQ.notify_one(false));
+    assert_eq!(live.join(), Some(0));
+    assert_eq!(WOKEN.load(Ordering::Relaxed), 1);
+    assert_eq!(killed.join(), Some(axtask::KILLED_EXIT_CODE));
+    assert!(!WQ.notify_one(false));
+}
+
+#[test]
+fn test_killed_passes_notification_on() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    static WQ: WaitQueue = WaitQueue::new();
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+    let notified = axtask::spawn(|| {
+        STARTED.fetch_add(1, Ordering::Relaxed);
+        WQ.wait();
+        unreachable!("killed task resumed");
+    });
+    let next = axtask::spawn(|| {
+        STARTED.fetch_add(1, Ordering::Relaxed);
+        WQ.wait();
+    });
+    while STARTED.load(Ordering::Relaxed) < 2 {
+        axtask::yield_now();
+    }
+
+    // Killed after being notified, the notification goes to the next task.
+    assert!(WQ.notify_one(false));
+    assert!(notified.kill());
+    assert_eq!(notified.join(), Some(axtask::KILLED_EXIT_CODE));
+    assert_eq!(next.join(), Some(0));
+
+    // `wait_until_or_killed` returns to let the task clean up.
+    let task = axtask::spawn(|| {
+        assert!(!WQ.wait_until_or_killed(|| false));
+        assert!(current().is_killed());
+        axtask::exit(axtask::KILLED_EXIT_CODE);
+    });
+    axtask::yield_now();
+    assert!(task.kill());
+    assert_eq!(task.join(), Some(axtask::KILLED_EXIT_CODE));
+    assert!(!WQ.notify_one(false));
+}
+
+#[test]
+fn test_task_stats() {
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_YIELDS: u64 = 5;
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+    static WQ: WaitQueue = WaitQueue::new();
+
+    let task = axtask::spawn_raw(
+        || {
+            for _ in 0..NUM_YIELDS {
+                axtask::yield_now();
+            }
+            STARTED.store(1, Ordering::Relaxed);
+            WQ.wait();
+        },
+        "stats".into(),
+        0x1000,
+    );
+    while STARTED.load(Ordering::Relaxed) == 0 {
+        axtask::yield_now();
+    }
+
+    // the task is blocked in `WQ`, and can be found in the task table.
+    let mut found = false;
+    axtask::for_each_task(|t| found |= Arc::ptr_eq(t, &task));
+    assert!(found);
+    let stats = axtask::task_stats(task.id()).unwrap();
+    assert_eq!(stats, task.stats());
+    assert_eq!(stats.voluntary_switches, NUM_YIELDS + 1);
+    assert_eq!(stats.involuntary_switches, 0);
+
+    WQ.notify_one(true);
+    assert_eq!(task.join(), Some(0));
+    assert_eq!(task.stats().voluntary_switches, NUM_YIELDS + 2);
+}
+
+#[test]
+fn test_async_executor() {
+    use crate::{block_on, Executor};
+    use core::future::{poll_fn, Future};
+    use core::task::Poll;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    // A future that is pending once, and woken up by another task.
+    fn wake_later() -> impl Future<Output = ()> + Send {
+        let mut polled = false;
+        poll_fn(move |cx| {
+            if polled {
+                return Poll::Ready(());
+            }
+            polled = true;
+            let waker = cx.waker().clone();
+            axtask::spawn(move || waker.wake());
+            Poll::Pending
+        })
+    }
+
+    block_on(wake_later());
+    assert_eq!(block_on(async { 42 }), 42);
+
+    const NUM_FUTURES: usize = 100;
+    static FINISHED: AtomicUsize = AtomicUsize::new(0);
+
+    let executor = Executor::new();
+    for _ in 0..NUM_FUTURES {
+        executor.spawn(async {
+            wake_later().await;
+            FINISHED.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+    let worker = executor.clone();
+    let task = axtask::spawn(move || worker.run());
+    executor.run();
+    assert_eq!(task.join(), Some(0));
+    assert_eq!(FINISHED.load(Ordering::Relaxed), NUM_FUTURES);
+    assert_eq!(executor.pending_count(), 0);
+}
+
+#[cfg(feature = "sched_rt")]
+#[test]
+fn test_sched_rt() {
+    use crate::RtParams;
+    use core::time::Duration;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    const NUM_TASKS: usize = 3;
+    static ORDER: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+    let ms = Duration::from_millis;
+    // The main task has the earliest deadline, so spawned tasks can't preempt it.
+    assert!(axtask::set_rt_params(RtParams::new(ms(1), ms(10))));
+    for i in (0..NUM_TASKS).rev() {
+        let params = RtParams::new(ms(1), ms(100)).with_deadline(ms(20 + i as u64 * 10));
+        let task = axtask::spawn_rt(
+            move || ORDER.lock().unwrap().push(i),
+            format!("RT{}", i),
+            0x1000,
+            params,
+        );
+        assert!(task.is_some());
+    }
+    // Spawned real-time tasks run by the earliest deadline first, before the
+    // main task in the fair class.
+    axtask::clear_rt_params();
+    assert_eq!(*ORDER.lock().unwrap(), [0, 1, 2]);
+
+    // admission control
+    assert!(!axtask::set_rt_params(RtParams::new(ms(2), ms(1))));
+    assert!(axtask::set_rt_params(RtParams::new(ms(6), ms(10))));
+    let params = RtParams::new(ms(5), ms(10));
+    assert!(axtask::spawn_rt(|| {}, "RT".into(), 0x1000, params).is_none());
+    axtask::clear_rt_params();
+    assert!(axtask::spawn_rt(|| {}, "RT".into(), 0x1000, params).is_some());
+}
+
+#[cfg(feature = "irq")]
+#[test]
+fn test_timers() {
+    use crate::timers::{self, on_timer_irq};
+    use core::time::Duration;
+
+    let _lock = SERIAL.lock();
+    INIT.call_once(axtask::init_scheduler);
+
+    // The clock does not advance in tests, timers set to `now` have expired.
+    let now = axhal::time::wall_time();
+
+    // one-shot
+    static ONESHOT: AtomicUsize = AtomicUsize::new(0);
+    let timer = timers::set_timer(now, || {
+        ONESHOT.fetch_add(1, Ordering::Relaxed);
+    });
+    assert!(timer.is_pending());
+    on_timer_irq();
+    assert_eq!(ONESHOT.load(Ordering::Relaxed), 1);
+    assert!(!timer.is_pending());
+    on_timer_irq();
+    assert_eq!(ONESHOT.load(Ordering::Relaxed), 1);
+    assert!(!timer.cancel());
+
+    // periodic, re-armed for the next period after firing.
+    static PERIODIC: AtomicUsize = AtomicUsize::new(0);
+    let timer = timers::set_periodic_timer(now, Duration::from_millis(1), || {
+        PERIODIC.fetch_add(1, Ordering::Relaxed);
+    });
+    on_timer_irq();
+    assert_eq!(PERIODIC.load(Ordering::Relaxed), 1);
+    assert!(timer.is_pending());
+    on_timer_irq();
+    assert_eq!(PERIODIC.load(Ordering::Relaxed), 1);
+    assert!(timer.cancel());
+    assert!(!timer.is_pending());
+
+    // cancelled before firing, and re-armed.
+    static CANCELLED: AtomicUsize = AtomicUsize::new(0);
+    let timer = timers::set_timer(now, || {
+        CANCELLED.fetch_add(1, Ordering::Relaxed);
+    });
+    assert!(timer.cancel());
+    assert!(!timer.cancel());
+    on_timer_irq();
+    assert_eq!(CANCELLED.load(Ordering::Relaxed), 0);
+    timer.rearm(now);
+    on_timer_irq();
+    assert_eq!(CANCELLED.load(Ordering::Relaxed), 1);
+}
+
+#[cfg(not(feature = "paging"))]
+#[test]
+fn test_stack_canary() {
+    use crate::TaskInner;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    const STACK_SIZE: usize = 0x1000;
+    let task = TaskInner::new(|| {}, "canary".into(), STACK_SIZE);
+    task.check_stack_canary();
+
+    // An overflow overwrites the bottom of the stack.
+    let bottom = task.kernel_stack_top().unwrap().as_usize() - STACK_SIZE;
+    unsafe { (bottom as *mut u64).write_volatile(0) };
+    let result = catch_unwind(AssertUnwindSafe(|| task.check_stack_canary()));
+    assert!(result.is_err());
+}
@@ -1,48 +1,292 @@
+//! Timer events and high-resolution timers.
+//!
+//! All timed events, including task wakeups by [`sleep`](crate::sleep) and
+//! user timers set by [`set_timer`] or [`set_periodic_timer`], are kept in a
+//! timer list ordered by deadline. Instead of polling the list on each fixed
+//! tick, the timer hardware is programmed to fire at the earliest of the next
+//! event and the next scheduler tick, so timers fire at their exact
+//! deadlines.
+//!
+//! With the `tickless` feature, the periodic scheduler tick is also stopped
+//! while the CPU is idle, so an idle CPU is only interrupted by timer events.
+//! The tick is kept while any CPU has ready tasks, so the idle CPU can steal
+//! them.
+
+use alloc::boxed::Box;
 use alloc::sync::Arc;
-use axhal::time::wall_time;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use axhal::time::{epochoffset_nanos, monotonic_time_nanos, wall_time, NANOS_PER_SEC};
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
 use timer_list::{TimeValue, TimerEvent, TimerList};
 
-use crate::{AxTaskRef, RUN_QUEUE};
+use crate::{select_run_queue, AxTaskRef};
 
 // TODO: per-CPU
-static TIMER_LIST: LazyInit<SpinNoIrq<TimerList<TaskWakeupEvent>>> = LazyInit::new();
+static TIMER_LIST: LazyInit<SpinNoIrq<TimerList<Event>>> = LazyInit::new();
+
+/// Interval of the periodic scheduler tick.
+const TICK_INTERVAL_NANOS: u64 = NANOS_PER_SEC / axconfig::TICKS_PER_SEC as u64;
+
+/// The longest time that an idle CPU is left without timer interrupts.
+#[cfg(feature = "tickless")]
+const MAX_IDLE_INTERVAL_NANOS: u64 = NANOS_PER_SEC;
+
+/// Monotonic time of the next scheduler tick on this CPU.
+#[percpu::def_percpu]
+static NEXT_TICK_NANOS: u64 = 0;
+
+/// Monotonic time that the timer hardware of this CPU is programmed to.
+#[percpu::def_percpu]
+static HW_DEADLINE_NANOS: u64 = 0;
+
+enum Event {
+    TaskWakeup(AxTaskRef),
+    /// A user timer, which is stale if the generation does not match.
+    Timer(Arc<TimerInner>, u64),
+}
+
+impl Event {
+    fn fire(self, deadline: TimeValue, now: TimeValue) {
+        match self {
+            Self::TaskWakeup(task) => {
+                task.set_in_timer_list(false);
+                select_run_queue(&task).unblock_task(task, true);
+            }
+            Self::Timer(timer, generation) => timer.fire(generation, deadline, now),
+        }
+    }
+}
+
+impl TimerEvent for Event {
+    fn callback(self, now: TimeValue) {
+        self.fire(now, now);
+    }
+}
+
+struct TimerInner {
+    callback: Box<dyn Fn() + Send + Sync>,
+    /// Period in nanoseconds, or 0 for one-shot timers.
+    period: u64,
+    /// Increased on each (re)arm or cancellation, to invalidate the events
+    /// set before it.
+    generation: AtomicU64,
+    pending: AtomicBool,
+}
+
+impl TimerInner {
+    fn fire(self: Arc<Self>, generation: u64, deadline: TimeValue, now: TimeValue) {
+        {
+            let mut timers = TIMER_LIST.lock();
+            if self.generation.load(Ordering::Acquire) != generation {
+                return; // cancelled or re-armed
+            }
+            if self.period == 0 {
+                self.pending.store(false, Ordering::Release);
+            } else {
+                let period = Duration::from_nanos(self.period);
+                let mut next = deadline + period;
+                if next <= now {
+                    // Skip the missed periods.
+                    next = now + period;
+                }
+                set_event(&mut timers, next, Event::Timer(self.clone(), generation));
+            }
+        }
+        (self.callback)();
+    }
+
+    /// Sets the timer to fire at `deadline`, invalidating the previous
+    /// events.
+    fn arm(self: &Arc<Self>, timers: &mut TimerList<Event>, deadline: TimeValue) {
+        timers.cancel(|e| matches!(e, Event::Timer(t, _) if Arc::ptr_eq(t, self)));
+        let generation = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        self.pending.store(true, Ordering::Release);
+        set_event(timers, deadline, Event::Timer(self.clone(), generation));
+    }
+}
+
+/// A handle to a timer set by [`set_timer`] or [`set_periodic_timer`].
+///
+/// Dropping the handle does not cancel the timer.
+#[derive(Clone)]
+pub struct TimerHandle {
+    inner: Arc<TimerInner>,
+}
+
+impl TimerHandle {
+    /// Cancels the timer.
+    ///
+    /// Returns `true` if the timer was pending. The callback may still be
+    /// running on another CPU when it returns.
+    pub fn cancel(&self) -> bool {
+        let mut timers = TIMER_LIST.lock();
+        let inner = &self.inner;
+        timers.cancel(|e| matches!(e, Event::Timer(t, _) if Arc::ptr_eq(t, inner)));
+        inner.generation.fetch_add(1, Ordering::AcqRel);
+        inner.pending.swap(false, Ordering::AcqRel)
+    }
 
-struct TaskWakeupEvent(AxTaskRef);
+    /// Re-arms the timer to fire at the given deadline (in wall time), no
+    /// matter whether it has fired or been cancelled.
+    ///
+    /// For periodic timers, the following periods start from the new
+    /// deadline.
+    pub fn rearm(&self, deadline: TimeValue) {
+        self.inner.arm(&mut TIMER_LIST.lock(), deadline);
+    }
+
+    /// Whether the timer is waiting to fire.
+    pub fn is_pending(&self) -> bool {
+        self.inner.pending.load(Ordering::Acquire)
+    }
+}
+
+/// Sets a one-shot timer that calls `callback` at the given deadline (in
+/// wall time).
+///
+/// The callback runs in the timer interrupt handler with IRQs disabled, so it
+/// must not block.
+pub fn set_timer<F>(deadline: TimeValue, callback: F) -> TimerHandle
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    new_timer(deadline, 0, callback)
+}
+
+/// Sets a periodic timer that calls `callback` at the given deadline (in
+/// wall time), and then every `period`.
+///
+/// If the callback is late for more than one period, the missed calls are
+/// skipped. The callback runs in the timer interrupt handler with IRQs
+/// disabled, so it must not block.
+///
+/// # Panics
+///
+/// Panics if `period` is zero.
+pub fn set_periodic_timer<F>(deadline: TimeValue, period: Duration, callback: F) -> TimerHandle
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let period_nanos = period.as_nanos() as u64;
+    assert!(period_nanos > 0, "timer period must be non-zero");
+    new_timer(deadline, period_nanos, callback)
+}
+
+fn new_timer<F>(deadline: TimeValue, period: u64, callback: F) -> TimerHandle
+where
+    F: Fn() + Send + Sync + 'static,
+{
+    let inner = Arc::new(TimerInner {
+        callback: Box::new(callback),
+        period,
+        generation: AtomicU64::new(0),
+        pending: AtomicBool::new(false),
+    });
+    inner.arm(&mut TIMER_LIST.lock(), deadline);
+    TimerHandle { inner }
+}
+
+/// Converts a wall time deadline to the monotonic time in nanoseconds.
+fn monotonic_nanos(deadline: TimeValue) -> u64 {
+    (deadline.as_nanos() as u64).saturating_sub(epochoffset_nanos())
+}
+
+/// Programs the timer hardware of this CPU, IRQs must be disabled.
+fn program_timer(deadline_ns: u64) {
+    unsafe { HW_DEADLINE_NANOS.write_current_raw(deadline_ns) };
+    axhal::time::set_oneshot_timer(deadline_ns);
+}
 
-impl TimerEvent for TaskWakeupEvent {
-    fn callback(self, _now: TimeValue) {
-        let mut rq = RUN_QUEUE.lock();
-        self.0.set_in_timer_list(false);
-        rq.unblock_task(self.0, true);
+/// Adds an event to the timer list, and reprograms the timer hardware if the
+/// event is earlier than the programmed deadline.
+fn set_event(timers: &mut TimerList<Event>, deadline: TimeValue, event: Event) {
+    timers.set(deadline, event);
+    // IRQs are disabled by the timer list lock.
+    let deadline_ns = monotonic_nanos(deadline);
+    if deadline_ns < unsafe { HW_DEADLINE_NANOS.read_current_raw() } {
+        program_timer(deadline_ns);
     }
 }
 
-pub fn set_alarm_wakeup(deadline: TimeValue, task: AxTaskRef) {
+pub(crate) fn set_alarm_wakeup(deadline: TimeValue, task: AxTaskRef) {
     let mut timers = TIMER_LIST.lock();
     task.set_in_timer_list(true);
-    timers.set(deadline, TaskWakeupEvent(task));
+    set_event(&mut timers, deadline, Event::TaskWakeup(task));
 }
 
-pub fn cancel_alarm(task: &AxTaskRef) {
+pub(crate) fn cancel_alarm(task: &AxTaskRef) {
     let mut timers = TIMER_LIST.lock();
     task.set_in_timer_list(false);
-    timers.cancel(|t| Arc::ptr_eq(&t.0, task));
+    timers.cancel(|e| matches!(e, Event::TaskWakeup(t) if Arc::ptr_eq(t, task)));
 }
 
-pub fn check_events() {
+fn check_events() {
     loop {
         let now = wall_time();
         let event = TIMER_LIST.lock().expire_one(now);
-        if let Some((_deadline, event)) = event {
-            event.callback(now);
+        if let Some((deadline, event)) = event {
+            event.fire(deadline, now);
         } else {
             break;
         }
     }
 }
 
-pub fn init() {
+/// Programs the timer hardware to the earliest of the next event and the
+/// next scheduler tick.
+fn program_next_deadline(now_ns: u64) {
+    let mut deadline_ns = unsafe { NEXT_TICK_NANOS.read_current_raw() };
+    #[cfg(feature = "tickless")]
+    if crate::current().is_idle() {
+        if crate::run_queue::try_stop_tick() {
+            // Stop the tick, until the CPU leaves idle.
+            deadline_ns = now_ns + MAX_IDLE_INTERVAL_NANOS;
+        }
+    }
+    if let Some(next) = TIMER_LIST.lock().next_deadline() {
+        deadline_ns = deadline_ns.min(monotonic_nanos(next));
+    }
+    program_timer(deadline_ns.max(now_ns));
+}
+
+/// Handles the timer interrupt of this CPU: fires the expired events, and
+/// programs the timer hardware for the next one.
+///
+/// Returns `true` if a scheduler tick has elapsed.
+pub(crate) fn on_timer_irq() -> bool {
+    check_events();
+    let now_ns = monotonic_time_nanos();
+    // Safety: IRQs are disabled in the IRQ handler.
+    let next_tick = unsafe { NEXT_TICK_NANOS.read_current_raw() };
+    let ticked = now_ns >= next_tick;
+    if ticked {
+        let mut next_tick = next_tick + TICK_INTERVAL_NANOS;
+        if next_tick <= now_ns {
+            next_tick = now_ns + TICK_INTERVAL_NANOS;
+        }
+        unsafe { NEXT_TICK_NANOS.write_current_raw(next_tick) };
+    }
+    program_next_deadline(now_ns);
+    ticked
+}
+
+/// Restarts the scheduler tick stopped while the CPU is idle, called when
+/// switching from the idle task. IRQs must be disabled.
+#[cfg(feature = "tickless")]
+pub(crate) fn restart_tick() {
+    crate::run_queue::tick_restarted();
+    let next_tick = monotonic_time_nanos() + TICK_INTERVAL_NANOS;
+    unsafe {
+        NEXT_TICK_NANOS.write_current_raw(next_tick);
+        if next_tick < HW_DEADLINE_NANOS.read_current_raw() {
+            program_timer(next_tick);
+        }
+    }
+}
+
+pub(crate) fn init() {
     TIMER_LIST.init_once(SpinNoIrq::new(TimerList::new()));
 }
@@ -2,10 +2,17 @@ use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use kspin::SpinRaw;
 
-use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
+use crate::{current_run_queue, select_run_queue, AxTaskRef, CurrentTask, TaskInner};
 
 /// A queue to store sleeping tasks.
 ///
+/// All the `wait*` methods but [`wait_until_or_killed`] are cancellation
+/// points: if the current task is killed by [`TaskInner::kill`] before or
+/// while waiting, it exits with [`KILLED_EXIT_CODE`](crate::KILLED_EXIT_CODE)
+/// instead of returning.
+///
+/// [`wait_until_or_killed`]: WaitQueue::wait_until_or_killed
+///
 /// # Examples
 ///
 /// ```
@@ -27,7 +34,7 @@ use crate::{AxRunQueue, AxTaskRef, CurrentTask, RUN_QUEUE};
 /// assert_eq!(VALUE.load(Ordering::Relaxed), 1);
 /// ```
 pub struct WaitQueue {
-    queue: SpinRaw<VecDeque<AxTaskRef>>, // we already disabled IRQs when lock the `RUN_QUEUE`
+    queue: SpinRaw<VecDeque<AxTaskRef>>, // IRQs and preemption are disabled by callers
 }
 
 impl WaitQueue {
@@ -45,27 +52,64 @@ impl WaitQueue {
         }
     }
 
-    fn cancel_events(&self, curr: CurrentTask) {
-        // A task can be wake up only one events (timer or `notify()`), remove
-        // the event from another queue.
+    /// Removes the current task from the queue and the timer list, after it
+    /// is woken up. Returns `false` if it has been killed.
+    fn leave_events(&self, curr: &CurrentTask) -> bool {
+        // A task can be wake up only one events (timer, `notify()` or
+        // `kill()`), remove the event from other queues.
         if curr.in_wait_queue() {
-            // wake up by timer (timeout).
-            // `RUN_QUEUE` is not locked here, so disable IRQs.
+            // wake up by timer (timeout) or `kill()`.
+            // the current run queue is not locked here, so disable IRQs.
             let _guard = kernel_guard::IrqSave::new();
             self.queue.lock().retain(|t| !curr.ptr_eq(t));
             curr.set_in_wait_queue(false);
+        } else if curr.is_killed() {
+            // Notified but killed before running, pass the notification on,
+            // or it's lost for the other waiters.
+            self.notify_one(false);
         }
         #[cfg(feature = "irq")]
         if curr.in_timer_list() {
             // timeout was set but not triggered (wake up by `WaitQueue::notify()`)
             crate::timers::cancel_alarm(curr.as_task_ref());
         }
+        !curr.is_killed()
+    }
+
+    fn cancel_events(&self, curr: CurrentTask) {
+        self.leave_events(&curr);
+        // Woken up by `kill()`, terminate the task here.
+        TaskInner::current_check_killed();
+    }
+
+    /// Blocks the current task until `condition` becomes true, returns
+    /// `false` if it's killed before that.
+    fn block_until<F>(&self, condition: F) -> bool
+    where
+        F: Fn() -> bool,
+    {
+        loop {
+            let mut rq = current_run_queue();
+            // Check the condition with the wait queue locked, so notifications
+            // from other CPUs can't be lost.
+            let mut wq = self.queue.lock();
+            if condition() {
+                return true;
+            }
+            if crate::current().is_killed() {
+                return false;
+            }
+            rq.block_current(move |task| {
+                task.set_in_wait_queue(true);
+                wq.push_back(task);
+            });
+        }
     }
 
     /// Blocks the current task and put it into the wait queue, until other task
     /// notifies it.
     pub fn wait(&self) {
-        RUN_QUEUE.lock().block_current(|task| {
+        current_run_queue().block_current(|task| {
             task.set_in_wait_queue(true);
             self.queue.lock().push_back(task)
         });
@@ -81,19 +125,26 @@ impl WaitQueue {
     where
         F: Fn() -> bool,
     {
-        loop {
-            let mut rq = RUN_QUEUE.lock();
-            if condition() {
-                break;
-            }
-            rq.block_current(|task| {
-                task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
-            });
-        }
+        self.block_until(condition);
         self.cancel_events(crate::current());
     }
 
+    /// Same as [`wait_until`](Self::wait_until), but it's not a cancellation
+    /// point: if the current task is killed before the condition becomes
+    /// true, it returns `false` instead of exiting.
+    ///
+    /// It's for the callers that must undo their bookkeeping of waiting, e.g.,
+    /// the number of waiting tasks, then exit by
+    /// [`exit(KILLED_EXIT_CODE)`](crate::exit).
+    pub fn wait_until_or_killed<F>(&self, condition: F) -> bool
+    where
+        F: Fn() -> bool,
+    {
+        let satisfied = self.block_until(condition);
+        self.leave_events(&crate::current());
+        satisfied
+    }
+
     /// Blocks the current task and put it into the wait queue, until other tasks
     /// notify it, or the given duration has elapsed.
     #[cfg(feature = "irq")]
@@ -105,11 +156,13 @@ impl WaitQueue {
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
-        RUN_QUEUE.lock().block_current(|task| {
+        current_run_queue().block_current(|task| {
             task.set_in_wait_queue(true);
-            self.queue.lock().push_back(task)
+            self.queue.lock().push_back(task.clone());
+            // Arm the timer after the task is blocked, otherwise the wakeup
+            // may be lost if it fires on another CPU.
+            crate::timers::set_alarm_wakeup(deadline, task);
         });
         let timeout = curr.in_wait_queue(); // still in the wait queue, must have timed out
         self.cancel_events(curr);
@@ -133,18 +186,25 @@ impl WaitQueue {
             curr.id_name(),
             deadline
         );
-        crate::timers::set_alarm_wakeup(deadline, curr.clone());
 
         let mut timeout = true;
         while axhal::time::wall_time() < deadline {
-            let mut rq = RUN_QUEUE.lock();
+            let mut rq = current_run_queue();
+            let mut wq = self.queue.lock();
             if condition() {
                 timeout = false;
                 break;
             }
-            rq.block_current(|task| {
+            if curr.is_killed() {
+                break;
+            }
+            rq.block_current(move |task| {
                 task.set_in_wait_queue(true);
-                self.queue.lock().push_back(task);
+                wq.push_back(task.clone());
+                drop(wq);
+                if !task.in_timer_list() {
+                    crate::timers::set_alarm_wakeup(deadline, task);
+                }
             });
         }
         self.cancel_events(curr);
@@ -153,14 +213,20 @@ impl WaitQueue {
 
     /// Wakes up one task in the wait queue, usually the first one.
     ///
+    /// Tasks that have already been woken up by others, e.g., killed or timed
+    /// out, but not yet left the queue, are skipped.
+    ///
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_one(&self, resched: bool) -> bool {
-        let mut rq = RUN_QUEUE.lock();
-        if !self.queue.lock().is_empty() {
-            self.notify_one_locked(resched, &mut rq)
-        } else {
-            false
+        let _guard = kernel_guard::NoPreemptIrqSave::new();
+        loop {
+            let task = self.queue.lock().pop_front();
+            match task {
+                Some(task) if unblock(task, resched) => return true,
+                Some(_) => continue,
+                None => return false,
+            }
         }
     }
 
@@ -169,15 +235,15 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_all(&self, resched: bool) {
+        let _guard = kernel_guard::NoPreemptIrqSave::new();
         loop {
-            let mut rq = RUN_QUEUE.lock();
-            if let Some(task) = self.queue.lock().pop_front() {
-                task.set_in_wait_queue(false);
-                rq.unblock_task(task, resched);
+            // we must unlock `self.queue` before unblocking the task.
+            let task = self.queue.lock().pop_front();
+            if let Some(task) = task {
+                unblock(task, resched);
             } else {
                 break;
             }
-            drop(rq); // we must unlock `RUN_QUEUE` after unlocking `self.queue`.
         }
     }
 
@@ -186,31 +252,20 @@ impl WaitQueue {
     /// If `resched` is true, the current task will be preempted when the
     /// preemption is enabled.
     pub fn notify_task(&mut self, resched: bool, task: &AxTaskRef) -> bool {
-        let mut rq = RUN_QUEUE.lock();
+        let _guard = kernel_guard::NoPreemptIrqSave::new();
         let mut wq = self.queue.lock();
         if let Some(index) = wq.iter().position(|t| Arc::ptr_eq(t, task)) {
-            task.set_in_wait_queue(false);
-            rq.unblock_task(wq.remove(index).unwrap(), resched);
-            true
-        } else {
-            false
-        }
-    }
-
-    pub(crate) fn notify_one_locked(&self, resched: bool, rq: &mut AxRunQueue) -> bool {
-        if let Some(task) = self.queue.lock().pop_front() {
-            task.set_in_wait_queue(false);
-            rq.unblock_task(task, resched);
-            true
+            let task = wq.remove(index).unwrap();
+            drop(wq);
+            unblock(task, resched)
         } else {
             false
         }
     }
+}
 
-    pub(crate) fn notify_all_locked(&self, resched: bool, rq: &mut AxRunQueue) {
-        while let Some(task) = self.queue.lock().pop_front() {
-            task.set_in_wait_queue(false);
-            rq.unblock_task(task, resched);
-        }
-    }
+/// Returns `false` if the task has been woken up by others.
+fn unblock(task: AxTaskRef, resched: bool) -> bool {
+    task.set_in_wait_queue(false);
+    select_run_queue(&task).unblock_task(task, resched)
 }
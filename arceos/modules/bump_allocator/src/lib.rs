@@ -1,6 +1,8 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
-use allocator::{BaseAllocator, ByteAllocator, PageAllocator};
+use allocator::{AllocError, AllocResult, BaseAllocator, ByteAllocator, PageAllocator};
+use core::alloc::Layout;
+use core::ptr::NonNull;
 
 /// Early memory allocator
 /// Use it before formal bytes-allocator and pages-allocator can work!
@@ -16,73 +18,219 @@ use allocator::{BaseAllocator, ByteAllocator, PageAllocator};
 /// When it goes down to ZERO, free bytes-used area.
 /// For pages area, it will never be freed!
 ///
-pub struct EarlyAllocator<const SIZE: usize> {}
+pub struct EarlyAllocator<const SIZE: usize> {
+    start: usize,
+    end: usize,
+    b_pos: usize,
+    p_pos: usize,
+    count: usize,
+}
+
+const fn align_down(pos: usize, align: usize) -> usize {
+    pos & !(align - 1)
+}
+
+const fn align_up(pos: usize, align: usize) -> Option<usize> {
+    match pos.checked_add(align - 1) {
+        Some(pos) => Some(align_down(pos, align)),
+        None => None,
+    }
+}
 
 impl<const SIZE: usize> EarlyAllocator<SIZE> {
     pub const fn new() -> Self {
-        Self {}
+        Self {
+            start: 0,
+            end: 0,
+            b_pos: 0,
+            p_pos: 0,
+            count: 0,
+        }
+    }
+}
+
+impl<const SIZE: usize> Default for EarlyAllocator<SIZE> {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl<const SIZE: usize> BaseAllocator for EarlyAllocator<SIZE> {
     fn init(&mut self, start: usize, size: usize) {
-        todo!()
+        self.start = start;
+        self.end = start + size;
+        self.b_pos = start;
+        self.p_pos = align_down(self.end, SIZE).max(start);
+        self.count = 0;
     }
 
-    fn add_memory(&mut self, start: usize, size: usize) -> allocator::AllocResult {
-        todo!()
+    fn add_memory(&mut self, _start: usize, _size: usize) -> AllocResult {
+        // Only one contiguous memory range is supported.
+        Err(AllocError::InvalidParam)
     }
 }
 
 impl<const SIZE: usize> ByteAllocator for EarlyAllocator<SIZE> {
-    fn alloc(
-        &mut self,
-        layout: core::alloc::Layout,
-    ) -> allocator::AllocResult<core::ptr::NonNull<u8>> {
-        todo!()
+    fn alloc(&mut self, layout: Layout) -> AllocResult<NonNull<u8>> {
+        let start = align_up(self.b_pos, layout.align()).ok_or(AllocError::NoMemory)?;
+        let end = start
+            .checked_add(layout.size())
+            .ok_or(AllocError::NoMemory)?;
+        if end > self.p_pos {
+            return Err(AllocError::NoMemory);
+        }
+        self.b_pos = end;
+        self.count += 1;
+        NonNull::new(start as *mut u8).ok_or(AllocError::NoMemory)
     }
 
-    fn dealloc(&mut self, pos: core::ptr::NonNull<u8>, layout: core::alloc::Layout) {
-        todo!()
+    fn dealloc(&mut self, _pos: NonNull<u8>, _layout: Layout) {
+        debug_assert!(self.count > 0, "dealloc without alloc");
+        self.count = self.count.saturating_sub(1);
+        if self.count == 0 {
+            self.b_pos = self.start;
+        }
     }
 
     fn total_bytes(&self) -> usize {
-        todo!()
+        self.end - self.start
     }
 
     fn used_bytes(&self) -> usize {
-        todo!()
+        self.b_pos - self.start
     }
 
     fn available_bytes(&self) -> usize {
-        todo!()
+        self.p_pos - self.b_pos
     }
 }
 
 impl<const SIZE: usize> PageAllocator for EarlyAllocator<SIZE> {
     const PAGE_SIZE: usize = SIZE;
 
-    fn alloc_pages(
-        &mut self,
-        num_pages: usize,
-        align_pow2: usize,
-    ) -> allocator::AllocResult<usize> {
-        todo!()
+    fn alloc_pages(&mut self, num_pages: usize, align_pow2: usize) -> AllocResult<usize> {
+        if !align_pow2.is_power_of_two() || align_pow2 % SIZE != 0 {
+            return Err(AllocError::InvalidParam);
+        }
+        let size = num_pages
+            .checked_mul(SIZE)
+            .ok_or(AllocError::InvalidParam)?;
+        let pos = self.p_pos.checked_sub(size).ok_or(AllocError::NoMemory)?;
+        let pos = align_down(pos, align_pow2);
+        if pos < self.b_pos {
+            return Err(AllocError::NoMemory);
+        }
+        self.p_pos = pos;
+        Ok(pos)
     }
 
-    fn dealloc_pages(&mut self, pos: usize, num_pages: usize) {
-        todo!()
+    fn dealloc_pages(&mut self, _pos: usize, _num_pages: usize) {
+        // Pages are never freed.
     }
 
     fn total_pages(&self) -> usize {
-        todo!()
+        (self.end - self.start) / SIZE
     }
 
     fn used_pages(&self) -> usize {
-        todo!()
+        (self.end - self.p_pos) / SIZE
     }
 
     fn available_pages(&self) -> usize {
-        todo!()
+        (self.p_pos - self.b_pos) / SIZE
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE_SIZE: usize = 0x1000;
+    const START: usize = 0x1000_0000;
+
+    /// The allocator never accesses the memory, so any address range works.
+    fn new_allocator(size: usize) -> EarlyAllocator<PAGE_SIZE> {
+        let mut early = EarlyAllocator::new();
+        early.init(START, size);
+        early
+    }
+
+    fn layout(size: usize, align: usize) -> Layout {
+        Layout::from_size_align(size, align).unwrap()
+    }
+
+    #[test]
+    fn alignment() {
+        let mut early = new_allocator(0x10000);
+        let a = early.alloc(layout(3, 1)).unwrap().as_ptr() as usize;
+        assert_eq!(a, START);
+        let b = early.alloc(layout(8, 8)).unwrap().as_ptr() as usize;
+        assert_eq!(b, START + 8);
+        let c = early.alloc(layout(1, 256)).unwrap().as_ptr() as usize;
+        assert_eq!(c, START + 256);
+        assert_eq!(early.used_bytes(), 257);
+
+        let p = early.alloc_pages(1, PAGE_SIZE).unwrap();
+        assert_eq!(p, START + 0xf000);
+        let p = early.alloc_pages(2, PAGE_SIZE * 4).unwrap();
+        assert_eq!(p, START + 0xc000);
+        assert_eq!(early.used_pages(), 4);
+        assert_eq!(
+            early.alloc_pages(1, PAGE_SIZE / 2),
+            Err(AllocError::InvalidParam)
+        );
+        assert_eq!(
+            early.alloc_pages(1, PAGE_SIZE * 3),
+            Err(AllocError::InvalidParam)
+        );
+    }
+
+    #[test]
+    fn unaligned_end() {
+        let mut early = new_allocator(0x2800);
+        assert_eq!(early.total_pages(), 2);
+        assert_eq!(early.alloc_pages(1, PAGE_SIZE), Ok(START + 0x1000));
+    }
+
+    #[test]
+    fn byte_area_reclaim() {
+        let mut early = new_allocator(0x10000);
+        let a = early.alloc(layout(100, 8)).unwrap();
+        let b = early.alloc(layout(200, 8)).unwrap();
+        early.dealloc(a, layout(100, 8));
+        // Not reclaimed until all bytes are freed.
+        assert_eq!(early.used_bytes(), 304);
+        let c = early.alloc(layout(16, 8)).unwrap();
+        assert_eq!(c.as_ptr() as usize, START + 304);
+        early.dealloc(b, layout(200, 8));
+        early.dealloc(c, layout(16, 8));
+        assert_eq!(early.used_bytes(), 0);
+        let d = early.alloc(layout(16, 8)).unwrap();
+        assert_eq!(d.as_ptr() as usize, START);
+
+        // Pages are never freed.
+        let p = early.alloc_pages(1, PAGE_SIZE).unwrap();
+        early.dealloc_pages(p, 1);
+        assert_eq!(early.used_pages(), 1);
+        assert_eq!(early.alloc_pages(1, PAGE_SIZE), Ok(p - PAGE_SIZE));
+    }
+
+    #[test]
+    fn areas_meet() {
+        let mut early = new_allocator(0x4000);
+        early.alloc_pages(2, PAGE_SIZE).unwrap();
+        assert_eq!(early.available_bytes(), 0x2000);
+        early.alloc(layout(0x1800, 8)).unwrap();
+        assert_eq!(early.available_pages(), 0);
+        // Neither area can grow into the other.
+        assert_eq!(early.alloc_pages(1, PAGE_SIZE), Err(AllocError::NoMemory));
+        assert_eq!(
+            early.alloc(layout(0x801, 1)).unwrap_err(),
+            AllocError::NoMemory
+        );
+        let last = early.alloc(layout(0x800, 1)).unwrap();
+        assert_eq!(last.as_ptr() as usize, START + 0x1800);
+        assert_eq!(early.available_bytes(), 0);
+        assert_eq!(early.alloc(layout(1, 1)).unwrap_err(), AllocError::NoMemory);
+    }
+}
@@ -2,5 +2,8 @@
 
 define unit_test
   $(call run_cmd,cargo test,-p axfs $(1) --features "myfs" -- --nocapture)
+  $(call run_cmd,cargo test,-p axfs $(1) --features "ext4fs" -- --nocapture)
+  $(call run_cmd,cargo test,-p axsync $(1) --features "axtask/sched_cfs" -- --nocapture --include-ignored)
+  $(call run_cmd,cargo test,-p axalloc $(1) --features "debug" -- --nocapture)
   $(call run_cmd,cargo test,--workspace $(1) -- --nocapture)
 endef
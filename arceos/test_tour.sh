@@ -13,5 +13,6 @@ make run A=tour/u_4_0
 make run A=tour/u_5_0
 make run A=tour/u_6_0
 make run A=tour/u_6_1
+make run A=tour/u_6_2 SMP=4
 make run A=tour/u_7_0 BLK=y
 make run A=tour/u_8_0 BLK=y
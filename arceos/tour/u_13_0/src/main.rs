@@ -0,0 +1,71 @@
+//! Compares the page table frames and mapping time of a linear mapping in
+//! huge pages with the same mapping in 4K pages, then splits a huge page.
+
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[macro_use]
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+
+use std::time::Instant;
+
+use axhal::mem::{PhysAddr, VirtAddr};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+
+const BASE: usize = 0x4000_0000;
+const SIZE: usize = 0x4000_0000;
+const PAGE_SIZE: usize = 0x1000;
+
+/// Maps `SIZE` bytes at `BASE` to `paddr`, returns the number of page table
+/// frames used.
+fn bench_map_linear(name: &str, paddr: PhysAddr) -> usize {
+    let flags = MappingFlags::READ | MappingFlags::WRITE;
+    let mut aspace = AddrSpace::new_empty(VirtAddr::from(BASE), SIZE).unwrap();
+    let start = Instant::now();
+    aspace
+        .map_linear(aspace.base(), paddr, SIZE, flags)
+        .unwrap();
+    let map_time = start.elapsed();
+    let frames = aspace.page_table_frames();
+    let start = Instant::now();
+    aspace.unmap(aspace.base(), SIZE).unwrap();
+    let unmap_time = start.elapsed();
+    println!(
+        "{}: {} page table frames, map {:?}, unmap {:?}",
+        name, frames, map_time, unmap_time
+    );
+    frames
+}
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    // The physical addresses are never accessed, an aligned one allows 1G
+    // pages, and a misaligned one forces 4K pages.
+    let huge = bench_map_linear("huge pages", PhysAddr::from(SIZE));
+    let small = bench_map_linear("4K pages", PhysAddr::from(SIZE + PAGE_SIZE));
+    assert!(huge < small);
+    println!("Page table frames reduced: {} -> {}", small, huge);
+
+    // Changing a page in the middle splits the huge pages around it.
+    let flags = MappingFlags::READ | MappingFlags::WRITE;
+    let mut aspace = AddrSpace::new_empty(VirtAddr::from(BASE), SIZE).unwrap();
+    let base = aspace.base();
+    aspace
+        .map_linear(base, PhysAddr::from(SIZE), SIZE, flags)
+        .unwrap();
+    println!("{:?}", aspace);
+    let vaddr = base + SIZE / 2 + PAGE_SIZE;
+    aspace
+        .protect(vaddr, PAGE_SIZE, MappingFlags::READ)
+        .unwrap();
+    println!("{:?}", aspace);
+    let (paddr, page_flags, _) = aspace.page_table().query(vaddr).unwrap();
+    assert_eq!(paddr, PhysAddr::from(SIZE + SIZE / 2 + PAGE_SIZE));
+    assert_eq!(page_flags, MappingFlags::READ);
+    let (paddr, page_flags, _) = aspace.page_table().query(vaddr + PAGE_SIZE).unwrap();
+    assert_eq!(paddr, PhysAddr::from(SIZE + SIZE / 2 + PAGE_SIZE * 2));
+    assert_eq!(page_flags, flags);
+    println!("Huge pages OK!");
+}
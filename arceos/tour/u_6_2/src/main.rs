@@ -0,0 +1,80 @@
+#![cfg_attr(feature = "axstd", no_std)]
+#![cfg_attr(feature = "axstd", no_main)]
+
+#[cfg(feature = "axstd")]
+extern crate axstd as std;
+#[macro_use]
+extern crate axlog;
+
+use std::os::arceos::modules::axconfig::SMP;
+use std::os::arceos::modules::axhal::cpu::this_cpu_id;
+use std::os::arceos::modules::axtask::{self, CpuMask};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::vec::Vec;
+
+const NUM_TASKS: usize = 1000;
+const NUM_YIELDS: usize = 4;
+
+static TASKS_ON_CPU: [AtomicUsize; SMP] = [const { AtomicUsize::new(0) }; SMP];
+
+#[cfg_attr(feature = "axstd", no_mangle)]
+fn main() {
+    ax_println!("Multi-core scheduling is starting on {SMP} CPUs ...");
+
+    // Many short tasks, spread over all CPUs by wakeup migration and stealing.
+    let tasks: Vec<_> = (0..NUM_TASKS)
+        .map(|i| {
+            thread::spawn(move || {
+                let mut sum = 0;
+                for j in 0..NUM_YIELDS {
+                    sum += i * j;
+                    thread::yield_now();
+                }
+                TASKS_ON_CPU[this_cpu_id()].fetch_add(1, Ordering::Relaxed);
+                sum
+            })
+        })
+        .collect();
+    for (i, t) in tasks.into_iter().enumerate() {
+        assert_eq!(t.join().unwrap(), i * NUM_YIELDS * (NUM_YIELDS - 1) / 2);
+    }
+
+    let mut busy_cpus = 0;
+    for (cpu_id, n) in TASKS_ON_CPU.iter().enumerate() {
+        let n = n.load(Ordering::Relaxed);
+        ax_println!("CPU {cpu_id}: {n} tasks finished");
+        if n > 0 {
+            busy_cpus += 1;
+        }
+    }
+    assert_eq!(
+        TASKS_ON_CPU
+            .iter()
+            .map(|n| n.load(Ordering::Relaxed))
+            .sum::<usize>(),
+        NUM_TASKS
+    );
+    if SMP > 1 {
+        assert!(busy_cpus > 1, "tasks are not balanced between CPUs");
+    }
+
+    // Pin tasks to each CPU, they must stay there.
+    let pinned: Vec<_> = (0..SMP)
+        .map(|cpu_id| {
+            thread::spawn(move || {
+                assert!(axtask::set_current_affinity(CpuMask::one(cpu_id)));
+                for _ in 0..NUM_YIELDS {
+                    assert_eq!(this_cpu_id(), cpu_id);
+                    thread::yield_now();
+                }
+                assert_eq!(this_cpu_id(), cpu_id);
+            })
+        })
+        .collect();
+    for t in pinned {
+        t.join().unwrap();
+    }
+
+    ax_println!("Multi-core scheduling ok!");
+}
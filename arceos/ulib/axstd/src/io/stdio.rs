@@ -7,6 +7,17 @@ use alloc::{string::String, vec::Vec};
 struct StdinRaw;
 struct StdoutRaw;
 
+/// Locks the mutex of a standard stream, which is never poisoned.
+#[cfg(feature = "multitask")]
+fn lock_stream<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap()
+}
+
+#[cfg(not(feature = "multitask"))]
+fn lock_stream<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock()
+}
+
 impl Read for StdinRaw {
     // Non-blocking read, returns number of bytes read.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
@@ -53,27 +64,27 @@ impl Stdin {
         // Locks this handle with 'static lifetime. This depends on the
         // implementation detail that the underlying `Mutex` is static.
         StdinLock {
-            inner: self.inner.lock(),
+            inner: lock_stream(self.inner),
         }
     }
 
     /// Locks this handle and reads a line of input, appending it to the specified buffer.
     #[cfg(feature = "alloc")]
     pub fn read_line(&self, buf: &mut String) -> io::Result<usize> {
-        self.inner.lock().read_line(buf)
+        lock_stream(self.inner).read_line(buf)
     }
 }
 
 impl Read for Stdin {
     // Block until at least one byte is read.
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        let read_len = self.inner.lock().read(buf)?;
+        let read_len = lock_stream(self.inner).read(buf)?;
         if buf.is_empty() || read_len > 0 {
             return Ok(read_len);
         }
         // try again until we got something
         loop {
-            let read_len = self.inner.lock().read(buf)?;
+            let read_len = lock_stream(self.inner).read(buf)?;
             if read_len > 0 {
                 return Ok(read_len);
             }
@@ -126,17 +137,17 @@ impl Stdout {
     /// returned guard also implements the `Write` trait for writing data.
     pub fn lock(&self) -> StdoutLock<'static> {
         StdoutLock {
-            inner: self.inner.lock(),
+            inner: lock_stream(self.inner),
         }
     }
 }
 
 impl Write for Stdout {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.inner.lock().write(buf)
+        lock_stream(self.inner).write(buf)
     }
     fn flush(&mut self) -> io::Result<()> {
-        self.inner.lock().flush()
+        lock_stream(self.inner).flush()
     }
 }
 
@@ -20,6 +20,12 @@
 //!     - `alloc-tlsf`: Use the TLSF allocator.
 //!     - `alloc-slab`: Use the slab allocator.
 //!     - `alloc-buddy`: Use the buddy system allocator.
+//!     - `alloc-tag`: Attribute heap and page allocations to tasks and subsystems, shown
+//!       in `/proc/meminfo`.
+//!     - `alloc-debug`: Check heap allocations with redzones and poisoning, to
+//!       catch overflows, use-after-free and double free.
+//!     - `myalloc`: Allow users to define their custom byte allocator to override the default.
+//!     - `alloc-early`: Allow memory allocation from the beginning of the boot.
 //!     - `paging`: Enable page table manipulation.
 //!     - `tls`: Enable thread-local storage.
 //! - Task management
@@ -27,9 +33,13 @@
 //!     - `sched_fifo`: Use the FIFO cooperative scheduler.
 //!     - `sched_rr`: Use the Round-robin preemptive scheduler.
 //!     - `sched_cfs`: Use the Completely Fair Scheduler (CFS) preemptive scheduler.
+//!     - `sched_rt`: Add an earliest-deadline-first real-time scheduling class.
+//!     - `lockdep`: Check the acquisition order of locks to detect possible deadlocks.
+//!     - `tickless`: Stop the periodic timer tick on idle CPUs.
 //! - Upperlayer stacks
 //!     - `fs`: Enable file system support.
 //!     - `myfs`: Allow users to define their custom filesystems to override the default.
+//!     - `ext4fs`: Use an ext2/ext4 filesystem as the root filesystem.
 //!     - `net`: Enable networking support.
 //!     - `dns`: Enable DNS lookup support.
 //!     - `display`: Enable graphics support.
@@ -39,6 +49,7 @@
 //!     - `driver-ramdisk`: Use the RAM disk to emulate the block device.
 //!     - `driver-ixgbe`: Enable the Intel 82599 10Gbit NIC driver.
 //!     - `driver-bcm2835-sdhci`: Enable the BCM2835 SDHCI driver (Raspberry Pi SD card).
+//!     - `driver-virtio-balloon`: Enable the VirtIO memory balloon driver, to let the host reclaim free memory.
 //! - Logging
 //!     - `log-level-off`: Disable all logging.
 //!     - `log-level-error`, `log-level-warn`, `log-level-info`, `log-level-debug`,
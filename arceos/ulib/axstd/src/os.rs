@@ -5,4 +5,30 @@ pub mod arceos {
     pub use arceos_api as api;
     #[doc(no_inline)]
     pub use arceos_api::modules;
+
+    /// Attribution of allocations to threads and subsystems, enabled by the
+    /// `alloc-tag` feature.
+    #[cfg(feature = "alloc-tag")]
+    pub mod alloc_tag {
+        use alloc::vec::Vec;
+
+        pub use arceos_api::mem::{AxAllocTag as AllocTag, AxTagStats as TagStats};
+
+        /// Runs `f` with the heap and page allocations of the current thread
+        /// attributed to the subsystem `tag`.
+        ///
+        /// For single-threaded configuration (`multitask` feature is
+        /// disabled), it just runs `f`.
+        pub fn with_alloc_tag<R>(tag: &'static str, f: impl FnOnce() -> R) -> R {
+            let mut f = Some(f);
+            let mut ret = None;
+            arceos_api::mem::ax_with_alloc_tag(tag, &mut || ret = f.take().map(|f| f()));
+            ret.unwrap()
+        }
+
+        /// Returns the heap and page usage statistics of each allocation tag.
+        pub fn tag_stats() -> Vec<TagStats> {
+            arceos_api::mem::ax_alloc_tag_stats()
+        }
+    }
 }
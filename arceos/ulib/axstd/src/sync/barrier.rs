@@ -0,0 +1,102 @@
+//! A barrier to synchronize multiple threads.
+
+use core::fmt;
+
+use arceos_api::task::{self as api, AxWaitQueueHandle};
+use kspin::SpinNoIrq;
+
+/// A barrier enables multiple threads to synchronize the beginning of some
+/// computation, similar to
+/// [`std::sync::Barrier`](https://doc.rust-lang.org/std/sync/struct.Barrier.html).
+pub struct Barrier {
+    state: SpinNoIrq<BarrierState>,
+    wq: AxWaitQueueHandle,
+    num_threads: usize,
+}
+
+struct BarrierState {
+    count: usize,
+    generation_id: usize,
+}
+
+/// A `BarrierWaitResult` is returned by [`Barrier::wait()`] when all threads
+/// in the [`Barrier`] have rendezvoused.
+pub struct BarrierWaitResult(bool);
+
+impl Barrier {
+    /// Creates a new barrier that can block a given number of threads.
+    ///
+    /// A barrier will block `n`-1 threads which call [`wait()`] and then wake
+    /// up all threads at once when the `n`th thread calls [`wait()`].
+    ///
+    /// [`wait()`]: Barrier::wait
+    pub const fn new(n: usize) -> Self {
+        Self {
+            state: SpinNoIrq::new(BarrierState {
+                count: 0,
+                generation_id: 0,
+            }),
+            wq: AxWaitQueueHandle::new(),
+            num_threads: n,
+        }
+    }
+
+    /// Blocks the current thread until all threads have rendezvoused here.
+    ///
+    /// Barriers are re-usable after all threads have rendezvoused once, and
+    /// can be used continuously.
+    ///
+    /// A single (arbitrary) thread will receive a [`BarrierWaitResult`] that
+    /// returns `true` from [`BarrierWaitResult::is_leader()`] when returning
+    /// from this function, and all other threads will receive a result that
+    /// will return `false` from [`BarrierWaitResult::is_leader()`].
+    ///
+    /// A thread killed while waiting is not counted as arrived any more.
+    pub fn wait(&self) -> BarrierWaitResult {
+        let mut state = self.state.lock();
+        let local_gen = state.generation_id;
+        state.count += 1;
+        if state.count < self.num_threads {
+            drop(state);
+            if !api::ax_wait_queue_wait_or_killed(&self.wq, || {
+                self.state.lock().generation_id != local_gen
+            }) {
+                let mut state = self.state.lock();
+                if state.generation_id == local_gen {
+                    state.count -= 1;
+                }
+                drop(state);
+                api::ax_exit_killed();
+            }
+            BarrierWaitResult(false)
+        } else {
+            state.count = 0;
+            state.generation_id = state.generation_id.wrapping_add(1);
+            drop(state);
+            api::ax_wait_queue_wake(&self.wq, u32::MAX);
+            BarrierWaitResult(true)
+        }
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Barrier").finish_non_exhaustive()
+    }
+}
+
+impl BarrierWaitResult {
+    /// Returns `true` if this thread is the "leader thread" for the call to
+    /// [`Barrier::wait()`].
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl fmt::Debug for BarrierWaitResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BarrierWaitResult")
+            .field("is_leader", &self.is_leader())
+            .finish()
+    }
+}
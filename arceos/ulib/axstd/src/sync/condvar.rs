@@ -0,0 +1,148 @@
+//! A sleeping condition variable.
+
+use core::fmt;
+use core::sync::atomic::{AtomicU32, Ordering};
+use core::time::Duration;
+
+use arceos_api::task::{self as api, AxWaitQueueHandle};
+
+use super::poison::map_result;
+use super::{LockResult, MutexGuard};
+use crate::time::Instant;
+
+/// A type indicating whether a timed wait on a condition variable returned
+/// due to a time out or not.
+///
+/// It is returned by the [`wait_timeout`] method.
+///
+/// [`wait_timeout`]: Condvar::wait_timeout
+#[derive(Debug, PartialEq, Eq, Copy, Clone)]
+pub struct WaitTimeoutResult(bool);
+
+impl WaitTimeoutResult {
+    /// Returns `true` if the wait was known to have timed out.
+    pub fn timed_out(&self) -> bool {
+        self.0
+    }
+}
+
+/// A condition variable, similar to
+/// [`std::sync::Condvar`](https://doc.rust-lang.org/std/sync/struct.Condvar.html).
+///
+/// Like in `std`, waiting threads may be woken up spuriously, so the
+/// condition should be checked in a loop, or use [`wait_while`] instead.
+///
+/// [`wait_while`]: Condvar::wait_while
+pub struct Condvar {
+    wq: AxWaitQueueHandle,
+    /// Increased by every notification, so threads that are going to wait
+    /// won't miss the notifications sent after they unlock the mutex.
+    seq: AtomicU32,
+}
+
+impl Condvar {
+    /// Creates a new condition variable which is ready to be waited on and
+    /// notified.
+    pub const fn new() -> Self {
+        Self {
+            wq: AxWaitQueueHandle::new(),
+            seq: AtomicU32::new(0),
+        }
+    }
+
+    /// Blocks the current thread until this condition variable receives a
+    /// notification.
+    ///
+    /// The mutex of `guard` is unlocked while waiting, and locked again
+    /// before returning.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Acquire);
+        drop(guard);
+        api::ax_wait_queue_wait(&self.wq, || self.seq.load(Ordering::Acquire) != seq, None);
+        mutex.lock()
+    }
+
+    /// Blocks the current thread as long as `condition` returns `true`.
+    pub fn wait_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        mut condition: F,
+    ) -> LockResult<MutexGuard<'a, T>>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Blocks the current thread until this condition variable receives a
+    /// notification, or the given duration has elapsed.
+    ///
+    /// The timeout is ignored if the `irq` feature is not enabled.
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        dur: Duration,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)> {
+        let mutex = guard.mutex();
+        let seq = self.seq.load(Ordering::Acquire);
+        drop(guard);
+        let timed_out = api::ax_wait_queue_wait(
+            &self.wq,
+            || self.seq.load(Ordering::Acquire) != seq,
+            Some(dur),
+        );
+        map_result(mutex.lock(), |guard| (guard, WaitTimeoutResult(timed_out)))
+    }
+
+    /// Blocks the current thread as long as `condition` returns `true`, or
+    /// the given duration has elapsed.
+    pub fn wait_timeout_while<'a, T: ?Sized, F>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        dur: Duration,
+        mut condition: F,
+    ) -> LockResult<(MutexGuard<'a, T>, WaitTimeoutResult)>
+    where
+        F: FnMut(&mut T) -> bool,
+    {
+        let start = Instant::now();
+        loop {
+            if !condition(&mut *guard) {
+                return Ok((guard, WaitTimeoutResult(false)));
+            }
+            let timeout = match dur.checked_sub(start.elapsed()) {
+                Some(timeout) => timeout,
+                None => return Ok((guard, WaitTimeoutResult(true))),
+            };
+            guard = self.wait_timeout(guard, timeout)?.0;
+        }
+    }
+
+    /// Wakes up one thread blocked on this condition variable.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        api::ax_wait_queue_wake(&self.wq, 1);
+    }
+
+    /// Wakes up all threads blocked on this condition variable.
+    pub fn notify_all(&self) {
+        self.seq.fetch_add(1, Ordering::Release);
+        api::ax_wait_queue_wake(&self.wq, u32::MAX);
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Condvar { .. }")
+    }
+}
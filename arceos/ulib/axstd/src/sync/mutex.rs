@@ -7,6 +7,8 @@ use core::sync::atomic::{AtomicU64, Ordering};
 
 use arceos_api::task::{self as api, AxWaitQueueHandle};
 
+use super::{LockResult, TryLockError, TryLockResult};
+
 /// A mutual exclusion primitive useful for protecting shared data, similar to
 /// [`std::sync::Mutex`](https://doc.rust-lang.org/std/sync/struct.Mutex.html).
 ///
@@ -16,6 +18,8 @@ use arceos_api::task::{self as api, AxWaitQueueHandle};
 pub struct Mutex<T: ?Sized> {
     wq: AxWaitQueueHandle,
     owner_id: AtomicU64,
+    #[cfg(feature = "lockdep")]
+    class: &'static core::panic::Location<'static>,
     data: UnsafeCell<T>,
 }
 
@@ -34,21 +38,24 @@ unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
 impl<T> Mutex<T> {
     /// Creates a new [`Mutex`] wrapping the supplied data.
     #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
     pub const fn new(data: T) -> Self {
         Self {
             wq: AxWaitQueueHandle::new(),
             owner_id: AtomicU64::new(0),
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
             data: UnsafeCell::new(data),
         }
     }
 
     /// Consumes this [`Mutex`] and unwraps the underlying data.
     #[inline(always)]
-    pub fn into_inner(self) -> T {
+    pub fn into_inner(self) -> LockResult<T> {
         // We know statically that there are no outstanding references to
         // `self` so there's no need to lock.
         let Mutex { data, .. } = self;
-        data.into_inner()
+        Ok(data.into_inner())
     }
 }
 
@@ -68,8 +75,10 @@ impl<T: ?Sized> Mutex<T> {
     ///
     /// The returned value may be dereferenced for data access
     /// and the lock will be dropped when the guard falls out of scope.
-    pub fn lock(&self) -> MutexGuard<T> {
+    pub fn lock(&self) -> LockResult<MutexGuard<T>> {
         let current_id = api::ax_current_task_id();
+        #[cfg(feature = "lockdep")]
+        api::ax_lockdep_check_acquire(self.class);
         loop {
             // Can fail to lock even if the spinlock is not locked. May be more efficient than `try_lock`
             // when called in a loop.
@@ -91,15 +100,19 @@ impl<T: ?Sized> Mutex<T> {
                 }
             }
         }
-        MutexGuard {
+        #[cfg(feature = "lockdep")]
+        api::ax_lockdep_on_acquire(self.class);
+        Ok(MutexGuard {
             lock: self,
             data: unsafe { &mut *self.data.get() },
-        }
+        })
     }
 
     /// Try to lock this [`Mutex`], returning a lock guard if successful.
+    ///
+    /// Returns [`TryLockError::WouldBlock`] if it is held by others.
     #[inline(always)]
-    pub fn try_lock(&self) -> Option<MutexGuard<T>> {
+    pub fn try_lock(&self) -> TryLockResult<MutexGuard<T>> {
         let current_id = api::ax_current_task_id();
         // The reason for using a strong compare_exchange is explained here:
         // https://github.com/Amanieu/parking_lot/pull/207#issuecomment-575869107
@@ -108,15 +121,23 @@ impl<T: ?Sized> Mutex<T> {
             .compare_exchange(0, current_id, Ordering::Acquire, Ordering::Relaxed)
             .is_ok()
         {
-            Some(MutexGuard {
+            #[cfg(feature = "lockdep")]
+            api::ax_lockdep_on_acquire(self.class);
+            Ok(MutexGuard {
                 lock: self,
                 data: unsafe { &mut *self.data.get() },
             })
         } else {
-            None
+            Err(TryLockError::WouldBlock)
         }
     }
 
+    /// Determines whether the mutex is poisoned, which is always `false`.
+    #[inline(always)]
+    pub fn is_poisoned(&self) -> bool {
+        false
+    }
+
     /// Force unlock the [`Mutex`].
     ///
     /// # Safety
@@ -132,6 +153,8 @@ impl<T: ?Sized> Mutex<T> {
             "Thread({}) tried to release mutex it doesn't own",
             current_id,
         );
+        #[cfg(feature = "lockdep")]
+        api::ax_lockdep_on_release(self.class);
         // wake up one waiting thread.
         api::ax_wait_queue_wake(&self.wq, 1);
     }
@@ -142,10 +165,10 @@ impl<T: ?Sized> Mutex<T> {
     /// Rust, no actual locking needs to take place -- the mutable borrow statically guarantees no locks exist. As
     /// such, this is a 'zero-cost' operation.
     #[inline(always)]
-    pub fn get_mut(&mut self) -> &mut T {
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
         // We know statically that there are no other references to `self`, so
         // there's no need to lock the inner mutex.
-        unsafe { &mut *self.data.get() }
+        Ok(unsafe { &mut *self.data.get() })
     }
 }
 
@@ -159,14 +182,22 @@ impl<T: ?Sized + Default> Default for Mutex<T> {
 impl<T: ?Sized + fmt::Debug> fmt::Debug for Mutex<T> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self.try_lock() {
-            Some(guard) => write!(f, "Mutex {{ data: ")
+            Ok(guard) => write!(f, "Mutex {{ data: ")
                 .and_then(|()| (*guard).fmt(f))
                 .and_then(|()| write!(f, "}}")),
-            None => write!(f, "Mutex {{ <locked> }}"),
+            Err(_) => write!(f, "Mutex {{ <locked> }}"),
         }
     }
 }
 
+impl<'a, T: ?Sized> MutexGuard<'a, T> {
+    /// Returns the mutex that the guard is created from.
+    #[inline(always)]
+    pub(super) fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
 impl<'a, T: ?Sized> Deref for MutexGuard<'a, T> {
     type Target = T;
     #[inline(always)]
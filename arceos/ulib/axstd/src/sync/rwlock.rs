@@ -0,0 +1,297 @@
+//! A naïve sleeping reader-writer lock.
+
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use arceos_api::task::{self as api, AxWaitQueueHandle};
+
+use super::{LockResult, TryLockError, TryLockResult};
+
+/// The lock state when it is held by a writer, otherwise the state is the
+/// number of readers.
+const WRITER: usize = usize::MAX;
+
+/// A reader-writer lock, similar to
+/// [`std::sync::RwLock`](https://doc.rust-lang.org/std/sync/struct.RwLock.html).
+///
+/// It allows multiple readers or at most one writer at any point in time.
+/// Threads that can not get the lock block and are put into the wait queue.
+/// Writers are preferred: new readers wait if there are writers waiting.
+pub struct RwLock<T: ?Sized> {
+    wq: AxWaitQueueHandle,
+    state: AtomicUsize,
+    writers_waiting: AtomicUsize,
+    #[cfg(feature = "lockdep")]
+    class: &'static core::panic::Location<'static>,
+    data: UnsafeCell<T>,
+}
+
+/// Counts a writer waiting for the [`RwLock`] while it's alive, which keeps
+/// new readers off.
+///
+/// The count is dropped after the writer gets the lock, or is killed while
+/// waiting, otherwise the readers would wait forever.
+struct WaitingWriter<'a, T: ?Sized> {
+    lock: &'a RwLock<T>,
+}
+
+/// A guard that provides immutable data access.
+///
+/// When the guard falls out of scope it will release the shared read access.
+pub struct RwLockReadGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    data: *const T,
+}
+
+/// A guard that provides mutable data access.
+///
+/// When the guard falls out of scope it will release the exclusive write
+/// access.
+pub struct RwLockWriteGuard<'a, T: ?Sized + 'a> {
+    lock: &'a RwLock<T>,
+    data: *mut T,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for RwLock<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLock<T> {}
+
+unsafe impl<T: ?Sized + Sync> Sync for RwLockReadGuard<'_, T> {}
+unsafe impl<T: ?Sized + Sync> Sync for RwLockWriteGuard<'_, T> {}
+
+impl<T> RwLock<T> {
+    /// Creates a new [`RwLock`] wrapping the supplied data.
+    #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    pub const fn new(data: T) -> Self {
+        Self {
+            wq: AxWaitQueueHandle::new(),
+            state: AtomicUsize::new(0),
+            writers_waiting: AtomicUsize::new(0),
+            #[cfg(feature = "lockdep")]
+            class: core::panic::Location::caller(),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    /// Consumes this [`RwLock`] and unwraps the underlying data.
+    #[inline(always)]
+    pub fn into_inner(self) -> LockResult<T> {
+        let RwLock { data, .. } = self;
+        Ok(data.into_inner())
+    }
+}
+
+impl<T: ?Sized> RwLock<T> {
+    fn can_read(&self) -> bool {
+        self.state.load(Ordering::Relaxed) != WRITER
+            && self.writers_waiting.load(Ordering::Relaxed) == 0
+    }
+
+    fn acquire_read(&self) -> Option<RwLockReadGuard<T>> {
+        let mut state = self.state.load(Ordering::Relaxed);
+        while self.can_read() {
+            match self.state.compare_exchange_weak(
+                state,
+                state + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    #[cfg(feature = "lockdep")]
+                    api::ax_lockdep_on_acquire(self.class);
+                    return Some(RwLockReadGuard {
+                        lock: self,
+                        data: self.data.get(),
+                    });
+                }
+                Err(s) => state = s,
+            }
+        }
+        None
+    }
+
+    fn acquire_write(&self) -> Option<RwLockWriteGuard<T>> {
+        if self
+            .state
+            .compare_exchange(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            #[cfg(feature = "lockdep")]
+            api::ax_lockdep_on_acquire(self.class);
+            Some(RwLockWriteGuard {
+                lock: self,
+                data: self.data.get(),
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Locks this [`RwLock`] with shared read access, blocking the current
+    /// thread until it can be acquired.
+    pub fn read(&self) -> LockResult<RwLockReadGuard<T>> {
+        #[cfg(feature = "lockdep")]
+        api::ax_lockdep_check_acquire(self.class);
+        loop {
+            if let Some(guard) = self.acquire_read() {
+                return Ok(guard);
+            }
+            api::ax_wait_queue_wait(&self.wq, || self.can_read(), None);
+        }
+    }
+
+    /// Attempts to acquire this [`RwLock`] with shared read access.
+    ///
+    /// Returns [`TryLockError::WouldBlock`] if it is held by a writer or
+    /// there are writers waiting.
+    pub fn try_read(&self) -> TryLockResult<RwLockReadGuard<T>> {
+        self.acquire_read().ok_or(TryLockError::WouldBlock)
+    }
+
+    /// Locks this [`RwLock`] with exclusive write access, blocking the
+    /// current thread until it can be acquired.
+    pub fn write(&self) -> LockResult<RwLockWriteGuard<T>> {
+        #[cfg(feature = "lockdep")]
+        api::ax_lockdep_check_acquire(self.class);
+        if let Some(guard) = self.acquire_write() {
+            return Ok(guard);
+        }
+        let waiting = WaitingWriter::new(self);
+        loop {
+            if !api::ax_wait_queue_wait_or_killed(&self.wq, || {
+                self.state.load(Ordering::Relaxed) == 0
+            }) {
+                drop(waiting);
+                api::ax_exit_killed();
+            }
+            if let Some(guard) = self.acquire_write() {
+                return Ok(guard);
+            }
+        }
+    }
+
+    /// Attempts to acquire this [`RwLock`] with exclusive write access.
+    ///
+    /// Returns [`TryLockError::WouldBlock`] if it is held by others.
+    pub fn try_write(&self) -> TryLockResult<RwLockWriteGuard<T>> {
+        self.acquire_write().ok_or(TryLockError::WouldBlock)
+    }
+
+    /// Determines whether the lock is poisoned, which is always `false`.
+    #[inline(always)]
+    pub fn is_poisoned(&self) -> bool {
+        false
+    }
+
+    /// Returns a mutable reference to the underlying data.
+    ///
+    /// Since this call borrows the [`RwLock`] mutably, no actual locking needs
+    /// to take place.
+    #[inline(always)]
+    pub fn get_mut(&mut self) -> LockResult<&mut T> {
+        Ok(unsafe { &mut *self.data.get() })
+    }
+
+    fn read_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        api::ax_lockdep_on_release(self.class);
+        if self.state.fetch_sub(1, Ordering::Release) == 1 {
+            // the last reader, wake up writers.
+            api::ax_wait_queue_wake(&self.wq, u32::MAX);
+        }
+    }
+
+    fn write_unlock(&self) {
+        #[cfg(feature = "lockdep")]
+        api::ax_lockdep_on_release(self.class);
+        self.state.store(0, Ordering::Release);
+        api::ax_wait_queue_wake(&self.wq, u32::MAX);
+    }
+}
+
+impl<T: ?Sized + Default> Default for RwLock<T> {
+    #[inline(always)]
+    #[cfg_attr(feature = "lockdep", track_caller)]
+    fn default() -> Self {
+        Self::new(Default::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLock<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.acquire_read() {
+            Some(guard) => write!(f, "RwLock {{ data: ")
+                .and_then(|()| (*guard).fmt(f))
+                .and_then(|()| write!(f, "}}")),
+            None => write!(f, "RwLock {{ <locked> }}"),
+        }
+    }
+}
+
+impl<'a, T: ?Sized> WaitingWriter<'a, T> {
+    fn new(lock: &'a RwLock<T>) -> Self {
+        lock.writers_waiting.fetch_add(1, Ordering::Relaxed);
+        Self { lock }
+    }
+}
+
+impl<T: ?Sized> Drop for WaitingWriter<'_, T> {
+    fn drop(&mut self) {
+        let lock = self.lock;
+        let last = lock.writers_waiting.fetch_sub(1, Ordering::Relaxed) == 1;
+        if last && lock.state.load(Ordering::Relaxed) != WRITER {
+            // no writers are waiting or holding the lock, wake up readers.
+            api::ax_wait_queue_wake(&lock.wq, u32::MAX);
+        }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockReadGuard<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockWriteGuard<'a, T> {
+    type Target = T;
+    #[inline(always)]
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockWriteGuard<'a, T> {
+    #[inline(always)]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for RwLockReadGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized + fmt::Debug> fmt::Debug for RwLockWriteGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockReadGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.read_unlock();
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.write_unlock();
+    }
+}
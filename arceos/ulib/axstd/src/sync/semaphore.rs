@@ -0,0 +1,81 @@
+//! A sleeping counting semaphore.
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use arceos_api::task::{self as api, AxWaitQueueHandle};
+
+/// A counting semaphore.
+///
+/// It maintains a number of permits. [`acquire`] takes a permit, blocking
+/// the current thread until one is available, and [`release`] gives a permit
+/// back and wakes up a waiting thread.
+///
+/// [`acquire`]: Semaphore::acquire
+/// [`release`]: Semaphore::release
+pub struct Semaphore {
+    wq: AxWaitQueueHandle,
+    count: AtomicUsize,
+}
+
+/// An RAII guard which will release a permit of the semaphore when dropped.
+pub struct SemaphoreGuard<'a> {
+    sem: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with the initial number of permits.
+    pub const fn new(count: usize) -> Self {
+        Self {
+            wq: AxWaitQueueHandle::new(),
+            count: AtomicUsize::new(count),
+        }
+    }
+
+    /// Returns the number of permits currently available.
+    pub fn available_permits(&self) -> usize {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Acquires a permit, blocking the current thread until one is available.
+    pub fn acquire(&self) {
+        while !self.try_acquire() {
+            api::ax_wait_queue_wait(&self.wq, || self.available_permits() > 0, None);
+        }
+    }
+
+    /// Acquires a permit if one is available, returns `true` on success.
+    pub fn try_acquire(&self) -> bool {
+        self.count
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |count| {
+                count.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    /// Releases a permit, and wakes up a thread waiting for it.
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::Release);
+        api::ax_wait_queue_wake(&self.wq, 1);
+    }
+
+    /// Acquires a permit, and returns a guard that releases it when dropped.
+    pub fn access(&self) -> SemaphoreGuard {
+        self.acquire();
+        SemaphoreGuard { sem: self }
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Semaphore")
+            .field("count", &self.available_permits())
+            .finish()
+    }
+}
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        self.sem.release();
+    }
+}
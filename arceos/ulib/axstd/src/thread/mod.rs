@@ -3,7 +3,11 @@
 #[cfg(feature = "multitask")]
 mod multi;
 #[cfg(feature = "multitask")]
+mod timer;
+#[cfg(feature = "multitask")]
 pub use multi::*;
+#[cfg(feature = "multitask")]
+pub use timer::Timer;
 
 use arceos_api::task as api;
 
@@ -0,0 +1,55 @@
+//! Timers that call a function at a given time.
+
+use arceos_api::task::{self as api, AxTimerHandle};
+use arceos_api::time::{ax_wall_time, AxTimeValue};
+use core::time::Duration;
+
+/// A timer that calls a function after a delay, or periodically.
+///
+/// The function runs in the timer interrupt handler, so it must be short and
+/// must not block. Timers never fire if the `irq` feature is not enabled.
+///
+/// Dropping the timer does not cancel it.
+pub struct Timer(AxTimerHandle);
+
+impl Timer {
+    /// Creates a timer that calls `f` once after `dur`.
+    pub fn after<F>(dur: Duration, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        Self::at(ax_wall_time() + dur, f)
+    }
+
+    /// Creates a timer that calls `f` once at the given deadline.
+    pub fn at<F>(deadline: AxTimeValue, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        Self(api::ax_set_timer(deadline, None, f))
+    }
+
+    /// Creates a timer that calls `f` every `period`, starting from one
+    /// period later.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `period` is zero.
+    pub fn periodic<F>(period: Duration, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        Self(api::ax_set_timer(ax_wall_time() + period, Some(period), f))
+    }
+
+    /// Re-arms the timer to fire after `dur`, no matter whether it has fired
+    /// or been cancelled.
+    pub fn reset(&self, dur: Duration) {
+        api::ax_rearm_timer(&self.0, ax_wall_time() + dur);
+    }
+
+    /// Cancels the timer, returns whether it was pending.
+    pub fn cancel(&self) -> bool {
+        api::ax_cancel_timer(&self.0)
+    }
+}
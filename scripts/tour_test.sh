@@ -76,6 +76,14 @@ run_test "tour/u_6_0" "n" "" "" "" "Multi-task(Preemptible) ok!"
 run_test "tour/u_6_1" "n" "" "" "" "worker2 ok!" "worker1 ok!" "WaitQ ok!"
 run_test "tour/u_7_0" "y" "" "" "" "[mkfs.fat]" "worker1 ok!" "Load app from disk ok!"
 run_test "tour/u_8_0" "y" "" "" "" "worker1 checks code:" "worker1 ok!" "Load app from disk ok!"
+run_test "tour/u_13_0" "n" "" "" "" "Page table frames reduced" "Huge pages OK!"
+run_test "exercises/stack_guard" "n" "" "" "" "Overflowing a task stack" "stack overflow"
+NET=y run_test "exercises/async_tcp" "n" "" "" "" "Connected to 10.0.2.2:5555" "Echoed 65536 bytes" "Server closed" "Async TCP OK!"
+run_test "exercises/aspace_cow" "n" "" "" "" "Frames shared after cloning" "Pages diverged after writes" "Write-protected pages OK" "Copy-on-write OK!"
+run_test "exercises/mmap_file" "y" "" "" "" "Private mapping OK" "Shared mapping OK" "File mapping OK!"
+run_test "exercises/swap_pages" "y" "" "" "" "Swapped out" "Reclaimed pages of another address space" "Swapped in pages OK" "Swap OK!"
+run_test "exercises/aspace_maps" "n" "" "" "" "Areas listed" "Area found at addresses" "Areas renamed" "Area info OK!"
+run_test "exercises/alloc_tag" "n" "" "" "" "Heap allocations tagged" "Page allocations tagged" "Kernel stacks tagged" "Alloc tags OK!"
 run_test "tour/m_1_0" "y" "y" "payload/origin/origin" "" "monolithic kernel exit [Some(0)] normally!"
 run_test "tour/m_1_1" "y" "y" "payload/origin/origin" "" "monolithic kernel exit [Some(0)] normally!"
 run_test "tour/m_2_0" "y" "y" "payload/origin/origin" "" "handle page fault OK!" "monolithic kernel exit [Some(0)] normally!"